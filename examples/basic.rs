@@ -0,0 +1,26 @@
+mod support;
+
+use support::*;
+
+#[tokio::main]
+async fn main() -> SqlResult<()> {
+    let pool = open_pool("basic").await?;
+    let mut conn = pool.get_conn().await?;
+    recreate_table(&mut conn, "users", "name VARCHAR(64) NOT NULL UNIQUE").await?;
+
+    let id = conn.insert_returning_id(sql_query("INSERT INTO users (name) VALUES (?)").bind("alice")).await?;
+    println!("inserted alice with id {}", id);
+
+    let ret = conn.insert_returning_id(sql_query("INSERT INTO users (name) VALUES (?)").bind("alice")).await;
+    let e = ret.expect_err("duplicate insert must fail");
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    println!("duplicate insert rejected: {:?}", e.code());
+
+    let row = conn.query_one(sql_query("SELECT id, name FROM users WHERE id = ?").bind(id)).await?;
+    let name: String = row.get("name");
+    println!("loaded {} {}", id, name);
+
+    let ret = conn.query_one(sql_query("SELECT id FROM users WHERE name = ?").bind("bob")).await;
+    assert_eq!(ret.err().map(|e| e.code()), Some(SqlErrorCode::NotFound));
+    Ok(())
+}
@@ -0,0 +1,30 @@
+mod support;
+
+use support::*;
+
+const ROWS: usize = 1000;
+const BATCH: usize = 100;
+
+#[tokio::main]
+async fn main() -> SqlResult<()> {
+    let pool = open_pool("bulk_insert").await?;
+    let mut conn = pool.get_conn().await?;
+    recreate_table(&mut conn, "events", "seq BIGINT NOT NULL, payload VARCHAR(64) NOT NULL").await?;
+
+    conn.begin_transaction().await?;
+    for start in (0..ROWS).step_by(BATCH) {
+        let values = vec!["(?, ?)"; BATCH].join(", ");
+        let sql = format!("INSERT INTO events (seq, payload) VALUES {}", values);
+        let mut query = sql_query(sql.as_str());
+        for seq in start..start + BATCH {
+            query = query.bind(seq as i64).bind(format!("event-{}", seq));
+        }
+        conn.execute_sql(query).await?;
+    }
+    conn.commit_transaction().await?;
+
+    let row = conn.query_one(sql_query("SELECT count(*) AS c FROM events")).await?;
+    let count: i64 = row.get("c");
+    println!("inserted {} rows", count);
+    Ok(())
+}
@@ -0,0 +1,29 @@
+mod support;
+
+use support::*;
+
+async fn fill(lease: LeasedConnection) -> SqlResult<()> {
+    let mut conn = lease.lock().await?;
+    conn.execute_sql(sql_query("INSERT INTO tmp_ids (v) VALUES (1), (2), (3)")).await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> SqlResult<()> {
+    let pool = open_pool("lease").await?;
+    let lease = pool.lease_conn("tmp table report").await?;
+    {
+        let mut conn = lease.lock().await?;
+        conn.execute_sql(sql_query("CREATE TEMPORARY TABLE tmp_ids (v BIGINT NOT NULL)")).await?;
+    }
+    fill(lease.clone_handle()).await?;
+    {
+        let mut conn = lease.lock().await?;
+        let row = conn.query_one(sql_query("SELECT count(*) AS c FROM tmp_ids")).await?;
+        let count: i64 = row.get("c");
+        println!("temp table rows seen through the lease: {}", count);
+    }
+    lease.release().await;
+    println!("idle connections after release: {}", pool.num_idle());
+    Ok(())
+}
@@ -0,0 +1,32 @@
+mod support;
+
+use support::*;
+
+const MIGRATIONS: &[(i64, &str)] = &[
+    (1, "CREATE TABLE notes (id BIGINT PRIMARY KEY, body VARCHAR(255) NOT NULL)"),
+    (2, "ALTER TABLE notes ADD COLUMN created_at BIGINT NOT NULL DEFAULT 0"),
+    (3, "CREATE INDEX idx_notes_created_at ON notes (created_at)"),
+];
+
+async fn current_version(conn: &mut SqlConnection) -> SqlResult<i64> {
+    let row = conn.query_one(sql_query("SELECT COALESCE(MAX(version), 0) AS v FROM schema_version")).await?;
+    Ok(row.get("v"))
+}
+
+#[tokio::main]
+async fn main() -> SqlResult<()> {
+    let pool = open_pool("migration").await?;
+    let mut conn = pool.get_conn().await?;
+    conn.execute_sql(sql_query("DROP TABLE IF EXISTS notes")).await?;
+    conn.execute_sql(sql_query("DROP TABLE IF EXISTS schema_version")).await?;
+    conn.execute_sql(sql_query("CREATE TABLE schema_version (version BIGINT PRIMARY KEY)")).await?;
+
+    let version = current_version(&mut conn).await?;
+    for (target, sql) in MIGRATIONS.iter().filter(|(v, _)| *v > version) {
+        conn.execute_sql(sql_query(sql)).await?;
+        conn.execute_sql(sql_query("INSERT INTO schema_version (version) VALUES (?)").bind(*target)).await?;
+        println!("applied migration {}", target);
+    }
+    println!("schema at version {}", current_version(&mut conn).await?);
+    Ok(())
+}
@@ -0,0 +1,20 @@
+mod support;
+
+use support::*;
+
+#[tokio::main]
+async fn main() -> SqlResult<()> {
+    let pool = open_pool("schema").await?;
+    let mut conn = pool.get_conn().await?;
+    recreate_table(&mut conn, "items", "title VARCHAR(128) NOT NULL").await?;
+
+    if !conn.is_column_exist("items", "price", None).await? {
+        conn.execute_sql(sql_query("ALTER TABLE items ADD COLUMN price BIGINT NOT NULL DEFAULT 0")).await?;
+    }
+    if !conn.is_index_exist("items", "idx_items_title", None).await? {
+        conn.execute_sql(sql_query("CREATE INDEX idx_items_title ON items (title)")).await?;
+    }
+    println!("price column: {}", conn.is_column_exist("items", "price", None).await?);
+    println!("title index: {}", conn.is_index_exist("items", "idx_items_title", None).await?);
+    Ok(())
+}
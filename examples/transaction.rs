@@ -0,0 +1,26 @@
+mod support;
+
+use support::*;
+
+async fn count(conn: &mut SqlConnection) -> SqlResult<i64> {
+    let row = conn.query_one(sql_query("SELECT count(*) AS c FROM accounts")).await?;
+    Ok(row.get("c"))
+}
+
+#[tokio::main]
+async fn main() -> SqlResult<()> {
+    let pool = open_pool("transaction").await?;
+    let mut conn = pool.get_conn().await?;
+    recreate_table(&mut conn, "accounts", "balance BIGINT NOT NULL").await?;
+
+    conn.begin_transaction().await?;
+    conn.execute_sql(sql_query("INSERT INTO accounts (balance) VALUES (?)").bind(100i64)).await?;
+    conn.rollback_transaction().await?;
+    println!("after rollback: {} rows", count(&mut conn).await?);
+
+    conn.begin_transaction().await?;
+    conn.execute_sql(sql_query("INSERT INTO accounts (balance) VALUES (?)").bind(100i64)).await?;
+    conn.commit_transaction().await?;
+    println!("after commit: {} rows", count(&mut conn).await?);
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, SqlConnection, SqlPool};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, SqlValue};
+
+struct BatchStatement {
+    table: String,
+    columns: Vec<String>,
+}
+
+struct ReplyState<E> {
+    ret: Option<Result<(), E>>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+// Outcome of one submitted row, handed from the task writing its batch to the submitter.
+struct ReplySender<E>(Arc<Mutex<ReplyState<E>>>);
+
+impl<E> ReplySender<E> {
+    fn send(self, ret: Result<(), E>) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).ret = Some(ret);
+    }
+}
+
+impl<E> Drop for ReplySender<E> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// None when the batch was dropped without being written.
+async fn receive<E>(state: Arc<Mutex<ReplyState<E>>>) -> Option<Result<(), E>> {
+    std::future::poll_fn(|cx| {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ret) = state.ret.take() {
+            Poll::Ready(Some(ret))
+        } else if state.closed {
+            Poll::Ready(None)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }).await
+}
+
+struct PendingBatch<E> {
+    rows: Vec<(Vec<SqlValue>, ReplySender<E>)>,
+    since: Instant,
+}
+
+struct BatcherInner<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pool: SqlPool<DB, EM>,
+    statements: HashMap<String, BatchStatement>,
+    pending: Mutex<HashMap<String, PendingBatch<EM::OutError>>>,
+    max_batch: usize,
+    max_delay: Duration,
+    closed: AtomicBool,
+}
+
+// Groups single-row inserts submitted by many tasks into multi-row inserts. A batch is written
+// once it holds max_batch rows, or once its first row has waited max_delay, checked every half
+// max_delay. When a batch fails, its rows are written one by one so every submitter gets the
+// outcome of its own row, a constraint violation included.
+pub struct StatementBatcher<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    inner: Arc<BatcherInner<DB, EM>>,
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> Clone for StatementBatcher<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+pub struct StatementBatcherBuilder<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pool: SqlPool<DB, EM>,
+    statements: HashMap<String, BatchStatement>,
+    max_batch: usize,
+    max_delay: Duration,
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> StatementBatcherBuilder<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      EM::OutError: Send, {
+    // Registers the insert into table of columns under key, the key submit takes.
+    pub fn statement(mut self, key: &str, table: &str, columns: &[&str]) -> Self {
+        self.statements.insert(key.to_string(), BatchStatement {
+            table: table.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+        });
+        self
+    }
+
+    // Rows of one insert, lowered per statement to what the bound parameter limit allows.
+    pub fn max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = max_batch.max(1);
+        self
+    }
+
+    // How long a submitted row waits for others to join its batch.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    // Builds the batcher without a background task, batches short of max_batch are only
+    // written through flush_now.
+    pub fn build(self) -> StatementBatcher<DB, EM> {
+        StatementBatcher {
+            inner: Arc::new(BatcherInner {
+                pool: self.pool,
+                statements: self.statements,
+                pending: Mutex::new(HashMap::new()),
+                max_batch: self.max_batch,
+                max_delay: self.max_delay,
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    // Builds the batcher and spawns the task writing the batches that waited max_delay.
+    pub fn start(self) -> StatementBatcher<DB, EM> {
+        let batcher = self.build();
+        let task = batcher.clone();
+        sqlx_core::rt::spawn(async move {
+            task.run().await;
+        });
+        batcher
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> StatementBatcher<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      EM::OutError: Send, {
+    pub fn builder(pool: &SqlPool<DB, EM>) -> StatementBatcherBuilder<DB, EM> {
+        StatementBatcherBuilder {
+            pool: pool.clone(),
+            statements: HashMap::new(),
+            max_batch: 500,
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    // Queues row for the statement registered under key and waits until its batch was written.
+    pub async fn submit(&self, key: &str, row: Vec<SqlValue>) -> Result<(), EM::OutError> {
+        let statement = match self.inner.statements.get(key) {
+            Some(statement) => statement,
+            None => return Err(EM::map_parameter_mismatch(format!("no statement {} registered with the batcher", key).as_str())),
+        };
+        if row.len() != statement.columns.len() {
+            return Err(EM::map_parameter_mismatch(format!("row has {} values, statement {} has {} columns", row.len(), key, statement.columns.len()).as_str()));
+        }
+        if self.inner.closed.load(Ordering::SeqCst) {
+            return Err(EM::map_shutting_down(format!("batcher is shut down, statement {}", key).as_str()));
+        }
+        let max_batch = self.inner.max_batch.min((self.inner.pool.max_bind_params() / statement.columns.len().max(1)).max(1));
+        let state = Arc::new(Mutex::new(ReplyState { ret: None, waker: None, closed: false }));
+        let full = {
+            let mut pending = self.inner.pending.lock().unwrap_or_else(|e| e.into_inner());
+            let now = self.inner.pool.clock().now();
+            let batch = pending.entry(key.to_string()).or_insert_with(|| PendingBatch { rows: Vec::new(), since: now });
+            batch.rows.push((row, ReplySender(state.clone())));
+            if batch.rows.len() >= max_batch {
+                pending.remove(key)
+            } else {
+                None
+            }
+        };
+        if let Some(batch) = full {
+            // Spawned, so dropping this submit does not drop the rows of the other submitters.
+            let batcher = self.clone();
+            let key = key.to_string();
+            sqlx_core::rt::spawn(async move {
+                batcher.write_batch(key.as_str(), batch).await;
+            });
+        }
+        match receive(state).await {
+            Some(ret) => ret,
+            None => Err(EM::map_shutting_down(format!("batch of statement {} dropped before it was written", key).as_str())),
+        }
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.inner.pending.lock().unwrap_or_else(|e| e.into_inner()).values().map(|b| b.rows.len()).sum()
+    }
+
+    // Writes every pending batch, whatever its age.
+    pub async fn flush_now(&self) {
+        for (key, batch) in self.take_batches(true) {
+            self.write_batch(key.as_str(), batch).await;
+        }
+    }
+
+    // Stops the background task, rejects further submits and writes what is still pending.
+    pub async fn shutdown(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.flush_now().await
+    }
+
+    async fn run(&self) {
+        let tick = (self.inner.max_delay / 2).max(Duration::from_millis(1));
+        let clock = self.inner.pool.clock();
+        while !self.inner.closed.load(Ordering::SeqCst) {
+            clock.sleep(tick).await;
+            for (key, batch) in self.take_batches(false) {
+                self.write_batch(key.as_str(), batch).await;
+            }
+        }
+    }
+
+    fn take_batches(&self, all: bool) -> Vec<(String, PendingBatch<EM::OutError>)> {
+        let clock = self.inner.pool.clock();
+        let mut pending = self.inner.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let keys: Vec<String> = pending.iter()
+            .filter(|(_, batch)| all || clock.elapsed(batch.since) >= self.inner.max_delay)
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.into_iter().filter_map(|key| pending.remove(&key).map(|batch| (key, batch))).collect()
+    }
+
+    async fn write_batch(&self, key: &str, batch: PendingBatch<EM::OutError>) {
+        let statement = match self.inner.statements.get(key) {
+            Some(statement) => statement,
+            None => return,
+        };
+        let (rows, senders): (Vec<Vec<SqlValue>>, Vec<ReplySender<EM::OutError>>) = batch.rows.into_iter().unzip();
+        let mut conn = self.inner.pool.get_conn().await.ok();
+        if rows.len() > 1 {
+            if let Some(conn) = conn.as_mut() {
+                if self.insert(conn, statement, rows.as_slice()).await.is_ok() {
+                    for sender in senders {
+                        sender.send(Ok(()));
+                    }
+                    return;
+                }
+                log::warn!("batch of {} rows into {} failed, writing them one by one", rows.len(), statement.table);
+            }
+        }
+        for (row, sender) in rows.into_iter().zip(senders) {
+            let rows = std::slice::from_ref(&row);
+            let ret = match conn.as_mut() {
+                Some(conn) => self.insert(conn, statement, rows).await,
+                None => match self.inner.pool.get_conn().await {
+                    Ok(mut conn) => self.insert(&mut conn, statement, rows).await,
+                    Err(e) => Err(e),
+                },
+            };
+            sender.send(ret);
+        }
+    }
+
+    async fn insert(&self, conn: &mut SqlConnection<DB, EM>, statement: &BatchStatement, rows: &[Vec<SqlValue>]) -> Result<(), EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let columns = statement.columns.iter().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+        let values = (0..rows.len()).map(|r| {
+            let row = (1..=statement.columns.len()).map(|c| backend.placeholder(r * statement.columns.len() + c)).collect::<Vec<_>>();
+            format!("({})", row.join(", "))
+        }).collect::<Vec<_>>().join(", ");
+        let sql = format!("INSERT INTO {} ({}) VALUES {}", quote_qualified(statement.table.as_str(), quote), columns, values);
+        let mut query = sqlx::query::<DB>(sql.as_str());
+        for row in rows.iter() {
+            for value in row.iter() {
+                query = query.bind_value(value.clone());
+            }
+        }
+        conn.execute_sql(query).await.map(|_| ())
+    }
+}
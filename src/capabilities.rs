@@ -0,0 +1,80 @@
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::target::SqlBackend;
+
+// Server features some helpers depend on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Capability {
+    // INSERT ... RETURNING.
+    Returning,
+    JsonFunctions,
+    FullTextSearch,
+    // mysql performance_schema, used to find the holders of metadata locks.
+    PerformanceSchema,
+    // sqlite journal in wal mode, which some filesystems such as network shares refuse.
+    WalMode,
+}
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Returning => "RETURNING",
+            Capability::JsonFunctions => "json functions",
+            Capability::FullTextSearch => "full text search",
+            Capability::PerformanceSchema => "performance_schema",
+            Capability::WalMode => "wal journal mode",
+        }
+    }
+}
+
+// What the server behind a pool supports, probed on first use and cached on the pool.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Capabilities {
+    pub backend: SqlBackend,
+    pub server_version: String,
+    pub returning: bool,
+    pub json_functions: bool,
+    pub full_text_search: bool,
+    pub performance_schema: bool,
+    pub wal_mode: bool,
+}
+
+impl Capabilities {
+    pub fn has(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Returning => self.returning,
+            Capability::JsonFunctions => self.json_functions,
+            Capability::FullTextSearch => self.full_text_search,
+            Capability::PerformanceSchema => self.performance_schema,
+            Capability::WalMode => self.wal_mode,
+        }
+    }
+
+    // Fails with SqlErrorCode::Unsupported naming both the capability and needed_by, for helpers
+    // to call before sending sql the server would reject with a less telling error.
+    pub fn require(&self, capability: Capability, needed_by: &str) -> SqlResult<()> {
+        if self.has(capability) {
+            return Ok(());
+        }
+        Err(sql_err!(SqlErrorCode::Unsupported, "{:?} server {} lacks {}, required for {}",
+                     self.backend, self.server_version, capability.name(), needed_by))
+    }
+
+    // One line per capability, for attaching to support tickets.
+    pub fn report(&self) -> String {
+        let mut report = format!("backend: {:?}\nserver version: {}\n", self.backend, self.server_version);
+        for capability in [Capability::Returning, Capability::JsonFunctions, Capability::FullTextSearch,
+                           Capability::PerformanceSchema, Capability::WalMode] {
+            report.push_str(format!("{}: {}\n", capability.name(), if self.has(capability) { "yes" } else { "no" }).as_str());
+        }
+        report
+    }
+}
+
+// Leading "major.minor.patch" of a server version such as "8.0.36-log" or "10.11.6-MariaDB",
+// missing parts are 0.
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) fn parse_version(version: &str) -> (u32, u32, u32) {
+    let mut parts = version.trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split(|c: char| !c.is_ascii_digit()).map(|p| p.parse::<u32>().unwrap_or(0));
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
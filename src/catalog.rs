@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, SqlConnection};
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::sql_lexer::count_placeholders;
+use crate::target::SqlBackend;
+
+#[derive(Debug, Clone)]
+struct StatementDef {
+    sql: String,
+    params: Vec<String>,
+}
+
+// Named sql statements kept in one place. A statement registered for a specific backend wins
+// over the portable one, portable sql uses `?` placeholders, which both backends accept.
+pub struct StatementCatalog<DB: Database> {
+    statements: HashMap<String, StatementDef>,
+    _db: PhantomData<DB>,
+}
+
+pub struct StatementCatalogBuilder<DB: Database> {
+    portable: HashMap<String, StatementDef>,
+    dialect: HashMap<String, StatementDef>,
+    required: Vec<String>,
+    _db: PhantomData<DB>,
+}
+
+impl<DB: Database> StatementCatalogBuilder<DB> {
+    // params names every placeholder in order, the count is checked at build.
+    pub fn statement(mut self, name: &str, sql: &str, params: &[&str]) -> Self {
+        self.portable.insert(name.to_string(), StatementDef {
+            sql: sql.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+        });
+        self
+    }
+
+    pub fn statement_for(mut self, backend: SqlBackend, name: &str, sql: &str, params: &[&str]) -> Self {
+        if backend == SqlBackend::from_db_name(DB::NAME) {
+            self.dialect.insert(name.to_string(), StatementDef {
+                sql: sql.to_string(),
+                params: params.iter().map(|p| p.to_string()).collect(),
+            });
+        }
+        self
+    }
+
+    // Names the application calls, build fails when one of them is missing.
+    pub fn require(mut self, names: &[&str]) -> Self {
+        self.required.extend(names.iter().map(|n| n.to_string()));
+        self
+    }
+
+    pub fn build(self) -> SqlResult<StatementCatalog<DB>> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let mut statements = self.portable;
+        statements.extend(self.dialect);
+
+        let mut problems = Vec::new();
+        for name in self.required.iter() {
+            if !statements.contains_key(name) {
+                problems.push(format!("{}: not registered", name));
+            }
+        }
+        for (name, def) in statements.iter() {
+            let count = count_placeholders(def.sql.as_str(), backend);
+            if count != def.params.len() {
+                problems.push(format!("{}: {} placeholders but {} documented params", name, count, def.params.len()));
+            }
+        }
+        if !problems.is_empty() {
+            problems.sort();
+            return Err(sql_err!(SqlErrorCode::Failed, "invalid statement catalog: {}", problems.join("; ")));
+        }
+        Ok(StatementCatalog {
+            statements,
+            _db: Default::default(),
+        })
+    }
+}
+
+impl<DB: Database> StatementCatalog<DB> {
+    pub fn builder() -> StatementCatalogBuilder<DB> {
+        StatementCatalogBuilder {
+            portable: HashMap::new(),
+            dialect: HashMap::new(),
+            required: Vec::new(),
+            _db: Default::default(),
+        }
+    }
+
+    pub fn sql(&self, name: &str) -> SqlResult<&str> {
+        self.statements.get(name)
+            .map(|def| def.sql.as_str())
+            .ok_or_else(|| sql_err!(SqlErrorCode::NotFound, "statement {} not registered", name))
+    }
+
+    pub fn params(&self, name: &str) -> SqlResult<&[String]> {
+        self.statements.get(name)
+            .map(|def| def.params.as_slice())
+            .ok_or_else(|| sql_err!(SqlErrorCode::NotFound, "statement {} not registered", name))
+    }
+
+    pub fn query(&self, name: &str) -> SqlResult<sqlx::query::Query<'_, DB, DB::Arguments<'_>>> {
+        Ok(sqlx::query(self.sql(name)?))
+    }
+
+    // Prepares every statement on conn so references to missing tables or columns surface at
+    // startup, all failures are reported together.
+    pub async fn validate<EM: 'static + ErrorMap<InError = sqlx::Error>>(&self, conn: &mut SqlConnection<DB, EM>) -> SqlResult<()>
+    where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+          for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+        let mut names: Vec<&String> = self.statements.keys().collect();
+        names.sort();
+        let mut problems = Vec::new();
+        for name in names {
+            if let Err(e) = conn.prepare_raw(self.statements[name].sql.as_str()).await {
+                problems.push(format!("{}: {}", name, e));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(sql_err!(SqlErrorCode::Failed, "invalid statements: {}", problems.join("; ")));
+        }
+        Ok(())
+    }
+}
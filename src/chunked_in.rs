@@ -0,0 +1,82 @@
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use std::collections::{HashMap, HashSet};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use std::hash::Hash;
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, SqlConnection};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::sql_lexer::count_placeholders;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::target::SqlBackend;
+use crate::value::BindValue;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::value::SqlValue;
+
+// Replaced by the placeholder list of a chunk of keys.
+pub const KEYS_MARKER: &str = "{keys}";
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Runs sql once per chunk of keys, with KEYS_MARKER replaced by as many `?` as the chunk has
+    // keys, and concatenates the decoded rows. other_args are the values of the plain `?` outside
+    // the marker, in statement order. Duplicate keys are sent once, no key means no query.
+    // With key_of the result is reordered by the position of each row's key in keys, rows of
+    // one key keep their order and rows whose key is not in keys go last.
+    #[cfg(any(feature = "mysql", feature = "sqlite"))]
+    pub(crate) async fn query_all_chunked_in_with<T, K>(&mut self,
+                                                        sql: &str,
+                                                        keys: &[K],
+                                                        other_args: &[SqlValue],
+                                                        max_params: usize,
+                                                        key_of: Option<&dyn Fn(&T) -> K>) -> Result<Vec<T>, EM::OutError>
+    where T: for<'r> sqlx::FromRow<'r, DB::Row>,
+          K: Clone + Eq + Hash + Into<SqlValue>, {
+        let (before, after) = match sql.split_once(KEYS_MARKER) {
+            Some(parts) => parts,
+            None => return Err(EM::map_parameter_mismatch(format!("sql has no {} marker: {}", KEYS_MARKER, sql).as_str())),
+        };
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let args_before = count_placeholders(before, backend);
+        let expected = args_before + count_placeholders(after, backend);
+        if expected != other_args.len() {
+            return Err(EM::map_parameter_mismatch(format!("parameter mismatch, expected {} actual {} sql: {}", expected, other_args.len(), sql).as_str()));
+        }
+        let chunk_size = max_params.saturating_sub(other_args.len());
+        if chunk_size == 0 {
+            return Err(EM::map_parameter_mismatch(format!("{} arguments leave no room for keys under the limit of {}", other_args.len(), max_params).as_str()));
+        }
+
+        let mut seen = HashSet::with_capacity(keys.len());
+        let unique: Vec<&K> = keys.iter().filter(|k| seen.insert(*k)).collect();
+        let mut results = Vec::new();
+        for chunk in unique.chunks(chunk_size) {
+            let chunk_sql = format!("{}{}{}", before, vec!["?"; chunk.len()].join(", "), after);
+            let mut query = sqlx::query::<DB>(chunk_sql.as_str());
+            for value in other_args[..args_before].iter() {
+                query = query.bind_value(value.clone());
+            }
+            for key in chunk.iter() {
+                query = query.bind_value((*key).clone().into());
+            }
+            for value in other_args[args_before..].iter() {
+                query = query.bind_value(value.clone());
+            }
+            let rows = self.query_all(query).await?;
+            for row in rows.iter() {
+                results.push(T::from_row(row).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))?);
+            }
+        }
+
+        if let Some(key_of) = key_of {
+            let positions: HashMap<&K, usize> = unique.iter().enumerate().map(|(i, k)| (*k, i)).collect();
+            let mut keyed: Vec<(usize, T)> = results.into_iter()
+                .map(|row| (positions.get(&key_of(&row)).copied().unwrap_or(usize::MAX), row))
+                .collect();
+            keyed.sort_by_key(|(position, _)| *position);
+            results = keyed.into_iter().map(|(_, row)| row).collect();
+        }
+        Ok(results)
+    }
+}
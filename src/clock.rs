@@ -0,0 +1,50 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use crate::db_helper::SqlFuture;
+
+// Source of time for everything the crate measures or waits on: retry backoff, pool wait
+// statistics, lease leak detection, coalescer and sink flush intervals.
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> SqlFuture<'static, ()>;
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+}
+
+// Wall clock with the sleep of the sqlx runtime.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RuntimeClock;
+
+impl Clock for RuntimeClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> SqlFuture<'static, ()> {
+        Box::pin(sqlx_core::rt::sleep(duration))
+    }
+}
+
+pub(crate) fn runtime_clock() -> Arc<dyn Clock> {
+    Arc::new(RuntimeClock)
+}
+
+// Output of fut, or None when the clock passed the deadline first. No deadline waits for fut.
+pub(crate) async fn timeout<F: Future>(deadline: Option<(Arc<dyn Clock>, Duration)>, fut: F) -> Option<F::Output> {
+    let (clock, duration) = match deadline {
+        Some(deadline) => deadline,
+        None => return Some(fut.await),
+    };
+    let mut fut = std::pin::pin!(fut);
+    let mut sleep = clock.sleep(duration);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(v) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Some(v));
+        }
+        sleep.as_mut().poll(cx).map(|_| None)
+    }).await
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, SqlPool};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, SqlValue};
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum CoalesceKey {
+    Int(i64),
+    Text(String),
+}
+
+impl From<i64> for CoalesceKey {
+    fn from(v: i64) -> Self {
+        CoalesceKey::Int(v)
+    }
+}
+
+impl From<&str> for CoalesceKey {
+    fn from(v: &str) -> Self {
+        CoalesceKey::Text(v.to_string())
+    }
+}
+
+impl From<String> for CoalesceKey {
+    fn from(v: String) -> Self {
+        CoalesceKey::Text(v)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct CounterId {
+    table: String,
+    key: CoalesceKey,
+    column: String,
+}
+
+struct PendingDelta {
+    delta: i64,
+    failures: u32,
+}
+
+struct CoalescerInner<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pool: SqlPool<DB, EM>,
+    pending: Mutex<HashMap<CounterId, PendingDelta>>,
+    key_columns: HashMap<String, String>,
+    interval: Duration,
+    max_pending: usize,
+    max_retries: u32,
+    closed: AtomicBool,
+    flush_lock: async_lock::Mutex<()>,
+}
+
+// Accumulates counter increments in memory and writes them as `col = col + delta` updates in
+// one transaction per flush. Increments not yet flushed are lost if the process dies, so at most
+// one interval of them can go missing. A failed flush puts the deltas back until max_retries.
+pub struct WriteCoalescer<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    inner: Arc<CoalescerInner<DB, EM>>,
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> Clone for WriteCoalescer<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+pub struct WriteCoalescerBuilder<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pool: SqlPool<DB, EM>,
+    key_columns: HashMap<String, String>,
+    interval: Duration,
+    max_pending: usize,
+    max_retries: u32,
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> WriteCoalescerBuilder<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      EM::OutError: Send, {
+    // Key column of a table, "id" when not set.
+    pub fn key_column(mut self, table: &str, column: &str) -> Self {
+        self.key_columns.insert(table.to_string(), column.to_string());
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // Number of distinct counters that triggers a flush before the interval ends.
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    // Builds the coalescer without a background task, flushes only happen through flush_now.
+    pub fn build(self) -> WriteCoalescer<DB, EM> {
+        WriteCoalescer {
+            inner: Arc::new(CoalescerInner {
+                pool: self.pool,
+                pending: Mutex::new(HashMap::new()),
+                key_columns: self.key_columns,
+                interval: self.interval,
+                max_pending: self.max_pending,
+                max_retries: self.max_retries,
+                closed: AtomicBool::new(false),
+                flush_lock: async_lock::Mutex::new(()),
+            }),
+        }
+    }
+
+    // Builds the coalescer and spawns the task flushing it every interval until shutdown.
+    pub fn start(self) -> WriteCoalescer<DB, EM> {
+        let coalescer = self.build();
+        let task = coalescer.clone();
+        sqlx_core::rt::spawn(async move {
+            task.run().await;
+        });
+        coalescer
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> WriteCoalescer<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      EM::OutError: Send, {
+    pub fn builder(pool: &SqlPool<DB, EM>) -> WriteCoalescerBuilder<DB, EM> {
+        WriteCoalescerBuilder {
+            pool: pool.clone(),
+            key_columns: HashMap::new(),
+            interval: Duration::from_secs(1),
+            max_pending: 10000,
+            max_retries: 3,
+        }
+    }
+
+    pub fn add(&self, table: &str, key: impl Into<CoalesceKey>, column: &str, delta: i64) {
+        let id = CounterId {
+            table: table.to_string(),
+            key: key.into(),
+            column: column.to_string(),
+        };
+        let mut pending = self.inner.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.entry(id).or_insert(PendingDelta { delta: 0, failures: 0 }).delta += delta;
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.inner.pending.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub async fn flush_now(&self) -> Result<(), EM::OutError> {
+        let _guard = self.inner.flush_lock.lock().await;
+        let batch: Vec<(CounterId, PendingDelta)> = {
+            let mut pending = self.inner.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending.drain().filter(|(_, d)| d.delta != 0).collect()
+        };
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let ret = self.write_batch(&batch).await;
+        if ret.is_err() {
+            self.requeue(batch);
+        }
+        ret
+    }
+
+    // Stops the background task and writes what is still pending.
+    pub async fn shutdown(&self) -> Result<(), EM::OutError> {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.flush_now().await
+    }
+
+    async fn run(&self) {
+        let tick = self.inner.interval.min(Duration::from_millis(100));
+        let clock = self.inner.pool.clock();
+        let mut last_flush = clock.now();
+        while !self.inner.closed.load(Ordering::SeqCst) {
+            clock.sleep(tick).await;
+            if clock.elapsed(last_flush) >= self.inner.interval || self.pending_len() >= self.inner.max_pending {
+                let _ = self.flush_now().await;
+                last_flush = clock.now();
+            }
+        }
+    }
+
+    async fn write_batch(&self, batch: &[(CounterId, PendingDelta)]) -> Result<(), EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let mut conn = self.inner.pool.get_conn().await?;
+        conn.begin_transaction().await?;
+        for (id, delta) in batch.iter() {
+            let key_column = self.inner.key_columns.get(&id.table).map(|c| c.as_str()).unwrap_or("id");
+            let column = quote_ident(id.column.as_str(), quote);
+            let sql = format!("UPDATE {} SET {} = {} + {} WHERE {} = {}", quote_qualified(id.table.as_str(), quote), column, column,
+                              backend.placeholder(1), quote_ident(key_column, quote), backend.placeholder(2));
+            let key = match &id.key {
+                CoalesceKey::Int(v) => SqlValue::Int(*v),
+                CoalesceKey::Text(v) => SqlValue::Text(v.clone()),
+            };
+            let query = sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Int(delta.delta)).bind_value(key);
+            if let Err(e) = conn.execute_sql(query).await {
+                let _ = conn.rollback_transaction().await;
+                return Err(e);
+            }
+        }
+        conn.commit_transaction().await
+    }
+
+    fn requeue(&self, batch: Vec<(CounterId, PendingDelta)>) {
+        let mut pending = self.inner.pending.lock().unwrap_or_else(|e| e.into_inner());
+        for (id, delta) in batch {
+            if delta.failures >= self.inner.max_retries {
+                log::error!("drop counter delta {} for {}.{} after {} failed flushes", delta.delta, id.table, id.column, delta.failures + 1);
+                continue;
+            }
+            let entry = pending.entry(id).or_insert(PendingDelta { delta: 0, failures: 0 });
+            entry.delta += delta.delta;
+            entry.failures = entry.failures.max(delta.failures + 1);
+        }
+    }
+}
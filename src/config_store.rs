@@ -0,0 +1,256 @@
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use futures_core::Stream;
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, RowsAffected, SqlConnection, SqlFuture, SqlPool};
+use crate::target::SqlBackend;
+use crate::text_search::quote_qualified;
+use crate::value::{BindValue, RowToMap, SqlValue};
+
+pub const DEFAULT_CONFIG_TABLE: &str = "sfo_config";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConfigEntry {
+    pub value: Vec<u8>,
+    // 1 when first written, raised by every write. compare_and_set takes it as expected_version.
+    pub version: u64,
+}
+
+// Key-value settings in a table of their own, one row per key. Values are bytes, the typed
+// get and set of the json feature store them as JSON text. Writes raise the version of the key,
+// so compare_and_set can tell a value was changed by someone else since it was read.
+pub struct ConfigStore<DB: Database, EM: ErrorMap<InError = sqlx::Error>> {
+    table: String,
+    _types: PhantomData<fn() -> (DB, EM)>,
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> Clone for ConfigStore<DB, EM> {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> ConfigStore<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected,
+      DB::Row: RowToMap, {
+    // The store in DEFAULT_CONFIG_TABLE, created when missing.
+    pub async fn ensure(conn: &mut SqlConnection<DB, EM>) -> Result<Self, EM::OutError> {
+        Self::ensure_table(conn, DEFAULT_CONFIG_TABLE).await
+    }
+
+    pub async fn ensure_table(conn: &mut SqlConnection<DB, EM>, table: &str) -> Result<Self, EM::OutError> {
+        let store = Self {
+            table: table.to_string(),
+            _types: PhantomData,
+        };
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let blob = match backend {
+            SqlBackend::MySql => "LONGBLOB",
+            SqlBackend::Postgres => "BYTEA",
+            _ => "BLOB",
+        };
+        let sql = format!("CREATE TABLE IF NOT EXISTS {} (config_key VARCHAR(191) NOT NULL PRIMARY KEY, config_value {} NOT NULL, version BIGINT NOT NULL)",
+                          store.quoted_table(), blob);
+        conn.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+        Ok(store)
+    }
+
+    pub fn table(&self) -> &str {
+        self.table.as_str()
+    }
+
+    fn quoted_table(&self) -> String {
+        quote_qualified(self.table.as_str(), SqlBackend::from_db_name(DB::NAME).ident_quote())
+    }
+
+    pub async fn get_raw(&self, conn: &mut SqlConnection<DB, EM>, key: &str) -> Result<Option<ConfigEntry>, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let sql = format!("SELECT config_value, version FROM {} WHERE config_key = {}", self.quoted_table(), backend.placeholder(1));
+        let row = match conn.query_optional(sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Text(key.to_string()))).await? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let mut row = map_row::<EM, _>(&row, sql.as_str())?;
+        let value = match row.shift_remove("config_value") {
+            Some(SqlValue::Blob(value)) => value,
+            Some(SqlValue::Text(value)) => value.into_bytes(),
+            _ => Vec::new(),
+        };
+        let version = row.shift_remove("version").and_then(|v| v.to_i128().ok().flatten()).unwrap_or(0);
+        Ok(Some(ConfigEntry {
+            value,
+            version: version as u64,
+        }))
+    }
+
+    // Writes value whatever the current version is.
+    pub async fn set_raw(&self, conn: &mut SqlConnection<DB, EM>, key: &str, value: &[u8]) -> Result<(), EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let table = self.quoted_table();
+        let sql = match backend {
+            SqlBackend::MySql => format!("INSERT INTO {} (config_key, config_value, version) VALUES (?, ?, 1) \
+                                          ON DUPLICATE KEY UPDATE config_value = VALUES(config_value), version = version + 1", table),
+            _ => format!("INSERT INTO {} AS c (config_key, config_value, version) VALUES ({}, {}, 1) \
+                          ON CONFLICT (config_key) DO UPDATE SET config_value = excluded.config_value, version = c.version + 1",
+                         table, backend.placeholder(1), backend.placeholder(2)),
+        };
+        conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+            .bind_value(SqlValue::Text(key.to_string()))
+            .bind_value(SqlValue::Blob(value.to_vec()))).await?;
+        Ok(())
+    }
+
+    // Writes value only when the key is still at expected_version, 0 meaning it must not exist
+    // yet. false when another writer got there first, the caller reads the key again and
+    // retries or gives up.
+    pub async fn compare_and_set_raw(&self, conn: &mut SqlConnection<DB, EM>, key: &str, expected_version: u64, value: &[u8]) -> Result<bool, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let table = self.quoted_table();
+        let affected = if expected_version == 0 {
+            let sql = match backend {
+                SqlBackend::MySql => format!("INSERT IGNORE INTO {} (config_key, config_value, version) VALUES (?, ?, 1)", table),
+                _ => format!("INSERT INTO {} (config_key, config_value, version) VALUES ({}, {}, 1) ON CONFLICT (config_key) DO NOTHING",
+                             table, backend.placeholder(1), backend.placeholder(2)),
+            };
+            conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+                .bind_value(SqlValue::Text(key.to_string()))
+                .bind_value(SqlValue::Blob(value.to_vec()))).await?.affected()
+        } else {
+            let sql = format!("UPDATE {} SET config_value = {}, version = version + 1 WHERE config_key = {} AND version = {}",
+                              table, backend.placeholder(1), backend.placeholder(2), backend.placeholder(3));
+            conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+                .bind_value(SqlValue::Blob(value.to_vec()))
+                .bind_value(SqlValue::Text(key.to_string()))
+                .bind_value(SqlValue::UInt(expected_version))).await?.affected()
+        };
+        Ok(affected == 1)
+    }
+
+    // false when the key did not exist.
+    pub async fn remove(&self, conn: &mut SqlConnection<DB, EM>, key: &str) -> Result<bool, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let sql = format!("DELETE FROM {} WHERE config_key = {}", self.quoted_table(), backend.placeholder(1));
+        let ret = conn.execute_sql(sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Text(key.to_string()))).await?;
+        Ok(ret.affected() > 0)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> ConfigStore<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected,
+      DB::Row: RowToMap, {
+    pub async fn get<T: serde::de::DeserializeOwned>(&self, conn: &mut SqlConnection<DB, EM>, key: &str) -> Result<Option<T>, EM::OutError> {
+        Ok(self.get_versioned(conn, key).await?.map(|(value, _)| value))
+    }
+
+    // The value with the version to pass to compare_and_set.
+    pub async fn get_versioned<T: serde::de::DeserializeOwned>(&self, conn: &mut SqlConnection<DB, EM>, key: &str) -> Result<Option<(T, u64)>, EM::OutError> {
+        match self.get_raw(conn, key).await? {
+            Some(entry) => {
+                let value = serde_json::from_slice(entry.value.as_slice())
+                    .map_err(|e| EM::map(sqlx::Error::Decode(Box::new(e)), format!("config {} in {}", key, self.table).as_str()))?;
+                Ok(Some((value, entry.version)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set<T: serde::Serialize + ?Sized>(&self, conn: &mut SqlConnection<DB, EM>, key: &str, value: &T) -> Result<(), EM::OutError> {
+        let value = encode_json::<T, EM>(key, value)?;
+        self.set_raw(conn, key, value.as_slice()).await
+    }
+
+    pub async fn compare_and_set<T: serde::Serialize + ?Sized>(&self, conn: &mut SqlConnection<DB, EM>, key: &str, expected_version: u64, value: &T) -> Result<bool, EM::OutError> {
+        let value = encode_json::<T, EM>(key, value)?;
+        self.compare_and_set_raw(conn, key, expected_version, value.as_slice()).await
+    }
+}
+
+#[cfg(feature = "json")]
+fn encode_json<T: serde::Serialize + ?Sized, EM: ErrorMap<InError = sqlx::Error>>(key: &str, value: &T) -> Result<Vec<u8>, EM::OutError> {
+    serde_json::to_vec(value).map_err(|e| EM::map_parameter_mismatch(format!("config {} not serializable: {}", key, e).as_str()))
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> ConfigStore<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected,
+      DB::Row: RowToMap,
+      EM::OutError: Send, {
+    // Polls key every interval on a connection of pool, yielding the entry each time it
+    // changed, None once the key was removed. Writes between two polls are seen as one change.
+    // For simple signaling between processes sharing the database, not for high rates.
+    pub async fn watch_poll(&self, pool: &SqlPool<DB, EM>, key: &str, interval: Duration) -> Result<ConfigWatch<EM::OutError>, EM::OutError> {
+        let last = {
+            let mut conn = pool.get_conn().await?;
+            self.get_raw(&mut conn, key).await?
+        };
+        let store = self.clone();
+        let pool = pool.clone();
+        let key = key.to_string();
+        Ok(ConfigWatch {
+            poll: Box::new(move |last: Option<ConfigEntry>| -> SqlFuture<'static, Result<Option<ConfigEntry>, EM::OutError>> {
+                let store = store.clone();
+                let pool = pool.clone();
+                let key = key.clone();
+                Box::pin(async move {
+                    loop {
+                        pool.clock().sleep(interval).await;
+                        let mut conn = pool.get_conn().await?;
+                        let entry = store.get_raw(&mut conn, key.as_str()).await?;
+                        if entry != last {
+                            return Ok(entry);
+                        }
+                    }
+                })
+            }),
+            last,
+            pending: None,
+        })
+    }
+}
+
+type EntryPoll<E> = Box<dyn FnMut(Option<ConfigEntry>) -> SqlFuture<'static, Result<Option<ConfigEntry>, E>> + Send>;
+
+// Changes of one key, see ConfigStore::watch_poll. Never ends, dropping it stops the polling.
+// A failed poll is yielded as the error and polling goes on.
+pub struct ConfigWatch<E> {
+    poll: EntryPoll<E>,
+    last: Option<ConfigEntry>,
+    pending: Option<SqlFuture<'static, Result<Option<ConfigEntry>, E>>>,
+}
+
+impl<E> ConfigWatch<E> {
+    // Next change, for callers without a stream combinator crate.
+    pub async fn next(&mut self) -> Option<Result<Option<ConfigEntry>, E>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<E> Stream for ConfigWatch<E> {
+    type Item = Result<Option<ConfigEntry>, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let last = this.last.clone();
+        let pending = this.pending.get_or_insert_with(|| (this.poll)(last));
+        let ret = std::task::ready!(pending.as_mut().poll(cx));
+        this.pending = None;
+        if let Ok(entry) = &ret {
+            this.last = entry.clone();
+        }
+        Poll::Ready(Some(ret))
+    }
+}
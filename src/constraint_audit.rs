@@ -0,0 +1,89 @@
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, RowToMap, SqlValue};
+
+// Examples kept per audited constraint.
+const MAX_EXAMPLES: usize = 10;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PlannedConstraint {
+    NotNull { table: String, column: String },
+    // Rows with a NULL in any of the columns never conflict, as in sql.
+    Unique { table: String, columns: Vec<String> },
+    ForeignKey { child: String, columns: Vec<String>, parent: String, parent_columns: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstraintAudit {
+    pub constraint: PlannedConstraint,
+    // NotNull: rows with a NULL. Unique: groups of duplicate values. ForeignKey: child rows
+    // without a parent.
+    pub violations: u64,
+    // Duplicate values with their row count as the last value, or the keys of orphan rows.
+    pub examples: Vec<Vec<SqlValue>>,
+}
+
+impl ConstraintAudit {
+    pub fn is_satisfied(&self) -> bool {
+        self.violations == 0
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Counts the rows the constraints would reject, reading only. Every count is one aggregate
+    // query over the table, so audit big tables off peak.
+    pub async fn audit_constraints(&mut self, constraints: &[PlannedConstraint]) -> Result<Vec<ConstraintAudit>, EM::OutError>
+    where DB::Row: RowToMap {
+        let quote = SqlBackend::from_db_name(DB::NAME).ident_quote();
+        let quote_list = |columns: &[String], alias: &str| columns.iter()
+            .map(|c| format!("{}{}", alias, quote_ident(c, quote)))
+            .collect::<Vec<_>>();
+        let mut audits = Vec::with_capacity(constraints.len());
+        for constraint in constraints.iter() {
+            let (count_sql, example_sql) = match constraint {
+                PlannedConstraint::NotNull { table, column } => {
+                    (format!("SELECT COUNT(*) AS c FROM {} WHERE {} IS NULL", quote_qualified(table, quote), quote_ident(column, quote)), None)
+                }
+                PlannedConstraint::Unique { table, columns } => {
+                    let cols = quote_list(columns, "").join(", ");
+                    let not_null = quote_list(columns, "").iter().map(|c| format!("{} IS NOT NULL", c)).collect::<Vec<_>>().join(" AND ");
+                    let groups = format!("SELECT {}, COUNT(*) AS dup_count FROM {} WHERE {} GROUP BY {} HAVING COUNT(*) > 1",
+                                         cols, quote_qualified(table, quote), not_null, cols);
+                    (format!("SELECT COUNT(*) AS c FROM ({}) AS dup_groups", groups),
+                     Some(format!("{} ORDER BY dup_count DESC LIMIT {}", groups, MAX_EXAMPLES)))
+                }
+                PlannedConstraint::ForeignKey { child, columns, parent, parent_columns } => {
+                    if columns.len() != parent_columns.len() || columns.is_empty() {
+                        return Err(EM::map_parameter_mismatch(format!("foreign key {} -> {} has {} columns referencing {}", child, parent, columns.len(), parent_columns.len()).as_str()));
+                    }
+                    let child_cols = quote_list(columns, "ch.");
+                    let parent_cols = quote_list(parent_columns, "pa.");
+                    let not_null = child_cols.iter().map(|c| format!("{} IS NOT NULL", c)).collect::<Vec<_>>().join(" AND ");
+                    let joined = child_cols.iter().zip(parent_cols.iter()).map(|(c, p)| format!("{} = {}", p, c)).collect::<Vec<_>>().join(" AND ");
+                    let orphans = format!("FROM {} AS ch WHERE {} AND NOT EXISTS (SELECT 1 FROM {} AS pa WHERE {})",
+                                          quote_qualified(child, quote), not_null, quote_qualified(parent, quote), joined);
+                    (format!("SELECT COUNT(*) AS c {}", orphans),
+                     Some(format!("SELECT {} {} LIMIT {}", child_cols.join(", "), orphans, MAX_EXAMPLES)))
+                }
+            };
+
+            let row = self.query_one(sqlx::query::<DB>(count_sql.as_str())).await?;
+            let violations = map_row::<EM, _>(&row, count_sql.as_str())?.shift_remove("c")
+                .and_then(|c| c.to_i128().ok().flatten())
+                .unwrap_or(0) as u64;
+            let mut examples = Vec::new();
+            if let (Some(sql), true) = (example_sql, violations > 0) {
+                for row in self.query_all(sqlx::query::<DB>(sql.as_str())).await?.iter() {
+                    examples.push(map_row::<EM, _>(row, sql.as_str())?.into_values().collect());
+                }
+            }
+            audits.push(ConstraintAudit { constraint: constraint.clone(), violations, examples });
+        }
+        Ok(audits)
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use aes_gcm::aead::{Aead, AeadCore, OsRng};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use sqlx::{ColumnIndex, Database, Decode, Encode, Row, Type};
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+
+// Encrypted column layout: version(1) | key id len(1) | key id | cipher data.
+// The key id travels with the value, so rows written under an old key stay readable after rotation.
+const PAYLOAD_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const KEY_CHECK_LEN: usize = 4;
+
+pub trait FieldCipher: Send + Sync {
+    // Key id new values are encrypted with.
+    fn key_id(&self) -> &str;
+    fn encrypt(&self, plaintext: &[u8]) -> SqlResult<Vec<u8>>;
+    fn decrypt(&self, key_id: &str, data: &[u8]) -> SqlResult<Vec<u8>>;
+}
+
+// AES-256-GCM keyring, data is a key check, the nonce, then ciphertext and tag. GCM cannot tell a
+// wrong key from changed data, the key check can: it is the start of the tag of an empty message
+// under the key, so a key id mapped to a different key fails on it before decrypting.
+pub struct AesGcmCipher {
+    key_id: String,
+    keys: HashMap<String, (Aes256Gcm, [u8; KEY_CHECK_LEN])>,
+}
+
+fn keyed(key: &[u8; 32]) -> (Aes256Gcm, [u8; KEY_CHECK_LEN]) {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    // The zero nonce is only used for this empty message, values get random nonces.
+    let tag = cipher.encrypt(Nonce::from_slice(&[0u8; NONCE_LEN]), &[][..]).unwrap_or_default();
+    let mut check = [0u8; KEY_CHECK_LEN];
+    check.copy_from_slice(&tag[..KEY_CHECK_LEN]);
+    (cipher, check)
+}
+
+impl AesGcmCipher {
+    pub fn new(key_id: &str, key: &[u8; 32]) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(key_id.to_string(), keyed(key));
+        Self {
+            key_id: key_id.to_string(),
+            keys,
+        }
+    }
+
+    // Keeps a retired key for decrypting values written before rotation.
+    pub fn with_old_key(mut self, key_id: &str, key: &[u8; 32]) -> Self {
+        self.keys.insert(key_id.to_string(), keyed(key));
+        self
+    }
+}
+
+impl FieldCipher for AesGcmCipher {
+    fn key_id(&self) -> &str {
+        self.key_id.as_str()
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> SqlResult<Vec<u8>> {
+        let (cipher, check) = &self.keys[&self.key_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext)
+            .map_err(|_| sql_err!(SqlErrorCode::Crypto, "encrypt failed with key id {}", self.key_id))?;
+        let mut data = Vec::with_capacity(KEY_CHECK_LEN + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(check);
+        data.extend_from_slice(nonce.as_slice());
+        data.extend_from_slice(ciphertext.as_slice());
+        Ok(data)
+    }
+
+    fn decrypt(&self, key_id: &str, data: &[u8]) -> SqlResult<Vec<u8>> {
+        let (cipher, check) = self.keys.get(key_id)
+            .ok_or_else(|| sql_err!(SqlErrorCode::Crypto, "unknown key id {}", key_id))?;
+        if data.len() < KEY_CHECK_LEN + NONCE_LEN {
+            return Err(sql_err!(SqlErrorCode::Crypto, "corrupted encrypted payload, data too short"));
+        }
+        let (value_check, data) = data.split_at(KEY_CHECK_LEN);
+        if value_check != check {
+            return Err(sql_err!(SqlErrorCode::Crypto, "wrong key for key id {}, the value was encrypted with a different key", key_id));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| sql_err!(SqlErrorCode::Crypto, "corrupted encrypted payload, authentication failed with key id {}", key_id))
+    }
+}
+
+pub fn seal<C: FieldCipher + ?Sized>(cipher: &C, plaintext: &[u8]) -> SqlResult<Vec<u8>> {
+    let key_id = cipher.key_id().as_bytes();
+    if key_id.len() > u8::MAX as usize {
+        return Err(sql_err!(SqlErrorCode::Crypto, "key id longer than {} bytes", u8::MAX));
+    }
+    let data = cipher.encrypt(plaintext)?;
+    let mut payload = Vec::with_capacity(2 + key_id.len() + data.len());
+    payload.push(PAYLOAD_VERSION);
+    payload.push(key_id.len() as u8);
+    payload.extend_from_slice(key_id);
+    payload.extend_from_slice(data.as_slice());
+    Ok(payload)
+}
+
+pub fn unseal<C: FieldCipher + ?Sized>(cipher: &C, payload: &[u8]) -> SqlResult<Vec<u8>> {
+    if payload.len() < 2 || payload[0] != PAYLOAD_VERSION {
+        return Err(sql_err!(SqlErrorCode::Crypto, "corrupted encrypted payload, bad header"));
+    }
+    let key_id_len = payload[1] as usize;
+    if payload.len() < 2 + key_id_len {
+        return Err(sql_err!(SqlErrorCode::Crypto, "corrupted encrypted payload, truncated key id"));
+    }
+    let key_id = std::str::from_utf8(&payload[2..2 + key_id_len])
+        .map_err(|_| sql_err!(SqlErrorCode::Crypto, "corrupted encrypted payload, invalid key id"))?;
+    cipher.decrypt(key_id, &payload[2 + key_id_len..])
+}
+
+// Key id an encrypted value was written with, used to find rows that still need re-encryption.
+pub fn payload_key_id(payload: &[u8]) -> Option<&str> {
+    if payload.len() < 2 || payload[0] != PAYLOAD_VERSION || payload.len() < 2 + payload[1] as usize {
+        return None;
+    }
+    std::str::from_utf8(&payload[2..2 + payload[1] as usize]).ok()
+}
+
+pub trait BindEncrypted: Sized {
+    fn bind_encrypted<C: FieldCipher + ?Sized>(self, cipher: &C, plaintext: &[u8]) -> SqlResult<Self>;
+}
+
+impl<'q, DB: Database> BindEncrypted for sqlx::query::Query<'q, DB, DB::Arguments<'q>>
+where Vec<u8>: 'q + Encode<'q, DB> + Type<DB>, {
+    fn bind_encrypted<C: FieldCipher + ?Sized>(self, cipher: &C, plaintext: &[u8]) -> SqlResult<Self> {
+        Ok(self.bind(seal(cipher, plaintext)?))
+    }
+}
+
+pub trait RowExt {
+    fn get_decrypted<C: FieldCipher + ?Sized>(&self, cipher: &C, col: &str) -> SqlResult<Vec<u8>>;
+}
+
+impl<R: Row> RowExt for R
+where for<'r> Vec<u8>: Decode<'r, R::Database> + Type<R::Database>,
+      for<'a> &'a str: ColumnIndex<R>, {
+    fn get_decrypted<C: FieldCipher + ?Sized>(&self, cipher: &C, col: &str) -> SqlResult<Vec<u8>> {
+        let payload: Vec<u8> = self.try_get(col)
+            .map_err(|e| sql_err!(SqlErrorCode::Failed, "read encrypted column {} failed: {}", col, e))?;
+        unseal(cipher, payload.as_slice())
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::panic::Location;
+use std::sync::Mutex;
+use chrono::{DateTime, NaiveDateTime, SubsecRound, TimeZone, Utc};
+use sqlx::{ColumnIndex, Database, Decode, Encode, Row, Type};
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::target::SqlBackend;
+
+// Datetimes are stored in UTC. mysql gets a DATETIME value, sqlite a TEXT in ISO-8601 with a
+// fixed number of fraction digits, "2024-05-01T08:30:00.123Z" at millisecond precision, so text
+// comparison orders values as long as a column keeps one precision.
+//
+// Values are truncated to the precision before binding: mysql rounds extra digits away on a
+// DATETIME(p) column, so a value read back would differ from the one bound.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DateTimePrecision {
+    #[default]
+    Seconds,
+    Millis,
+    Micros,
+}
+
+impl DateTimePrecision {
+    pub fn digits(&self) -> u16 {
+        match self {
+            DateTimePrecision::Seconds => 0,
+            DateTimePrecision::Millis => 3,
+            DateTimePrecision::Micros => 6,
+        }
+    }
+
+    pub fn truncate<Tz: TimeZone>(&self, dt: &DateTime<Tz>) -> DateTime<Utc> {
+        dt.with_timezone(&Utc).trunc_subsecs(self.digits())
+    }
+}
+
+// The sqlite storage form of dt.
+pub fn to_storage_text<Tz: TimeZone>(dt: &DateTime<Tz>, precision: DateTimePrecision) -> String {
+    let dt = precision.truncate(dt);
+    match precision {
+        DateTimePrecision::Seconds => dt.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        DateTimePrecision::Millis => dt.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        DateTimePrecision::Micros => dt.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string(),
+    }
+}
+
+// Reads the storage form, and the text other writers commonly leave in sqlite: a space instead
+// of T, any number of fraction digits, an offset or no zone at all, which is taken as UTC.
+pub fn parse_storage_text(text: &str) -> SqlResult<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f%:z") {
+        return Ok(dt.with_timezone(&Utc));
+    }
+    let naive = text.trim_end_matches(['Z', 'z']);
+    NaiveDateTime::parse_from_str(naive, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(naive, "%Y-%m-%d %H:%M:%S%.f"))
+        .map(|dt| dt.and_utc())
+        .map_err(|e| sql_err!(SqlErrorCode::Failed, "invalid datetime {:?}: {}", text, e))
+}
+
+static NAIVE_CALLERS: Mutex<Option<HashSet<&'static Location<'static>>>> = Mutex::new(None);
+
+// Each call site binding a naive datetime warns once, with its location so it can be found,
+// later binds from the same site log at debug.
+fn log_naive_bind(dt: &NaiveDateTime, caller: &'static Location<'static>) {
+    let first = NAIVE_CALLERS.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(HashSet::new).insert(caller);
+    let level = if first { log::Level::Warn } else { log::Level::Debug };
+    log::log!(level, "naive datetime {} bound at {}, taken as UTC; bind a DateTime with a zone instead", dt, caller);
+}
+
+pub trait BindDateTime: Sized {
+    fn bind_datetime_utc<Tz: TimeZone>(self, dt: &DateTime<Tz>, precision: DateTimePrecision) -> Self;
+
+    // A naive datetime is taken as UTC. Its zone is a guess, so every call site doing it logs
+    // a warning the first time.
+    #[track_caller]
+    fn bind_naive_datetime(self, dt: &NaiveDateTime, precision: DateTimePrecision) -> Self {
+        log_naive_bind(dt, Location::caller());
+        self.bind_datetime_utc(&dt.and_utc(), precision)
+    }
+}
+
+impl<'q, DB: Database> BindDateTime for sqlx::query::Query<'q, DB, DB::Arguments<'q>>
+where String: 'q + Encode<'q, DB> + Type<DB>,
+      DateTime<Utc>: 'q + Encode<'q, DB> + Type<DB>, {
+    fn bind_datetime_utc<Tz: TimeZone>(self, dt: &DateTime<Tz>, precision: DateTimePrecision) -> Self {
+        match SqlBackend::from_db_name(DB::NAME) {
+            SqlBackend::Sqlite => self.bind(to_storage_text(dt, precision)),
+            _ => self.bind(precision.truncate(dt)),
+        }
+    }
+}
+
+pub trait DateTimeRowExt {
+    // The value truncated to precision, so it compares equal to the value that was bound.
+    // mysql DATETIME and TIMESTAMP values are read as UTC, the session time_zone of a mysql
+    // pool is pinned to +00:00 so TIMESTAMP columns are converted to it.
+    fn get_datetime_utc(&self, col: &str, precision: DateTimePrecision) -> SqlResult<DateTime<Utc>>;
+}
+
+impl<R: Row> DateTimeRowExt for R
+where for<'r> String: Decode<'r, R::Database> + Type<R::Database>,
+      for<'r> NaiveDateTime: Decode<'r, R::Database> + Type<R::Database>,
+      for<'a> &'a str: ColumnIndex<R>, {
+    fn get_datetime_utc(&self, col: &str, precision: DateTimePrecision) -> SqlResult<DateTime<Utc>> {
+        let dt = match SqlBackend::from_db_name(<R::Database as Database>::NAME) {
+            SqlBackend::Sqlite => {
+                let text: String = self.try_get(col)
+                    .map_err(|e| sql_err!(SqlErrorCode::Failed, "read datetime column {} failed: {}", col, e))?;
+                parse_storage_text(text.as_str())?
+            }
+            _ => {
+                let naive: NaiveDateTime = self.try_get(col)
+                    .map_err(|e| sql_err!(SqlErrorCode::Failed, "read datetime column {} failed: {}", col, e))?;
+                naive.and_utc()
+            }
+        };
+        Ok(precision.truncate(&dt))
+    }
+}
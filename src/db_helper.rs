@@ -1,17 +1,167 @@
+use std::future::Future;
 use std::marker::PhantomData;
-use std::ops::Deref;
-use sqlx::{Transaction, Connection, Executor, Database};
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use sqlx::{Connection, Executor, Database, TransactionManager};
+use sqlx::database::HasStatementCache;
 use sqlx::pool::PoolConnection;
-use sqlx::Execute;
+use sqlx::{Arguments, Execute};
 pub use sqlx::Row as SqlRow;
-use crate::errors::{sql_err, SqlError, SqlErrorCode};
+pub use crate::clock::{Clock, RuntimeClock};
+pub use crate::chunked_in::KEYS_MARKER;
+pub use crate::catalog::{StatementCatalog, StatementCatalogBuilder};
+pub use crate::sink::{InsertBatchError, InsertSink, InsertSinkOptions, InsertSinkReport, SinkTarget};
+pub use crate::constraint_audit::{ConstraintAudit, PlannedConstraint};
+pub use crate::batcher::{StatementBatcher, StatementBatcherBuilder};
+pub use crate::coalescer::{CoalesceKey, WriteCoalescer, WriteCoalescerBuilder};
+pub use crate::config_store::{ConfigEntry, ConfigStore, ConfigWatch, DEFAULT_CONFIG_TABLE};
+pub use crate::dual_write::{DualWriteOptions, DualWritePool, DualWriteStatement, DualWriteStats, DualWriteTarget, ReadMismatch};
+pub use crate::capabilities::{Capabilities, Capability};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) use crate::capabilities::parse_version;
+pub use crate::filter::{BuiltFilter, FilterBuilder, LikeMode};
+pub use crate::guard::Guard;
+pub use crate::index_report::{IndexInfo, IndexReport};
+pub use crate::lock_watch::{LockBlocker, LockWatchOptions};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) use crate::lock_watch::LockProbe;
+#[cfg(feature = "serde")]
+pub use crate::metrics::METRICS_SCHEMA_VERSION;
+pub use crate::outbox::{Outbox, OutboxMessage, DEFAULT_OUTBOX_TABLE};
+pub use crate::partition::{PartitionRouter, PartitionScheme, TABLE_MARKER};
+pub use crate::query_cache::CachedRows;
+pub use crate::reconcile::{ReconcileOptions, ReconcileReport};
+pub use crate::retention::{RetentionPolicy, RetentionReport, TableRetentionReport};
+pub use crate::row_hash::{compute_row_hash, RowHashReport, RowHashSpec, DEFAULT_ROW_HASH_COLUMN};
+pub use crate::row_stream::SqlRowStream;
+pub use crate::sample::{SampleResult, SampleStrategy};
+pub use crate::seed::{SeedConflict, SeedReport, SeedSet, SeedTable, SeedTableReport};
+pub use crate::page::Page;
+pub use crate::sort::{SortDirection, SortOrder, SortSpec};
+pub use crate::spool::{SpoolIter, SpoolOptions, SpooledResult};
+pub use crate::startup::{Migration, StartupCheck, StartupMigrations, StartupPolicy, StartupReport};
+pub use crate::observer::{StatementEvent, StatementJournal, StatementObserver};
+pub use crate::stats::{AdaptiveTimeout, StatementStat};
+pub use crate::switch::SwitchablePool;
+pub use crate::target::{SqlBackend, TargetInfo};
+pub use crate::text_search::TextSearchOptions;
+pub use crate::unit_of_work::{UnitOfWork, UnitOp};
+pub use crate::value::{AggregateRowExt, BindValue, IndexMap, RowToMap, SqlValue, SqlValueType};
+use crate::sql_lexer::{count_placeholders, normalize_sql, split_statements, written_tables};
+use crate::stats::{StatementKey, StatementStats};
+use crate::query_cache::{table_key, QueryCache};
+use crate::text_search::quote_ident;
 
 pub trait ErrorMap: 'static + Clone + Send + Sync {
     type OutError;
     type InError;
     fn map(e: Self::InError, msg: &str) -> Self::OutError;
+
+    fn map_parameter_mismatch(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::Protocol(msg.to_string()).into(), msg)
+    }
+
+    fn map_shutting_down(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::PoolClosed.into(), msg)
+    }
+
+    fn map_rows_not_affected(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::RowNotFound.into(), msg)
+    }
+
+    fn map_not_in_transaction(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::Protocol(msg.to_string()).into(), msg)
+    }
+
+    fn map_locked(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::Protocol(msg.to_string()).into(), msg)
+    }
+
+    fn map_rollback_only(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::Protocol(msg.to_string()).into(), msg)
+    }
+
+    fn map_guard_tripped(msg: &str) -> Self::OutError
+    where Self::InError: From<sqlx::Error> {
+        Self::map(sqlx::Error::Protocol(msg.to_string()).into(), msg)
+    }
+
+    // Whether running the same work again may succeed, e.g. busy database or deadlock.
+    fn is_transient(_e: &Self::OutError) -> bool {
+        false
+    }
+
+    // Whether the connection was lost, so the work may succeed on another one.
+    fn is_disconnect(_e: &Self::OutError) -> bool {
+        false
+    }
+}
+
+// Rows an UPDATE or DELETE has to affect for execute_expecting_rows to succeed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RowExpectation {
+    Any,
+    AtLeastOne,
+    ExactlyOne,
+}
+
+impl RowExpectation {
+    pub fn is_met(&self, affected: u64) -> bool {
+        match self {
+            RowExpectation::Any => true,
+            RowExpectation::AtLeastOne => affected >= 1,
+            RowExpectation::ExactlyOne => affected == 1,
+        }
+    }
+}
+
+// Affected row count of a backend's query result.
+pub trait RowsAffected {
+    fn affected(&self) -> u64;
+}
+
+// Copy of the arguments of a pool-level read to run it again on a new connection, see
+// SqlPool::retry_reads_on_disconnect. None when the backend's arguments cannot be copied.
+pub trait ReplayArguments: Database {
+    fn replay_arguments<'q>(arguments: &Self::Arguments<'q>) -> Option<Self::Arguments<'q>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(1 << attempt.saturating_sub(1).min(16)).min(self.max_delay)
+    }
 }
 
+pub type SqlFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
 #[macro_export]
 macro_rules! sql_query {
     ($query:expr) => ({
@@ -27,9 +177,209 @@ pub struct SqlPool<DB: sqlx::Database, EM: ErrorMap<InError = sqlx::Error>>
 where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
     pub(crate) pool: sqlx::pool::Pool<DB>,
     pub(crate) uri: String,
+    pub(crate) target: Arc<TargetInfo>,
+    pub(crate) lease_leak_timeout: Duration,
+    pub(crate) validate_placeholders: bool,
+    pub(crate) retry_reads: bool,
+    pub(crate) normal_slots: Option<Arc<async_lock::Semaphore>>,
+    pub(crate) state: Arc<PoolState>,
     pub(crate) _em: PhantomData<EM>,
 }
 
+pub(crate) const DEFAULT_LEASE_LEAK_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_STATEMENT_STATS: usize = 256;
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Counts a get_conn in progress for as long as it lives, including when the call is cancelled.
+struct PendingAcquire<'a>(&'a AtomicUsize);
+
+impl<'a> PendingAcquire<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count)
+    }
+}
+
+impl Drop for PendingAcquire<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Shared by every clone of a pool.
+#[derive(Default)]
+pub(crate) struct PoolState {
+    shutting_down: AtomicBool,
+    // get_conn calls past the shutdown check that have not got their connection yet.
+    acquiring: AtomicUsize,
+    next_lease_id: AtomicU64,
+    leases: Mutex<HashMap<u64, String>>,
+    read_replays: AtomicU64,
+    normal_acquires: AtomicU64,
+    normal_wait_us: AtomicU64,
+    high_acquires: AtomicU64,
+    high_wait_us: AtomicU64,
+    #[cfg(feature = "sqlite")]
+    pub(crate) compile_options: OnceLock<Vec<String>>,
+    // Whether the json functions answered the probe, see sqlite SqlConnection::supports.
+    #[cfg(feature = "sqlite")]
+    pub(crate) json1: OnceLock<bool>,
+    // Held for writing by sqlite with_schema_change, get_conn hands out no connection meanwhile.
+    #[cfg(feature = "sqlite")]
+    pub(crate) schema_gate: async_lock::RwLock<()>,
+    // Bound parameter limit probed from the database, SqlBackend::max_bind_params until then.
+    pub(crate) bind_params: OnceLock<usize>,
+    pub(crate) capabilities: OnceLock<Capabilities>,
+    clock: RwLock<Option<Arc<dyn Clock>>>,
+    // mysql user variables set through set_user_var on any connection of the pool.
+    #[cfg(feature = "mysql")]
+    pub(crate) user_vars: Mutex<std::collections::BTreeSet<String>>,
+    #[cfg(feature = "mysql")]
+    pub(crate) clear_user_vars: AtomicBool,
+    // mysql connection charset and collation the pool was opened with.
+    #[cfg(feature = "mysql")]
+    pub(crate) charset: OnceLock<(String, String)>,
+    statement_stats: OnceLock<StatementStats>,
+    adaptive_timeout: OnceLock<AdaptiveTimeout>,
+    pub(crate) query_cache: OnceLock<QueryCache>,
+    next_transaction_id: AtomicU64,
+    pub(crate) transactions_begun: AtomicU64,
+    pub(crate) transactions_committed: AtomicU64,
+    pub(crate) transactions_rolled_back: AtomicU64,
+    tag_transactions: AtomicBool,
+    observer: OnceLock<Arc<dyn StatementObserver>>,
+    poisoned_connections: AtomicU64,
+    #[cfg(any(test, feature = "test-util"))]
+    fault_injector: OnceLock<crate::test_util::FaultInjector>,
+}
+
+// Size and timeouts of a pool. The defaults are the ones open uses, latency sensitive services
+// usually want a much shorter acquire_timeout so a starved pool fails fast.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    // None keeps idle connections open.
+    pub idle_timeout: Option<Duration>,
+    // None keeps connections open however old they are.
+    pub max_lifetime: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(300),
+            idle_timeout: Some(Duration::from_secs(300)),
+            max_lifetime: Some(Duration::from_secs(1800)),
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn new(max_connections: u32) -> Self {
+        Self {
+            max_connections,
+            ..Default::default()
+        }
+    }
+
+    pub fn min_connections(mut self, min_connections: u32) -> Self {
+        self.min_connections = min_connections;
+        self
+    }
+
+    pub fn acquire_timeout(mut self, acquire_timeout: Duration) -> Self {
+        self.acquire_timeout = acquire_timeout;
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn max_lifetime(mut self, max_lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    pub(crate) fn pool_options<DB: Database>(&self) -> sqlx::pool::PoolOptions<DB> {
+        sqlx::pool::PoolOptions::<DB>::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .max_lifetime(self.max_lifetime)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SqlPoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    pub read_replays: u64,
+    pub normal_acquires: u64,
+    pub normal_wait: Duration,
+    pub high_acquires: u64,
+    pub high_wait: Duration,
+    // Connections closed instead of reused because they were dropped mid-statement, or with a
+    // transaction open while a panic unwound.
+    pub poisoned_connections: u64,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+impl PoolState {
+    #[cfg(feature = "mysql")]
+    pub(crate) fn user_var_names(&self) -> Vec<String> {
+        self.user_vars.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_else(crate::clock::runtime_clock)
+    }
+
+    pub(crate) fn bind_params(&self, backend: SqlBackend) -> usize {
+        self.bind_params.get().copied().unwrap_or(backend.max_bind_params())
+    }
+
+    fn statement_timeout(&self, sql: &str, tag: Option<&str>, backend: SqlBackend) -> Option<Duration> {
+        let policy = self.adaptive_timeout.get()?;
+        let (executions, p95) = self.statement_stats.get()
+            .and_then(|s| match tag {
+                Some(tag) => s.latency(&StatementKey::Tag(tag.to_string())),
+                None => s.latency(&StatementKey::Statement(normalize_sql(sql, backend))),
+            })
+            .unwrap_or((0, Duration::ZERO));
+        policy.timeout_for(executions, p95)
+    }
+
+    fn lease_labels(&self) -> Vec<String> {
+        self.leases.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+    }
+
+    // Zeroes the counters, each on its own, the gauges such as the pool size stay.
+    #[cfg(feature = "serde")]
+    pub(crate) fn reset_counters(&self) {
+        for counter in [&self.read_replays, &self.normal_acquires, &self.normal_wait_us, &self.high_acquires, &self.high_wait_us,
+                        &self.poisoned_connections, &self.transactions_begun, &self.transactions_committed, &self.transactions_rolled_back] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        if let Some(stats) = self.statement_stats.get() {
+            stats.reset();
+        }
+    }
+}
+
 impl<DB: sqlx::Database, EM: ErrorMap<InError = sqlx::Error>> Clone for SqlPool<DB, EM>
 where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
 
@@ -37,7 +387,13 @@ where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
         Self {
             pool: self.pool.clone(),
             uri: self.uri.clone(),
-            _em: self._em.clone()
+            target: self.target.clone(),
+            lease_leak_timeout: self.lease_leak_timeout,
+            validate_placeholders: self.validate_placeholders,
+            retry_reads: self.retry_reads,
+            normal_slots: self.normal_slots.clone(),
+            state: self.state.clone(),
+            _em: self._em
         }
     }
 }
@@ -54,7 +410,200 @@ where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
 impl<DB: sqlx::Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlPool<DB, EM>
 where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
     pub fn from_raw_pool(pool: sqlx::pool::Pool<DB>) -> Self {
-        Self { pool, uri: "".to_string(), _em: Default::default() }
+        Self::from_raw_pool_with_uri(pool, "")
+    }
+
+    pub fn from_raw_pool_with_uri(pool: sqlx::pool::Pool<DB>, uri: &str) -> Self {
+        Self::from_raw_pool_with_state(pool, uri, Default::default())
+    }
+
+    // For backends whose pool hooks need the shared state before the pool exists.
+    pub(crate) fn from_raw_pool_with_state(pool: sqlx::pool::Pool<DB>, uri: &str, state: Arc<PoolState>) -> Self {
+        Self {
+            pool,
+            uri: uri.to_string(),
+            target: Arc::new(TargetInfo::parse(uri)),
+            lease_leak_timeout: DEFAULT_LEASE_LEAK_TIMEOUT,
+            validate_placeholders: false,
+            retry_reads: false,
+            normal_slots: None,
+            state,
+            _em: Default::default(),
+        }
+    }
+
+    pub fn target(&self) -> &TargetInfo {
+        &self.target
+    }
+
+    // Checks placeholder count against bound arguments before sending each statement.
+    pub fn with_placeholder_validation(mut self, enable: bool) -> Self {
+        self.validate_placeholders = enable;
+        self
+    }
+
+    // A pool-level read whose connection breaks under it, e.g. cut by a failover, is run once
+    // more on a new connection and counted in SqlPoolStats::read_replays. The broken connection
+    // is closed. Writes and connection-level calls are never replayed, nor are postgres reads
+    // with arguments, which cannot be copied.
+    pub fn retry_reads_on_disconnect(mut self, enable: bool) -> Self {
+        self.retry_reads = enable;
+        self
+    }
+
+    // Open connections, idle ones included.
+    pub fn pool_size(&self) -> u32 {
+        self.pool.size()
+    }
+
+    pub fn idle_connections(&self) -> usize {
+        self.pool.num_idle()
+    }
+
+    // Clones of this pool and connections taken from it that are alive, this handle included.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn handles(&self) -> usize {
+        Arc::strong_count(&self.state)
+    }
+
+    // Connections checked out right now. size and idle are read one after the other, so this
+    // is a snapshot that may be off by the connections acquired or returned in between.
+    pub fn in_use(&self) -> usize {
+        (self.pool.size() as usize).saturating_sub(self.pool.num_idle())
+    }
+
+    pub fn stats(&self) -> SqlPoolStats {
+        let size = self.pool.size();
+        let idle = self.pool.num_idle();
+        SqlPoolStats {
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+            read_replays: self.state.read_replays.load(Ordering::Relaxed),
+            normal_acquires: self.state.normal_acquires.load(Ordering::Relaxed),
+            normal_wait: Duration::from_micros(self.state.normal_wait_us.load(Ordering::Relaxed)),
+            high_acquires: self.state.high_acquires.load(Ordering::Relaxed),
+            high_wait: Duration::from_micros(self.state.high_wait_us.load(Ordering::Relaxed)),
+            poisoned_connections: self.state.poisoned_connections.load(Ordering::Relaxed),
+        }
+    }
+
+    // Keeps the last `high` connections for get_conn_priority(Priority::High), normal acquires
+    // wait once max_connections - high connections are out. Idle connections are shared by both.
+    pub fn reserve_for_priority(mut self, high: u32) -> Self {
+        let normal = self.pool.options().get_max_connections().saturating_sub(high).max(1);
+        self.normal_slots = Some(Arc::new(async_lock::Semaphore::new(normal as usize)));
+        self
+    }
+
+    fn should_replay_read(&self, e: &sqlx::Error) -> bool {
+        if !matches!(e, sqlx::Error::Io(_) | sqlx::Error::WorkerCrashed) {
+            return false;
+        }
+        self.state.read_replays.fetch_add(1, Ordering::Relaxed);
+        log::warn!("connection to {} broken during read, replayed on a new connection: {}", self.target.uri, e);
+        true
+    }
+
+    pub fn with_lease_leak_timeout(mut self, timeout: Duration) -> Self {
+        self.lease_leak_timeout = timeout;
+        self
+    }
+
+    // Replaces the runtime clock on every clone of this pool and on the connections it hands out,
+    // tests pass a test_util::ManualClock to control time.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        *self.state.clock.write().unwrap_or_else(|e| e.into_inner()) = Some(clock);
+        self
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.state.clock()
+    }
+
+    // Bound parameters one statement may have, batches of the sink and the batcher are sized
+    // to stay under it.
+    pub fn max_bind_params(&self) -> usize {
+        self.state.bind_params(SqlBackend::from_db_name(DB::NAME))
+    }
+
+    // Keeps count, time, errors and rows per normalized statement for at most top_n statements,
+    // executed through any connection of the pool. Only the first call takes effect.
+    pub fn with_statement_stats(self, top_n: usize) -> Self {
+        let _ = self.state.statement_stats.set(StatementStats::new(top_n));
+        self
+    }
+
+    // Cuts statements off at the timeout policy computes from their statistics, turning the
+    // statistics on with the default size when with_statement_stats was not called. A statement
+    // cut off fails with SqlErrorCode::Timeout, its connection fails anything else but a rollback
+    // from then on and is closed instead of returned to the pool. Only the first call takes
+    // effect.
+    pub fn with_adaptive_timeout(self, policy: AdaptiveTimeout) -> Self {
+        let _ = self.state.statement_stats.get_or_init(|| StatementStats::new(DEFAULT_STATEMENT_STATS));
+        let _ = self.state.adaptive_timeout.set(policy);
+        self
+    }
+
+    // The timeout the adaptive timeout policy currently gives sql, for debugging.
+    pub fn adaptive_timeout_for(&self, sql: &str) -> Option<Duration> {
+        self.state.statement_timeout(sql, None, SqlBackend::from_db_name(DB::NAME))
+    }
+
+    // Slowest statements by total time first, empty unless with_statement_stats was called.
+    pub fn statement_stats(&self) -> Vec<StatementStat> {
+        self.state.statement_stats.get().map(|s| s.snapshot()).unwrap_or_default()
+    }
+
+    // Hands every statement run through a connection of the pool to observer, e.g. a
+    // StatementJournal. Only the first call takes effect.
+    pub fn with_observer(self, observer: Arc<dyn StatementObserver>) -> Self {
+        let _ = self.state.observer.set(observer);
+        self
+    }
+
+    // Runs every statement of the pool through injector first. Only the first call takes effect.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn with_fault_injector(self, injector: crate::test_util::FaultInjector) -> Self {
+        let _ = self.state.fault_injector.set(injector);
+        self
+    }
+
+    // Appends the transaction id to the text tag_sql returns and to the scripts execute_batch
+    // runs in a transaction.
+    pub fn tag_transactions(self, enable: bool) -> Self {
+        self.state.tag_transactions.store(enable, Ordering::Relaxed);
+        self
+    }
+
+    // Enables query_all_cached on the connections of this pool, keeping at most max_entries
+    // results. A write run by a connection of the pool, through whichever method, invalidates the
+    // results depending on the written tables.
+    pub fn with_query_cache(self, max_entries: usize) -> Self {
+        let _ = self.state.query_cache.set(QueryCache::new(max_entries));
+        self
+    }
+
+    // For writes made outside this pool, such as by another process.
+    pub fn invalidate_query_cache(&self, tables: &[&str]) {
+        if let Some(cache) = self.state.query_cache.get() {
+            let tables: Vec<String> = tables.iter().map(|t| table_key(t)).collect();
+            if !tables.is_empty() {
+                cache.invalidate(&tables);
+            }
+        }
+    }
+
+    pub fn clear_query_cache(&self) {
+        if let Some(cache) = self.state.query_cache.get() {
+            cache.invalidate(&[]);
+        }
+    }
+
+    pub fn reset_statement_stats(&self) {
+        if let Some(stats) = self.state.statement_stats.get() {
+            stats.reset();
+        }
     }
 
     pub async fn raw_pool(&self) -> sqlx::pool::Pool<DB> {
@@ -62,117 +611,1398 @@ where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
     }
 
     pub async fn get_conn(&self) -> Result<SqlConnection<DB, EM>, EM::OutError> {
+        self.get_conn_priority(Priority::Normal).await
+    }
+
+    pub async fn get_conn_priority(&self, priority: Priority) -> Result<SqlConnection<DB, EM>, EM::OutError> {
+        if self.is_shutting_down() {
+            return Err(EM::map_shutting_down(format!("[{} {}] pool is shutting down", line!(), self.target.uri).as_str()));
+        }
+        let _acquiring = PendingAcquire::new(&self.state.acquiring);
+        let clock = self.state.clock();
+        let start = clock.now();
+        let permit = match (priority, self.normal_slots.as_ref()) {
+            (Priority::Normal, Some(slots)) => Some(slots.acquire_arc().await),
+            _ => None,
+        };
+        #[cfg(feature = "sqlite")]
+        let conn = loop {
+            drop(self.state.schema_gate.read().await);
+            let conn = self.pool.acquire().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), self.uri.as_str()).as_str()))?;
+            // Got as a schema change shut the gate, it goes back unused.
+            if self.state.schema_gate.try_read().is_none() {
+                drop(conn);
+                continue;
+            }
+            break conn;
+        };
+        #[cfg(not(feature = "sqlite"))]
         let conn = self.pool.acquire().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), self.uri.as_str()).as_str()))?;
-        Ok(SqlConnection::<DB, EM>::from(conn))
+        let wait_us = clock.elapsed(start).as_micros() as u64;
+        match priority {
+            Priority::Normal => {
+                self.state.normal_acquires.fetch_add(1, Ordering::Relaxed);
+                self.state.normal_wait_us.fetch_add(wait_us, Ordering::Relaxed);
+            }
+            Priority::High => {
+                self.state.high_acquires.fetch_add(1, Ordering::Relaxed);
+                self.state.high_wait_us.fetch_add(wait_us, Ordering::Relaxed);
+            }
+        }
+        let mut conn = SqlConnection::<DB, EM>::from(conn);
+        conn.priority_permit = permit;
+        conn.target = self.target.clone();
+        conn.pool_state = self.state.clone();
+        conn.validate_placeholders = self.validate_placeholders;
+        Ok(conn)
     }
-}
 
-pub fn sql_query<DB: Database>(sql: &str) -> sqlx::query::Query<DB, DB::Arguments<'_>>
-where for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,{
-    sqlx::query(sql)
-}
+    // Holds one pooled connection across several steps for connection-scoped state such as
+    // temporary tables, user variables or GET_LOCK. The connection goes back to the pool when
+    // the last handle is dropped or release is called.
+    pub async fn lease_conn(&self, purpose: &str) -> Result<LeasedConnection<DB, EM>, EM::OutError> {
+        let conn = self.get_conn().await?;
+        let id = self.state.next_lease_id.fetch_add(1, Ordering::Relaxed);
+        self.state.leases.lock().unwrap_or_else(|e| e.into_inner()).insert(id, purpose.to_string());
+        let leak_warned = Arc::new(AtomicBool::new(false));
+        sqlx_core::rt::spawn(watch_lease(Arc::downgrade(&self.state), id, purpose.to_string(), self.lease_leak_timeout, leak_warned.clone()));
+        Ok(LeasedConnection {
+            inner: Arc::new(LeaseInner {
+                conn: async_lock::Mutex::new(Some(conn)),
+                id,
+                pool_state: self.state.clone(),
+                purpose: purpose.to_string(),
+                leased_at: self.state.clock().now(),
+                leak_timeout: self.lease_leak_timeout,
+                leak_warned,
+            }),
+        })
+    }
 
-pub fn sql_query_with<'a, DB: Database>(sql: &'a str, arguments: DB::Arguments<'a>) -> sqlx::query::Query<'a, DB, DB::Arguments<'a>>
-where for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,{
-    sqlx::query_with(sql, arguments)
-}
+    // New get_conn and lease_conn calls fail from now on, on every clone of this pool.
+    pub fn begin_shutdown(&self) {
+        self.state.shutting_down.store(true, Ordering::SeqCst);
+    }
 
-pub enum SqlConnectionType<DB: Database>
-where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,{
-    PoolConn(PoolConnection<DB>),
-    Conn(DB::Connection),
-}
-pub struct SqlConnection<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
-where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
-    pub(crate) trans: Option<Transaction<'static, DB>>,
-    pub(crate) conn: SqlConnectionType<DB>,
-    pub(crate) _em: PhantomData<EM>,
-}
+    // Undoes begin_shutdown, for a pool that was drained but not closed.
+    pub(crate) fn cancel_shutdown(&self) {
+        self.state.shutting_down.store(false, Ordering::SeqCst);
+    }
 
-impl <DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> From<sqlx::pool::PoolConnection<DB>> for SqlConnection<DB, EM>
-where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
-    fn from(conn: sqlx::pool::PoolConnection<DB>) -> Self {
-        Self { conn: SqlConnectionType::PoolConn(conn), _em: Default::default(), trans: None }
+    // Closes the pool from code that is not async, such as a shutdown hook, waiting at most
+    // timeout for checked-out connections. Returns whether the pool closed in time. Runs on the
+    // current runtime when called from a multi-thread one, otherwise on a runtime of its own.
+    // A current-thread runtime is blocked meanwhile: connections it holds cannot be returned,
+    // so they have to be dropped before the call, and server connections it opened cannot be
+    // closed gracefully, their sockets are shut when the close gives up at timeout. sqlite
+    // connections close on their worker threads either way.
+    #[cfg(feature = "runtime-tokio")]
+    pub fn close_blocking(&self, timeout: Duration) -> bool {
+        self.begin_shutdown();
+        let pool = self.pool.clone();
+        let close = async move { tokio::time::timeout(timeout, pool.close()).await.is_ok() };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(close))
+            }
+            // A runtime cannot be started on a thread that runs one.
+            Ok(_) => std::thread::scope(|scope| {
+                scope.spawn(|| block_on_own_runtime(close, self.target.uri.as_str())).join().unwrap_or(false)
+            }),
+            Err(_) => block_on_own_runtime(close, self.target.uri.as_str()),
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.shutting_down.load(Ordering::SeqCst)
+    }
+
+    // Waits until every checked-out connection has been returned to the pool and no get_conn
+    // is still waiting for one.
+    pub async fn await_idle(&self, timeout: Duration) -> Result<(), EM::OutError> {
+        let clock = self.state.clock();
+        let start = clock.now();
+        loop {
+            let in_use = self.in_use();
+            let acquiring = self.state.acquiring.load(Ordering::SeqCst);
+            if in_use == 0 && acquiring == 0 {
+                return Ok(());
+            }
+            if clock.elapsed(start) >= timeout {
+                let msg = format!("[{} {}] {} connections still in use, {} being acquired, leases {:?}",
+                                  line!(), self.target.uri, in_use, acquiring, self.state.lease_labels());
+                return Err(EM::map(sqlx::Error::PoolTimedOut, msg.as_str()));
+            }
+            clock.sleep(Duration::from_millis(10)).await;
+        }
     }
 }
 
-impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+impl<DB: sqlx::Database + ReplayArguments, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlPool<DB, EM>
 where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
       for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
-    pub async fn execute_sql<'a>(&mut self, query: sqlx::query::Query<'a, DB, <DB as Database>::Arguments<'a>>) -> Result<DB::QueryResult, EM::OutError>
-    {
+    // Runs a pool-level read, and with retry_reads once more on a new connection when the
+    // connection breaks under it. The replay is rebuilt from the text and a copy of the
+    // arguments taken before the first run, which consumes them.
+    async fn read<'a, Op: StatementOp<DB>>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Op::Output, EM::OutError> {
+        let mut conn = self.get_conn().await?;
+        let query = conn.check_placeholders(query)?;
         let sql = query.sql();
-        match &mut self.conn {
-            SqlConnectionType::PoolConn(conn) => {
-                conn.execute(query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
-            },
-            SqlConnectionType::Conn(conn) => {
-                conn.execute(query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+        let replay = match &query.arguments {
+            _ if !self.retry_reads => None,
+            Some(arguments) => DB::replay_arguments(arguments).map(Some),
+            None => Some(None),
+        };
+        let ret = match (conn.run_checked::<Op, _>(sql, None, query).await, replay) {
+            (Err(e), Some(arguments)) if self.should_replay_read(&e) => {
+                conn.discard();
+                let mut conn = self.get_conn().await?;
+                let query = CheckedQuery { query: sqlx::query(sql), arguments };
+                conn.run_checked::<Op, _>(sql, None, query).await
             }
-        }
+            (ret, _) => ret,
+        };
+        ret.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
     }
 
-    pub async fn query_one<'a>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<DB::Row, EM::OutError> {
+    pub async fn query_one<'a>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<DB::Row, EM::OutError> {
+        self.read::<FetchOne>(query).await
+    }
+
+    pub async fn query_all<'a>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<DB::Row>, EM::OutError> {
+        self.read::<FetchAll>(query).await
+    }
+
+    pub async fn query_optional<'a>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Option<DB::Row>, EM::OutError> {
+        self.read::<FetchOptional>(query).await
+    }
+
+    // See SqlConnection::query_scalar.
+    pub async fn query_scalar<'a, T>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<T, EM::OutError>
+    where T: for<'r> sqlx::Decode<'r, DB> + sqlx::Type<DB>,
+          usize: sqlx::ColumnIndex<DB::Row>, {
         let sql = query.sql();
-        match &mut self.conn {
-            SqlConnectionType::PoolConn(conn) => {
-                conn.fetch_one(query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
-            },
-            SqlConnectionType::Conn(conn) => {
-                conn.fetch_one(query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
-            }
+        let row = self.query_one(query).await?;
+        row.try_get::<T, _>(0).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_scalar_optional<'a, T>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Option<T>, EM::OutError>
+    where T: for<'r> sqlx::Decode<'r, DB> + sqlx::Type<DB>,
+          usize: sqlx::ColumnIndex<DB::Row>, {
+        let sql = query.sql();
+        match self.query_optional(query).await? {
+            Some(row) => row.try_get::<T, _>(0).map(Some).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str())),
+            None => Ok(None),
         }
     }
 
-    pub async fn query_all<'a>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<DB::Row>, EM::OutError> {
+    pub async fn query_one_as<'a, T>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<T, EM::OutError>
+    where T: for<'r> sqlx::FromRow<'r, DB::Row>, {
         let sql = query.sql();
-        match &mut self.conn {
-            SqlConnectionType::PoolConn(conn) => {
-                conn.fetch_all(query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
-            },
-            SqlConnectionType::Conn(conn) => {
-                conn.fetch_all(query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+        let row = self.query_one(query).await?;
+        T::from_row(&row).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_all_as<'a, T>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<T>, EM::OutError>
+    where T: for<'r> sqlx::FromRow<'r, DB::Row>, {
+        let sql = query.sql();
+        let rows = self.query_all(query).await?;
+        decode_rows::<DB, EM, T>(rows.as_slice(), sql)
+    }
+}
+
+impl<DB: sqlx::Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlPool<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+    // Readiness probe, SELECT 1 on a connection of the pool. Fails with a timeout after 5s
+    // instead of waiting out the acquire timeout of a starved pool.
+    pub async fn ping(&self) -> Result<(), EM::OutError> {
+        self.ping_within(DEFAULT_PING_TIMEOUT).await
+    }
+
+    pub async fn ping_within(&self, timeout: Duration) -> Result<(), EM::OutError> {
+        let ping = async {
+            let mut conn = self.get_conn().await?;
+            conn.query_one(sqlx::query::<DB>("SELECT 1")).await?;
+            Ok::<(), EM::OutError>(())
+        };
+        match crate::clock::timeout(Some((self.state.clock(), timeout)), ping).await {
+            Some(ret) => ret,
+            None => {
+                let e = sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("ping timed out after {:?}", timeout)));
+                Err(EM::map(e, format!("[{} {}] ping", line!(), self.target.uri).as_str()))
             }
         }
     }
 
-    pub async fn begin_transaction(&mut self) -> Result<(), EM::OutError> {
-        let this: &'static mut Self = unsafe {std::mem::transmute(self)};
-        let trans = match &mut this.conn {
-            SqlConnectionType::PoolConn(conn) => {
-                conn.begin().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "begin trans").as_str()))
-            },
-            SqlConnectionType::Conn(conn) => {
-                conn.begin().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "begin trans").as_str()))
-            }
-        }?;
-        this.trans = Some(trans);
-        Ok(())
+    // Runs f in a transaction on a connection of the pool, as SqlConnection::transaction does.
+    // The connection goes back to the pool on every path, a panic included, unless the panic
+    // left a statement half done, then it is closed.
+    pub async fn with_transaction<F, T>(&self, f: F) -> Result<T, EM::OutError>
+    where F: for<'c> FnOnce(&'c mut SqlConnection<DB, EM>) -> SqlFuture<'c, Result<T, EM::OutError>> {
+        let mut conn = self.get_conn().await?;
+        conn.transaction(f).await
     }
 
-    pub async fn rollback_transaction(&mut self) -> Result<(), EM::OutError> {
-        if self.trans.is_none() {
-            Ok(())
-        } else {
-            self.trans.take().unwrap().rollback().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "rollback trans").as_str()))
-        }
+    // Runs f in a transaction on a connection of the pool, started over when f or the commit
+    // fails with a transient error, see SqlConnection::with_retrying_transaction.
+    pub async fn retry_transaction<F, R>(&self, policy: &RetryPolicy, f: F) -> Result<R, EM::OutError>
+    where F: for<'c> FnMut(&'c mut SqlConnection<DB, EM>) -> SqlFuture<'c, Result<R, EM::OutError>> {
+        let mut conn = self.get_conn().await?;
+        conn.with_retrying_transaction(policy, f).await
     }
 
-    pub async fn commit_transaction(&mut self) -> Result<(), EM::OutError> {
-        if self.trans.is_none() {
-            return Ok(())
-        } else {
-            self.trans.take().unwrap().commit().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "commit trans").as_str()))
+    // Like SqlConnection::with_retry, on a connection of its own per attempt, so a lost
+    // connection is retried as well.
+    pub async fn with_retry<F, T>(&self, policy: &RetryPolicy, mut f: F) -> Result<T, EM::OutError>
+    where F: for<'c> FnMut(&'c mut SqlConnection<DB, EM>) -> SqlFuture<'c, Result<T, EM::OutError>> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let ret = match self.get_conn().await {
+                Ok(mut conn) => f(&mut conn).await,
+                Err(e) => Err(e),
+            };
+            match ret {
+                Err(e) if attempt < policy.max_attempts && (EM::is_transient(&e) || EM::is_disconnect(&e)) => {
+                    log::warn!("attempt {} failed with retryable error, retry", attempt);
+                    self.state.clock().sleep(policy.delay(attempt)).await;
+                }
+                ret => return ret,
+            }
         }
     }
+}
 
+struct LeaseInner<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    conn: async_lock::Mutex<Option<SqlConnection<DB, EM>>>,
+    id: u64,
+    pool_state: Arc<PoolState>,
+    purpose: String,
+    leased_at: Instant,
+    leak_timeout: Duration,
+    // Shared with the watch_lease task, so a lease is reported once.
+    leak_warned: Arc<AtomicBool>,
 }
 
-impl<DB: sqlx::Database,EM: ErrorMap<InError=sqlx::Error>> Drop for SqlConnection<DB, EM>
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> LeaseInner<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn check_leak(&self) {
+        let elapsed = self.pool_state.clock().elapsed(self.leased_at);
+        if elapsed >= self.leak_timeout && !self.leak_warned.swap(true, Ordering::Relaxed) {
+            log::warn!("leased connection [{}] held for {:?}, possible leak", self.purpose, elapsed);
+        }
+    }
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> Drop for LeaseInner<DB, EM>
 where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
     fn drop(&mut self) {
-        if self.trans.is_some() {
-            let _ = self.trans.take();
+        if self.conn.get_mut().is_some() {
+            self.check_leak();
         }
+        self.pool_state.leases.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.id);
+    }
+}
+
+// Warns when lease id is still outstanding after timeout. Runs apart from the lease, so a lease
+// whose handles were forgotten or kept alive by a reference cycle, and never dropped, is still
+// reported. Ends after one check, a released lease is simply not found.
+// Holds the pool state weakly, so a pool whose leases are gone is not kept alive until the timer fires.
+async fn watch_lease(pool_state: std::sync::Weak<PoolState>, id: u64, purpose: String, timeout: Duration, leak_warned: Arc<AtomicBool>) {
+    let clock = match pool_state.upgrade() {
+        Some(state) => state.clock(),
+        None => return,
+    };
+    clock.sleep(timeout).await;
+    let outstanding = match pool_state.upgrade() {
+        Some(state) => state.leases.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&id),
+        None => false,
+    };
+    if outstanding && !leak_warned.swap(true, Ordering::Relaxed) {
+        log::warn!("leased connection [{}] held for {:?}, possible leak", purpose, timeout);
     }
 }
+
+pub struct LeasedConnection<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    inner: Arc<LeaseInner<DB, EM>>,
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> LeasedConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pub fn clone_handle(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+
+    pub fn purpose(&self) -> &str {
+        self.inner.purpose.as_str()
+    }
+
+    pub async fn lock(&self) -> Result<LeasedConnectionGuard<'_, DB, EM>, EM::OutError> {
+        self.inner.check_leak();
+        let guard = self.inner.conn.lock().await;
+        if guard.is_none() {
+            return Err(EM::map(sqlx::Error::PoolClosed, format!("[{} {}]", line!(), self.inner.purpose).as_str()));
+        }
+        Ok(LeasedConnectionGuard { guard })
+    }
+
+    // Returns the connection to the pool now, every other handle of this lease fails afterwards.
+    pub async fn release(self) {
+        self.inner.check_leak();
+        let conn = self.inner.conn.lock().await.take();
+        drop(conn);
+        self.inner.pool_state.leases.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.inner.id);
+    }
+}
+
+pub struct LeasedConnectionGuard<'a, DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    guard: async_lock::MutexGuard<'a, Option<SqlConnection<DB, EM>>>,
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> Deref for LeasedConnectionGuard<'_, DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    type Target = SqlConnection<DB, EM>;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> DerefMut for LeasedConnectionGuard<'_, DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+// Rows decoded into T. A row that fails names its index in the message next to the column
+// sqlx reports, so bad data can be found among many rows.
+fn decode_rows<DB: Database, EM: ErrorMap<InError = sqlx::Error>, T>(rows: &[DB::Row], sql: &str) -> Result<Vec<T>, EM::OutError>
+where T: for<'r> sqlx::FromRow<'r, DB::Row>, {
+    rows.iter().enumerate()
+        .map(|(i, row)| T::from_row(row).map_err(|e| EM::map(e, format!("[{} {}] row {} of {}", line!(), sql, i, rows.len()).as_str())))
+        .collect()
+}
+
+// row.to_map with the error mapped through EM.
+pub(crate) fn map_row<EM: ErrorMap<InError = sqlx::Error>, R: RowToMap>(row: &R, sql: &str) -> Result<IndexMap<String, SqlValue>, EM::OutError> {
+    row.to_map().map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+}
+
+pub fn sql_query<DB: Database>(sql: &str) -> sqlx::query::Query<'_, DB, DB::Arguments<'_>>
+where for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,{
+    sqlx::query(sql)
+}
+
+pub fn sql_query_with<'a, DB: Database>(sql: &'a str, arguments: DB::Arguments<'a>) -> sqlx::query::Query<'a, DB, DB::Arguments<'a>>
+where for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,{
+    sqlx::query_with(sql, arguments)
+}
+
+// Calls f until it succeeds, backing off between attempts. Each attempt is cut off at what is left
+// of max_wait, so an attempt that hangs, e.g. on a connect to an unreachable host, cannot hold
+// the caller past it. Returns the last error, as text, together with the attempt count once
+// max_wait has elapsed.
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) async fn retry_until<T, E: std::fmt::Debug, F, Fut>(clock: Arc<dyn Clock>, what: &str, max_wait: Duration, mut f: F) -> Result<T, (String, u32)>
+where F: FnMut() -> Fut,
+      Fut: Future<Output = Result<T, E>> {
+    let start = clock.now();
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let remaining = max_wait.saturating_sub(clock.elapsed(start));
+        let error = match crate::clock::timeout(Some((clock.clone(), remaining)), f()).await {
+            Some(Ok(v)) => {
+                log::info!("{} ready after {} attempts", what, attempt);
+                return Ok(v);
+            }
+            Some(Err(e)) => format!("{:?}", e),
+            None => format!("attempt timed out after {:?}", remaining),
+        };
+        let elapsed = clock.elapsed(start);
+        if elapsed >= max_wait {
+            log::error!("{} not ready after {} attempts: {}", what, attempt, error);
+            return Err((error, attempt));
+        }
+        log::warn!("{} not ready, attempt {}: {}", what, attempt, error);
+        clock.sleep(delay.min(max_wait - elapsed)).await;
+        delay = (delay * 2).min(Duration::from_secs(5));
+    }
+}
+
+// Name of the running binary, falls back to this crate's name.
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+pub(crate) fn default_application_name() -> String {
+    std::env::current_exe().ok()
+        .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+}
+
+pub type CommitCallback = Box<dyn FnOnce() -> SqlFuture<'static, ()> + Send>;
+
+pub enum SqlConnectionType<DB: Database>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,{
+    PoolConn(PoolConnection<DB>),
+    Conn(DB::Connection),
+}
+
+// A query whose arguments were taken out to be counted. Executes as the query it came from,
+// statement, persistence and all, so nothing is rebuilt.
+pub(crate) struct CheckedQuery<'a, DB: Database> {
+    query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>,
+    arguments: Option<DB::Arguments<'a>>,
+}
+
+impl<'a, DB: Database> Execute<'a, DB> for CheckedQuery<'a, DB>
+where DB::Arguments<'a>: sqlx::IntoArguments<'a, DB>, {
+    fn sql(&self) -> &'a str {
+        self.query.sql()
+    }
+
+    fn statement(&self) -> Option<&DB::Statement<'a>> {
+        self.query.statement()
+    }
+
+    fn take_arguments(&mut self) -> Result<Option<DB::Arguments<'a>>, sqlx::error::BoxDynError> {
+        Ok(self.arguments.take())
+    }
+
+    fn persistent(&self) -> bool {
+        self.query.persistent()
+    }
+}
+
+// What SqlConnection::run_checked does with a statement once it is sent, and how many rows
+// the outcome counts for the statement stats and observers.
+pub(crate) trait StatementOp<DB: Database> {
+    type Output;
+
+    fn send<'e, 'q: 'e, Q: 'q + Execute<'q, DB>>(conn: &'e mut DB::Connection, query: Q) -> SqlFuture<'e, Result<Self::Output, sqlx::Error>>;
+
+    fn rows(output: &Self::Output) -> u64;
+}
+
+pub(crate) struct FetchOne;
+pub(crate) struct FetchAll;
+pub(crate) struct FetchOptional;
+pub(crate) struct ExecuteStatement;
+
+impl<DB: Database> StatementOp<DB> for FetchOne
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    type Output = DB::Row;
+
+    fn send<'e, 'q: 'e, Q: 'q + Execute<'q, DB>>(conn: &'e mut DB::Connection, query: Q) -> SqlFuture<'e, Result<DB::Row, sqlx::Error>> {
+        conn.fetch_one(query)
+    }
+
+    fn rows(_: &DB::Row) -> u64 {
+        1
+    }
+}
+
+impl<DB: Database> StatementOp<DB> for FetchAll
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    type Output = Vec<DB::Row>;
+
+    fn send<'e, 'q: 'e, Q: 'q + Execute<'q, DB>>(conn: &'e mut DB::Connection, query: Q) -> SqlFuture<'e, Result<Vec<DB::Row>, sqlx::Error>> {
+        conn.fetch_all(query)
+    }
+
+    fn rows(rows: &Vec<DB::Row>) -> u64 {
+        rows.len() as u64
+    }
+}
+
+impl<DB: Database> StatementOp<DB> for FetchOptional
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    type Output = Option<DB::Row>;
+
+    fn send<'e, 'q: 'e, Q: 'q + Execute<'q, DB>>(conn: &'e mut DB::Connection, query: Q) -> SqlFuture<'e, Result<Option<DB::Row>, sqlx::Error>> {
+        conn.fetch_optional(query)
+    }
+
+    fn rows(row: &Option<DB::Row>) -> u64 {
+        row.is_some() as u64
+    }
+}
+
+impl<DB: Database> StatementOp<DB> for ExecuteStatement
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    type Output = DB::QueryResult;
+
+    fn send<'e, 'q: 'e, Q: 'q + Execute<'q, DB>>(conn: &'e mut DB::Connection, query: Q) -> SqlFuture<'e, Result<DB::QueryResult, sqlx::Error>> {
+        conn.execute(query)
+    }
+
+    fn rows(_: &DB::QueryResult) -> u64 {
+        0
+    }
+}
+
+fn tag_with_transaction(sql: &str, transaction_id: u64) -> String {
+    format!("{} /* txn:{} */", sql, transaction_id)
+}
+
+// What dropping a SqlConnection with its transaction still open does. The transaction is
+// rolled back in every case, Panic is for tests that treat a leaked transaction as a bug.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransactionDropPolicy {
+    RollbackSilently,
+    RollbackAndLog,
+    Panic,
+}
+
+impl Default for TransactionDropPolicy {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            TransactionDropPolicy::RollbackAndLog
+        } else {
+            TransactionDropPolicy::RollbackSilently
+        }
+    }
+}
+
+pub struct SqlConnection<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    // BEGIN was issued on conn and neither COMMIT nor ROLLBACK yet. The transaction lives in the
+    // connection, nothing borrows conn for it.
+    pub(crate) in_transaction: bool,
+    // Dropped by hand, so it can be leaked when no runtime is left to return it to the pool.
+    pub(crate) conn: ManuallyDrop<SqlConnectionType<DB>>,
+    pub(crate) target: Arc<TargetInfo>,
+    pub(crate) pool_state: Arc<PoolState>,
+    pub(crate) validate_placeholders: bool,
+    pub(crate) priority_permit: Option<async_lock::SemaphoreGuardArc>,
+    pub(crate) commit_callbacks: Vec<CommitCallback>,
+    pub(crate) savepoints: Vec<(String, usize)>,
+    // Tables written in the current transaction, invalidated in the query cache at commit.
+    pub(crate) pending_writes: Vec<Vec<String>>,
+    pub(crate) transaction_id: Option<u64>,
+    // begin_transaction calls nested in the open transaction, each closed by a commit or
+    // rollback that only counts down. A nested rollback leaves the transaction rollback only.
+    pub(crate) transaction_depth: u32,
+    pub(crate) rollback_only: bool,
+    // The open transaction was started by begin_read_transaction.
+    pub(crate) read_transaction: bool,
+    // Set while a statement runs. Still set at drop, the statement future was dropped before it
+    // finished and the protocol state of the connection is unknown.
+    pub(crate) in_flight: bool,
+    // Timeout of the statement the adaptive timeout cut off. The connection is closed at drop
+    // and refuses anything but a rollback until then.
+    pub(crate) cut_off: Option<Duration>,
+    pub(crate) drop_policy: TransactionDropPolicy,
+    // Last statement of the open transaction, named when the transaction is left open at drop.
+    pub(crate) transaction_sql: String,
+    pub(crate) _em: PhantomData<EM>,
+}
+
+impl <DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> From<sqlx::pool::PoolConnection<DB>> for SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn from(conn: sqlx::pool::PoolConnection<DB>) -> Self {
+        Self::from_conn_type(SqlConnectionType::PoolConn(conn), Default::default())
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pub(crate) fn from_conn_type(conn: SqlConnectionType<DB>, target: Arc<TargetInfo>) -> Self {
+        Self {
+            in_transaction: false,
+            conn: ManuallyDrop::new(conn),
+            target,
+            pool_state: Default::default(),
+            validate_placeholders: false,
+            priority_permit: None,
+            commit_callbacks: Vec::new(),
+            savepoints: Vec::new(),
+            pending_writes: Vec::new(),
+            transaction_id: None,
+            transaction_depth: 0,
+            rollback_only: false,
+            read_transaction: false,
+            in_flight: false,
+            cut_off: None,
+            drop_policy: Default::default(),
+            transaction_sql: String::new(),
+            _em: Default::default(),
+        }
+    }
+
+    pub(crate) fn executor(&mut self) -> &mut DB::Connection {
+        match &mut *self.conn {
+            SqlConnectionType::PoolConn(conn) => conn,
+            SqlConnectionType::Conn(conn) => conn,
+        }
+    }
+
+    fn close_on_drop(&mut self) {
+        if let SqlConnectionType::PoolConn(conn) = &mut *self.conn {
+            conn.close_on_drop();
+        }
+    }
+
+    // Fails fast once a statement was cut off, the connection may still be busy with it and
+    // would make the next call wait for it or read its leftover results.
+    pub(crate) fn check_not_cut_off(&self) -> Result<(), sqlx::Error> {
+        match self.cut_off {
+            Some(timeout) => Err(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::NotConnected,
+                format!("connection unusable, a statement was cut off after {:?}", timeout)))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+    pub fn target(&self) -> &TargetInfo {
+        &self.target
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.pool_state.clock()
+    }
+
+    pub fn set_placeholder_validation(&mut self, enable: bool) {
+        self.validate_placeholders = enable;
+    }
+
+    pub fn set_transaction_drop_policy(&mut self, policy: TransactionDropPolicy) {
+        self.drop_policy = policy;
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn split_query<'a>(&self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<(&'a str, DB::Arguments<'a>, bool), EM::OutError> {
+        let mut query = self.check_placeholders(query)?;
+        let args = query.arguments.take().unwrap_or_default();
+        Ok((query.sql(), args, query.persistent()))
+    }
+
+    pub(crate) fn check_placeholders<'a>(&self, mut query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<CheckedQuery<'a, DB>, EM::OutError> {
+        let sql = query.sql();
+        let arguments = query.take_arguments()
+            .map_err(|e| EM::map(sqlx::Error::Encode(e), format!("[{} {}]", line!(), sql).as_str()))?;
+        if self.validate_placeholders {
+            let expected = count_placeholders(sql, SqlBackend::from_db_name(DB::NAME));
+            let actual = arguments.as_ref().map(|args| args.len()).unwrap_or(0);
+            if expected != actual {
+                return Err(EM::map_parameter_mismatch(format!("parameter mismatch, expected {} actual {} sql: {}", expected, actual, sql).as_str()));
+            }
+        }
+        Ok(CheckedQuery { query, arguments })
+    }
+
+    pub(crate) async fn prepare_raw(&mut self, sql: &str) -> Result<(), sqlx::Error> {
+        self.check_not_cut_off()?;
+        self.executor().prepare(sql).await.map(|_| ())
+    }
+
+    // Sends query with the checks and bookkeeping every statement of the connection gets: the
+    // cut off and fault checks, the statement timeout, and the stats and observers. sql is the
+    // text as the caller passed it, which query may carry with the transaction tag appended, tag
+    // the operation tag of the tagged variants.
+    pub(crate) async fn run_checked<'q, Op: StatementOp<DB>, Q: 'q + Execute<'q, DB>>(&mut self, sql: &str, tag: Option<&str>, query: Q) -> Result<Op::Output, sqlx::Error> {
+        self.check_not_cut_off()?;
+        let start = self.statement_start();
+        let deadline = self.statement_deadline(sql, tag);
+        self.in_flight = true;
+        let ret = match self.inject_fault(sql).await {
+            Ok(()) => {
+                let ret = crate::clock::timeout(deadline.clone(), Op::send(self.executor(), query)).await;
+                self.cut_off(sql, deadline, ret)
+            }
+            Err(e) => Err(e),
+        };
+        self.in_flight = false;
+        self.record_statement(sql, tag, start, ret.as_ref().ok().map(Op::rows));
+        ret
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    async fn inject_fault(&mut self, sql: &str) -> Result<(), sqlx::Error> {
+        let injector = match self.pool_state.fault_injector.get() {
+            Some(injector) => injector.clone(),
+            None => return Ok(()),
+        };
+        match injector.on_statement(sql, SqlBackend::from_db_name(DB::NAME)) {
+            crate::test_util::FaultAction::Run => Ok(()),
+            crate::test_util::FaultAction::Delay(delay) => {
+                self.pool_state.clock().sleep(delay).await;
+                Ok(())
+            }
+            crate::test_util::FaultAction::Fail(e, disconnect) => {
+                if let (true, SqlConnectionType::PoolConn(conn)) = (disconnect, &mut *self.conn) {
+                    conn.close_on_drop();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    #[cfg(not(any(test, feature = "test-util")))]
+    async fn inject_fault(&mut self, _sql: &str) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    fn statement_deadline(&self, sql: &str, tag: Option<&str>) -> Option<(Arc<dyn Clock>, Duration)> {
+        self.pool_state.statement_timeout(sql, tag, SqlBackend::from_db_name(DB::NAME))
+            .map(|timeout| (self.pool_state.clock(), timeout))
+    }
+
+    // Turns a statement the deadline cut off into a timeout error. The connection is left in an
+    // unknown state, so it refuses further statements and is closed instead of returned to the
+    // pool.
+    fn cut_off<T>(&mut self, sql: &str, deadline: Option<(Arc<dyn Clock>, Duration)>, ret: Option<Result<T, sqlx::Error>>) -> Result<T, sqlx::Error> {
+        ret.unwrap_or_else(|| {
+            let timeout = deadline.map(|(_, timeout)| timeout).unwrap_or_default();
+            log::warn!("statement cut off after adaptive timeout {:?}: {}", timeout, sql);
+            self.cut_off = Some(timeout);
+            self.close_on_drop();
+            Err(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("statement timed out after {:?}", timeout))))
+        })
+    }
+
+    fn statement_start(&self) -> Option<Instant> {
+        if self.pool_state.statement_stats.get().is_some() || self.pool_state.observer.get().is_some() {
+            Some(self.pool_state.clock().now())
+        } else {
+            None
+        }
+    }
+
+    // rows is None when the statement failed. Every statement of the connection passes here,
+    // whichever method ran it, so a write in a fetch (a DML with RETURNING through query_all)
+    // invalidates the query cache as one through execute_sql does.
+    fn record_statement(&mut self, sql: &str, tag: Option<&str>, start: Option<Instant>, rows: Option<u64>) {
+        if self.in_transaction {
+            self.transaction_sql.clear();
+            self.transaction_sql.push_str(sql);
+        }
+        if let (Some(stats), Some(start)) = (self.pool_state.statement_stats.get(), start) {
+            let statement = normalize_sql(sql, SqlBackend::from_db_name(DB::NAME));
+            let key = StatementKey::new(tag, statement.as_str());
+            stats.record(key, statement, self.pool_state.clock().elapsed(start), rows);
+        }
+        if let (Some(observer), Some(start)) = (self.pool_state.observer.get(), start) {
+            observer.on_statement(&StatementEvent {
+                sql: sql.to_string(),
+                transaction_id: self.transaction_id,
+                tag: tag.map(|tag| tag.to_string()),
+                elapsed: self.pool_state.clock().elapsed(start),
+                rows,
+                // Statements after a cut off one are refused before they get here.
+                timed_out: self.cut_off.is_some(),
+            });
+        }
+        if rows.is_some() {
+            self.capture_write(sql);
+        }
+    }
+
+    // Invalidates the cached reads of the tables the statements of sql write, at commit inside
+    // a transaction.
+    pub(crate) fn capture_write(&mut self, sql: &str) {
+        let cache = match self.pool_state.query_cache.get() {
+            Some(cache) => cache,
+            None => return,
+        };
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        for statement in split_statements(sql, backend) {
+            if let Some(tables) = written_tables(statement, backend) {
+                if self.in_transaction {
+                    self.pending_writes.push(tables);
+                } else {
+                    cache.invalidate(&tables);
+                }
+            }
+        }
+    }
+
+    // Closes the underlying connection instead of returning it to the pool.
+    fn discard(mut self) {
+        self.close_on_drop();
+    }
+
+    pub async fn execute_sql<'a>(&mut self, query: sqlx::query::Query<'a, DB, <DB as Database>::Arguments<'a>>) -> Result<DB::QueryResult, EM::OutError>
+    {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<ExecuteStatement, _>(sql, None, query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    // Runs a script of ;-separated statements as one unprepared call without arguments, e.g. a
+    // schema setup file. sqlite and postgres take several statements per call, and so does mysql
+    // as sqlx connects with CLIENT_MULTI_STATEMENTS. Statements before a failing one stay applied
+    // unless the script runs in a transaction, and mysql commits implicitly on DDL even then.
+    pub async fn execute_batch(&mut self, sql: &str) -> Result<(), EM::OutError> {
+        let tagged = match self.transaction_id {
+            Some(id) if self.pool_state.tag_transactions.load(Ordering::Relaxed) => Some(tag_with_transaction(sql, id)),
+            _ => None,
+        };
+        let ret = self.run_checked::<ExecuteStatement, _>(sql, None, sqlx::raw_sql(tagged.as_deref().unwrap_or(sql))).await;
+        ret.map(|_| ()).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_one<'a>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<DB::Row, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<FetchOne, _>(sql, None, query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_all<'a>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<DB::Row>, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<FetchAll, _>(sql, None, query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    // Ok(None) when no row matches, unlike query_one nothing has to be told apart from a
+    // NotFound error, every other error maps through EM as before. Of several rows the first is
+    // returned. Runs in the open transaction when there is one.
+    pub async fn query_optional<'a>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Option<DB::Row>, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<FetchOptional, _>(sql, None, query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    // Runs an INSERT, UPDATE or DELETE with a RETURNING clause and returns the rows it produced.
+    // sqlite before 3.35 and mysql other than MariaDB 10.5+ reject the clause, see
+    // capabilities().
+    pub async fn execute_returning<'a>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<DB::Row>, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        let ret = self.run_checked::<FetchAll, _>(sql, None, query).await;
+        ret.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    // First column of the first row, e.g. of "SELECT count(*) FROM t". No row fails with
+    // NotFound as query_one does. A value T cannot be decoded from, such as text for an i64,
+    // fails through EM instead of panicking as row.get does. So does a NULL for anything but an
+    // Option, except on sqlite, which decodes it as zero or empty. count(*) decodes as i64 on
+    // every backend.
+    pub async fn query_scalar<'a, T>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<T, EM::OutError>
+    where T: for<'r> sqlx::Decode<'r, DB> + sqlx::Type<DB>,
+          usize: sqlx::ColumnIndex<DB::Row>, {
+        let sql = query.sql();
+        let row = self.query_one(query).await?;
+        row.try_get::<T, _>(0).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_scalar_optional<'a, T>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Option<T>, EM::OutError>
+    where T: for<'r> sqlx::Decode<'r, DB> + sqlx::Type<DB>,
+          usize: sqlx::ColumnIndex<DB::Row>, {
+        let sql = query.sql();
+        match self.query_optional(query).await? {
+            Some(row) => row.try_get::<T, _>(0).map(Some).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str())),
+            None => Ok(None),
+        }
+    }
+
+    // The first row decoded into T, e.g. a struct deriving sqlx::FromRow. No row fails with
+    // NotFound as query_one does, a missing column or a type mismatch maps through EM.
+    pub async fn query_one_as<'a, T>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<T, EM::OutError>
+    where T: for<'r> sqlx::FromRow<'r, DB::Row>, {
+        let sql = query.sql();
+        let row = self.query_one(query).await?;
+        T::from_row(&row).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_all_as<'a, T>(&mut self, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<T>, EM::OutError>
+    where T: for<'r> sqlx::FromRow<'r, DB::Row>, {
+        let sql = query.sql();
+        let rows = self.query_all(query).await?;
+        decode_rows::<DB, EM, T>(rows.as_slice(), sql)
+    }
+
+    // Tagged variants label the operation with a stable name instead of the raw sql, so
+    // per-operation timing does not grow with every distinct or dynamically built statement. The
+    // statement stats, and with them metrics_json and the adaptive timeout, keep one entry per
+    // tag, and observers get the tag in StatementEvent::tag.
+    pub async fn execute_sql_tagged<'a>(&mut self, tag: &str, query: sqlx::query::Query<'a, DB, <DB as Database>::Arguments<'a>>) -> Result<DB::QueryResult, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<ExecuteStatement, _>(sql, Some(tag), query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_one_tagged<'a>(&mut self, tag: &str, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<DB::Row, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<FetchOne, _>(sql, Some(tag), query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_all_tagged<'a>(&mut self, tag: &str, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Vec<DB::Row>, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<FetchAll, _>(sql, Some(tag), query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    pub async fn query_optional_tagged<'a>(&mut self, tag: &str, query: sqlx::query::Query<'a, DB, DB::Arguments<'a>>) -> Result<Option<DB::Row>, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql();
+        self.run_checked::<FetchOptional, _>(sql, Some(tag), query).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))
+    }
+
+    // Runs f in a transaction and commits, starting over in a fresh transaction when f or the
+    // commit fails with a transient error. f may run several times, so everything it does outside
+    // this connection must be idempotent. Nested in an open transaction it runs f once, the
+    // outer transaction cannot be started over from here.
+    pub async fn with_retrying_transaction<F, R>(&mut self, policy: &RetryPolicy, mut f: F) -> Result<R, EM::OutError>
+    where F: for<'c> FnMut(&'c mut Self) -> SqlFuture<'c, Result<R, EM::OutError>> {
+        let nested = self.in_transaction;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match self.transaction(&mut f).await {
+                Err(e) if attempt < policy.max_attempts && !nested && EM::is_transient(&e) => {
+                    log::warn!("transaction attempt {} failed with transient error, retry", attempt);
+                    self.pool_state.clock().sleep(policy.delay(attempt)).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    // Runs f again when it fails with a transient error such as a deadlock, up to
+    // policy.max_attempts times with the policy's delay in between. Nothing is undone between
+    // attempts, f has to be safe to run twice. An error inside an open transaction is returned
+    // right away, the transaction has to be started over as a whole, see
+    // with_retrying_transaction.
+    pub async fn with_retry<F, T>(&mut self, policy: &RetryPolicy, f: F) -> Result<T, EM::OutError>
+    where F: for<'c> FnMut(&'c mut Self) -> SqlFuture<'c, Result<T, EM::OutError>> {
+        self.with_retry_when(policy, EM::is_transient, f).await
+    }
+
+    // with_retry deciding by retryable which errors are worth another attempt.
+    pub async fn with_retry_when<F, P, T>(&mut self, policy: &RetryPolicy, retryable: P, mut f: F) -> Result<T, EM::OutError>
+    where F: for<'c> FnMut(&'c mut Self) -> SqlFuture<'c, Result<T, EM::OutError>>,
+          P: Fn(&EM::OutError) -> bool, {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match f(self).await {
+                Err(e) if attempt < policy.max_attempts && !self.in_transaction && retryable(&e) => {
+                    log::warn!("attempt {} failed with retryable error, retry", attempt);
+                    self.pool_state.clock().sleep(policy.delay(attempt)).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    // Runs f in a transaction, committing when it returns Ok and rolling back when it returns
+    // Err or panics, the panic then carries on. An error of the rollback is logged, f's error is
+    // the one returned. Nested in an open transaction it is one more level of it, see
+    // begin_transaction. f may nest further but has to end every level it begins and none it
+    // did not, otherwise its transaction is rolled back and the call fails.
+    pub async fn transaction<F, T>(&mut self, f: F) -> Result<T, EM::OutError>
+    where F: for<'c> FnOnce(&'c mut Self) -> SqlFuture<'c, Result<T, EM::OutError>> {
+        self.begin_transaction().await?;
+        let (id, depth) = (self.transaction_id, self.transaction_depth);
+        let ret = catch_unwind(f(self)).await;
+        let balanced = self.restore_level(id, depth).await;
+        match ret {
+            Ok(Ok(v)) if balanced.is_ok() => {
+                self.commit_transaction().await?;
+                Ok(v)
+            }
+            Ok(ret) => {
+                if self.transaction_id == id && self.rollback_transaction().await.is_err() {
+                    log::warn!("rollback after a failed transaction body failed");
+                }
+                Err(ret.err().or(balanced.err()).expect("failed or unbalanced body"))
+            }
+            Err(panic) => {
+                // A panic mid-statement leaves nothing to talk to, drop closes the connection.
+                if !self.in_flight && self.transaction_id == id {
+                    let _ = self.rollback_transaction().await;
+                }
+                std::panic::resume_unwind(panic)
+            }
+        }
+    }
+
+    // Puts the nesting back to the level id and depth the body of transaction got, should the
+    // body have begun levels it did not end or ended levels it did not begin. The transaction is
+    // then rollback only and an error is returned. When the body ended the transaction as a
+    // whole nothing can be put back, a transaction it began instead is rolled back.
+    async fn restore_level(&mut self, id: Option<u64>, depth: u32) -> Result<(), EM::OutError> {
+        if self.transaction_id != id {
+            if self.in_transaction {
+                self.transaction_depth = 0;
+                let _ = self.rollback_transaction().await;
+            }
+            return Err(EM::map_not_in_transaction(format!("transaction {:?} ended inside its body", id).as_str()));
+        }
+        if self.transaction_depth == depth {
+            return Ok(());
+        }
+        let msg = if self.transaction_depth > depth {
+            format!("transaction {:?} body left {} nested levels open, rollback only", id, self.transaction_depth - depth)
+        } else {
+            format!("transaction {:?} body ended {} levels it did not begin, rollback only", id, depth - self.transaction_depth)
+        };
+        self.transaction_depth = depth;
+        self.rollback_only = true;
+        Err(EM::map_rollback_only(msg.as_str()))
+    }
+
+    // The one place transactions nest, transaction and with_retrying_transaction come here as
+    // well. In an open transaction this only nests: the matching commit_transaction counts down
+    // and the outermost one commits. A nested rollback_transaction rolls nothing back yet, it
+    // makes the outermost commit roll back and fail with SqlErrorCode::RollbackOnly. Use
+    // savepoint to undo part of a transaction.
+    pub async fn begin_transaction(&mut self) -> Result<(), EM::OutError> {
+        if self.in_transaction {
+            self.transaction_depth += 1;
+            log::debug!("begin nested transaction {:?} depth {}", self.transaction_id, self.transaction_depth);
+            return Ok(());
+        }
+        self.check_not_cut_off().map_err(|e| EM::map(e, format!("[{} {}]", line!(), "begin trans").as_str()))?;
+        DB::TransactionManager::begin(self.executor()).await
+            .map_err(|e| EM::map(e, format!("[{} {}]", line!(), "begin trans").as_str()))?;
+        self.in_transaction = true;
+        self.transaction_sql.clear();
+        let id = self.pool_state.next_transaction_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.pool_state.transactions_begun.fetch_add(1, Ordering::Relaxed);
+        self.transaction_id = Some(id);
+        log::debug!("begin transaction {}", id);
+        Ok(())
+    }
+
+    // Starts a transaction the database rejects writes in: START TRANSACTION READ ONLY on mysql
+    // and postgres, on sqlite a deferred transaction with the query_only pragma set until it
+    // ends. Nested in a read transaction it counts like begin_transaction, nested in a read
+    // write one it fails with SqlErrorCode::NotInTransaction, as the caller is not in a read
+    // transaction and that one could not be made read only any more.
+    pub async fn begin_read_transaction(&mut self) -> Result<(), EM::OutError> {
+        if self.in_transaction {
+            if !self.read_transaction {
+                return Err(EM::map_not_in_transaction(format!("read transaction nested in read write transaction {:?}", self.transaction_id).as_str()));
+            }
+            return self.begin_transaction().await;
+        }
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let before = match backend {
+            SqlBackend::MySql => Some("SET TRANSACTION READ ONLY"),
+            SqlBackend::Sqlite => Some("PRAGMA query_only = ON"),
+            _ => None,
+        };
+        if let Some(sql) = before {
+            self.check_not_cut_off().map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))?;
+            self.executor().execute(sql).await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))?;
+        }
+        self.read_transaction = true;
+        if let Err(e) = self.begin_transaction().await {
+            self.end_read_transaction().await;
+            return Err(e);
+        }
+        if backend == SqlBackend::Postgres {
+            let sql = "SET TRANSACTION READ ONLY";
+            if let Err(e) = self.executor().execute(sql).await {
+                let _ = self.rollback_transaction().await;
+                return Err(EM::map(e, format!("[{} {}]", line!(), sql).as_str()));
+            }
+        }
+        Ok(())
+    }
+
+    // Lifts the query_only pragma begin_read_transaction set on sqlite, on the other backends
+    // read only ends with the transaction.
+    async fn end_read_transaction(&mut self) {
+        if std::mem::take(&mut self.read_transaction) && SqlBackend::from_db_name(DB::NAME) == SqlBackend::Sqlite {
+            if let Err(e) = self.executor().execute("PRAGMA query_only = OFF").await {
+                log::warn!("reset query_only after read transaction failed, connection closed: {}", e);
+                self.close_on_drop();
+            }
+        }
+    }
+
+    // Id of the open transaction, increasing per pool, for correlating the log lines of one
+    // transaction.
+    pub fn current_transaction_id(&self) -> Option<u64> {
+        self.transaction_id
+    }
+
+    // sql with the transaction id appended as a comment, "/* txn:42 */", when the pool tags
+    // transactions and one is open, so it shows up in server side logs such as the mysql slow
+    // log. The execution methods send a query's text as it is, build the query from this text
+    // to tag it, with persistent(false) as it differs per transaction. Observers get the id
+    // of every statement in StatementEvent::transaction_id either way.
+    pub fn tag_sql<'s>(&self, sql: &'s str) -> std::borrow::Cow<'s, str> {
+        match self.transaction_id {
+            Some(id) if self.pool_state.tag_transactions.load(Ordering::Relaxed) => tag_with_transaction(sql, id).into(),
+            _ => sql.into(),
+        }
+    }
+
+    pub async fn rollback_transaction(&mut self) -> Result<(), EM::OutError> {
+        if self.transaction_depth > 0 {
+            log::debug!("rollback nested transaction {:?} depth {}, rollback only", self.transaction_id, self.transaction_depth);
+            self.transaction_depth -= 1;
+            self.rollback_only = true;
+            return Ok(());
+        }
+        self.rollback_only = false;
+        self.commit_callbacks.clear();
+        self.savepoints.clear();
+        self.pending_writes.clear();
+        if let Some(id) = self.transaction_id.take() {
+            log::debug!("rollback transaction {}", id);
+        }
+        if !std::mem::take(&mut self.in_transaction) {
+            return Ok(());
+        }
+        self.pool_state.transactions_rolled_back.fetch_add(1, Ordering::Relaxed);
+        if self.cut_off.is_some() {
+            // The connection is closed at drop, which rolls the transaction back.
+            self.read_transaction = false;
+            return Ok(());
+        }
+        let ret = DB::TransactionManager::rollback(self.executor()).await;
+        if ret.is_err() {
+            // Queues the rollback for the next use of the connection, as dropping a sqlx
+            // Transaction does.
+            DB::TransactionManager::start_rollback(self.executor());
+        }
+        self.end_read_transaction().await;
+        ret.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "rollback trans").as_str()))
+    }
+
+    pub async fn commit_transaction(&mut self) -> Result<(), EM::OutError> {
+        if self.transaction_depth > 0 {
+            self.transaction_depth -= 1;
+            return Ok(());
+        }
+        if self.rollback_only {
+            let id = self.transaction_id;
+            if self.rollback_transaction().await.is_err() {
+                log::warn!("rollback of rollback only transaction {:?} failed", id);
+            }
+            return Err(EM::map_rollback_only(format!("transaction {:?} rolled back, a nested transaction rolled back", id).as_str()));
+        }
+        let callbacks = std::mem::take(&mut self.commit_callbacks);
+        self.savepoints.clear();
+        if let Some(id) = self.transaction_id.take() {
+            log::debug!("commit transaction {}", id);
+        }
+        if !std::mem::take(&mut self.in_transaction) {
+            Ok(())
+        } else if let Err(e) = self.check_not_cut_off() {
+            // Nothing of the transaction is committed, the close at drop rolls it back.
+            self.pending_writes.clear();
+            self.read_transaction = false;
+            self.pool_state.transactions_rolled_back.fetch_add(1, Ordering::Relaxed);
+            Err(EM::map(e, format!("[{} {}]", line!(), "commit trans").as_str()))
+        } else {
+            let ret = DB::TransactionManager::commit(self.executor()).await;
+            if ret.is_err() {
+                DB::TransactionManager::start_rollback(self.executor());
+            }
+            // Also on a failed commit, whose outcome is not known for sure.
+            let written = std::mem::take(&mut self.pending_writes);
+            if let Some(cache) = self.pool_state.query_cache.get() {
+                for tables in written.iter() {
+                    cache.invalidate(tables);
+                }
+            }
+            self.end_read_transaction().await;
+            ret.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "commit trans").as_str()))?;
+            self.pool_state.transactions_committed.fetch_add(1, Ordering::Relaxed);
+            for callback in callbacks {
+                callback().await;
+            }
+            Ok(())
+        }
+    }
+
+    // Runs f once the current transaction has committed, or right away outside a transaction.
+    // Queued callbacks are dropped on rollback, and on rollback_to_savepoint for the ones
+    // registered after that savepoint.
+    pub async fn on_commit<F, Fut>(&mut self, f: F)
+    where F: FnOnce() -> Fut + Send + 'static,
+          Fut: Future<Output = ()> + Send + 'static {
+        if !self.in_transaction {
+            f().await;
+        } else {
+            self.commit_callbacks.push(Box::new(move || Box::pin(f())));
+        }
+    }
+
+    // Nests a scope in the open transaction, closed by release_savepoint or rollback_to_savepoint.
+    // Outside a transaction the savepoint functions fail with SqlErrorCode::NotInTransaction.
+    pub async fn begin_savepoint(&mut self, name: &str) -> Result<(), EM::OutError> {
+        self.check_in_transaction("savepoint", name)?;
+        let sql = format!("SAVEPOINT {}", Self::savepoint_ident(name)?);
+        self.execute_sql(sqlx::query(sql.as_str())).await?;
+        self.savepoints.push((name.to_string(), self.commit_callbacks.len()));
+        Ok(())
+    }
+
+    // Same as begin_savepoint.
+    pub async fn savepoint(&mut self, name: &str) -> Result<(), EM::OutError> {
+        self.begin_savepoint(name).await
+    }
+
+    pub(crate) fn check_in_transaction(&self, op: &str, name: &str) -> Result<(), EM::OutError> {
+        if self.in_transaction {
+            return Ok(());
+        }
+        Err(EM::map_not_in_transaction(format!("{} {} outside a transaction", op, name).as_str()))
+    }
+
+    // Savepoint names are quoted, so any text is safe, but an empty name or a NUL is refused
+    // rather than left to the server to reject.
+    fn savepoint_ident(name: &str) -> Result<String, EM::OutError> {
+        if name.is_empty() || name.contains('\0') {
+            return Err(EM::map_parameter_mismatch(format!("invalid savepoint name {:?}", name).as_str()));
+        }
+        Ok(quote_ident(name, SqlBackend::from_db_name(DB::NAME).ident_quote()))
+    }
+
+    // The savepoint stays active after rolling back to it, as in sql.
+    pub async fn rollback_to_savepoint(&mut self, name: &str) -> Result<(), EM::OutError> {
+        self.check_in_transaction("rollback to savepoint", name)?;
+        let sql = format!("ROLLBACK TO SAVEPOINT {}", Self::savepoint_ident(name)?);
+        self.execute_sql(sqlx::query(sql.as_str())).await?;
+        if let Some(pos) = self.savepoints.iter().rposition(|(n, _)| n == name) {
+            self.commit_callbacks.truncate(self.savepoints[pos].1);
+            self.savepoints.truncate(pos + 1);
+        }
+        Ok(())
+    }
+
+    pub async fn release_savepoint(&mut self, name: &str) -> Result<(), EM::OutError> {
+        self.check_in_transaction("release savepoint", name)?;
+        let sql = format!("RELEASE SAVEPOINT {}", Self::savepoint_ident(name)?);
+        self.execute_sql(sqlx::query(sql.as_str())).await?;
+        if let Some(pos) = self.savepoints.iter().rposition(|(n, _)| n == name) {
+            self.savepoints.truncate(pos);
+        }
+        Ok(())
+    }
+
+}
+
+impl<DB: Database + HasStatementCache, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pub async fn clear_statement_cache(&mut self) -> Result<(), EM::OutError> {
+        match &mut *self.conn {
+            SqlConnectionType::PoolConn(conn) => {
+                conn.clear_cached_statements().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "clear statement cache").as_str()))
+            },
+            SqlConnectionType::Conn(conn) => {
+                conn.clear_cached_statements().await.map_err(|e| EM::map(e, format!("[{} {}]", line!(), "clear statement cache").as_str()))
+            }
+        }
+    }
+
+    // Prepared statements the connection keeps. Statements tagged with a transaction id are
+    // not kept, see tag_sql. sqlite counts a statement once its rows are sent, so the count may
+    // lag the statement that just returned.
+    pub fn cached_statements(&self) -> usize {
+        match &*self.conn {
+            SqlConnectionType::PoolConn(conn) => conn.cached_statements_size(),
+            SqlConnectionType::Conn(conn) => conn.cached_statements_size(),
+        }
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      DB::QueryResult: RowsAffected, {
+    // Returns the affected row count, or the rows-not-affected error of the backend with the
+    // actual count when it does not meet expectation.
+    pub async fn execute_expecting_rows<'a>(&mut self, query: sqlx::query::Query<'a, DB, <DB as Database>::Arguments<'a>>, expectation: RowExpectation) -> Result<u64, EM::OutError> {
+        let sql = query.sql();
+        let affected = self.execute_sql(query).await?.affected();
+        if !expectation.is_met(affected) {
+            return Err(EM::map_rows_not_affected(format!("expected {:?} rows affected, actual {} sql: {}", expectation, affected, sql).as_str()));
+        }
+        Ok(affected)
+    }
+}
+
+impl<DB: sqlx::Database,EM: ErrorMap<InError=sqlx::Error>> Drop for SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn drop(&mut self) {
+        // A panic with a transaction open may have left it half done, the rollback sqlx queues
+        // on drop is not trusted with it. Both cases close the connection instead of reusing it.
+        let poisoned = self.in_flight || (self.in_transaction && std::thread::panicking());
+        let leaked = std::mem::take(&mut self.in_transaction);
+        if leaked {
+            DB::TransactionManager::start_rollback(self.executor());
+            if self.drop_policy != TransactionDropPolicy::RollbackSilently {
+                log::warn!("connection to {} dropped with transaction {:?} open, rolled back, last statement: {}",
+                           self.target.uri, self.transaction_id, self.transaction_sql);
+            }
+        }
+        // The query_only pragma of a sqlite read transaction would outlive it on a reused
+        // connection.
+        if leaked && self.read_transaction && SqlBackend::from_db_name(DB::NAME) == SqlBackend::Sqlite {
+            self.close_on_drop();
+        }
+        if poisoned {
+            log::warn!("connection to {} dropped {}, closed instead of reused",
+                       self.target.uri, if self.in_flight { "mid-statement" } else { "in a transaction during a panic" });
+            self.pool_state.poisoned_connections.fetch_add(1, Ordering::Relaxed);
+            self.close_on_drop();
+        }
+        // Safety: conn is not used again, drop only runs once.
+        let conn = unsafe { ManuallyDrop::take(&mut self.conn) };
+        match conn {
+            SqlConnectionType::PoolConn(conn) if !runtime_available() => {
+                // Returning a pooled connection to the pool runs a task on the runtime, which is
+                // gone. The connection is taken out of the pool, freeing its slot, and closed
+                // right here: a sqlite connection's worker thread closes the file, a server
+                // connection's socket is shut. An open transaction is rolled back by the server.
+                drop(conn.detach());
+            }
+            conn => drop(conn),
+        }
+        // Raised last so the connection is still released, and never during a panic, which
+        // would abort.
+        if leaked && self.drop_policy == TransactionDropPolicy::Panic && !std::thread::panicking() {
+            panic!("connection to {} dropped with transaction {:?} open, last statement: {}",
+                   self.target.uri, self.transaction_id, self.transaction_sql);
+        }
+    }
+}
+
+// Output of fut, or the payload of a panic raised while polling it.
+async fn catch_unwind<F: Future>(fut: F) -> std::thread::Result<F::Output> {
+    let mut fut = std::pin::pin!(fut);
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(panic) => std::task::Poll::Ready(Err(panic)),
+        }
+    }).await
+}
+
+#[cfg(feature = "runtime-tokio")]
+fn block_on_own_runtime(close: impl Future<Output = bool>, uri: &str) -> bool {
+    match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime.block_on(close),
+        Err(e) => {
+            log::error!("no runtime to close pool {}: {}", uri, e);
+            false
+        }
+    }
+}
+
+// Whether a task can still be spawned from the current thread.
+#[cfg(feature = "runtime-tokio")]
+pub(crate) fn runtime_available() -> bool {
+    tokio::runtime::Handle::try_current().is_ok()
+}
+
+#[cfg(not(feature = "runtime-tokio"))]
+pub(crate) fn runtime_available() -> bool {
+    true
+}
@@ -0,0 +1,297 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use sqlx::{Database, Executor};
+use crate::clock::{runtime_clock, Clock};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::db_helper::{ErrorMap, RowsAffected, SqlConnection};
+use crate::db_helper::SqlFuture;
+use crate::errors::SqlResult;
+use crate::startup::value_text;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::value::BindValue;
+use crate::value::{IndexMap, SqlValue};
+
+pub type DualWriteStatement = (String, Vec<SqlValue>);
+
+// A pool DualWritePool sends statements to, implemented by the sqlite and mysql pools so the two
+// sides of a migration can be different backends. The sql must be valid on both.
+pub trait DualWriteTarget: Send + Sync + 'static {
+    // Runs the statements in one transaction, returning the rows they affected.
+    fn execute<'a>(&'a self, statements: &'a [DualWriteStatement]) -> SqlFuture<'a, SqlResult<u64>>;
+    fn query<'a>(&'a self, sql: &'a str, args: &'a [SqlValue]) -> SqlFuture<'a, SqlResult<Vec<IndexMap<String, SqlValue>>>>;
+
+    // Clock the replay task of a pool with this primary waits on.
+    fn clock(&self) -> Arc<dyn Clock> {
+        runtime_clock()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DualWriteOptions {
+    // Write batches waiting for the secondary beyond this are dropped and counted.
+    pub queue_capacity: usize,
+    // Share of reads, 0 to 100, also run on the secondary and compared.
+    pub read_sample_percent: u8,
+}
+
+impl Default for DualWriteOptions {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 10000,
+            read_sample_percent: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReadMismatch {
+    pub sql: String,
+    // First difference found, e.g. "row 3 column name: \"a\" vs \"b\"".
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DualWriteStats {
+    pub replayed: u64,
+    pub replay_failures: u64,
+    // Batches dropped because the queue was full.
+    pub dropped: u64,
+    pub compared_reads: u64,
+    pub mismatches: u64,
+}
+
+type MismatchCallback = Box<dyn Fn(&ReadMismatch) + Send + Sync>;
+
+struct DualWriteInner {
+    primary: Arc<dyn DualWriteTarget>,
+    secondary: Arc<dyn DualWriteTarget>,
+    options: DualWriteOptions,
+    queue: Mutex<VecDeque<Vec<DualWriteStatement>>>,
+    replay_lock: async_lock::Mutex<()>,
+    on_mismatch: Option<MismatchCallback>,
+    reads: AtomicU64,
+    replayed: AtomicU64,
+    replay_failures: AtomicU64,
+    dropped: AtomicU64,
+    compared_reads: AtomicU64,
+    mismatches: AtomicU64,
+    closed: AtomicBool,
+}
+
+// Writes to the primary and replays them to the secondary in the background, reads from the
+// primary and checks a sample of them against the secondary, for a period of dual writing while
+// moving to another backend. The secondary never fails a caller: replay failures and mismatches
+// are logged and counted. A compared read may see the secondary behind on replay, so expect some
+// mismatches right after writes.
+#[derive(Clone)]
+pub struct DualWritePool {
+    inner: Arc<DualWriteInner>,
+}
+
+impl DualWritePool {
+    pub fn new(primary: Arc<dyn DualWriteTarget>, secondary: Arc<dyn DualWriteTarget>, options: DualWriteOptions) -> Self {
+        Self::build(primary, secondary, options, None)
+    }
+
+    pub fn with_mismatch_callback(primary: Arc<dyn DualWriteTarget>, secondary: Arc<dyn DualWriteTarget>, options: DualWriteOptions,
+                                  on_mismatch: impl Fn(&ReadMismatch) + Send + Sync + 'static) -> Self {
+        Self::build(primary, secondary, options, Some(Box::new(on_mismatch)))
+    }
+
+    fn build(primary: Arc<dyn DualWriteTarget>, secondary: Arc<dyn DualWriteTarget>, options: DualWriteOptions, on_mismatch: Option<MismatchCallback>) -> Self {
+        Self {
+            inner: Arc::new(DualWriteInner {
+                primary,
+                secondary,
+                options,
+                queue: Mutex::new(VecDeque::new()),
+                replay_lock: async_lock::Mutex::new(()),
+                on_mismatch,
+                reads: AtomicU64::new(0),
+                replayed: AtomicU64::new(0),
+                replay_failures: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                compared_reads: AtomicU64::new(0),
+                mismatches: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    // Spawns the task replaying writes to the secondary until shutdown, polling the queue every
+    // 10ms of the primary's clock when it is empty. Without it replay only happens through
+    // replay_now.
+    pub fn start(self) -> Self {
+        let task = self.clone();
+        let clock = self.inner.primary.clock();
+        sqlx_core::rt::spawn(async move {
+            while !task.inner.closed.load(Ordering::SeqCst) {
+                if task.replay_now().await == 0 {
+                    clock.sleep(Duration::from_millis(10)).await;
+                }
+            }
+        });
+        self
+    }
+
+    pub async fn execute(&self, sql: &str, args: Vec<SqlValue>) -> SqlResult<u64> {
+        self.transaction(vec![(sql.to_string(), args)]).await
+    }
+
+    // Runs the statements in one transaction on the primary, the batch is replayed to the
+    // secondary once it committed.
+    pub async fn transaction(&self, statements: Vec<DualWriteStatement>) -> SqlResult<u64> {
+        let affected = self.inner.primary.execute(&statements).await?;
+        let mut queue = self.inner.queue.lock().unwrap_or_else(|e| e.into_inner());
+        if queue.len() >= self.inner.options.queue_capacity {
+            self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+            log::warn!("dual write queue full, batch of {} statements not replayed", statements.len());
+        } else {
+            queue.push_back(statements);
+        }
+        Ok(affected)
+    }
+
+    pub async fn query(&self, sql: &str, args: Vec<SqlValue>) -> SqlResult<Vec<IndexMap<String, SqlValue>>> {
+        let rows = self.inner.primary.query(sql, &args).await?;
+        let n = self.inner.reads.fetch_add(1, Ordering::Relaxed);
+        if n % 100 < u64::from(self.inner.options.read_sample_percent.min(100)) {
+            let this = self.clone();
+            let (sql, primary_rows) = (sql.to_string(), rows.clone());
+            sqlx_core::rt::spawn(async move {
+                this.compare(sql.as_str(), &args, &primary_rows).await;
+            });
+        }
+        Ok(rows)
+    }
+
+    // Replays the queued batches in order, returning how many were taken off the queue.
+    pub async fn replay_now(&self) -> usize {
+        let _guard = self.inner.replay_lock.lock().await;
+        let mut count = 0;
+        loop {
+            let batch = match self.inner.queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front() {
+                Some(batch) => batch,
+                None => return count,
+            };
+            count += 1;
+            match self.inner.secondary.execute(&batch).await {
+                Ok(_) => {
+                    self.inner.replayed.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    self.inner.replay_failures.fetch_add(1, Ordering::Relaxed);
+                    log::warn!("dual write replay of {:?} failed: {:?}", batch.first().map(|s| s.0.as_str()), e);
+                }
+            }
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.inner.queue.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn stats(&self) -> DualWriteStats {
+        DualWriteStats {
+            replayed: self.inner.replayed.load(Ordering::Relaxed),
+            replay_failures: self.inner.replay_failures.load(Ordering::Relaxed),
+            dropped: self.inner.dropped.load(Ordering::Relaxed),
+            compared_reads: self.inner.compared_reads.load(Ordering::Relaxed),
+            mismatches: self.inner.mismatches.load(Ordering::Relaxed),
+        }
+    }
+
+    // Stops the replay task after replaying what is queued.
+    pub async fn shutdown(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        self.replay_now().await;
+    }
+
+    async fn compare(&self, sql: &str, args: &[SqlValue], primary: &[IndexMap<String, SqlValue>]) {
+        self.inner.compared_reads.fetch_add(1, Ordering::Relaxed);
+        let summary = match self.inner.secondary.query(sql, args).await {
+            Ok(secondary) => match diff_rows(primary, &secondary) {
+                Some(summary) => summary,
+                None => return,
+            },
+            Err(e) => format!("secondary failed: {:?}", e),
+        };
+        self.inner.mismatches.fetch_add(1, Ordering::Relaxed);
+        log::warn!("dual write read mismatch on {}: {}", sql, summary);
+        if let Some(callback) = &self.inner.on_mismatch {
+            callback(&ReadMismatch { sql: sql.to_string(), summary });
+        }
+    }
+}
+
+// Backends return one value in different representations, an integer as Int on one side and
+// as UInt, Bool or DECIMAL text on the other, so numbers are compared as numbers.
+fn values_match(a: &SqlValue, b: &SqlValue) -> bool {
+    match (a, b) {
+        (SqlValue::Null(_), SqlValue::Null(_)) => true,
+        (SqlValue::Null(_), _) | (_, SqlValue::Null(_)) => false,
+        _ => match (a.to_f64(), b.to_f64()) {
+            (Ok(Some(x)), Ok(Some(y))) => x == y || (x - y).abs() <= f64::EPSILON * x.abs().max(y.abs()) * 4.0,
+            _ => value_text(a) == value_text(b),
+        },
+    }
+}
+
+// The first difference between two results, None when they match.
+fn diff_rows(primary: &[IndexMap<String, SqlValue>], secondary: &[IndexMap<String, SqlValue>]) -> Option<String> {
+    if primary.len() != secondary.len() {
+        return Some(format!("{} rows vs {} rows", primary.len(), secondary.len()));
+    }
+    for (i, (p, s)) in primary.iter().zip(secondary.iter()).enumerate() {
+        for (column, value) in p.iter() {
+            match s.get(column) {
+                Some(other) if values_match(value, other) => {}
+                Some(other) => return Some(format!("row {} column {}: {:?} vs {:?}", i, column, value, other)),
+                None => return Some(format!("row {} column {} missing on the secondary", i, column)),
+            }
+        }
+        if let Some(column) = s.keys().find(|c| !p.contains_key(*c)) {
+            return Some(format!("row {} column {} missing on the primary", i, column));
+        }
+    }
+    None
+}
+
+// Used by the DualWriteTarget of the sqlite and mysql pools.
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected, {
+    // A single statement runs without a transaction.
+    pub(crate) async fn execute_statements(&mut self, statements: &[DualWriteStatement]) -> Result<u64, EM::OutError> {
+        if statements.len() == 1 {
+            return self.execute_values(&statements[0].0, &statements[0].1).await;
+        }
+        self.begin_transaction().await?;
+        let mut affected = 0;
+        for (sql, args) in statements.iter() {
+            match self.execute_values(sql, args).await {
+                Ok(n) => affected += n,
+                Err(e) => {
+                    let _ = self.rollback_transaction().await;
+                    return Err(e);
+                }
+            }
+        }
+        self.commit_transaction().await?;
+        Ok(affected)
+    }
+
+    async fn execute_values(&mut self, sql: &str, args: &[SqlValue]) -> Result<u64, EM::OutError> {
+        let mut query = sqlx::query::<DB>(sql);
+        for value in args.iter() {
+            query = query.bind_value(value.clone());
+        }
+        Ok(self.execute_sql(query).await?.affected())
+    }
+}
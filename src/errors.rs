@@ -1,13 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex, RwLock};
+use log::{Level, LevelFilter};
 pub use sfo_result::err as sql_err;
 
 #[repr(u16)]
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
 pub enum SqlErrorCode {
     #[default]
     Failed,
     NotFound,
     AlreadyExists,
+    SchemaChanged,
+    Timeout,
+    ReadOnly,
+    ParameterMismatch,
+    ShuttingDown,
+    Crypto,
+    Busy,
+    Deadlock,
+    RowsNotAffected,
+    Overflow,
+    NotInTransaction,
+    Locked,
+    RollbackOnly,
+    GuardTripped,
+    Unsupported,
+    // A row lock was not granted within the server's lock wait timeout.
+    LockWaitTimeout,
+    // Input from a caller that the crate refuses before building sql, e.g. an unknown sort key.
+    InvalidArgument,
+}
+
+impl SqlErrorCode {
+    pub const ALL: [SqlErrorCode; 20] = [
+        SqlErrorCode::Failed, SqlErrorCode::NotFound, SqlErrorCode::AlreadyExists, SqlErrorCode::SchemaChanged,
+        SqlErrorCode::Timeout, SqlErrorCode::ReadOnly, SqlErrorCode::ParameterMismatch, SqlErrorCode::ShuttingDown,
+        SqlErrorCode::Crypto, SqlErrorCode::Busy, SqlErrorCode::Deadlock, SqlErrorCode::RowsNotAffected,
+        SqlErrorCode::Overflow, SqlErrorCode::NotInTransaction, SqlErrorCode::Locked, SqlErrorCode::RollbackOnly,
+        SqlErrorCode::GuardTripped, SqlErrorCode::Unsupported, SqlErrorCode::LockWaitTimeout, SqlErrorCode::InvalidArgument,
+    ];
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, SqlErrorCode::SchemaChanged | SqlErrorCode::Busy | SqlErrorCode::Deadlock | SqlErrorCode::LockWaitTimeout)
+    }
 }
 
 pub type SqlError = sfo_result::Error<SqlErrorCode>;
 pub type SqlResult<T> = sfo_result::Result<T, SqlErrorCode>;
+
+// The sqlx error a SqlError was mapped from, kept as its source, for inspecting the database
+// error behind it beyond the code and message.
+pub trait SqlErrorSource {
+    fn sqlx_error(&self) -> Option<&sqlx::Error>;
+
+    // SQLSTATE, or the extended result code on sqlite, of a database error.
+    fn database_code(&self) -> Option<String> {
+        match self.sqlx_error() {
+            Some(sqlx::Error::Database(e)) => e.code().map(|code| code.into_owned()),
+            _ => None,
+        }
+    }
+
+    // Constraint a database error was raised by, when the backend reports it.
+    fn constraint(&self) -> Option<&str> {
+        match self.sqlx_error() {
+            Some(sqlx::Error::Database(e)) => e.constraint(),
+            _ => None,
+        }
+    }
+}
+
+impl SqlErrorSource for SqlError {
+    fn sqlx_error(&self) -> Option<&sqlx::Error> {
+        std::error::Error::source(self).and_then(|e| e.downcast_ref::<sqlx::Error>())
+    }
+}
+
+// Controls the level a mapped database error is logged at, None means not logged.
+// NotFound is expected control flow and is not logged by default, AlreadyExists from a constraint
+// violation is logged at debug, error level is left to failures nobody expects.
+// max_level caps the picked level, Error by default, so expected failures logged at Debug stay
+// quiet in test suites and applications unless asked for.
+#[derive(Debug, Clone)]
+pub struct ErrorLogPolicy {
+    default_level: Option<Level>,
+    levels: HashMap<SqlErrorCode, Option<Level>>,
+    max_level: LevelFilter,
+}
+
+impl Default for ErrorLogPolicy {
+    fn default() -> Self {
+        let mut levels = HashMap::new();
+        levels.insert(SqlErrorCode::NotFound, None);
+        levels.insert(SqlErrorCode::AlreadyExists, Some(Level::Debug));
+        levels.insert(SqlErrorCode::ShuttingDown, Some(Level::Warn));
+        levels.insert(SqlErrorCode::RowsNotAffected, Some(Level::Debug));
+        Self {
+            default_level: Some(Level::Error),
+            levels,
+            max_level: LevelFilter::Error,
+        }
+    }
+}
+
+impl ErrorLogPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn default_level(mut self, level: Option<Level>) -> Self {
+        self.default_level = level;
+        self
+    }
+
+    pub fn level(mut self, code: SqlErrorCode, level: Option<Level>) -> Self {
+        self.levels.insert(code, level);
+        self
+    }
+
+    pub fn max_level(mut self, level: LevelFilter) -> Self {
+        self.max_level = level;
+        self
+    }
+
+    pub fn level_of(&self, code: SqlErrorCode) -> Option<Level> {
+        match self.levels.get(&code) {
+            Some(level) => *level,
+            None => self.default_level,
+        }
+    }
+
+    // Level an error of the code is logged at once max_level is applied, None when not logged.
+    pub fn logged_level(&self, code: SqlErrorCode) -> Option<Level> {
+        self.level_of(code).filter(|level| *level <= self.max_level)
+    }
+}
+
+static ERROR_LOG_POLICY: LazyLock<RwLock<ErrorLogPolicy>> = LazyLock::new(|| RwLock::new(ErrorLogPolicy::default()));
+
+// Replaces the process wide policy, its max_level included.
+pub fn set_error_log_policy(policy: ErrorLogPolicy) {
+    *ERROR_LOG_POLICY.write().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+pub fn error_log_policy() -> ErrorLogPolicy {
+    ERROR_LOG_POLICY.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// Sets max_level of the process wide policy, leaving the levels per code as they are.
+pub fn set_error_log_level(level: LevelFilter) {
+    ERROR_LOG_POLICY.write().unwrap_or_else(|e| e.into_inner()).max_level = level;
+}
+
+pub fn error_log_level() -> LevelFilter {
+    ERROR_LOG_POLICY.read().unwrap_or_else(|e| e.into_inner()).max_level
+}
+
+static ERROR_COUNTS: Mutex<BTreeMap<u16, u64>> = Mutex::new(BTreeMap::new());
+
+// Mapped database errors by code since start or the last reset, over every pool of the process.
+pub fn error_counts() -> Vec<(SqlErrorCode, u64)> {
+    let counts = ERROR_COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+    SqlErrorCode::ALL.iter().filter_map(|code| counts.get(&(*code as u16)).map(|n| (*code, *n))).collect()
+}
+
+pub fn reset_error_counts() {
+    ERROR_COUNTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+pub(crate) fn log_sql_error(code: SqlErrorCode, msg: &str) {
+    *ERROR_COUNTS.lock().unwrap_or_else(|e| e.into_inner()).entry(code as u16).or_insert(0) += 1;
+    let level = ERROR_LOG_POLICY.read().unwrap_or_else(|e| e.into_inner()).logged_level(code);
+    if let Some(level) = level {
+        log::log!(level, "{}", msg);
+    }
+}
@@ -0,0 +1,62 @@
+// One pool and connection type for applications written once against sqlite or mysql, the
+// backend being picked by enabling exactly one of the features default-sqlite and default-mysql.
+// Only this module depends on the choice, so retargeting an application is a feature flip as
+// long as it keeps to what both backends offer.
+//
+// The module only exists with one of the two features, so forgetting both fails where
+// sfo_sql::facade is imported.
+
+#[cfg(all(feature = "default-sqlite", feature = "default-mysql"))]
+compile_error!("sfo-sql features default-sqlite and default-mysql are mutually exclusive, enable only the backend DefaultPool should use");
+
+use std::ops::Deref;
+use crate::errors::SqlResult;
+
+#[cfg(feature = "default-sqlite")]
+use crate::sqlite as backend;
+#[cfg(all(feature = "default-mysql", not(feature = "default-sqlite")))]
+use crate::mysql as backend;
+
+pub use backend::SqlConnection as DefaultConnection;
+
+// What DefaultPool::open takes, the same for both backends.
+#[derive(Debug, Clone)]
+pub struct SqlConfig {
+    pub uri: String,
+    pub max_connections: u32,
+}
+
+impl SqlConfig {
+    pub fn new(uri: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
+            max_connections: 10,
+        }
+    }
+}
+
+// The pool of the default backend, dereferencing to it for everything but opening.
+#[derive(Clone)]
+pub struct DefaultPool(backend::SqlPool);
+
+impl DefaultPool {
+    pub async fn open(config: &SqlConfig) -> SqlResult<Self> {
+        #[cfg(feature = "default-sqlite")]
+        let pool = backend::SqlPool::open(config.uri.as_str(), config.max_connections, None).await?;
+        #[cfg(all(feature = "default-mysql", not(feature = "default-sqlite")))]
+        let pool = backend::SqlPool::open(config.uri.as_str(), config.max_connections).await?;
+        Ok(Self(pool))
+    }
+
+    pub fn into_inner(self) -> backend::SqlPool {
+        self.0
+    }
+}
+
+impl Deref for DefaultPool {
+    type Target = backend::SqlPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
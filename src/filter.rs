@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::target::SqlBackend;
+use crate::text_search::quote_ident;
+use crate::value::{BindValue, SqlValue};
+
+// Values per IN list, longer lists become several IN lists joined by OR.
+const IN_CHUNK: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LikeMode {
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone)]
+enum FilterNode {
+    Compare(String, &'static str, SqlValue),
+    Like(String, String, LikeMode),
+    In(String, Vec<SqlValue>),
+    IsNull(String, bool),
+    Between(String, SqlValue, SqlValue),
+    Group(FilterBuilder),
+}
+
+// Builds a WHERE clause from conditions on whitelisted columns, every value is a bound
+// parameter. Conditions of one builder are joined by AND, or by OR for a group made with or.
+#[derive(Debug, Clone)]
+pub struct FilterBuilder {
+    allowed: Arc<HashSet<String>>,
+    any: bool,
+    nodes: Vec<FilterNode>,
+}
+
+// Placeholder numbering of one build.
+struct Params {
+    backend: SqlBackend,
+    bound: usize,
+}
+
+impl Params {
+    // Adds value to args and returns its placeholder.
+    fn push(&self, args: &mut Vec<SqlValue>, value: SqlValue) -> String {
+        args.push(value);
+        self.backend.placeholder(self.bound + args.len())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuiltFilter {
+    // " WHERE ..." or empty when there is no condition.
+    pub sql: String,
+    pub args: Vec<SqlValue>,
+}
+
+impl BuiltFilter {
+    // Binds the arguments in order, after whatever query already has bound.
+    pub fn bind<Q: BindValue>(&self, query: Q) -> Q {
+        self.args.iter().cloned().fold(query, |q, v| q.bind_value(v))
+    }
+}
+
+impl FilterBuilder {
+    pub fn new(allowed_columns: &[&str]) -> Self {
+        Self {
+            allowed: Arc::new(allowed_columns.iter().map(|c| c.to_string()).collect()),
+            any: false,
+            nodes: Vec::new(),
+        }
+    }
+
+    fn compare(mut self, column: &str, op: &'static str, value: impl Into<SqlValue>) -> Self {
+        self.nodes.push(FilterNode::Compare(column.to_string(), op, value.into()));
+        self
+    }
+
+    pub fn eq(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.compare(column, "=", value)
+    }
+
+    pub fn ne(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.compare(column, "<>", value)
+    }
+
+    pub fn lt(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.compare(column, "<", value)
+    }
+
+    pub fn le(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.compare(column, "<=", value)
+    }
+
+    pub fn gt(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.compare(column, ">", value)
+    }
+
+    pub fn ge(self, column: &str, value: impl Into<SqlValue>) -> Self {
+        self.compare(column, ">=", value)
+    }
+
+    // text is matched literally, % and _ in it are escaped.
+    pub fn like(mut self, column: &str, text: &str, mode: LikeMode) -> Self {
+        self.nodes.push(FilterNode::Like(column.to_string(), text.to_string(), mode));
+        self
+    }
+
+    // An empty list matches no row.
+    pub fn is_in(mut self, column: &str, values: Vec<SqlValue>) -> Self {
+        self.nodes.push(FilterNode::In(column.to_string(), values));
+        self
+    }
+
+    pub fn is_null(mut self, column: &str) -> Self {
+        self.nodes.push(FilterNode::IsNull(column.to_string(), true));
+        self
+    }
+
+    pub fn is_not_null(mut self, column: &str) -> Self {
+        self.nodes.push(FilterNode::IsNull(column.to_string(), false));
+        self
+    }
+
+    pub fn between(mut self, column: &str, low: impl Into<SqlValue>, high: impl Into<SqlValue>) -> Self {
+        self.nodes.push(FilterNode::Between(column.to_string(), low.into(), high.into()));
+        self
+    }
+
+    // Adds a parenthesized group whose conditions are joined by AND.
+    pub fn and<F: FnOnce(FilterBuilder) -> FilterBuilder>(self, f: F) -> Self {
+        self.group(false, f)
+    }
+
+    // Adds a parenthesized group whose conditions are joined by OR.
+    pub fn or<F: FnOnce(FilterBuilder) -> FilterBuilder>(self, f: F) -> Self {
+        self.group(true, f)
+    }
+
+    fn group<F: FnOnce(FilterBuilder) -> FilterBuilder>(mut self, any: bool, f: F) -> Self {
+        let group = f(FilterBuilder {
+            allowed: self.allowed.clone(),
+            any,
+            nodes: Vec::new(),
+        });
+        self.nodes.push(FilterNode::Group(group));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.iter().all(|n| matches!(n, FilterNode::Group(g) if g.is_empty()))
+    }
+
+    // Placeholders are the backend's, numbered from $1 on postgres.
+    pub fn build(&self, backend: SqlBackend) -> SqlResult<BuiltFilter> {
+        self.build_after(backend, 0)
+    }
+
+    // For a filter appended to a statement that already has bound parameters before it, numbered
+    // placeholders continue after them.
+    pub fn build_after(&self, backend: SqlBackend, bound: usize) -> SqlResult<BuiltFilter> {
+        let mut args = Vec::new();
+        let sql = self.render(&Params { backend, bound }, &mut args)?;
+        Ok(BuiltFilter {
+            sql: sql.map(|s| format!(" WHERE {}", s)).unwrap_or_default(),
+            args,
+        })
+    }
+
+    fn column(&self, column: &str, backend: SqlBackend) -> SqlResult<String> {
+        if !self.allowed.contains(column) {
+            return Err(sql_err!(SqlErrorCode::InvalidArgument, "column {:?} is not allowed in filters", column));
+        }
+        Ok(quote_ident(column, backend.ident_quote()))
+    }
+
+    // None for a builder without conditions, empty groups are left out.
+    fn render(&self, params: &Params, args: &mut Vec<SqlValue>) -> SqlResult<Option<String>> {
+        let backend = params.backend;
+        let mut terms = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let term = match node {
+                FilterNode::Compare(column, op, value) => {
+                    format!("{} {} {}", self.column(column, backend)?, op, params.push(args, value.clone()))
+                }
+                FilterNode::Like(column, text, mode) => {
+                    let escaped = text.replace('!', "!!").replace('%', "!%").replace('_', "!_");
+                    let pattern = SqlValue::Text(match mode {
+                        LikeMode::Contains => format!("%{}%", escaped),
+                        LikeMode::StartsWith => format!("{}%", escaped),
+                        LikeMode::EndsWith => format!("%{}", escaped),
+                    });
+                    format!("{} LIKE {} ESCAPE '!'", self.column(column, backend)?, params.push(args, pattern))
+                }
+                FilterNode::In(column, values) => {
+                    let column = self.column(column, backend)?;
+                    if values.is_empty() {
+                        "1 = 0".to_string()
+                    } else {
+                        let lists = values.chunks(IN_CHUNK).map(|chunk| {
+                            let placeholders = chunk.iter().map(|v| params.push(args, v.clone())).collect::<Vec<_>>();
+                            format!("{} IN ({})", column, placeholders.join(", "))
+                        }).collect::<Vec<_>>();
+                        if lists.len() == 1 {
+                            lists.into_iter().next().unwrap()
+                        } else {
+                            format!("({})", lists.join(" OR "))
+                        }
+                    }
+                }
+                FilterNode::IsNull(column, true) => format!("{} IS NULL", self.column(column, backend)?),
+                FilterNode::IsNull(column, false) => format!("{} IS NOT NULL", self.column(column, backend)?),
+                FilterNode::Between(column, low, high) => {
+                    let column = self.column(column, backend)?;
+                    let low = params.push(args, low.clone());
+                    format!("{} BETWEEN {} AND {}", column, low, params.push(args, high.clone()))
+                }
+                FilterNode::Group(group) => match group.render(params, args)? {
+                    Some(sql) => format!("({})", sql),
+                    None => continue,
+                },
+            };
+            terms.push(term);
+        }
+        if terms.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(terms.join(if self.any { " OR " } else { " AND " })))
+    }
+}
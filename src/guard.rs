@@ -0,0 +1,60 @@
+use sqlx::{Database, Execute, Executor};
+use crate::db_helper::{ErrorMap, RowsAffected, SqlConnection};
+use crate::sql_lexer::{is_read, written_tables};
+use crate::target::SqlBackend;
+use crate::value::BindValue;
+
+// Most rows a write run through execute_guarded may affect before its transaction is undone.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Guard {
+    pub max_affected: u64,
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected, {
+    // Runs a write, failing with SqlErrorCode::GuardTripped when it affected more rows than
+    // guard allows. The open transaction is then rolled back, or when nested left rollback only
+    // so the outermost commit undoes it. Outside a transaction the write runs in one of its own,
+    // so it is never committed.
+    // Reads, such as a SELECT, run unguarded. Statements that are neither a read nor a write
+    // whose tables can be told, a CALL or CREATE INDEX, are not run and fail with
+    // SqlErrorCode::GuardTripped, the guard could not vouch for them.
+    pub async fn execute_guarded<'a>(&mut self, query: sqlx::query::Query<'a, DB, <DB as Database>::Arguments<'a>>, guard: Guard) -> Result<u64, EM::OutError> {
+        let sql = query.sql();
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        if is_read(sql, backend) {
+            return Ok(self.execute_sql(query).await?.affected());
+        }
+        if written_tables(sql, backend).is_none() {
+            return Err(EM::map_guard_tripped(format!("not run, the guard cannot tell what it writes: {}", sql).as_str()));
+        }
+        let own_transaction = !self.in_transaction;
+        if own_transaction {
+            self.begin_transaction().await?;
+        }
+        let affected = match self.execute_sql(query).await {
+            Ok(ret) => ret.affected(),
+            Err(e) => {
+                if own_transaction {
+                    let _ = self.rollback_transaction().await;
+                }
+                return Err(e);
+            }
+        };
+        if affected > guard.max_affected {
+            if self.transaction_depth > 0 {
+                self.rollback_only = true;
+            } else if self.rollback_transaction().await.is_err() {
+                log::warn!("rollback after a tripped guard failed: {}", sql);
+            }
+            return Err(EM::map_guard_tripped(format!("{} rows affected, guard allows {}, rolled back: {}", affected, guard.max_affected, sql).as_str()));
+        }
+        if own_transaction {
+            self.commit_transaction().await?;
+        }
+        Ok(affected)
+    }
+}
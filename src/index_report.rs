@@ -0,0 +1,52 @@
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct IndexInfo {
+    pub table: String,
+    pub name: String,
+    // Expression parts are listed as "<expr>".
+    pub columns: Vec<String>,
+    pub unique: bool,
+    // mysql only, estimated distinct values of the whole index.
+    pub cardinality: Option<u64>,
+    // mysql only, rows read through the index since server start, None when performance_schema
+    // is off or not readable.
+    pub usage: Option<u64>,
+    // Index that makes this one redundant, because this one's columns are a leading prefix of it.
+    pub redundant_with: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct IndexReport {
+    pub indexes: Vec<IndexInfo>,
+}
+
+impl IndexReport {
+    pub fn redundant(&self) -> impl Iterator<Item = &IndexInfo> {
+        self.indexes.iter().filter(|i| i.redundant_with.is_some())
+    }
+
+    // An index is redundant when its columns lead another index of the same table, unless it is
+    // unique and the other one is not, since then it still enforces a constraint. Of two
+    // indexes with the same columns and uniqueness the later one by name is flagged.
+    #[cfg(any(feature = "mysql", feature = "sqlite"))]
+    pub(crate) fn flag_redundant(&mut self) {
+        let count = self.indexes.len();
+        for i in 0..count {
+            let index = &self.indexes[i];
+            // Expressions are not compared.
+            if index.columns.is_empty() || index.columns.iter().any(|c| c == "<expr>") {
+                continue;
+            }
+            let covering = (0..count).find(|&j| {
+                let other = &self.indexes[j];
+                j != i
+                    && other.table == index.table
+                    && other.columns.len() >= index.columns.len()
+                    && other.columns.starts_with(&index.columns)
+                    && (!index.unique || (other.unique && other.columns.len() == index.columns.len()))
+                    && (other.columns.len() > index.columns.len() || other.unique != index.unique || other.name < index.name)
+            });
+            let redundant_with = covering.map(|j| self.indexes[j].name.clone());
+            self.indexes[i].redundant_with = redundant_with;
+        }
+    }
+}
@@ -1,8 +1,69 @@
+
+mod batcher;
+mod capabilities;
+mod catalog;
+mod chunked_in;
+mod clock;
+mod coalescer;
+mod config_store;
+mod constraint_audit;
 mod db_helper;
+mod dual_write;
+mod filter;
+mod guard;
+mod index_report;
+mod lock_watch;
+#[cfg(feature = "serde")]
+mod metrics;
+mod observer;
+mod outbox;
+mod page;
+mod partition;
+mod query_cache;
+mod reconcile;
+mod retention;
+mod row_hash;
+mod row_stream;
+mod sample;
+mod seed;
+mod sink;
+mod sort;
+mod spool;
+mod sql_lexer;
+mod startup;
+mod stats;
+mod switch;
+mod target;
+mod text_search;
+mod unit_of_work;
+mod value;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 #[cfg(feature = "mysql")]
 pub mod mysql;
+#[cfg(feature = "postgres")]
+pub mod postgres;
 pub mod errors;
+pub mod prelude;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "chrono")]
+pub mod datetime;
+#[cfg(any(feature = "default-sqlite", feature = "default-mysql"))]
+pub mod facade;
 
+// Kept for compatibility, turn off reexport-sqlx to keep sqlx's names out of the crate root.
+#[cfg(feature = "reexport-sqlx")]
 pub use sqlx::*;
+// What the crate's api takes or returns, available without the glob above.
+pub use sqlx;
+pub use sqlx::{query, query_as, query_scalar, Column, ColumnIndex, Database, Decode, Encode, Error, Executor, FromRow,
+               Row, Transaction, Type, TypeInfo, ValueRef};
+#[cfg(feature = "sqlite")]
+pub use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+#[cfg(feature = "mysql")]
+pub use sqlx::{MySql, MySqlConnection, MySqlPool};
+#[cfg(feature = "postgres")]
+pub use sqlx::{PgConnection, PgPool, Postgres};
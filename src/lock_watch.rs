@@ -0,0 +1,95 @@
+use std::time::Duration;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use sqlx::{Database, Executor};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::db_helper::{ErrorMap, SqlConnection, SqlFuture};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::value::BindValue;
+
+// Finds what keeps a statement waiting, from a connection other than the waiting one.
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) type LockProbe<'a, E> = dyn FnMut() -> SqlFuture<'static, Result<Vec<LockBlocker>, E>> + Send + 'a;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LockWatchOptions {
+    // How often a waiting DDL statement looks for its blockers and logs them.
+    pub poll_interval: Duration,
+    // Gives up on the statement after this wait with SqlErrorCode::Locked listing the blockers.
+    // The waiting connection is closed instead of returned to the pool.
+    pub fail_after: Option<Duration>,
+}
+
+impl Default for LockWatchOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(5),
+            fail_after: None,
+        }
+    }
+}
+
+// A session holding a lock the statement waits for. mysql only, sqlite cannot tell which
+// connection holds its database lock.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LockBlocker {
+    pub connection_id: u64,
+    pub user: String,
+    // Seconds the session has been in its current state.
+    pub time: u64,
+    pub state: String,
+    // Statement the session runs, empty for an idle session with an open transaction.
+    pub info: String,
+}
+
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) fn describe_blockers(blockers: &[LockBlocker]) -> String {
+    blockers.iter()
+        .map(|b| format!("connection {} user {} {}s {:?} {:?}", b.connection_id, b.user, b.time, b.state, b.info))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      EM::OutError: std::fmt::Debug, {
+    // Runs sql, logging every poll_interval how long it has waited and what probe found
+    // blocking it, until it finishes or fail_after passes.
+    pub(crate) async fn execute_watched(&mut self, sql: &str, options: &LockWatchOptions, probe: &mut LockProbe<'_, EM::OutError>) -> Result<(), EM::OutError> {
+        let clock = self.clock();
+        let start = clock.now();
+        let poll_interval = options.poll_interval.max(Duration::from_millis(10));
+        let mut statement = std::pin::pin!(self.execute_sql(sqlx::query::<DB>(sql)));
+        loop {
+            let wait = match options.fail_after {
+                Some(limit) => limit.saturating_sub(clock.elapsed(start)).min(poll_interval),
+                None => poll_interval,
+            };
+            if let Some(ret) = crate::clock::timeout(Some((clock.clone(), wait)), statement.as_mut()).await {
+                return ret.map(|_| ());
+            }
+
+            let waited = clock.elapsed(start);
+            let blockers = match probe().await {
+                Ok(blockers) => blockers,
+                Err(e) => {
+                    log::warn!("looking up the blockers of {} failed: {:?}", sql, e);
+                    Vec::new()
+                }
+            };
+            if blockers.is_empty() {
+                log::warn!("waiting {:?} for a lock: {}", waited, sql);
+            } else {
+                log::warn!("waiting {:?} for a lock held by {}: {}", waited, describe_blockers(&blockers), sql);
+            }
+            if let Some(limit) = options.fail_after {
+                if waited >= limit {
+                    let blocked_by = if blockers.is_empty() { "unknown".to_string() } else { describe_blockers(&blockers) };
+                    return Err(EM::map_locked(format!("gave up after waiting {:?} for a lock held by {}: {}", waited, blocked_by, sql).as_str()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::atomic::Ordering;
+use serde_json::{json, Map, Value};
+use sqlx::Executor;
+use crate::db_helper::{ErrorMap, SqlPool};
+use crate::errors::{error_counts, reset_error_counts};
+
+// Version of the metrics_json layout. Keys are only ever added within a version, a key renamed
+// or removed or a changed unit bumps it. Version 1:
+//
+// {
+//   "schema_version": 1,
+//   "pool": {"size", "idle", "read_replays", "normal_acquires", "normal_wait_us", "high_acquires",
+//            "high_wait_us", "poisoned_connections"},
+//   "transactions": {"begun", "committed", "rolled_back"},
+//   "errors": {"<SqlErrorCode name>": count, ...},
+//   "statements": [{"statement", "tag", "count", "errors", "total_us", "max_us", "p95_us", "rows"}, ...]
+// }
+//
+// errors counts the mapped errors of every pool of the process and holds only the codes seen.
+// statements is empty unless with_statement_stats was called. It has an entry per operation tag
+// for the tagged statements, tag is null for the others.
+pub const METRICS_SCHEMA_VERSION: u64 = 1;
+
+impl<DB: sqlx::Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlPool<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pub fn metrics_json(&self) -> Value {
+        let stats = self.stats();
+        let mut errors = Map::new();
+        for (code, count) in error_counts() {
+            errors.insert(format!("{:?}", code), json!(count));
+        }
+        let statements: Vec<Value> = self.statement_stats().iter().map(|s| json!({
+            "statement": s.statement,
+            "tag": s.tag,
+            "count": s.count,
+            "errors": s.errors,
+            "total_us": s.total.as_micros() as u64,
+            "max_us": s.max.as_micros() as u64,
+            "p95_us": s.p95.as_micros() as u64,
+            "rows": s.rows,
+        })).collect();
+        json!({
+            "schema_version": METRICS_SCHEMA_VERSION,
+            "pool": {
+                "size": stats.size,
+                "idle": stats.idle,
+                "read_replays": stats.read_replays,
+                "normal_acquires": stats.normal_acquires,
+                "normal_wait_us": stats.normal_wait.as_micros() as u64,
+                "high_acquires": stats.high_acquires,
+                "high_wait_us": stats.high_wait.as_micros() as u64,
+                "poisoned_connections": stats.poisoned_connections,
+            },
+            "transactions": {
+                "begun": self.state.transactions_begun.load(Ordering::Relaxed),
+                "committed": self.state.transactions_committed.load(Ordering::Relaxed),
+                "rolled_back": self.state.transactions_rolled_back.load(Ordering::Relaxed),
+            },
+            "errors": errors,
+            "statements": statements,
+        })
+    }
+
+    // Zeroes the counters of metrics_json, the error counts included, each on its own, so a
+    // snapshot taken meanwhile may see some reset and others not. size and idle are gauges and
+    // stay.
+    pub fn reset_metrics(&self) {
+        self.state.reset_counters();
+        reset_error_counts();
+    }
+}
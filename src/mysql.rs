@@ -1,10 +1,19 @@
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 use log::LevelFilter;
-use sqlx::ConnectOptions;
+use sqlx::{Column, ConnectOptions, Executor, TypeInfo, ValueRef};
 use sqlx::mysql::MySqlSslMode;
-use crate::errors::{sql_err, SqlError, SqlErrorCode, SqlResult};
+use crate::errors::{log_sql_error, sql_err, SqlError, SqlErrorCode, SqlErrorSource, SqlResult};
 pub use crate::db_helper::*;
+use crate::startup::value_text;
+use crate::text_search::{quote_ident, quote_qualified, search_terms};
+use crate::value::{named_column_error, sum_sql};
+
+#[cfg(feature = "sqlite")]
+pub mod export;
+#[cfg(feature = "sqlite")]
+pub use export::{export_to_sqlite, ExportReport, TableExportReport, TableExportSpec};
 
 pub type SqlDB = sqlx::MySql;
 pub type SqlRawConnection = sqlx::MySqlConnection;
@@ -24,67 +33,461 @@ impl ErrorMap for RawErrorToSqlError {
     fn map(e: sqlx::Error, msg: &str) -> SqlError {
         match e {
             sqlx::Error::RowNotFound => {
-                // let msg = format!("not found, {}", msg);
-                sql_err!(SqlErrorCode::NotFound, "not found")
+                let msg = format!("not found, {}", msg);
+                log_sql_error(SqlErrorCode::NotFound, msg.as_str());
+                SqlError::from((SqlErrorCode::NotFound, "not found", e))
             },
             sqlx::Error::Database(ref err) => {
                 let msg = format!("sql error: {:?} info:{}", e, msg);
-                if cfg!(test) {
-                    println!("{}", msg);
-                } else {
-                    log::error!("{}", msg);
-                }
-
-                if let Some(code) = err.code() {
-                    if code.to_string().as_str() == "23000" {
-                        return sql_err!(SqlErrorCode::AlreadyExists, "already exists");
-                    }
+                // SQLSTATE 23000 also covers foreign key and not null violations, the error
+                // number tells a duplicate key apart. Errors not from the server, such as the
+                // ones test_util::FaultInjector raises, only carry the SQLSTATE and the kind.
+                let number = err.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>().map(|e| e.number());
+                let code = match (number, err.code().as_deref()) {
+                    (Some(1062), _) | (Some(1586), _) => SqlErrorCode::AlreadyExists,
+                    (Some(1213), _) | (_, Some("40001")) => SqlErrorCode::Deadlock,
+                    (Some(1205), _) => SqlErrorCode::LockWaitTimeout,
+                    (_, Some("25006")) => SqlErrorCode::ReadOnly,
+                    (None, Some("23000")) if err.kind() == sqlx::error::ErrorKind::UniqueViolation => SqlErrorCode::AlreadyExists,
+                    (None, Some("HY000")) => SqlErrorCode::Busy,
+                    _ => SqlErrorCode::Failed,
+                };
+                log_sql_error(code, msg.as_str());
+                if code == SqlErrorCode::AlreadyExists {
+                    return SqlError::from((SqlErrorCode::AlreadyExists, "already exists", e));
                 }
-                sql_err!(SqlErrorCode::Failed, "{}", msg)
+                SqlError::from((code, msg, e))
+            }
+            sqlx::Error::Io(ref err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                let msg = format!("sql error: {} info:{}", err, msg);
+                log_sql_error(SqlErrorCode::Timeout, msg.as_str());
+                SqlError::from((SqlErrorCode::Timeout, msg, e))
+            }
+            sqlx::Error::PoolTimedOut => {
+                let msg = format!("sql error: {:?} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Timeout, msg.as_str());
+                SqlError::from((SqlErrorCode::Timeout, msg, e))
+            }
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::ColumnNotFound(_) | sqlx::Error::Decode(_) => {
+                let msg = format!("decode error: {} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Failed, msg.as_str());
+                SqlError::from((SqlErrorCode::Failed, msg, e))
             }
             _ => {
                 let msg = format!("sql error: {:?} info:{}", e, msg);
-                if cfg!(test) {
-                    println!("{}", msg);
-                } else {
-                    log::error!("{}", msg);
-                }
-                sql_err!(SqlErrorCode::Failed, "")
+                log_sql_error(SqlErrorCode::Failed, msg.as_str());
+                SqlError::from((SqlErrorCode::Failed, "", e))
             }
         }
     }
+
+    fn map_parameter_mismatch(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::ParameterMismatch, msg);
+        sql_err!(SqlErrorCode::ParameterMismatch, "{}", msg)
+    }
+
+    fn map_shutting_down(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::ShuttingDown, msg);
+        sql_err!(SqlErrorCode::ShuttingDown, "{}", msg)
+    }
+
+    fn map_rows_not_affected(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::RowsNotAffected, msg);
+        sql_err!(SqlErrorCode::RowsNotAffected, "{}", msg)
+    }
+
+    fn map_not_in_transaction(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::NotInTransaction, msg);
+        sql_err!(SqlErrorCode::NotInTransaction, "{}", msg)
+    }
+
+    fn map_locked(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::Locked, msg);
+        sql_err!(SqlErrorCode::Locked, "{}", msg)
+    }
+
+    fn map_rollback_only(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::RollbackOnly, msg);
+        sql_err!(SqlErrorCode::RollbackOnly, "{}", msg)
+    }
+
+    fn map_guard_tripped(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::GuardTripped, msg);
+        sql_err!(SqlErrorCode::GuardTripped, "{}", msg)
+    }
+
+    fn is_transient(e: &SqlError) -> bool {
+        e.code().is_retryable()
+    }
+
+    fn is_disconnect(e: &SqlError) -> bool {
+        matches!(e.sqlx_error(), Some(sqlx::Error::Io(_)) | Some(sqlx::Error::Tls(_)) | Some(sqlx::Error::WorkerCrashed))
+    }
 }
 pub type SqlPool = crate::db_helper::SqlPool<sqlx::MySql, RawErrorToSqlError>;
 pub type SqlConnection = crate::db_helper::SqlConnection<sqlx::MySql, RawErrorToSqlError>;
+pub type ConfigStore = crate::db_helper::ConfigStore<sqlx::MySql, RawErrorToSqlError>;
+pub type Outbox = crate::db_helper::Outbox<sqlx::MySql, RawErrorToSqlError>;
+pub type StatementBatcher = crate::db_helper::StatementBatcher<sqlx::MySql, RawErrorToSqlError>;
+pub type LeasedConnection = crate::db_helper::LeasedConnection<sqlx::MySql, RawErrorToSqlError>;
+pub type WriteCoalescer = crate::db_helper::WriteCoalescer<sqlx::MySql, RawErrorToSqlError>;
+pub type StatementCatalog = crate::db_helper::StatementCatalog<sqlx::MySql>;
+pub type InsertSink<'a> = crate::db_helper::InsertSink<'a, sqlx::MySql, RawErrorToSqlError>;
+pub type SwitchablePool = crate::db_helper::SwitchablePool<sqlx::MySql, RawErrorToSqlError>;
+pub type UnitOfWork = crate::db_helper::UnitOfWork<sqlx::MySql>;
+
+// mysql counts changed rows, an UPDATE writing the values a row already has affects 0 rows.
+impl RowsAffected for sqlx::mysql::MySqlQueryResult {
+    fn affected(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+impl ReplayArguments for sqlx::MySql {
+    fn replay_arguments<'q>(arguments: &Self::Arguments<'q>) -> Option<Self::Arguments<'q>> {
+        Some(arguments.clone())
+    }
+}
+
+// Placeholders the mysql protocol allows in one prepared statement.
+pub const MAX_BIND_PARAMS: usize = SqlBackend::MySql.max_bind_params();
+
+// Session time_zone of every connection of a pool.
+pub const SESSION_TIME_ZONE: &str = "+00:00";
+
+pub const DEFAULT_CHARSET: &str = "utf8mb4";
+pub const DEFAULT_COLLATION: &str = "utf8mb4_unicode_ci";
+
+#[derive(Debug, Clone)]
+pub struct MySqlOpenOptions {
+    pub max_connections: u32,
+    // Kept in the session variable @application_name of every pooled connection, DBAs can
+    // find it in performance_schema.user_variables_by_thread.
+    pub application_name: String,
+    // Connection character set and collation, forced so servers defaulting to latin1 do not
+    // mangle text.
+    pub charset: String,
+    pub collation: String,
+    // Minimum, timeouts and lifetime of the pool connections, its max_connections is replaced
+    // by the one above.
+    pub pool: PoolConfig,
+}
+
+impl Default for MySqlOpenOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            application_name: default_application_name(),
+            charset: DEFAULT_CHARSET.to_string(),
+            collation: DEFAULT_COLLATION.to_string(),
+            pool: PoolConfig::default(),
+        }
+    }
+}
 
 impl SqlPool {
 
     pub async fn open(uri: &str,
                       max_connections: u32,
     ) -> SqlResult<Self> {
-        log::info!("open pool {} max_connections {}", uri, max_connections);
+        Self::open_with_application_name(uri, max_connections, default_application_name().as_str()).await
+    }
+
+    pub async fn open_with_application_name(uri: &str,
+                                            max_connections: u32,
+                                            application_name: &str,
+    ) -> SqlResult<Self> {
+        Self::open_with_options(uri, &MySqlOpenOptions {
+            max_connections,
+            application_name: application_name.to_string(),
+            ..Default::default()
+        }).await
+    }
+
+    pub async fn open_with_config(uri: &str, config: &PoolConfig) -> SqlResult<Self> {
+        Self::open_with_options(uri, &MySqlOpenOptions {
+            max_connections: config.max_connections,
+            pool: *config,
+            ..Default::default()
+        }).await
+    }
+
+    pub async fn open_with_options(uri: &str, open_options: &MySqlOpenOptions) -> SqlResult<Self> {
+        let config = PoolConfig { max_connections: open_options.max_connections, ..open_options.pool };
+        log::info!("open pool {} {:?} application {} charset {} collation {}",
+                   uri, config, open_options.application_name, open_options.charset, open_options.collation);
         #[cfg(feature = "mysql")]
         {
-            let pool_options = sqlx::mysql::MySqlPoolOptions::new()
-                .max_connections(max_connections)
-                .acquire_timeout(Duration::from_secs(300))
-                .min_connections(0)
-                .idle_timeout(Duration::from_secs(300));
+            let application_name = open_options.application_name.clone();
+            let state = Arc::new(crate::db_helper::PoolState::default());
+            let _ = state.charset.set((open_options.charset.clone(), open_options.collation.clone()));
+            let release_state = state.clone();
+            let pool_options = config.pool_options::<sqlx::MySql>()
+                .after_connect(move |conn, _meta| {
+                    let application_name = application_name.clone();
+                    Box::pin(async move {
+                        conn.execute(sql_query("SET @application_name = ?").bind(application_name)).await?;
+                        Ok(())
+                    })
+                })
+                .after_release(move |conn, _meta| {
+                    let state = release_state.clone();
+                    Box::pin(async move {
+                        if state.clear_user_vars.load(std::sync::atomic::Ordering::Relaxed) {
+                            let mut names = state.user_var_names();
+                            // Variables set by plain SET statements are only known to the server.
+                            if let Ok(set) = sqlx::query_scalar::<_, String>(THREAD_USER_VARS_SQL).fetch_all(&mut *conn).await {
+                                names.extend(set);
+                            }
+                            names.retain(|n| n != "application_name");
+                            names.sort();
+                            names.dedup();
+                            if !names.is_empty() {
+                                let sql = format!("SET {}", names.iter().map(|n| format!("@{} = NULL", quote_ident(n, '`'))).collect::<Vec<_>>().join(", "));
+                                conn.execute(sql.as_str()).await?;
+                            }
+                        }
+                        Ok(true)
+                    })
+                });
             let mut options = sqlx::mysql::MySqlConnectOptions::from_str(uri).map_err(|e| {
                 RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str())
             })?;
             options = options.log_slow_statements(LevelFilter::Error, Duration::from_secs(1));
             options = options.log_statements(LevelFilter::Off);
-            options = options.ssl_mode(MySqlSslMode::Disabled);
+            // Datetimes are bound and read as UTC, see datetime::DateTimeRowExt, so TIMESTAMP
+            // values have to be converted to UTC whatever time_zone the uri asked for.
+            options = options.ssl_mode(MySqlSslMode::Disabled)
+                .charset(open_options.charset.as_str())
+                .collation(open_options.collation.as_str())
+                .timezone(Some(SESSION_TIME_ZONE.to_string()));
             let pool = pool_options.connect_with(options).await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?;
-            Ok(Self {
-                pool,
-                uri: uri.to_string(),
-                _em: Default::default()
-            })
+            Ok(Self::from_raw_pool_with_state(pool, uri, state))
         }
     }
 
+    // Creates a database with a unique name on the server of base_uri and opens a pool on it,
+    // so parallel tests do not see each other's tables. Drop it with drop_test_schema.
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn open_test_schema(base_uri: &str, prefix: &str, max_connections: u32) -> SqlResult<(Self, String)> {
+        let schema = crate::test_util::unique_test_name(prefix);
+        let (base, params) = match base_uri.split_once('?') {
+            Some((base, params)) => (base, Some(params)),
+            None => (base_uri, None),
+        };
+        let authority_start = base.find("://").map(|i| i + 3).unwrap_or(0);
+        let server = match base[authority_start..].find('/') {
+            Some(i) => &base[..authority_start + i],
+            None => base,
+        };
+        let uri = format!("{}/{}{}", server, schema, params.map(|p| format!("?{}", p)).unwrap_or_default());
+        {
+            let mut conn = SqlConnection::open(base_uri).await?;
+            let sql = format!("CREATE DATABASE {} DEFAULT CHARSET={}", quote_ident(schema.as_str(), '`'), DEFAULT_CHARSET);
+            conn.execute_sql(sql_query(sql.as_str())).await?;
+        }
+        Ok((Self::open(uri.as_str(), max_connections).await?, schema))
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn drop_test_schema(&self, schema: &str) -> SqlResult<()> {
+        let mut conn = self.get_conn().await?;
+        let sql = format!("DROP DATABASE IF EXISTS {}", quote_ident(schema, '`'));
+        conn.execute_sql(sql_query(sql.as_str())).await?;
+        Ok(())
+    }
+
+    // Table options for CREATE TABLE matching the connection charset, e.g.
+    // "DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci".
+    pub fn table_options(&self) -> String {
+        let (charset, collation) = self.state.charset.get().cloned()
+            .unwrap_or_else(|| (DEFAULT_CHARSET.to_string(), DEFAULT_COLLATION.to_string()));
+        format!("DEFAULT CHARSET={} COLLATE={}", charset, collation)
+    }
+
+    // Resets the user variables of a connection when it goes back to the pool, so the next
+    // borrower does not see the values of the previous one. sqlx has no COM_RESET_CONNECTION:
+    // the variables are listed from performance_schema, which also finds those set by plain SET
+    // statements, and where it is disabled only those set through set_user_var are cleared.
+    // @application_name is kept.
+    pub fn clear_user_vars_on_release(self, enable: bool) -> Self {
+        self.state.clear_user_vars.store(enable, std::sync::atomic::Ordering::Relaxed);
+        self
+    }
+
+    // Startup gate: keeps opening the pool and running SELECT 1 until the database answers.
+    pub async fn wait_until_ready(uri: &str,
+                                  max_connections: u32,
+                                  max_wait: Duration,
+    ) -> SqlResult<Self> {
+        let target = TargetInfo::parse(uri);
+        retry_until(Arc::new(RuntimeClock), format!("database {}", target.uri).as_str(), max_wait, || async {
+            let pool = Self::open(uri, max_connections).await?;
+            pool.ping().await?;
+            Ok::<_, SqlError>(pool)
+        }).await.map_err(|(e, attempt)| {
+            sql_err!(SqlErrorCode::Timeout, "database {} not ready after {} attempts: {}", target.uri, attempt, e)
+        })
+    }
+
+    // Probed on first use and cached, see Capabilities.
+    pub async fn capabilities(&self) -> SqlResult<Capabilities> {
+        if let Some(capabilities) = self.state.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let mut conn = self.get_conn().await?;
+        conn.capabilities().await
+    }
+
+    pub async fn capabilities_report(&self) -> SqlResult<String> {
+        Ok(self.capabilities().await?.report())
+    }
+
+    // Runs a DDL statement, logging the sessions holding the metadata lock it waits for. They
+    // are looked up on a second connection of the pool.
+    pub async fn execute_ddl(&self, sql: &str, options: &LockWatchOptions) -> SqlResult<()> {
+        let mut conn = self.get_conn().await?;
+        let mut probe = self.lock_probe(&mut conn).await?;
+        conn.execute_watched(sql, options, probe.as_mut()).await
+    }
+
+    async fn lock_probe(&self, conn: &mut SqlConnection) -> SqlResult<Box<LockProbe<'static, SqlError>>> {
+        let row = conn.query_one(sql_query("SELECT CONNECTION_ID() AS id")).await?;
+        let waiting_id: u64 = row.get("id");
+        let pool = self.clone();
+        Ok(Box::new(move || -> SqlFuture<'static, SqlResult<Vec<LockBlocker>>> {
+            let pool = pool.clone();
+            Box::pin(async move { pool.lock_blockers(waiting_id).await })
+        }))
+    }
+
+    // Sessions holding a lock connection waiting_id waits for: the owners of the granted
+    // metadata locks on the objects it has pending ones on. Without the performance_schema
+    // metadata lock instrument, the sessions with an open transaction are returned instead.
+    pub async fn lock_blockers(&self, waiting_id: u64) -> SqlResult<Vec<LockBlocker>> {
+        let mut conn = self.get_conn().await?;
+        let sql = "SELECT DISTINCT p.ID AS id, p.USER AS user, p.TIME AS time, p.STATE AS state, p.INFO AS info \
+                   FROM performance_schema.metadata_locks w \
+                   JOIN performance_schema.threads wt ON wt.THREAD_ID = w.OWNER_THREAD_ID \
+                   JOIN performance_schema.metadata_locks g ON g.OBJECT_TYPE = w.OBJECT_TYPE AND g.OBJECT_SCHEMA <=> w.OBJECT_SCHEMA \
+                   AND g.OBJECT_NAME <=> w.OBJECT_NAME AND g.LOCK_STATUS = 'GRANTED' AND g.OWNER_THREAD_ID <> w.OWNER_THREAD_ID \
+                   JOIN performance_schema.threads gt ON gt.THREAD_ID = g.OWNER_THREAD_ID \
+                   JOIN information_schema.PROCESSLIST p ON p.ID = gt.PROCESSLIST_ID \
+                   WHERE w.LOCK_STATUS = 'PENDING' AND wt.PROCESSLIST_ID = ?";
+        let found = if conn.capabilities().await?.performance_schema {
+            conn.query_all(sql_query(sql).bind(waiting_id)).await.ok().filter(|rows| !rows.is_empty())
+        } else {
+            None
+        };
+        let rows = match found {
+            Some(rows) => rows,
+            None => {
+                let sql = "SELECT p.ID AS id, p.USER AS user, p.TIME AS time, p.STATE AS state, p.INFO AS info \
+                           FROM information_schema.PROCESSLIST p JOIN information_schema.INNODB_TRX t ON t.trx_mysql_thread_id = p.ID \
+                           WHERE p.ID <> ?";
+                conn.query_all(sql_query(sql).bind(waiting_id)).await?
+            }
+        };
+        let mut blockers = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let map = row_to_map(row)?;
+            let int = |name: &str| map.get(name).and_then(|v| v.to_i128().ok().flatten()).unwrap_or(0) as u64;
+            let text = |name: &str| match map.get(name) {
+                Some(SqlValue::Null(_)) | None => String::new(),
+                Some(v) => value_text(v),
+            };
+            blockers.push(LockBlocker {
+                connection_id: int("id"),
+                user: text("user"),
+                time: int("time"),
+                state: text("state"),
+                info: text("info"),
+            });
+        }
+        Ok(blockers)
+    }
+
+    // Runs the checks policy enables and returns what they found. A database that cannot be
+    // reached is reported as a connectivity problem and nothing else runs.
+    pub async fn startup_report(&self, policy: &StartupPolicy) -> StartupReport {
+        let mut report = StartupReport::default();
+        let mut conn = match self.get_conn().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                report.push("connectivity", false, format!("{:?}", e));
+                return report;
+            }
+        };
+        if policy.check_connectivity {
+            match conn.query_one(sql_query("SELECT 1")).await {
+                Ok(_) => report.push("connectivity", true, "ok"),
+                Err(e) => {
+                    report.push("connectivity", false, format!("{:?}", e));
+                    return report;
+                }
+            }
+        }
+        if policy.journal_mode.is_some() {
+            log::warn!("journal mode is sqlite only, not checked");
+        }
+        for (name, expected) in policy.session_settings.iter() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                report.push("session_settings", false, format!("invalid variable name {:?}", name));
+                continue;
+            }
+            let sql = format!("SELECT @@SESSION.{} AS v", name);
+            match conn.query_one(sql_query(sql.as_str())).await.and_then(|row| row_to_map(&row)) {
+                Ok(map) => {
+                    let actual = map.get("v").map(value_text).unwrap_or_default();
+                    let passed = if name.eq_ignore_ascii_case("sql_mode") {
+                        let modes: Vec<&str> = actual.split(',').map(|m| m.trim()).collect();
+                        expected.split(',').map(|m| m.trim()).filter(|m| !m.is_empty())
+                            .all(|m| modes.iter().any(|a| a.eq_ignore_ascii_case(m)))
+                    } else {
+                        actual.eq_ignore_ascii_case(expected.as_str())
+                    };
+                    report.push("session_settings", passed, format!("{} is {}, expected {}", name, actual, expected));
+                }
+                Err(e) => report.push("session_settings", false, format!("{} {:?}", name, e)),
+            }
+        }
+        if let Some(migrations) = &policy.migrations {
+            let mut lock_watch = None;
+            if let Some(options) = policy.lock_watch {
+                match self.lock_probe(&mut conn).await {
+                    Ok(probe) => lock_watch = Some((options, probe)),
+                    Err(e) => log::warn!("migrations run without lock watch: {:?}", e),
+                }
+            }
+            if let Err(e) = conn.startup_migrations_with(migrations, &mut report, lock_watch).await {
+                report.push("migrations", false, format!("{:?}", e));
+            }
+        }
+        if let Some(expected) = &policy.schema_fingerprint {
+            match conn.schema_fingerprint().await {
+                Ok(fingerprint) => {
+                    report.push("schema_fingerprint", fingerprint == *expected, format!("fingerprint {}, expected {}", fingerprint, expected));
+                    report.schema_fingerprint = Some(fingerprint);
+                }
+                Err(e) => report.push("schema_fingerprint", false, format!("{:?}", e)),
+            }
+        }
+        report
+    }
+
+    // Same checks as startup_report, failing with one error that lists every problem found.
+    pub async fn startup_check(&self, policy: &StartupPolicy) -> SqlResult<StartupReport> {
+        self.startup_report(policy).await.into_result()
+    }
+}
+
+impl SwitchablePool {
+    pub async fn open_standby(&self,
+                              uri: &str,
+                              max_connections: u32,
+    ) -> SqlResult<()> {
+        self.prepare_standby(SqlPool::open(uri, max_connections).await?).await
+    }
 }
 
 impl SqlConnection {
@@ -93,26 +496,80 @@ impl SqlConnection {
             let mut options = sqlx::mysql::MySqlConnectOptions::from_str(uri).map_err(|e| {
                 RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str())
             })?;
-            options = options.ssl_mode(MySqlSslMode::Disabled);
+            options = options.ssl_mode(MySqlSslMode::Disabled)
+                .charset(DEFAULT_CHARSET)
+                .collation(DEFAULT_COLLATION);
             options.connect().await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?
         };
 
-        Ok(Self {
-            conn: SqlConnectionType::Conn(conn),
-            _em: Default::default(),
-            trans: None
-        })
+        Ok(Self::from_conn_type(SqlConnectionType::Conn(conn), Arc::new(TargetInfo::parse(uri))))
+    }
+
+    // Character set and collation the server uses for this connection.
+    pub async fn connection_charset(&mut self) -> SqlResult<(String, String)> {
+        let sql = "SELECT CAST(@@character_set_connection AS CHAR) AS cs, CAST(@@collation_connection AS CHAR) AS co";
+        let row = self.query_one(sql_query(sql)).await?;
+        Ok((row.get("cs"), row.get("co")))
+    }
+
+    // Probed once per pool, cached on the pool the connection came from.
+    pub async fn capabilities(&mut self) -> SqlResult<Capabilities> {
+        if let Some(capabilities) = self.pool_state.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let sql = "SELECT CAST(VERSION() AS CHAR) AS v, CAST(@@performance_schema AS SIGNED) AS ps";
+        let row = self.query_one(sql_query(sql)).await?;
+        let server_version: String = row.get("v");
+        let performance_schema: i64 = row.get("ps");
+        let version = parse_version(server_version.as_str());
+        let mariadb = server_version.contains("MariaDB");
+        let capabilities = Capabilities {
+            backend: SqlBackend::MySql,
+            returning: mariadb && version >= (10, 5, 0),
+            json_functions: if mariadb { version >= (10, 2, 7) } else { version >= (5, 7, 8) },
+            full_text_search: version >= (5, 6, 0),
+            performance_schema: performance_schema != 0,
+            wal_mode: false,
+            server_version,
+        };
+        let _ = self.pool_state.capabilities.set(capabilities.clone());
+        Ok(capabilities)
     }
+
+    pub async fn set_user_var(&mut self, name: &str, value: SqlValue) -> SqlResult<()> {
+        check_user_var_name(name)?;
+        self.execute_sql(sql_query(format!("SET @{} = ?", name).as_str()).bind_value(value)).await?;
+        self.pool_state.user_vars.lock().unwrap_or_else(|e| e.into_inner()).insert(name.to_string());
+        Ok(())
+    }
+
+    // Null(None) when the variable was never set on this connection.
+    pub async fn get_user_var(&mut self, name: &str) -> SqlResult<SqlValue> {
+        check_user_var_name(name)?;
+        let row = self.query_one(sql_query(format!("SELECT @{} AS v", name).as_str())).await?;
+        let mut map = row_to_map(&row)?;
+        Ok(map.swap_remove("v").unwrap_or(SqlValue::Null(None)))
+    }
+
+    // The table needs an AUTO_INCREMENT primary key, otherwise mysql reports no generated id.
+    // MariaDB's RETURNING would need the name of the id column, so last_insert_id is used on
+    // both, use execute_returning with an explicit clause for more than the id.
+    pub async fn insert_returning_id<'a>(&mut self, query: SqlQuery<'a>) -> SqlResult<i64> {
+        let ret = self.execute_sql(query).await?;
+        if ret.rows_affected() == 0 || ret.last_insert_id() == 0 {
+            return Err(sql_err!(SqlErrorCode::Failed, "no auto increment id generated"));
+        }
+        Ok(ret.last_insert_id() as i64)
+    }
+
     pub async fn is_column_exist(&mut self, table_name: &str, column_name: &str, db_name: Option<&str>) -> SqlResult<bool> {
         {
-            let row = if db_name.is_none() {
-                let sql = "select count(*) as c from information_schema.columns where table_schema = database() and table_name = ? and column_name = ?";
-                let row = self.query_one(sql_query(sql).bind(table_name).bind(column_name)).await?;
-                row
-            } else {
+            let row = if let Some(db_name) = db_name {
                 let sql = "select count(*) as c from information_schema.columns where table_schema = ? and table_name = ? and column_name = ?";
-                let row = self.query_one(sql_query(sql).bind(db_name.unwrap()).bind(table_name).bind(column_name)).await?;
-                row
+                self.query_one(sql_query(sql).bind(db_name).bind(table_name).bind(column_name)).await?
+            } else {
+                let sql = "select count(*) as c from information_schema.columns where table_schema = database() and table_name = ? and column_name = ?";
+                self.query_one(sql_query(sql).bind(table_name).bind(column_name)).await?
             };
             let count: i32 = row.get("c");
             if count == 0 {
@@ -123,16 +580,179 @@ impl SqlConnection {
         }
     }
 
+    // Indexes of table, or of every table of the current database, with the ones made redundant
+    // by another flagged. Usage counts need performance_schema and are left empty without it.
+    pub async fn analyze_indexes(&mut self, table: Option<&str>) -> SqlResult<IndexReport> {
+        let sql = "select table_name as tbl, index_name as idx, cast(non_unique as signed) as non_unique, column_name as col, \
+            cast(cardinality as signed) as card from information_schema.statistics \
+            where table_schema = database() and (? is null or table_name = ?) order by table_name, index_name, seq_in_index";
+        let rows = self.query_all(sql_query(sql).bind(table).bind(table)).await?;
+        let mut report = IndexReport::default();
+        for row in rows.iter() {
+            let table: String = row.get("tbl");
+            let name: String = row.get("idx");
+            let column: Option<String> = row.get("col");
+            let column = column.unwrap_or_else(|| "<expr>".to_string());
+            let cardinality: Option<i64> = row.get("card");
+            match report.indexes.last_mut() {
+                Some(index) if index.table == table && index.name == name => {
+                    index.columns.push(column);
+                    // The cardinality of the last column is the one of the whole index.
+                    index.cardinality = cardinality.map(|c| c as u64);
+                }
+                _ => report.indexes.push(IndexInfo {
+                    table,
+                    name,
+                    columns: vec![column],
+                    unique: row.get::<i64, _>("non_unique") == 0,
+                    cardinality: cardinality.map(|c| c as u64),
+                    ..Default::default()
+                }),
+            }
+        }
+
+        let sql = "select object_name as tbl, index_name as idx, cast(count_read as signed) as reads \
+            from performance_schema.table_io_waits_summary_by_index_usage \
+            where object_schema = database() and index_name is not null";
+        match self.query_all(sql_query(sql)).await {
+            Ok(rows) => {
+                for row in rows.iter() {
+                    let table: String = row.get("tbl");
+                    let name: String = row.get("idx");
+                    if let Some(index) = report.indexes.iter_mut().find(|i| i.table == table && i.name == name) {
+                        index.usage = Some(row.get::<i64, _>("reads") as u64);
+                    }
+                }
+            }
+            Err(e) => log::debug!("index usage not available: {:?}", e),
+        }
+        report.flag_redundant();
+        Ok(report)
+    }
+
+    // Sum of column over a table or over the rows of a SELECT, 0 when there are no rows.
+    // mysql sums integers as DECIMAL(65), so no overflow below 10^38.
+    pub async fn sum(&mut self, table_or_query: &str, column: &str) -> SqlResult<i128> {
+        let sql = sum_sql(table_or_query, column, SqlBackend::MySql);
+        let row = self.query_one(sql_query(sql.as_str())).await?;
+        Ok(row.get_aggregate_i128("s")?.unwrap_or(0))
+    }
+
+    // Runs sql, containing KEYS_MARKER where the key list goes as in "where id in ({keys})",
+    // once per chunk of keys small enough for the bound parameter limit, and concatenates the
+    // rows. key_of reorders the rows by the position of their key in keys.
+    pub async fn query_all_chunked_in<T, K>(&mut self, sql: &str, keys: &[K], other_args: &[SqlValue], key_of: Option<&dyn Fn(&T) -> K>) -> SqlResult<Vec<T>>
+    where T: for<'r> sqlx::FromRow<'r, SqlRowObject>,
+          K: Clone + Eq + std::hash::Hash + Into<SqlValue>, {
+        self.query_all_chunked_in_with(sql, keys, other_args, MAX_BIND_PARAMS, key_of).await
+    }
+
+
+    // Hash of the schema of the current database, stable across builds: every column with its
+    // type, nullability and key, and every index.
+    pub async fn schema_fingerprint(&mut self) -> SqlResult<String> {
+        let sql = "select 'column' as kind, table_name as tbl, column_name as name, cast(ordinal_position as signed) as pos, \
+            column_type as detail, is_nullable as extra from information_schema.columns where table_schema = database() \
+            union all \
+            select 'index', table_name, index_name, cast(seq_in_index as signed), column_name, cast(non_unique as char) \
+            from information_schema.statistics where table_schema = database() \
+            order by kind, tbl, name, pos";
+        self.schema_fingerprint_with(sql).await
+    }
+
+
+
+
+
+    // table_name may be qualified, "schema.table", otherwise it is looked up in the current database.
+    pub async fn is_table_exist(&mut self, table_name: &str) -> SqlResult<bool> {
+        let (schema, table) = match table_name.split_once('.') {
+            Some((schema, table)) => (Some(schema), table),
+            None => (None, table_name),
+        };
+        let sql = "select count(*) as c from information_schema.tables where table_schema = coalesce(?, database()) and table_name = ?";
+        let row = self.query_one(sql_query(sql).bind(schema).bind(table)).await?;
+        let count: i64 = row.get("c");
+        Ok(count > 0)
+    }
+
+    // Runs f in a transaction once every schema of databases is found on the server. f reaches
+    // the tables of other schemas by qualified names, "schema.table", and as they are all on the
+    // one connection of self, they commit or roll back together. The connection id and database()
+    // are checked again before the commit, a body that switched the current database with USE or
+    // ran on another connection is rolled back.
+    pub async fn with_databases<F, R>(&mut self, databases: &[&str], f: F) -> SqlResult<R>
+    where F: for<'c> FnOnce(&'c mut Self) -> SqlFuture<'c, SqlResult<R>> {
+        let rows = self.query_all(sql_query("select schema_name as s from information_schema.schemata")).await?;
+        let existing = rows.iter().map(|row| row.get::<String, _>("s")).collect::<Vec<_>>();
+        let missing = databases.iter().filter(|db| !existing.iter().any(|e| e == *db)).copied().collect::<Vec<_>>();
+        let session = self.current_database().await?;
+        if !missing.is_empty() {
+            return Err(sql_err!(SqlErrorCode::NotFound, "databases {} not found on the server of {}", missing.join(", "), session.1.as_deref().unwrap_or_default()));
+        }
+
+        self.begin_transaction().await?;
+        let id = self.transaction_id;
+        let ret = match self.transaction(f).await {
+            Ok(v) => match self.current_database().await {
+                Ok(after) if after == session => Ok(v),
+                Ok(after) => Err(sql_err!(SqlErrorCode::Failed, "with_databases body moved from connection {} database {:?} to connection {} database {:?}",
+                                          session.0, session.1, after.0, after.1)),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match ret {
+            Ok(v) => {
+                self.commit_transaction().await?;
+                Ok(v)
+            }
+            Err(e) => {
+                if self.transaction_id == id && self.rollback_transaction().await.is_err() {
+                    log::warn!("rollback after a failed with_databases body failed");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // The connection id and current database, database(), of this connection.
+    async fn current_database(&mut self) -> SqlResult<(u64, Option<String>)> {
+        let row = self.query_one(sql_query("select connection_id() as c, database() as d")).await?;
+        Ok((row.get("c"), row.get("d")))
+    }
+
+    // Puts replacement in place of current with one RENAME TABLE, which mysql applies atomically.
+    // current is renamed to keep_old_as, or dropped right after the swap when it is None.
+    pub async fn swap_tables(&mut self, current: &str, replacement: &str, keep_old_as: Option<&str>) -> SqlResult<()> {
+        let old = keep_old_as.map(|n| n.to_string()).unwrap_or_else(|| format!("{}_swap_old", current));
+        for table in [current, replacement] {
+            if !self.is_table_exist(table).await? {
+                return Err(sql_err!(SqlErrorCode::NotFound, "table {} not found", table));
+            }
+        }
+        if self.is_table_exist(old.as_str()).await? {
+            return Err(sql_err!(SqlErrorCode::AlreadyExists, "table {} already exists", old));
+        }
+
+        let sql = format!("RENAME TABLE {} TO {}, {} TO {}",
+                          quote_qualified(current, '`'), quote_qualified(old.as_str(), '`'),
+                          quote_qualified(replacement, '`'), quote_qualified(current, '`'));
+        self.execute_sql(sql_query(sql.as_str())).await?;
+        if keep_old_as.is_none() {
+            self.execute_sql(sql_query(format!("DROP TABLE {}", quote_qualified(old.as_str(), '`')).as_str())).await?;
+        }
+        Ok(())
+    }
+
     pub async fn is_index_exist(&mut self, table_name: &str, index_name: &str, db_name: Option<&str>) -> SqlResult<bool> {
         {
-            let row = if db_name.is_none() {
-                let sql = "select count(*) as c from information_schema.statistics where table_schema = database() and table_name = ? and index_name = ?";
-                let row = self.query_one(sql_query(sql).bind(table_name).bind(index_name)).await?;
-                row
-            } else {
+            let row = if let Some(db_name) = db_name {
                 let sql = "select count(*) as c from information_schema.statistics where table_schema = ? and table_name = ? and index_name = ?";
-                let row = self.query_one(sql_query(sql).bind(db_name.unwrap()).bind(table_name).bind(index_name)).await?;
-                row
+                self.query_one(sql_query(sql).bind(db_name).bind(table_name).bind(index_name)).await?
+            } else {
+                let sql = "select count(*) as c from information_schema.statistics where table_schema = database() and table_name = ? and index_name = ?";
+                self.query_one(sql_query(sql).bind(table_name).bind(index_name)).await?
             };
             let count: i32 = row.get("c");
             if count == 0 {
@@ -142,4 +762,184 @@ impl SqlConnection {
             }
         }
     }
+
+    // Adds a FULLTEXT index ft_<table> over columns.
+    pub async fn create_search_index(&mut self, table: &str, columns: &[&str], options: &TextSearchOptions) -> SqlResult<()> {
+        let index = format!("ft_{}", table);
+        if self.is_index_exist(table, index.as_str(), None).await? {
+            return Ok(());
+        }
+        let cols = columns.iter().map(|c| quote_ident(c, '`')).collect::<Vec<_>>().join(", ");
+        let mut sql = format!("ALTER TABLE {} ADD FULLTEXT INDEX {} ({})", quote_ident(table, '`'), quote_ident(index.as_str(), '`'), cols);
+        if let Some(parser) = options.parser.as_ref() {
+            sql.push_str(format!(" WITH PARSER {}", quote_ident(parser, '`')).as_str());
+        }
+        self.execute_sql(sql_query(sql.as_str())).await?;
+        Ok(())
+    }
+
+    // Every term must match, returns the primary key with the boolean mode relevance,
+    // higher is more relevant; it is only comparable within one result set.
+    pub async fn search(&mut self, table: &str, query_text: &str, limit: u32) -> SqlResult<Vec<(i64, f64)>> {
+        let terms = search_terms(query_text);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let expr = terms.iter().map(|t| format!("+\"{}\"", t)).collect::<Vec<_>>().join(" ");
+
+        let sql = "select column_name as c from information_schema.statistics where table_schema = database() and table_name = ? and index_name = ? order by seq_in_index";
+        let rows = self.query_all(sql_query(sql).bind(table).bind(format!("ft_{}", table))).await?;
+        if rows.is_empty() {
+            return Err(sql_err!(SqlErrorCode::NotFound, "no search index on {}", table));
+        }
+        let cols = rows.iter().map(|row| quote_ident(row.get::<String, _>("c").as_str(), '`')).collect::<Vec<_>>().join(", ");
+        let sql = "select column_name as c from information_schema.statistics where table_schema = database() and table_name = ? and index_name = 'PRIMARY' order by seq_in_index";
+        let row = self.query_one(sql_query(sql).bind(table)).await?;
+        let id = quote_ident(row.get::<String, _>("c").as_str(), '`');
+
+        let sql = format!("SELECT CAST({} AS SIGNED) AS id, MATCH({}) AGAINST (? IN BOOLEAN MODE) AS score FROM {} WHERE MATCH({}) AGAINST (? IN BOOLEAN MODE) ORDER BY score DESC LIMIT ?",
+                          id, cols, quote_ident(table, '`'), cols);
+        let rows = self.query_all(sql_query(sql.as_str()).bind(expr.as_str()).bind(expr.as_str()).bind(limit)).await?;
+        Ok(rows.iter().map(|row| (row.get("id"), row.get("score"))).collect())
+    }
+
+    pub async fn drop_search_index(&mut self, table: &str) -> SqlResult<()> {
+        let index = format!("ft_{}", table);
+        if !self.is_index_exist(table, index.as_str(), None).await? {
+            return Ok(());
+        }
+        let sql = format!("ALTER TABLE {} DROP INDEX {}", quote_ident(table, '`'), quote_ident(index.as_str(), '`'));
+        self.execute_sql(sql_query(sql.as_str())).await?;
+        Ok(())
+    }
+
+}
+
+// User variables of the current connection, mysql 5.7 and MariaDB 10.5 with performance_schema on.
+const THREAD_USER_VARS_SQL: &str = "SELECT variable_name FROM performance_schema.user_variables_by_thread \
+    WHERE thread_id = (SELECT thread_id FROM performance_schema.threads WHERE processlist_id = CONNECTION_ID())";
+
+// Names are spliced into the sql, so only the characters mysql allows unquoted are accepted.
+fn check_user_var_name(name: &str) -> SqlResult<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.') {
+        return Err(sql_err!(SqlErrorCode::Failed, "invalid user variable name {}", name));
+    }
+    Ok(())
+}
+
+// Reads every column in order. Decimal and temporal types are returned as their text form, dates
+// as "YYYY-MM-DD", DATETIME and TIMESTAMP as "YYYY-MM-DD HH:MM:SS[.ffffff]" and TIME as
+// "[-]HH:MM:SS[.ffffff]", the way mysql prints them.
+pub fn row_to_map(row: &SqlRowObject) -> SqlResult<IndexMap<String, SqlValue>> {
+    row.to_map().map_err(|e| RawErrorToSqlError::map(e, format!("[{} row_to_map]", line!()).as_str()))
+}
+
+impl RowToMap for sqlx::mysql::MySqlRow {
+    fn to_map(&self) -> Result<IndexMap<String, SqlValue>, sqlx::Error> {
+        let mut map = IndexMap::with_capacity(self.len());
+        for (i, column) in self.columns().iter().enumerate() {
+            map.insert(column.name().to_string(), column_value(self, i)?);
+        }
+        Ok(map)
+    }
+}
+
+fn column_value(row: &SqlRowObject, i: usize) -> Result<SqlValue, sqlx::Error> {
+    let column = &row.columns()[i];
+    let raw = row.try_get_raw(i)?;
+    if raw.is_null() {
+        return Ok(SqlValue::Null(None));
+    }
+    let type_name = raw.type_info().name().to_string();
+    let ret = match type_name.as_str() {
+        "BOOLEAN" => row.try_get::<bool, _>(i).map(SqlValue::Bool),
+        "TINYINT" | "SMALLINT" | "INT" | "MEDIUMINT" | "BIGINT" => row.try_get::<i64, _>(i).map(SqlValue::Int),
+        "YEAR" => row.try_get_unchecked::<i64, _>(i).map(SqlValue::Int),
+        "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "INT UNSIGNED" | "MEDIUMINT UNSIGNED" | "BIGINT UNSIGNED" => row.try_get::<u64, _>(i).map(SqlValue::UInt),
+        "FLOAT" => row.try_get::<f32, _>(i).map(|v| SqlValue::Float(v as f64)),
+        "DOUBLE" => row.try_get::<f64, _>(i).map(SqlValue::Float),
+        "CHAR" | "VARCHAR" | "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" | "ENUM" | "SET" | "JSON" | "DECIMAL" => row.try_get_unchecked::<String, _>(i).map(SqlValue::Text),
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" => row.try_get::<Vec<u8>, _>(i).map(SqlValue::Blob),
+        "BIT" => row.try_get_unchecked::<Vec<u8>, _>(i).map(SqlValue::Blob),
+        "DATE" | "DATETIME" | "TIMESTAMP" => row.try_get_unchecked::<Vec<u8>, _>(i).map(|v| SqlValue::Text(format_date_time(&v, type_name == "DATE"))),
+        "TIME" => row.try_get::<sqlx::mysql::types::MySqlTime, _>(i).map(|v| SqlValue::Text(format_time(&v))),
+        _ => {
+            log::warn!("column {} has unsupported type {}, read as text", column.name(), type_name);
+            row.try_get_unchecked::<Vec<u8>, _>(i).map(|v| SqlValue::Text(String::from_utf8_lossy(&v).to_string()))
+        }
+    };
+    ret.map_err(|e| named_column_error(e, column.name()))
+}
+
+// Prepared statements return temporal values in the binary protocol, a length byte followed by
+// year (u16 le), month, day, hour, minute, second and microseconds (u32 le), the fields past the
+// length left out when zero. Text protocol results already are the text form.
+fn format_date_time(bytes: &[u8], date_only: bool) -> String {
+    if bytes.first().map(|len| *len as usize + 1 != bytes.len()).unwrap_or(true) {
+        return String::from_utf8_lossy(bytes).to_string();
+    }
+    let mut fields = [0u32; 7];
+    let body = &bytes[1..];
+    if body.len() >= 4 {
+        fields[0] = u16::from_le_bytes([body[0], body[1]]) as u32;
+        fields[1] = body[2] as u32;
+        fields[2] = body[3] as u32;
+    }
+    if body.len() >= 7 {
+        fields[3] = body[4] as u32;
+        fields[4] = body[5] as u32;
+        fields[5] = body[6] as u32;
+    }
+    if body.len() >= 11 {
+        fields[6] = u32::from_le_bytes([body[7], body[8], body[9], body[10]]);
+    }
+    let date = format!("{:04}-{:02}-{:02}", fields[0], fields[1], fields[2]);
+    if date_only {
+        return date;
+    }
+    let mut text = format!("{} {:02}:{:02}:{:02}", date, fields[3], fields[4], fields[5]);
+    if fields[6] != 0 {
+        text.push_str(format!(".{:06}", fields[6]).as_str());
+    }
+    text
+}
+
+fn format_time(time: &sqlx::mysql::types::MySqlTime) -> String {
+    let sign = if time.is_negative() { "-" } else { "" };
+    let mut text = format!("{}{:02}:{:02}:{:02}", sign, time.hours(), time.minutes(), time.seconds());
+    if time.microseconds() != 0 {
+        text.push_str(format!(".{:06}", time.microseconds()).as_str());
+    }
+    text
+}
+
+impl DualWriteTarget for SqlPool {
+    fn execute<'a>(&'a self, statements: &'a [DualWriteStatement]) -> SqlFuture<'a, SqlResult<u64>> {
+        Box::pin(async move {
+            let mut conn = self.get_conn().await?;
+            conn.execute_statements(statements).await
+        })
+    }
+
+    fn query<'a>(&'a self, sql: &'a str, args: &'a [SqlValue]) -> SqlFuture<'a, SqlResult<Vec<IndexMap<String, SqlValue>>>> {
+        Box::pin(async move {
+            let mut conn = self.get_conn().await?;
+            let mut query = sql_query(sql);
+            for value in args.iter() {
+                query = query.bind_value(value.clone());
+            }
+            conn.query_all(query).await?.iter().map(row_to_map).collect()
+        })
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        SqlPool::clock(self)
+    }
+}
+
+impl AggregateRowExt for sqlx::mysql::MySqlRow {
+    fn aggregate_value(&self, col: &str) -> SqlResult<SqlValue> {
+        let column = self.try_column(col).map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), col).as_str()))?;
+        column_value(self, column.ordinal()).map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), col).as_str()))
+    }
 }
@@ -0,0 +1,291 @@
+// Copies selected mysql tables into a new standalone sqlite file, for clients that need an
+// offline snapshot of server data.
+//
+// Column types are mapped as follows:
+//   TINYINT..BIGINT, BOOL, YEAR      -> INTEGER
+//   BIGINT UNSIGNED                  -> INTEGER, values above i64::MAX are stored as decimal TEXT
+//   FLOAT, DOUBLE                    -> REAL
+//   DECIMAL, NUMERIC                 -> TEXT, the exact decimal string, REAL would lose digits
+//   DATE                             -> TEXT 'YYYY-MM-DD'
+//   DATETIME, TIMESTAMP              -> TEXT 'YYYY-MM-DD HH:MM:SS[.ffffff]', TIMESTAMP in the
+//                                       session time zone of src. sqlite date functions accept both.
+//   TIME                             -> TEXT 'HH:MM:SS[.ffffff]'
+//   CHAR, VARCHAR, *TEXT, ENUM, SET, JSON -> TEXT
+//   BINARY, VARBINARY, *BLOB, BIT    -> BLOB
+//   anything else                    -> TEXT
+// NOT NULL and the primary key are kept, other indexes are created after the rows are loaded
+// as <table>_<index>. FULLTEXT and SPATIAL indexes and indexes on columns left out are skipped.
+
+use std::path::Path;
+use sqlx::Row;
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::sqlite;
+use crate::text_search::quote_ident;
+use super::{row_to_map, sql_query, BindValue, InsertSinkOptions, SeedConflict, SqlConnection, SqlValue};
+
+// Rows read from mysql per query, each chunk is written to sqlite in its own transaction.
+const EXPORT_CHUNK_ROWS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct TableExportSpec {
+    pub table: String,
+    // Condition appended as WHERE, with its ? placeholders bound to filter_args.
+    pub filter: Option<String>,
+    pub filter_args: Vec<SqlValue>,
+    // All columns when not set.
+    pub columns: Option<Vec<String>>,
+}
+
+impl TableExportSpec {
+    pub fn new(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            filter: None,
+            filter_args: Vec::new(),
+            columns: None,
+        }
+    }
+
+    pub fn filter(mut self, filter: &str, args: Vec<SqlValue>) -> Self {
+        self.filter = Some(filter.to_string());
+        self.filter_args = args;
+        self
+    }
+
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TableExportReport {
+    pub table: String,
+    pub rows: u64,
+    pub indexes: u32,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ExportReport {
+    pub tables: Vec<TableExportReport>,
+}
+
+struct ExportColumn {
+    name: String,
+    data_type: String,
+    nullable: bool,
+}
+
+struct ExportIndex {
+    name: String,
+    unique: bool,
+    columns: Vec<String>,
+}
+
+fn sqlite_type(data_type: &str) -> &'static str {
+    match data_type {
+        "tinyint" | "smallint" | "mediumint" | "int" | "integer" | "bigint" | "bool" | "boolean" | "year" => "INTEGER",
+        "float" | "double" | "real" => "REAL",
+        "binary" | "varbinary" | "tinyblob" | "blob" | "mediumblob" | "longblob" | "bit" => "BLOB",
+        _ => "TEXT",
+    }
+}
+
+fn is_temporal(data_type: &str) -> bool {
+    matches!(data_type, "date" | "datetime" | "timestamp" | "time")
+}
+
+// Reads the tables from src inside one transaction, so all of them come from the same snapshot
+// under the default REPEATABLE READ isolation. dest_path must not exist yet.
+pub async fn export_to_sqlite(src: &mut SqlConnection, dest_path: impl AsRef<Path>, tables: &[TableExportSpec]) -> SqlResult<ExportReport> {
+    let dest_path = dest_path.as_ref();
+    if dest_path.exists() {
+        return Err(sql_err!(SqlErrorCode::AlreadyExists, "export target {} already exists", dest_path.display()));
+    }
+    let dest_uri = sqlite::SqliteUriBuilder::new().path(dest_path).create().build();
+    let mut dest = sqlite::SqlConnection::open(dest_uri.as_str()).await?;
+
+    let own_trans = !src.in_transaction;
+    if own_trans {
+        src.begin_transaction().await?;
+    }
+    let mut report = ExportReport::default();
+    for spec in tables.iter() {
+        let ret = export_table(src, &mut dest, spec).await;
+        match ret {
+            Ok(table_report) => report.tables.push(table_report),
+            Err(e) => {
+                if own_trans {
+                    let _ = src.rollback_transaction().await;
+                }
+                return Err(e);
+            }
+        }
+    }
+    if own_trans {
+        src.rollback_transaction().await?;
+    }
+
+    dest.execute_sql(sqlite::sql_query("PRAGMA optimize")).await?;
+    Ok(report)
+}
+
+async fn load_columns(src: &mut SqlConnection, spec: &TableExportSpec) -> SqlResult<Vec<ExportColumn>> {
+    let sql = "select column_name as name, lower(data_type) as data_type, is_nullable as nullable from information_schema.columns \
+        where table_schema = database() and table_name = ? order by ordinal_position";
+    let rows = src.query_all(sql_query(sql).bind(spec.table.as_str())).await?;
+    let mut columns: Vec<ExportColumn> = rows.iter().map(|row| ExportColumn {
+        name: row.get("name"),
+        data_type: row.get("data_type"),
+        nullable: row.get::<String, _>("nullable") == "YES",
+    }).collect();
+    if columns.is_empty() {
+        return Err(sql_err!(SqlErrorCode::NotFound, "table {} not found", spec.table));
+    }
+
+    if let Some(subset) = &spec.columns {
+        let mut selected = Vec::with_capacity(subset.len());
+        for name in subset.iter() {
+            match columns.iter().position(|c| &c.name == name) {
+                Some(pos) => selected.push(columns.swap_remove(pos)),
+                None => return Err(sql_err!(SqlErrorCode::NotFound, "column {} not found in table {}", name, spec.table)),
+            }
+        }
+        columns = selected;
+    }
+    Ok(columns)
+}
+
+async fn load_indexes(src: &mut SqlConnection, table: &str) -> SqlResult<Vec<ExportIndex>> {
+    let sql = "select index_name as name, cast(non_unique as signed) as non_unique, column_name as col from information_schema.statistics \
+        where table_schema = database() and table_name = ? and index_type not in ('FULLTEXT', 'SPATIAL') order by index_name, seq_in_index";
+    let rows = src.query_all(sql_query(sql).bind(table)).await?;
+    let mut indexes: Vec<ExportIndex> = Vec::new();
+    for row in rows.iter() {
+        let name: String = row.get("name");
+        // Functional index parts have no column name.
+        let column: Option<String> = row.get("col");
+        let column = column.unwrap_or_default();
+        match indexes.last_mut() {
+            Some(index) if index.name == name => index.columns.push(column),
+            _ => indexes.push(ExportIndex {
+                name,
+                unique: row.get::<i64, _>("non_unique") == 0,
+                columns: vec![column],
+            }),
+        }
+    }
+    Ok(indexes)
+}
+
+async fn export_table(src: &mut SqlConnection, dest: &mut sqlite::SqlConnection, spec: &TableExportSpec) -> SqlResult<TableExportReport> {
+    let columns = load_columns(src, spec).await?;
+    let indexes = load_indexes(src, spec.table.as_str()).await?;
+    let exported = |index: &ExportIndex| index.columns.iter().all(|c| columns.iter().any(|col| &col.name == c));
+    let primary = indexes.iter().find(|i| i.name == "PRIMARY");
+
+    let mut defs: Vec<String> = columns.iter().map(|c| {
+        format!("{} {}{}", quote_ident(c.name.as_str(), '"'), sqlite_type(c.data_type.as_str()), if c.nullable { "" } else { " NOT NULL" })
+    }).collect();
+    if let Some(primary) = primary.filter(|p| exported(p)) {
+        defs.push(format!("PRIMARY KEY ({})", primary.columns.iter().map(|c| quote_ident(c, '"')).collect::<Vec<_>>().join(", ")));
+    }
+    let sql = format!("CREATE TABLE {} ({})", quote_ident(spec.table.as_str(), '"'), defs.join(", "));
+    dest.execute_sql(sqlite::sql_query(sql.as_str())).await?;
+
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+    let options = InsertSinkOptions {
+        batch_size: EXPORT_CHUNK_ROWS,
+        max_age: std::time::Duration::MAX,
+        transactional: true,
+        conflict: SeedConflict::Fail,
+        key_columns: Vec::new(),
+    };
+    let mut sink = sqlite::InsertSink::new(&mut *dest, spec.table.as_str(), names.as_slice(), options)?;
+
+    let mut select: Vec<String> = columns.iter().map(|c| {
+        let name = quote_ident(c.name.as_str(), '`');
+        if is_temporal(c.data_type.as_str()) {
+            format!("CAST({} AS CHAR) AS {}", name, name)
+        } else {
+            name
+        }
+    }).collect();
+    let table = quote_ident(spec.table.as_str(), '`');
+    let filter = spec.filter.as_ref().map(|f| format!("({})", f));
+    match primary {
+        // Chunks are read in primary key order, each one after the last key of the previous, so a
+        // chunk costs the same however deep into the table it is.
+        Some(primary) => {
+            // Key columns left out of the export are still read, under an alias, to page on.
+            let keys: Vec<String> = primary.columns.iter().enumerate().map(|(i, c)| {
+                if names.contains(&c.as_str()) {
+                    c.clone()
+                } else {
+                    let alias = format!("export_key_{}", i);
+                    select.push(format!("{} AS {}", quote_ident(c, '`'), quote_ident(alias.as_str(), '`')));
+                    alias
+                }
+            }).collect();
+            let key_list = primary.columns.iter().map(|c| quote_ident(c, '`')).collect::<Vec<_>>().join(", ");
+            let after = format!("({}) > ({})", key_list, vec!["?"; keys.len()].join(", "));
+            let first_sql = format!("SELECT {} FROM {}{} ORDER BY {} LIMIT ?", select.join(", "), table,
+                                    filter.as_ref().map(|f| format!(" WHERE {}", f)).unwrap_or_default(), key_list);
+            let next_sql = format!("SELECT {} FROM {} WHERE {} ORDER BY {} LIMIT ?", select.join(", "), table,
+                                   filter.iter().cloned().chain(std::iter::once(after)).collect::<Vec<_>>().join(" AND "), key_list);
+            let mut last_key: Option<Vec<SqlValue>> = None;
+            loop {
+                let mut query = sql_query(if last_key.is_some() { next_sql.as_str() } else { first_sql.as_str() });
+                for arg in spec.filter_args.iter() {
+                    query = query.bind_value(arg.clone());
+                }
+                for value in last_key.iter().flatten() {
+                    query = query.bind_value(value.clone());
+                }
+                let rows = src.query_all(query.bind(EXPORT_CHUNK_ROWS as i64)).await?;
+                for row in rows.iter() {
+                    let mut map = row_to_map(row)?;
+                    last_key = Some(keys.iter().map(|k| map.get(k.as_str()).cloned().unwrap_or(SqlValue::Null(None))).collect());
+                    map.truncate(names.len());
+                    sink.send(map.into_values().collect()).await?;
+                }
+                if rows.len() < EXPORT_CHUNK_ROWS {
+                    break;
+                }
+            }
+        }
+        // Without a primary key there is no order to page on, the rows are streamed from one query.
+        None => {
+            let sql = format!("SELECT {} FROM {}{}", select.join(", "), table, filter.map(|f| format!(" WHERE {}", f)).unwrap_or_default());
+            let mut query = sql_query(sql.as_str());
+            for arg in spec.filter_args.iter() {
+                query = query.bind_value(arg.clone());
+            }
+            let mut rows = src.query_stream(query)?;
+            while let Some(row) = rows.next().await {
+                sink.send(row_to_map(&row?)?.into_values().collect()).await?;
+            }
+        }
+    }
+    let sink_report = sink.close().await;
+    if let Some(failed) = sink_report.errors.into_iter().next() {
+        return Err(failed.error);
+    }
+
+    let mut created = 0;
+    for index in indexes.iter().filter(|i| i.name != "PRIMARY" && exported(i)) {
+        let sql = format!("CREATE {}INDEX {} ON {} ({})",
+                          if index.unique { "UNIQUE " } else { "" },
+                          quote_ident(format!("{}_{}", spec.table, index.name).as_str(), '"'),
+                          quote_ident(spec.table.as_str(), '"'),
+                          index.columns.iter().map(|c| quote_ident(c, '"')).collect::<Vec<_>>().join(", "));
+        dest.execute_sql(sqlite::sql_query(sql.as_str())).await?;
+        created += 1;
+    }
+
+    Ok(TableExportReport {
+        table: spec.table.clone(),
+        rows: sink_report.rows_written,
+        indexes: created,
+    })
+}
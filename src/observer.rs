@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// One statement run through a connection of a pool that has an observer, see
+// SqlPool::with_observer.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StatementEvent {
+    // As the caller passed it, without the transaction tag.
+    pub sql: String,
+    // Id of the transaction the statement ran in, see SqlConnection::current_transaction_id.
+    // The same id is in the text of tag_sql and execute_batch when the pool tags transactions.
+    pub transaction_id: Option<u64>,
+    // Operation tag the statement ran with, see SqlConnection::query_one_tagged.
+    pub tag: Option<String>,
+    pub elapsed: Duration,
+    // Rows returned, None when the statement failed.
+    pub rows: Option<u64>,
+    // The adaptive timeout cut the statement off, see SqlPool::with_adaptive_timeout. The
+    // connection refuses further statements.
+    pub timed_out: bool,
+}
+
+// Called after every statement of the pool, on the task that ran it, so it has to be quick.
+pub trait StatementObserver: Send + Sync {
+    fn on_statement(&self, event: &StatementEvent);
+}
+
+// Observer keeping the most recent statements in memory, to look at what a transaction ran
+// once it went wrong.
+pub struct StatementJournal {
+    capacity: usize,
+    events: Mutex<VecDeque<StatementEvent>>,
+}
+
+impl StatementJournal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            events: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Oldest first.
+    pub fn events(&self) -> Vec<StatementEvent> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    pub fn transaction(&self, transaction_id: u64) -> Vec<StatementEvent> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner()).iter()
+            .filter(|event| event.transaction_id == Some(transaction_id))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+impl StatementObserver for StatementJournal {
+    fn on_statement(&self, event: &StatementEvent) {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+    }
+}
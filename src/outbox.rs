@@ -0,0 +1,223 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, RowsAffected, SqlConnection, SqlPool};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, RowToMap, SqlValue};
+
+pub const DEFAULT_OUTBOX_TABLE: &str = "_sfo_sql_outbox";
+
+static NEXT_CLAIM: AtomicU64 = AtomicU64::new(0);
+
+// An event claimed by poll. It stays invisible to other pollers until the visibility timeout
+// passes, by then the relay has to have called mark_done or mark_failed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub topic: String,
+    pub payload: String,
+    // Claims so far, this one included.
+    pub attempts: u32,
+    claim_token: String,
+}
+
+// Events written in the transaction of the data they describe and relayed afterwards, so an
+// event is published if and only if its transaction committed. Relays may deliver an event
+// twice when one outlives its visibility timeout, consumers have to be idempotent.
+// Visibility is measured with the wall clock of the relay processes, which should agree.
+pub struct Outbox<DB: Database, EM: ErrorMap<InError = sqlx::Error>> {
+    table: String,
+    _types: PhantomData<fn() -> (DB, EM)>,
+}
+
+impl<DB: Database, EM: ErrorMap<InError = sqlx::Error>> Clone for Outbox<DB, EM> {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> Outbox<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected,
+      DB::Row: RowToMap, {
+    // The outbox in DEFAULT_OUTBOX_TABLE, created when missing.
+    pub async fn ensure(conn: &mut SqlConnection<DB, EM>) -> Result<Self, EM::OutError> {
+        Self::ensure_table(conn, DEFAULT_OUTBOX_TABLE).await
+    }
+
+    pub async fn ensure_table(conn: &mut SqlConnection<DB, EM>, table: &str) -> Result<Self, EM::OutError> {
+        let outbox = Self {
+            table: table.to_string(),
+            _types: PhantomData,
+        };
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let quoted = outbox.quoted_table();
+        let (schema, name) = match table.rsplit_once('.') {
+            Some((schema, name)) => (Some(schema), name),
+            None => (None, table),
+        };
+        let index = quote_ident(format!("{}_available", name).as_str(), quote);
+        let columns = "topic VARCHAR(191) NOT NULL, available_at BIGINT NOT NULL, claim_token VARCHAR(64) NULL, attempts INT NOT NULL DEFAULT 0";
+        match backend {
+            SqlBackend::MySql => {
+                let sql = format!("CREATE TABLE IF NOT EXISTS {} (id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY, payload LONGTEXT NOT NULL, {}, \
+                                   INDEX {} (available_at, id))", quoted, columns, index);
+                conn.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+            }
+            _ => {
+                let id = if backend == SqlBackend::Postgres { "id BIGSERIAL PRIMARY KEY" } else { "id INTEGER PRIMARY KEY" };
+                let sql = format!("CREATE TABLE IF NOT EXISTS {} ({}, payload TEXT NOT NULL, {})", quoted, id, columns);
+                conn.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+                // sqlite qualifies the index with the schema, postgres creates it in the schema of the table.
+                let sql = match schema {
+                    Some(schema) if backend == SqlBackend::Sqlite => format!("CREATE INDEX IF NOT EXISTS {}.{} ON {} (available_at, id)",
+                                                                             quote_qualified(schema, quote), index, quote_ident(name, quote)),
+                    _ => format!("CREATE INDEX IF NOT EXISTS {} ON {} (available_at, id)", index, quoted),
+                };
+                conn.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+            }
+        }
+        Ok(outbox)
+    }
+
+    pub fn table(&self) -> &str {
+        self.table.as_str()
+    }
+
+    fn quoted_table(&self) -> String {
+        quote_qualified(self.table.as_str(), SqlBackend::from_db_name(DB::NAME).ident_quote())
+    }
+
+    // Adds an event to the open transaction of conn, it becomes visible to poll when that
+    // transaction commits and is gone with it on rollback. Outside a transaction it fails with
+    // SqlErrorCode::NotInTransaction, the event would be published whatever happens to the data.
+    pub async fn enqueue(&self, conn: &mut SqlConnection<DB, EM>, topic: &str, payload_json: &str) -> Result<(), EM::OutError> {
+        conn.check_in_transaction("outbox enqueue", topic)?;
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let sql = format!("INSERT INTO {} (topic, payload, available_at, attempts) VALUES ({}, {}, {}, 0)",
+                          self.quoted_table(), backend.placeholder(1), backend.placeholder(2), backend.placeholder(3));
+        conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+            .bind_value(SqlValue::Text(topic.to_string()))
+            .bind_value(SqlValue::Text(payload_json.to_string()))
+            .bind_value(SqlValue::Int(now_millis()))).await?;
+        Ok(())
+    }
+
+    // Claims up to batch events, oldest first, hiding them from other pollers for
+    // visibility_timeout. mysql and postgres lock the candidates with FOR UPDATE SKIP LOCKED
+    // (mysql 8.0, MariaDB 10.6), so concurrent pollers skip each other's batch instead of waiting
+    // on it. sqlite claims with a single UPDATE, serialized by its write lock.
+    pub async fn poll(&self, pool: &SqlPool<DB, EM>, batch: usize, visibility_timeout: Duration) -> Result<Vec<OutboxMessage>, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let table = self.quoted_table();
+        let now = now_millis();
+        let hidden_until = now.saturating_add(visibility_timeout.as_millis() as i64);
+        let token = claim_token();
+        let mut conn = pool.get_conn().await?;
+        if backend == SqlBackend::Sqlite {
+            let sql = format!("UPDATE {} SET claim_token = ?, available_at = ?, attempts = attempts + 1 \
+                               WHERE id IN (SELECT id FROM {} WHERE available_at <= ? ORDER BY id LIMIT {})", table, table, batch.max(1));
+            conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+                .bind_value(SqlValue::Text(token.clone()))
+                .bind_value(SqlValue::Int(hidden_until))
+                .bind_value(SqlValue::Int(now))).await?;
+            let sql = format!("SELECT id, topic, payload, attempts FROM {} WHERE claim_token = ? ORDER BY id", table);
+            let rows = conn.query_all(sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Text(token.clone()))).await?;
+            return rows.iter().map(|row| self.message(row, token.as_str(), 0)).collect();
+        }
+
+        conn.begin_transaction().await?;
+        let sql = format!("SELECT id, topic, payload, attempts FROM {} WHERE available_at <= {} ORDER BY id LIMIT {} FOR UPDATE SKIP LOCKED",
+                          table, backend.placeholder(1), batch.max(1));
+        let rows = match conn.query_all(sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Int(now))).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                let _ = conn.rollback_transaction().await;
+                return Err(e);
+            }
+        };
+        let messages = match rows.iter().map(|row| self.message(row, token.as_str(), 1)).collect::<Result<Vec<_>, _>>() {
+            Ok(messages) => messages,
+            Err(e) => {
+                let _ = conn.rollback_transaction().await;
+                return Err(e);
+            }
+        };
+        if !messages.is_empty() {
+            let ids = (0..messages.len()).map(|i| backend.placeholder(i + 3)).collect::<Vec<_>>().join(", ");
+            let sql = format!("UPDATE {} SET claim_token = {}, available_at = {}, attempts = attempts + 1 WHERE id IN ({})",
+                              table, backend.placeholder(1), backend.placeholder(2), ids);
+            let query = messages.iter().fold(sqlx::query::<DB>(sql.as_str())
+                                                 .bind_value(SqlValue::Text(token.clone()))
+                                                 .bind_value(SqlValue::Int(hidden_until)),
+                                             |q, m| q.bind_value(SqlValue::Int(m.id)));
+            if let Err(e) = conn.execute_sql(query).await {
+                let _ = conn.rollback_transaction().await;
+                return Err(e);
+            }
+        }
+        conn.commit_transaction().await?;
+        Ok(messages)
+    }
+
+    // Removes a delivered event. false when its claim had expired and another poller claimed it
+    // since, the event is then delivered again by that poller.
+    pub async fn mark_done(&self, conn: &mut SqlConnection<DB, EM>, message: &OutboxMessage) -> Result<bool, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let sql = format!("DELETE FROM {} WHERE id = {} AND claim_token = {}", self.quoted_table(), backend.placeholder(1), backend.placeholder(2));
+        let ret = conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+            .bind_value(SqlValue::Int(message.id))
+            .bind_value(SqlValue::Text(message.claim_token.clone()))).await?;
+        Ok(ret.affected() > 0)
+    }
+
+    // Releases the claim, the event is polled again once retry_after has passed. false as for
+    // mark_done.
+    pub async fn mark_failed(&self, conn: &mut SqlConnection<DB, EM>, message: &OutboxMessage, retry_after: Duration) -> Result<bool, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let sql = format!("UPDATE {} SET claim_token = NULL, available_at = {} WHERE id = {} AND claim_token = {}",
+                          self.quoted_table(), backend.placeholder(1), backend.placeholder(2), backend.placeholder(3));
+        let ret = conn.execute_sql(sqlx::query::<DB>(sql.as_str())
+            .bind_value(SqlValue::Int(now_millis().saturating_add(retry_after.as_millis() as i64)))
+            .bind_value(SqlValue::Int(message.id))
+            .bind_value(SqlValue::Text(message.claim_token.clone()))).await?;
+        Ok(ret.affected() > 0)
+    }
+
+    // attempts_added is what the claim still has to add to the attempts read from row.
+    fn message(&self, row: &DB::Row, token: &str, attempts_added: u32) -> Result<OutboxMessage, EM::OutError> {
+        let mut row = map_row::<EM, _>(row, self.table.as_str())?;
+        let text = |value: Option<SqlValue>| match value {
+            Some(SqlValue::Text(v)) => v,
+            Some(SqlValue::Blob(v)) => String::from_utf8_lossy(v.as_slice()).to_string(),
+            _ => String::new(),
+        };
+        let id = row.shift_remove("id").and_then(|v| v.to_i128().ok().flatten()).unwrap_or(0);
+        let attempts = row.shift_remove("attempts").and_then(|v| v.to_i128().ok().flatten()).unwrap_or(0);
+        Ok(OutboxMessage {
+            id: id as i64,
+            topic: text(row.shift_remove("topic")),
+            payload: text(row.shift_remove("payload")),
+            attempts: attempts as u32 + attempts_added,
+            claim_token: token.to_string(),
+        })
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+}
+
+// Unique per claim across the processes sharing the outbox.
+fn claim_token() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:x}-{:x}-{:x}", std::process::id(), nanos, NEXT_CLAIM.fetch_add(1, Ordering::Relaxed))
+}
@@ -0,0 +1,60 @@
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, SqlConnection};
+use crate::sort::SortOrder;
+use crate::target::SqlBackend;
+use crate::value::{BindValue, SqlValue};
+
+#[derive(Debug, Clone)]
+pub struct Page<R> {
+    pub rows: Vec<R>,
+    // Rows of the whole result, across pages.
+    pub total: u64,
+    // From 0.
+    pub page: u64,
+    pub page_size: u64,
+}
+
+impl<R> Page<R> {
+    pub fn page_count(&self) -> u64 {
+        self.total.div_ceil(self.page_size.max(1))
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.page + 1 < self.page_count()
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Page page, from 0, of the rows of sql with args bound, page_size rows per page, together
+    // with the row count of the whole result. sql has no ORDER BY or LIMIT of its own, the order
+    // comes from sort, which should end with a unique key for pages not to overlap. The count
+    // and the page are two statements, run them in a transaction for a consistent pair.
+    pub async fn query_page(&mut self, sql: &str, args: &[SqlValue], sort: Option<&SortOrder>, page: u64, page_size: u64) -> Result<Page<DB::Row>, EM::OutError>
+    where i64: for<'r> sqlx::Decode<'r, DB> + sqlx::Type<DB>,
+          usize: sqlx::ColumnIndex<DB::Row>, {
+        let page_size = page_size.max(1);
+        let count_sql = format!("SELECT COUNT(*) FROM ({}) page_source", sql);
+        let mut query = sqlx::query::<DB>(count_sql.as_str());
+        for value in args.iter() {
+            query = query.bind_value(value.clone());
+        }
+        let total: i64 = self.query_scalar(query).await?;
+
+        let order = sort.map(|s| s.to_sql(SqlBackend::from_db_name(DB::NAME))).unwrap_or_default();
+        let page_sql = format!("{}{} LIMIT {} OFFSET {}", sql, order, page_size, page.saturating_mul(page_size));
+        let mut query = sqlx::query::<DB>(page_sql.as_str());
+        for value in args.iter() {
+            query = query.bind_value(value.clone());
+        }
+        let rows = self.query_all(query).await?;
+        Ok(Page {
+            rows,
+            total: total.max(0) as u64,
+            page,
+            page_size,
+        })
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use sqlx::{ColumnIndex, Database, Decode, Executor, Row, Type};
+use crate::db_helper::{ErrorMap, SqlConnection};
+use crate::filter::BuiltFilter;
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, IndexMap, SqlValue};
+
+// Replaced in the create sql of a PartitionRouter by the quoted name of the partition.
+pub const TABLE_MARKER: &str = "{table}";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PartitionScheme {
+    // base_2024_05, by a UTC time in unix seconds.
+    Monthly,
+    // base_2024_05_17, by a UTC time in unix seconds.
+    Daily,
+    // base_h0 .. base_h<n-1>, by any integer key modulo n.
+    HashModulo(u32),
+}
+
+// Routes rows of one logical table to tables of its own per month, day or hash bucket. Partition
+// names are made from base and the key only, so nothing a caller passes as data ever ends up in
+// identifier position. Partitions are created from create_sql, a CREATE TABLE statement with
+// TABLE_MARKER where the table name goes.
+pub struct PartitionRouter {
+    base: String,
+    scheme: PartitionScheme,
+    create_sql: String,
+    // Partitions this router created or found, so ensure_partition runs its DDL once per process.
+    ensured: Mutex<HashSet<String>>,
+}
+
+impl PartitionRouter {
+    pub fn new(base: &str, scheme: PartitionScheme, create_sql: &str) -> Self {
+        Self {
+            base: base.to_string(),
+            scheme,
+            create_sql: create_sql.to_string(),
+            ensured: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn scheme(&self) -> PartitionScheme {
+        self.scheme
+    }
+
+    pub fn partition_name(&self, key: i64) -> String {
+        match self.scheme {
+            PartitionScheme::Monthly => {
+                let (year, month, _) = civil_from_unix(key);
+                format!("{}_{:04}_{:02}", self.base, year, month)
+            }
+            PartitionScheme::Daily => {
+                let (year, month, day) = civil_from_unix(key);
+                format!("{}_{:04}_{:02}_{:02}", self.base, year, month, day)
+            }
+            PartitionScheme::HashModulo(n) => format!("{}_h{}", self.base, key.rem_euclid(n.max(1) as i64)),
+        }
+    }
+
+    // Partitions holding keys from..=to in key order, whether they exist or not. Every bucket
+    // for HashModulo, a range of keys says nothing about their hashes.
+    pub fn partitions_between(&self, from: i64, to: i64) -> Vec<String> {
+        match self.scheme {
+            PartitionScheme::HashModulo(n) => (0..n.max(1) as i64).map(|bucket| self.partition_name(bucket)).collect(),
+            _ if from > to => Vec::new(),
+            PartitionScheme::Daily => {
+                let last = to.div_euclid(86400);
+                (from.div_euclid(86400)..=last).map(|day| self.partition_name(day * 86400)).collect()
+            }
+            PartitionScheme::Monthly => {
+                let (mut year, mut month, _) = civil_from_unix(from);
+                let (last_year, last_month, _) = civil_from_unix(to);
+                let mut names = Vec::new();
+                while (year, month) <= (last_year, last_month) {
+                    names.push(format!("{}_{:04}_{:02}", self.base, year, month));
+                    if month == 12 {
+                        year += 1;
+                        month = 1;
+                    } else {
+                        month += 1;
+                    }
+                }
+                names
+            }
+        }
+    }
+}
+
+impl PartitionRouter {
+    // Creates the partition of key when missing and returns its name.
+    pub async fn ensure_partition<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>>(&self, conn: &mut SqlConnection<DB, EM>, key: i64) -> Result<String, EM::OutError>
+    where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+          for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+        let name = self.partition_name(key);
+        if self.ensured.lock().unwrap_or_else(|e| e.into_inner()).contains(&name) {
+            return Ok(name);
+        }
+        let table = quote_qualified(name.as_str(), SqlBackend::from_db_name(DB::NAME).ident_quote());
+        let sql = self.create_sql.replace(TABLE_MARKER, table.as_str());
+        conn.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+        self.ensured.lock().unwrap_or_else(|e| e.into_inner()).insert(name.clone());
+        Ok(name)
+    }
+
+    // Inserts row, column names to values, into the partition of key, creating it when missing.
+    pub async fn insert_routed<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>>(&self, conn: &mut SqlConnection<DB, EM>, key: i64,
+                                                                                          row: &IndexMap<String, SqlValue>) -> Result<DB::QueryResult, EM::OutError>
+    where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+          for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+          for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+        let name = self.ensure_partition(conn, key).await?;
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let columns = row.keys().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+        let values = (1..=row.len()).map(|n| backend.placeholder(n)).collect::<Vec<_>>().join(", ");
+        let sql = format!("INSERT INTO {} ({}) VALUES ({})", quote_qualified(name.as_str(), quote), columns, values);
+        let query = row.values().cloned().fold(sqlx::query::<DB>(sql.as_str()), |q, v| q.bind_value(v));
+        conn.execute_sql(query).await
+    }
+
+    // Rows matching filter in the existing partitions of keys from..=to, queried one partition
+    // after the other and concatenated in key order. filter should also bound the key column,
+    // the partitions at both ends hold keys outside the range.
+    pub async fn query_range<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>>(&self, conn: &mut SqlConnection<DB, EM>, from: i64, to: i64,
+                                                                                        filter: Option<&BuiltFilter>) -> Result<Vec<DB::Row>, EM::OutError>
+    where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+          for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+          for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+          String: for<'r> Decode<'r, DB> + Type<DB>,
+          usize: ColumnIndex<DB::Row>, {
+        let existing = self.existing_partitions(conn).await?;
+        let quote = SqlBackend::from_db_name(DB::NAME).ident_quote();
+        let mut rows = Vec::new();
+        for name in self.partitions_between(from, to) {
+            if !existing.contains(&name) {
+                continue;
+            }
+            let sql = format!("SELECT * FROM {}{}", quote_qualified(name.as_str(), quote), filter.map(|f| f.sql.as_str()).unwrap_or(""));
+            let mut query = sqlx::query::<DB>(sql.as_str());
+            if let Some(filter) = filter {
+                query = filter.bind(query);
+            }
+            rows.extend(conn.query_all(query).await?);
+        }
+        Ok(rows)
+    }
+
+    async fn existing_partitions<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>>(&self, conn: &mut SqlConnection<DB, EM>) -> Result<HashSet<String>, EM::OutError>
+    where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+          for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+          for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+          String: for<'r> Decode<'r, DB> + Type<DB>,
+          usize: ColumnIndex<DB::Row>, {
+        let sql = match SqlBackend::from_db_name(DB::NAME) {
+            SqlBackend::MySql => "SELECT CAST(table_name AS CHAR) FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name LIKE ?",
+            SqlBackend::Postgres => "SELECT CAST(tablename AS TEXT) FROM pg_tables WHERE schemaname = current_schema() AND tablename LIKE $1",
+            _ => "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE ?",
+        };
+        let pattern = format!("{}\\_%", self.base.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        let sql = if SqlBackend::from_db_name(DB::NAME) == SqlBackend::Sqlite {
+            format!("{} ESCAPE '\\'", sql)
+        } else {
+            sql.to_string()
+        };
+        let rows = conn.query_all(sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Text(pattern))).await?;
+        let mut names = HashSet::with_capacity(rows.len());
+        for row in rows.iter() {
+            let name: String = row.try_get(0).map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))?;
+            names.insert(name);
+        }
+        Ok(names)
+    }
+}
+
+// Year, month and day of a UTC time in unix seconds, proleptic Gregorian.
+fn civil_from_unix(secs: i64) -> (i64, u32, u32) {
+    let z = secs.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,323 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use log::LevelFilter;
+use sqlx::{Column, ConnectOptions, Execute, Row, TypeInfo, ValueRef};
+use crate::errors::{log_sql_error, sql_err, SqlError, SqlErrorCode, SqlErrorSource, SqlResult};
+use crate::sql_lexer::has_returning;
+pub use crate::db_helper::*;
+use crate::value::named_column_error;
+
+// Postgres numbers its placeholders, $1, $2 and so on. The helpers that build their statements
+// with ? placeholders, such as UnitOfWork or InsertSink, are not available here.
+
+pub type SqlDB = sqlx::Postgres;
+pub type SqlRawConnection = sqlx::PgConnection;
+pub type SqlRowObject = <sqlx::Postgres as sqlx::Database>::Row;
+pub type SqlTransaction<'a> = sqlx::Transaction<'a, sqlx::Postgres>;
+pub type SqlQuery<'a> = sqlx::query::Query<'a, sqlx::Postgres, <sqlx::Postgres as sqlx::Database>::Arguments<'a>>;
+pub type RawSqlPool = sqlx::PgPool;
+pub type SqlArguments<'a> = <sqlx::Postgres as sqlx::Database>::Arguments<'a>;
+
+#[derive(Clone)]
+pub struct RawErrorToSqlError;
+
+impl ErrorMap for RawErrorToSqlError {
+    type OutError = SqlError;
+    type InError = sqlx::Error;
+
+    fn map(e: sqlx::Error, msg: &str) -> SqlError {
+        match e {
+            sqlx::Error::RowNotFound => {
+                let msg = format!("not found, {}", msg);
+                log_sql_error(SqlErrorCode::NotFound, msg.as_str());
+                SqlError::from((SqlErrorCode::NotFound, "not found", e))
+            },
+            sqlx::Error::Database(ref err) => {
+                let msg = format!("sql error: {:?} info:{}", e, msg);
+                let code = match err.code().as_deref() {
+                    Some("23505") => SqlErrorCode::AlreadyExists,
+                    Some("40001") | Some("40P01") => SqlErrorCode::Deadlock,
+                    Some("55P03") => SqlErrorCode::Busy,
+                    Some("25006") => SqlErrorCode::ReadOnly,
+                    Some("57014") => SqlErrorCode::Timeout,
+                    _ => SqlErrorCode::Failed,
+                };
+                log_sql_error(code, msg.as_str());
+                if code == SqlErrorCode::AlreadyExists {
+                    return SqlError::from((SqlErrorCode::AlreadyExists, "already exists", e));
+                }
+                SqlError::from((code, msg, e))
+            }
+            sqlx::Error::Io(ref err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                let msg = format!("sql error: {} info:{}", err, msg);
+                log_sql_error(SqlErrorCode::Timeout, msg.as_str());
+                SqlError::from((SqlErrorCode::Timeout, msg, e))
+            }
+            sqlx::Error::PoolTimedOut => {
+                let msg = format!("sql error: {:?} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Timeout, msg.as_str());
+                SqlError::from((SqlErrorCode::Timeout, msg, e))
+            }
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::ColumnNotFound(_) | sqlx::Error::Decode(_) => {
+                let msg = format!("decode error: {} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Failed, msg.as_str());
+                SqlError::from((SqlErrorCode::Failed, msg, e))
+            }
+            _ => {
+                let msg = format!("sql error: {:?} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Failed, msg.as_str());
+                SqlError::from((SqlErrorCode::Failed, "", e))
+            }
+        }
+    }
+
+    fn map_parameter_mismatch(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::ParameterMismatch, msg);
+        sql_err!(SqlErrorCode::ParameterMismatch, "{}", msg)
+    }
+
+    fn map_shutting_down(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::ShuttingDown, msg);
+        sql_err!(SqlErrorCode::ShuttingDown, "{}", msg)
+    }
+
+    fn map_rows_not_affected(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::RowsNotAffected, msg);
+        sql_err!(SqlErrorCode::RowsNotAffected, "{}", msg)
+    }
+
+    fn map_not_in_transaction(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::NotInTransaction, msg);
+        sql_err!(SqlErrorCode::NotInTransaction, "{}", msg)
+    }
+
+    fn map_locked(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::Locked, msg);
+        sql_err!(SqlErrorCode::Locked, "{}", msg)
+    }
+
+    fn map_rollback_only(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::RollbackOnly, msg);
+        sql_err!(SqlErrorCode::RollbackOnly, "{}", msg)
+    }
+
+    fn map_guard_tripped(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::GuardTripped, msg);
+        sql_err!(SqlErrorCode::GuardTripped, "{}", msg)
+    }
+
+    fn is_transient(e: &SqlError) -> bool {
+        e.code().is_retryable()
+    }
+
+    fn is_disconnect(e: &SqlError) -> bool {
+        matches!(e.sqlx_error(), Some(sqlx::Error::Io(_)) | Some(sqlx::Error::Tls(_)) | Some(sqlx::Error::WorkerCrashed))
+    }
+}
+pub type SqlPool = crate::db_helper::SqlPool<sqlx::Postgres, RawErrorToSqlError>;
+pub type SqlConnection = crate::db_helper::SqlConnection<sqlx::Postgres, RawErrorToSqlError>;
+pub type ConfigStore = crate::db_helper::ConfigStore<sqlx::Postgres, RawErrorToSqlError>;
+pub type Outbox = crate::db_helper::Outbox<sqlx::Postgres, RawErrorToSqlError>;
+pub type StatementBatcher = crate::db_helper::StatementBatcher<sqlx::Postgres, RawErrorToSqlError>;
+pub type LeasedConnection = crate::db_helper::LeasedConnection<sqlx::Postgres, RawErrorToSqlError>;
+pub type SwitchablePool = crate::db_helper::SwitchablePool<sqlx::Postgres, RawErrorToSqlError>;
+
+impl RowsAffected for sqlx::postgres::PgQueryResult {
+    fn affected(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+// PgArguments cannot be cloned, only reads without arguments are replayed.
+impl ReplayArguments for sqlx::Postgres {
+    fn replay_arguments<'q>(_: &Self::Arguments<'q>) -> Option<Self::Arguments<'q>> {
+        None
+    }
+}
+
+impl SqlPool {
+
+    pub async fn open(uri: &str,
+                      max_connections: u32,
+    ) -> SqlResult<Self> {
+        Self::open_with_config(uri, &PoolConfig::new(max_connections)).await
+    }
+
+    pub async fn open_with_config(uri: &str, config: &PoolConfig) -> SqlResult<Self> {
+        log::info!("open pool {} {:?}", uri, config);
+        let pool_options = config.pool_options::<sqlx::Postgres>();
+        let mut options = sqlx::postgres::PgConnectOptions::from_str(uri).map_err(|e| {
+            RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str())
+        })?;
+        options = options.application_name(default_application_name().as_str());
+        options = options.log_slow_statements(LevelFilter::Error, Duration::from_secs(1));
+        options = options.log_statements(LevelFilter::Off);
+        let pool = pool_options.connect_with(options).await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?;
+        Ok(Self::from_raw_pool_with_uri(pool, uri))
+    }
+
+    // Creates a schema with a unique name in the database of base_uri and opens a pool whose
+    // connections have it as search_path, so parallel tests do not see each other's tables.
+    // Drop it with drop_test_schema.
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn open_test_schema(base_uri: &str, prefix: &str, max_connections: u32) -> SqlResult<(Self, String)> {
+        let schema = crate::test_util::unique_test_name(prefix);
+        {
+            let mut conn = SqlConnection::open(base_uri).await?;
+            let sql = format!("CREATE SCHEMA {}", crate::text_search::quote_ident(schema.as_str(), '"'));
+            conn.execute_sql(sql_query(sql.as_str())).await?;
+        }
+        let separator = if base_uri.contains('?') { '&' } else { '?' };
+        let uri = format!("{}{}options[search_path]={}", base_uri, separator, schema);
+        Ok((Self::open(uri.as_str(), max_connections).await?, schema))
+    }
+
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn drop_test_schema(&self, schema: &str) -> SqlResult<()> {
+        let mut conn = self.get_conn().await?;
+        let sql = format!("DROP SCHEMA IF EXISTS {} CASCADE", crate::text_search::quote_ident(schema, '"'));
+        conn.execute_sql(sql_query(sql.as_str())).await?;
+        Ok(())
+    }
+
+    // Probed on first use and cached, see Capabilities.
+    pub async fn capabilities(&self) -> SqlResult<Capabilities> {
+        if let Some(capabilities) = self.state.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let mut conn = self.get_conn().await?;
+        conn.capabilities().await
+    }
+
+    pub async fn capabilities_report(&self) -> SqlResult<String> {
+        Ok(self.capabilities().await?.report())
+    }
+}
+
+impl SwitchablePool {
+    pub async fn open_standby(&self,
+                              uri: &str,
+                              max_connections: u32,
+    ) -> SqlResult<()> {
+        self.prepare_standby(SqlPool::open(uri, max_connections).await?).await
+    }
+}
+
+impl SqlConnection {
+    pub async fn open(uri: &str) -> SqlResult<Self> {
+        let conn = {
+            let options = sqlx::postgres::PgConnectOptions::from_str(uri).map_err(|e| {
+                RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str())
+            })?;
+            options.application_name(default_application_name().as_str())
+                .connect().await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?
+        };
+
+        Ok(Self::from_conn_type(SqlConnectionType::Conn(conn), Arc::new(TargetInfo::parse(uri))))
+    }
+
+    // Probed once per pool, cached on the pool the connection came from.
+    pub async fn capabilities(&mut self) -> SqlResult<Capabilities> {
+        if let Some(capabilities) = self.pool_state.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let server_version: String = self.query_one(sql_query("show server_version")).await?.get("server_version");
+        let capabilities = Capabilities {
+            backend: SqlBackend::Postgres,
+            returning: true,
+            json_functions: true,
+            full_text_search: true,
+            performance_schema: false,
+            wal_mode: false,
+            server_version,
+        };
+        let _ = self.pool_state.capabilities.set(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    // postgres has no implicit row id, the statement names the id column in its own RETURNING
+    // clause, e.g. "INSERT INTO t (name) VALUES ($1) RETURNING id", and the first column of the
+    // returned row is read as the id.
+    pub async fn insert_returning_id<'a>(&mut self, query: SqlQuery<'a>) -> SqlResult<i64> {
+        if !has_returning(query.sql(), SqlBackend::Postgres) {
+            return Err(sql_err!(SqlErrorCode::ParameterMismatch, "insert_returning_id needs a RETURNING clause naming the id column on postgres: {}", query.sql()));
+        }
+        let sql = query.sql();
+        let rows = self.execute_returning(query).await?;
+        match rows.first() {
+            Some(row) => row.try_get::<i64, _>(0).map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), sql).as_str())),
+            None => Err(sql_err!(SqlErrorCode::Failed, "no row inserted, no id available")),
+        }
+    }
+
+    // table_name may be qualified, "schema.table", otherwise it is looked up in the current schema.
+    pub async fn is_table_exist(&mut self, table_name: &str) -> SqlResult<bool> {
+        let (schema, table) = match table_name.split_once('.') {
+            Some((schema, table)) => (Some(schema), table),
+            None => (None, table_name),
+        };
+        let sql = "select count(*) as c from information_schema.tables where table_schema = coalesce($1, current_schema()) and table_name = $2";
+        let row = self.query_one(sql_query(sql).bind(schema).bind(table)).await?;
+        let count: i64 = row.get("c");
+        Ok(count > 0)
+    }
+
+    // db_name is the schema here, the current one when None.
+    pub async fn is_column_exist(&mut self, table_name: &str, column_name: &str, db_name: Option<&str>) -> SqlResult<bool> {
+        let sql = "select count(*) as c from information_schema.columns where table_schema = coalesce($1, current_schema()) and table_name = $2 and column_name = $3";
+        let row = self.query_one(sql_query(sql).bind(db_name).bind(table_name).bind(column_name)).await?;
+        let count: i64 = row.get("c");
+        Ok(count > 0)
+    }
+
+
+
+    // information_schema has no indexes in postgres, pg_indexes lists them.
+    pub async fn is_index_exist(&mut self, table_name: &str, index_name: &str, db_name: Option<&str>) -> SqlResult<bool> {
+        let sql = "select count(*) as c from pg_indexes where schemaname = coalesce($1, current_schema()) and tablename = $2 and indexname = $3";
+        let row = self.query_one(sql_query(sql).bind(db_name).bind(table_name).bind(index_name)).await?;
+        let count: i64 = row.get("c");
+        Ok(count > 0)
+    }
+}
+
+// Reads every column in order. Types without a SqlValue counterpart, such as NUMERIC or the
+// temporal ones, fail; cast them to text in the query.
+pub fn row_to_map(row: &SqlRowObject) -> SqlResult<IndexMap<String, SqlValue>> {
+    row.to_map().map_err(|e| RawErrorToSqlError::map(e, format!("[{} row_to_map]", line!()).as_str()))
+}
+
+impl RowToMap for sqlx::postgres::PgRow {
+    fn to_map(&self) -> Result<IndexMap<String, SqlValue>, sqlx::Error> {
+        let mut map = IndexMap::with_capacity(self.len());
+        for (i, column) in self.columns().iter().enumerate() {
+            map.insert(column.name().to_string(), column_value(self, i)?);
+        }
+        Ok(map)
+    }
+}
+
+fn column_value(row: &SqlRowObject, i: usize) -> Result<SqlValue, sqlx::Error> {
+    let column = &row.columns()[i];
+    let raw = row.try_get_raw(i)?;
+    if raw.is_null() {
+        return Ok(SqlValue::Null(None));
+    }
+    let type_name = raw.type_info().name().to_string();
+    let ret = match type_name.as_str() {
+        "BOOL" => row.try_get::<bool, _>(i).map(SqlValue::Bool),
+        "INT2" => row.try_get::<i16, _>(i).map(|v| SqlValue::Int(v as i64)),
+        "INT4" => row.try_get::<i32, _>(i).map(|v| SqlValue::Int(v as i64)),
+        "INT8" => row.try_get::<i64, _>(i).map(SqlValue::Int),
+        "FLOAT4" => row.try_get::<f32, _>(i).map(|v| SqlValue::Float(v as f64)),
+        "FLOAT8" => row.try_get::<f64, _>(i).map(SqlValue::Float),
+        "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => row.try_get::<String, _>(i).map(SqlValue::Text),
+        "BYTEA" => row.try_get::<Vec<u8>, _>(i).map(SqlValue::Blob),
+        _ => return Err(sqlx::Error::ColumnDecode {
+            index: column.name().to_string(),
+            source: format!("unsupported type {}, cast it to text", type_name).into(),
+        }),
+    };
+    ret.map_err(|e| named_column_error(e, column.name()))
+}
@@ -0,0 +1,25 @@
+// The intended surface of the crate for `use sfo_sql::prelude::*;`, without sqlx's names.
+//
+// A version only ever gains items that cannot clash with user code, anything else goes to the
+// next one, so pinning `sfo_sql::prelude::v1::*` keeps compiling across releases.
+//
+// The backend aliases are the sqlite ones when the sqlite feature is enabled, otherwise the mysql
+// ones. With both enabled, import the mysql aliases from sfo_sql::mysql.
+
+pub mod v1 {
+    pub use crate::errors::{sql_err, SqlError, SqlErrorCode, SqlResult};
+    pub use crate::db_helper::{sql_query, sql_query_with, AggregateRowExt, BindValue, ErrorMap, FilterBuilder, IndexMap,
+                               Priority, RetryPolicy, RowExpectation, RowsAffected, SortDirection, SortSpec, SqlBackend,
+                               SqlRow, SqlValue, StatementCatalogBuilder, UnitOp, WriteCoalescerBuilder};
+    #[cfg(feature = "chrono")]
+    pub use crate::datetime::{BindDateTime, DateTimePrecision, DateTimeRowExt};
+
+    #[cfg(feature = "sqlite")]
+    pub use crate::sqlite::{row_to_map, InsertSink, LeasedConnection, SqlConnection, SqlPool, SqlQuery, SqlRowObject,
+                            SqlTransaction, SqliteJournalMode, SqliteUriBuilder, UnitOfWork};
+    #[cfg(all(feature = "mysql", not(feature = "sqlite")))]
+    pub use crate::mysql::{row_to_map, InsertSink, LeasedConnection, MySqlOpenOptions, SqlConnection, SqlPool, SqlQuery,
+                           SqlRowObject, SqlTransaction, UnitOfWork};
+}
+
+pub use v1::*;
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+use crate::value::{BindValue, IndexMap, RowToMap, SqlValue};
+
+pub type CachedRows = Arc<Vec<IndexMap<String, SqlValue>>>;
+
+struct CacheEntry {
+    rows: CachedRows,
+    tables: Vec<String>,
+}
+
+struct QueryCacheInner {
+    entries: IndexMap<String, CacheEntry>,
+    // Bumped by every invalidation, a read that raced with one is not stored.
+    generation: u64,
+}
+
+// Results of one pool's cached reads, keyed by sql and arguments, holding at most capacity
+// results and dropping the least recently used one to make room.
+pub(crate) struct QueryCache {
+    capacity: usize,
+    inner: Mutex<QueryCacheInner>,
+}
+
+// Table names are compared without schema prefix and case.
+pub(crate) fn table_key(table: &str) -> String {
+    table.rsplit('.').next().unwrap_or(table).trim_matches(|c| c == '`' || c == '"').to_lowercase()
+}
+
+impl QueryCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(QueryCacheInner {
+                entries: IndexMap::new(),
+                generation: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueryCacheInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<CachedRows> {
+        let mut inner = self.lock();
+        let entry = inner.entries.shift_remove(key)?;
+        let rows = entry.rows.clone();
+        inner.entries.insert(key.to_string(), entry);
+        Some(rows)
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    // Stores rows read at generation, unless an invalidation happened since.
+    pub(crate) fn insert(&self, key: String, rows: CachedRows, tables: Vec<String>, generation: u64) {
+        let mut inner = self.lock();
+        if inner.generation != generation {
+            return;
+        }
+        inner.entries.shift_remove(&key);
+        if inner.entries.len() >= self.capacity {
+            inner.entries.shift_remove_index(0);
+        }
+        inner.entries.insert(key, CacheEntry { rows, tables });
+    }
+
+    // Drops the results depending on any of tables, or every result when tables is empty.
+    pub(crate) fn invalidate(&self, tables: &[String]) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        if tables.is_empty() {
+            inner.entries.clear();
+        } else {
+            inner.entries.retain(|_, entry| !entry.tables.iter().any(|t| tables.contains(t)));
+        }
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Rows of sql from the pool's query cache, read and stored on a miss. The result is dropped
+    // when a statement of any connection of the pool writes one of depends_on_tables, at commit
+    // for a write inside a transaction. Inside a transaction that already wrote one of them the
+    // cache is bypassed, so the transaction sees its own writes and they do not leak to others.
+    pub async fn query_all_cached(&mut self, sql: &str, args: &[SqlValue], depends_on_tables: &[&str]) -> Result<CachedRows, EM::OutError>
+    where DB::Row: RowToMap {
+        let tables: Vec<String> = depends_on_tables.iter().map(|t| table_key(t)).collect();
+        let cache = self.pool_state.query_cache.get().filter(|_| {
+            !self.pending_writes.iter().any(|written| written.is_empty() || written.iter().any(|t| tables.contains(t)))
+        });
+        let key = format!("{}\u{0}{:?}", sql, args);
+        let generation = match cache {
+            Some(cache) => {
+                if let Some(rows) = cache.get(key.as_str()) {
+                    return Ok(rows);
+                }
+                Some(cache.generation())
+            }
+            None => None,
+        };
+
+        let mut query = sqlx::query::<DB>(sql);
+        for value in args.iter() {
+            query = query.bind_value(value.clone());
+        }
+        let rows = self.query_all(query).await?;
+        let rows: CachedRows = Arc::new(rows.iter().map(|row| map_row::<EM, _>(row, sql)).collect::<Result<Vec<_>, _>>()?);
+        if let (Some(cache), Some(generation)) = (self.pool_state.query_cache.get(), generation) {
+            cache.insert(key, rows.clone(), tables, generation);
+        }
+        Ok(rows)
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, IndexMap, RowToMap, SqlValue};
+
+#[derive(Debug, Clone)]
+pub struct ReconcileOptions {
+    // Source rows compared per round, the fixes of a round share one transaction.
+    pub chunk_size: usize,
+    // Delete target rows whose key is not in source, otherwise they are only reported.
+    pub delete_extra: bool,
+    // Only compare and report, the target is left untouched.
+    pub dry_run: bool,
+    // Keys kept per category in the report.
+    pub sample_limit: usize,
+}
+
+impl Default for ReconcileOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1000,
+            delete_extra: false,
+            dry_run: false,
+            sample_limit: 20,
+        }
+    }
+}
+
+// Counts are what was found, applied tells whether the fixes were written.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReconcileReport {
+    pub missing: u64,
+    pub changed: u64,
+    pub extra: u64,
+    pub missing_keys: Vec<Vec<SqlValue>>,
+    pub changed_keys: Vec<Vec<SqlValue>>,
+    pub extra_keys: Vec<Vec<SqlValue>>,
+    pub applied: bool,
+}
+
+// Values compared by content, so an integer read as Int on one side and UInt on the other match.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum NormValue {
+    Null,
+    Int(i128),
+    Float(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+// Source rows of a chunk by normalized key, with the key as read and the whole row.
+type SourceChunk = HashMap<Vec<NormValue>, (Vec<SqlValue>, IndexMap<String, SqlValue>)>;
+
+impl From<&SqlValue> for NormValue {
+    fn from(v: &SqlValue) -> Self {
+        match v {
+            SqlValue::Null(_) => NormValue::Null,
+            SqlValue::Bool(v) => NormValue::Int(*v as i128),
+            SqlValue::Int(v) => NormValue::Int(*v as i128),
+            SqlValue::UInt(v) => NormValue::Int(*v as i128),
+            SqlValue::Float(v) => NormValue::Float(v.to_bits()),
+            SqlValue::Text(v) => NormValue::Text(v.clone()),
+            SqlValue::Blob(v) => NormValue::Blob(v.clone()),
+        }
+    }
+}
+
+enum Fix {
+    Insert(IndexMap<String, SqlValue>),
+    Update(Vec<SqlValue>, IndexMap<String, SqlValue>),
+    Delete(Vec<SqlValue>),
+}
+
+fn push_sample(samples: &mut Vec<Vec<SqlValue>>, limit: usize, key: &[SqlValue]) {
+    if samples.len() < limit {
+        samples.push(key.to_vec());
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Makes target match source: inserts missing rows, updates changed ones and, with
+    // delete_extra, deletes rows source does not have. Both tables need the same columns.
+    // Walks source in key order chunk by chunk and compares each chunk with the target rows in
+    // the same key range, keeping only a hash of the non-key columns of target rows. Both tables
+    // are reached through this connection, a schema prefix such as "cache.items" is allowed, so
+    // attach or qualify the other database to reconcile across them.
+    pub async fn reconcile_tables(&mut self,
+                                  source: &str,
+                                  target: &str,
+                                  key_columns: &[&str],
+                                  options: &ReconcileOptions) -> Result<ReconcileReport, EM::OutError>
+    where DB::Row: RowToMap {
+        if key_columns.is_empty() {
+            return Err(EM::map_parameter_mismatch("reconcile needs at least one key column"));
+        }
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let keys = key_columns.iter().map(|c| quote_ident(c, quote)).collect::<Vec<_>>();
+        let key_tuple = if keys.len() == 1 { keys[0].clone() } else { format!("({})", keys.join(", ")) };
+        // The key tuple's placeholders, numbered from first.
+        let params = |first: usize| {
+            let params = (first..first + keys.len()).map(|n| backend.placeholder(n)).collect::<Vec<_>>();
+            if params.len() == 1 { params[0].clone() } else { format!("({})", params.join(", ")) }
+        };
+        let order = keys.join(", ");
+        let chunk_size = options.chunk_size.max(1);
+        let source_sql = |after: bool| format!("SELECT * FROM {}{} ORDER BY {} LIMIT {}",
+                                               quote_qualified(source, quote),
+                                               if after { format!(" WHERE {} > {}", key_tuple, params(1)) } else { String::new() },
+                                               order, chunk_size);
+        let target_sql = |after: bool, until: bool| {
+            let mut filters = Vec::new();
+            if after {
+                filters.push(format!("{} > {}", key_tuple, params(1)));
+            }
+            if until {
+                filters.push(format!("{} <= {}", key_tuple, params(if after { keys.len() + 1 } else { 1 })));
+            }
+            let filter = if filters.is_empty() { String::new() } else { format!(" WHERE {}", filters.join(" AND ")) };
+            format!("SELECT * FROM {}{} ORDER BY {} LIMIT {}", quote_qualified(target, quote), filter, order, chunk_size)
+        };
+
+        let mut report = ReconcileReport { applied: !options.dry_run, ..Default::default() };
+        let mut lower: Option<Vec<SqlValue>> = None;
+        loop {
+            let sql = source_sql(lower.is_some());
+            let mut query = sqlx::query::<DB>(sql.as_str());
+            for v in lower.iter().flatten() {
+                query = query.bind_value(v.clone());
+            }
+            let rows = self.query_all(query).await?;
+            let mut chunk: SourceChunk = HashMap::with_capacity(rows.len());
+            let mut chunk_order = Vec::with_capacity(rows.len());
+            let mut upper = None;
+            for row in rows.iter() {
+                let map = map_row::<EM, _>(row, sql.as_str())?;
+                let key = self.reconcile_key(&map, key_columns)?;
+                upper = Some(key.clone());
+                let norm: Vec<NormValue> = key.iter().map(NormValue::from).collect();
+                chunk_order.push(norm.clone());
+                chunk.insert(norm, (key, map));
+            }
+            let last_chunk = rows.len() < chunk_size;
+            drop(rows);
+
+            let mut fixes = Vec::new();
+            // Target rows after the previous chunk up to the last source key of this one, or
+            // everything left when source is exhausted.
+            let until = if last_chunk { None } else { upper.clone() };
+            let mut target_lower = lower.clone();
+            loop {
+                let sql = target_sql(target_lower.is_some(), until.is_some());
+                let mut query = sqlx::query::<DB>(sql.as_str());
+                for v in target_lower.iter().flatten().chain(until.iter().flatten()) {
+                    query = query.bind_value(v.clone());
+                }
+                let rows = self.query_all(query).await?;
+                let count = rows.len();
+                for row in rows.iter() {
+                    let map = map_row::<EM, _>(row, sql.as_str())?;
+                    let key = self.reconcile_key(&map, key_columns)?;
+                    let norm: Vec<NormValue> = key.iter().map(NormValue::from).collect();
+                    match chunk.remove(&norm) {
+                        Some((_, source_row)) => {
+                            if non_key_hash(&source_row, &source_row, key_columns) != non_key_hash(&source_row, &map, key_columns) {
+                                report.changed += 1;
+                                push_sample(&mut report.changed_keys, options.sample_limit, &key);
+                                fixes.push(Fix::Update(key.clone(), source_row));
+                            }
+                        }
+                        None => {
+                            report.extra += 1;
+                            push_sample(&mut report.extra_keys, options.sample_limit, &key);
+                            if options.delete_extra {
+                                fixes.push(Fix::Delete(key.clone()));
+                            }
+                        }
+                    }
+                    target_lower = Some(key);
+                }
+                if count < chunk_size {
+                    break;
+                }
+            }
+            for (key, row) in chunk_order.iter().filter_map(|k| chunk.remove(k)) {
+                report.missing += 1;
+                push_sample(&mut report.missing_keys, options.sample_limit, &key);
+                fixes.push(Fix::Insert(row));
+            }
+
+            if !options.dry_run && !fixes.is_empty() {
+                self.apply_reconcile_fixes(target, key_columns, fixes).await?;
+            }
+            if last_chunk {
+                break;
+            }
+            lower = upper;
+        }
+        Ok(report)
+    }
+
+    fn reconcile_key(&self, row: &IndexMap<String, SqlValue>, key_columns: &[&str]) -> Result<Vec<SqlValue>, EM::OutError> {
+        let mut key = Vec::with_capacity(key_columns.len());
+        for column in key_columns.iter() {
+            match row.get(*column) {
+                Some(v) => key.push(v.clone()),
+                None => return Err(EM::map(sqlx::Error::ColumnNotFound(column.to_string()), format!("[{} reconcile]", line!()).as_str())),
+            }
+        }
+        Ok(key)
+    }
+
+    async fn apply_reconcile_fixes(&mut self, target: &str, key_columns: &[&str], fixes: Vec<Fix>) -> Result<(), EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let table = quote_qualified(target, quote);
+        // The key filter's placeholders, numbered from first.
+        let key_filter = |first: usize| key_columns.iter().enumerate()
+            .map(|(i, c)| format!("{} = {}", quote_ident(c, quote), backend.placeholder(first + i)))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+
+        self.begin_transaction().await?;
+        for fix in fixes {
+            let ret = match fix {
+                Fix::Insert(row) => {
+                    let columns = row.keys().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+                    let params = (1..=row.len()).map(|n| backend.placeholder(n)).collect::<Vec<_>>().join(", ");
+                    let sql = format!("INSERT INTO {} ({}) VALUES ({})", table, columns, params);
+                    let mut query = sqlx::query::<DB>(sql.as_str());
+                    for value in row.into_values() {
+                        query = query.bind_value(value);
+                    }
+                    self.execute_sql(query).await
+                }
+                Fix::Update(key, row) => {
+                    let values: Vec<(String, SqlValue)> = row.into_iter().filter(|(c, _)| !key_columns.contains(&c.as_str())).collect();
+                    let sets = values.iter().enumerate()
+                        .map(|(i, (c, _))| format!("{} = {}", quote_ident(c, quote), backend.placeholder(i + 1)))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let sql = format!("UPDATE {} SET {} WHERE {}", table, sets, key_filter(values.len() + 1));
+                    let mut query = sqlx::query::<DB>(sql.as_str());
+                    for (_, value) in values {
+                        query = query.bind_value(value);
+                    }
+                    for value in key {
+                        query = query.bind_value(value);
+                    }
+                    self.execute_sql(query).await
+                }
+                Fix::Delete(key) => {
+                    let sql = format!("DELETE FROM {} WHERE {}", table, key_filter(1));
+                    let mut query = sqlx::query::<DB>(sql.as_str());
+                    for value in key {
+                        query = query.bind_value(value);
+                    }
+                    self.execute_sql(query).await
+                }
+            };
+            if let Err(e) = ret {
+                let _ = self.rollback_transaction().await;
+                return Err(e);
+            }
+        }
+        self.commit_transaction().await
+    }
+}
+
+// Hash of the non-key columns of columns_of, read from row. Columns missing in row hash as NULL.
+fn non_key_hash(columns_of: &IndexMap<String, SqlValue>, row: &IndexMap<String, SqlValue>, key_columns: &[&str]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for column in columns_of.keys().filter(|c| !key_columns.contains(&c.as_str())) {
+        row.get(column).map(NormValue::from).unwrap_or(NormValue::Null).hash(&mut hasher);
+    }
+    hasher.finish()
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, RowsAffected, SqlConnection, SqlPool};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, SqlValue};
+
+// Rows of table older than keep by timestamp_column are deleted, batch_size rows per statement
+// so no statement holds locks for long. Timestamps are compared as the datetime module stores
+// them: UTC DATETIME or TIMESTAMP values on mysql, UTC ISO-8601 text ending in Z on sqlite.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RetentionPolicy {
+    pub table: String,
+    pub timestamp_column: String,
+    pub keep: Duration,
+    pub batch_size: usize,
+    // Reclaims the space once this many rows were removed from the table in one run: PRAGMA
+    // incremental_vacuum on sqlite, which needs auto_vacuum=INCREMENTAL, OPTIMIZE TABLE on mysql.
+    pub compact_after: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TableRetentionReport {
+    pub table: String,
+    pub removed: u64,
+    pub took: Duration,
+    pub compacted: bool,
+    // Why the policy was not applied, a missing table or column.
+    pub skipped: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RetentionReport {
+    // In the order of the policies.
+    pub tables: Vec<TableRetentionReport>,
+}
+
+impl RetentionReport {
+    pub fn removed(&self) -> u64 {
+        self.tables.iter().map(|t| t.removed).sum()
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &TableRetentionReport> {
+        self.tables.iter().filter(|t| t.skipped.is_some())
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlPool<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected, {
+    // Applies every policy in turn. A policy whose table or column does not exist is reported
+    // as skipped and the run goes on, other errors end it.
+    pub async fn apply_retention(&self, policies: &[RetentionPolicy]) -> Result<RetentionReport, EM::OutError> {
+        let mut conn = self.get_conn().await?;
+        let mut report = RetentionReport::default();
+        for policy in policies.iter() {
+            report.tables.push(conn.apply_retention_policy(policy).await?);
+        }
+        Ok(report)
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected, {
+    async fn apply_retention_policy(&mut self, policy: &RetentionPolicy) -> Result<TableRetentionReport, EM::OutError> {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let table = quote_qualified(policy.table.as_str(), quote);
+        let column = quote_ident(policy.timestamp_column.as_str(), quote);
+        let mut report = TableRetentionReport { table: policy.table.clone(), ..Default::default() };
+
+        // Preparing does not run anything, and fails without logging an error when the table
+        // or the column is missing.
+        if let Err(e) = self.prepare_raw(format!("SELECT {} FROM {} WHERE 1 = 0", column, table).as_str()).await {
+            log::info!("retention of {} skipped: {}", policy.table, e);
+            report.skipped = Some(e.to_string());
+            return Ok(report);
+        }
+
+        let keep = policy.keep.as_secs();
+        let batch_size = policy.batch_size.max(1);
+        let (sql, cutoff) = match backend {
+            SqlBackend::MySql => (format!("DELETE FROM {} WHERE {} < UTC_TIMESTAMP() - INTERVAL ? SECOND LIMIT {}", table, column, batch_size),
+                                  SqlValue::UInt(keep)),
+            _ => (format!("DELETE FROM {} WHERE rowid IN (SELECT rowid FROM {} WHERE {} < strftime('%Y-%m-%dT%H:%M:%SZ', 'now', ?) LIMIT {})",
+                          table, table, column, batch_size),
+                  SqlValue::Text(format!("-{} seconds", keep))),
+        };
+        let clock = self.clock();
+        let start = clock.now();
+        loop {
+            let ret = self.execute_sql(sqlx::query::<DB>(sql.as_str()).bind_value(cutoff.clone())).await?;
+            report.removed += ret.affected();
+            if ret.affected() < batch_size as u64 {
+                break;
+            }
+        }
+
+        if policy.compact_after.map(|threshold| report.removed >= threshold).unwrap_or(false) {
+            let sql = match backend {
+                SqlBackend::MySql => format!("OPTIMIZE TABLE {}", table),
+                _ => match policy.table.split_once('.') {
+                    Some((schema, _)) => format!("PRAGMA {}.incremental_vacuum", quote_ident(schema, quote)),
+                    None => "PRAGMA incremental_vacuum".to_string(),
+                },
+            };
+            // OPTIMIZE TABLE returns its outcome as rows.
+            self.query_all(sqlx::query::<DB>(sql.as_str())).await?;
+            report.compacted = true;
+        }
+        report.took = clock.elapsed(start);
+        log::info!("retention of {} removed {} rows in {:?}", policy.table, report.removed, report.took);
+        Ok(report)
+    }
+}
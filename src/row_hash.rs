@@ -0,0 +1,162 @@
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+use crate::startup::fnv1a;
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, RowToMap, SqlValue};
+
+pub const DEFAULT_ROW_HASH_COLUMN: &str = "row_hash";
+
+// Hex FNV-1a of the columns in the given order, 16 characters. Each column is encoded as its
+// name, 0x1f, a type tag and the value, then 0x1e. Tags: 'n' for NULL without value, 'i' for
+// Int, UInt and Bool as decimal text (true is 1), 'f' for Float as Rust prints it, 't' for Text
+// as utf-8 and 'b' for Blob as is. Ints, UInts and Bools hash alike because backends read them
+// back as any of the three. For change detection in sync protocols, not against tampering.
+pub fn compute_row_hash(columns: &[(&str, SqlValue)]) -> String {
+    let mut hash = 0xcbf29ce484222325;
+    for (name, value) in columns.iter() {
+        hash = fnv1a(hash, name.as_bytes());
+        hash = fnv1a(hash, &[0x1f]);
+        hash = match value {
+            SqlValue::Null(_) => fnv1a(hash, b"n"),
+            SqlValue::Bool(v) => fnv1a(fnv1a(hash, b"i"), if *v { b"1" } else { b"0" }),
+            SqlValue::Int(v) => fnv1a(fnv1a(hash, b"i"), v.to_string().as_bytes()),
+            SqlValue::UInt(v) => fnv1a(fnv1a(hash, b"i"), v.to_string().as_bytes()),
+            SqlValue::Float(v) => fnv1a(fnv1a(hash, b"f"), v.to_string().as_bytes()),
+            SqlValue::Text(v) => fnv1a(fnv1a(hash, b"t"), v.as_bytes()),
+            SqlValue::Blob(v) => fnv1a(fnv1a(hash, b"b"), v.as_slice()),
+        };
+        hash = fnv1a(hash, &[0x1e]);
+    }
+    format!("{:016x}", hash)
+}
+
+// A table whose rows carry a hash of the listed columns, kept by the application so it works the
+// same on every backend. A generated column could compute it in mysql or postgres, but not with
+// one definition for all of them nor with an encoding clients can reproduce. Values must read
+// back as the type they were written as, a float written to an INTEGER column would not.
+#[derive(Debug, Clone)]
+pub struct RowHashSpec {
+    table: String,
+    key_column: String,
+    columns: Vec<String>,
+    hash_column: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RowHashReport {
+    pub scanned: u64,
+    // Key of every row whose stored hash differs from the hash of its columns.
+    pub mismatched: Vec<SqlValue>,
+}
+
+impl RowHashSpec {
+    pub fn new(table: &str, key_column: &str, columns: &[&str]) -> Self {
+        Self {
+            table: table.to_string(),
+            key_column: key_column.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            hash_column: DEFAULT_ROW_HASH_COLUMN.to_string(),
+        }
+    }
+
+    pub fn hash_column(mut self, hash_column: &str) -> Self {
+        self.hash_column = hash_column.to_string();
+        self
+    }
+
+    // Definition of the hash column for a CREATE or ALTER TABLE.
+    pub fn column_definition(&self, backend: SqlBackend) -> String {
+        format!("{} CHAR(16)", quote_ident(self.hash_column.as_str(), backend.ident_quote()))
+    }
+
+    // Hash of the spec's columns taken from values, in the spec's order. Fails naming the first
+    // column values lacks.
+    pub fn hash_of(&self, values: &[(&str, SqlValue)]) -> Result<String, String> {
+        let mut columns = Vec::with_capacity(self.columns.len());
+        for column in self.columns.iter() {
+            match values.iter().find(|(name, _)| name == column) {
+                Some((_, value)) => columns.push((column.as_str(), value.clone())),
+                None => return Err(format!("no value for hashed column {} of {}", column, self.table)),
+            }
+        }
+        Ok(compute_row_hash(columns.as_slice()))
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Inserts values and their hash. values must hold every hashed column and may hold others,
+    // the key included.
+    pub async fn insert_hashed(&mut self, spec: &RowHashSpec, values: &[(&str, SqlValue)]) -> Result<DB::QueryResult, EM::OutError> {
+        let hash = spec.hash_of(values).map_err(|msg| EM::map_parameter_mismatch(msg.as_str()))?;
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let mut columns = values.iter().map(|(name, _)| quote_ident(name, quote)).collect::<Vec<_>>();
+        columns.push(quote_ident(spec.hash_column.as_str(), quote));
+        let params = (1..=columns.len()).map(|n| backend.placeholder(n)).collect::<Vec<_>>();
+        let sql = format!("INSERT INTO {} ({}) VALUES ({})", quote_qualified(spec.table.as_str(), quote), columns.join(", "), params.join(", "));
+        let query = values.iter().fold(sqlx::query::<DB>(sql.as_str()), |q, (_, v)| q.bind_value(v.clone()));
+        self.execute_sql(query.bind_value(SqlValue::Text(hash))).await
+    }
+
+    // Updates the row of key to values and its hash. values must hold every hashed column, the
+    // ones that did not change too, a hash of part of the row would never verify.
+    pub async fn update_hashed(&mut self, spec: &RowHashSpec, key: SqlValue, values: &[(&str, SqlValue)]) -> Result<DB::QueryResult, EM::OutError> {
+        let hash = spec.hash_of(values).map_err(|msg| EM::map_parameter_mismatch(msg.as_str()))?;
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let mut sets = values.iter().enumerate()
+            .map(|(i, (name, _))| format!("{} = {}", quote_ident(name, quote), backend.placeholder(i + 1)))
+            .collect::<Vec<_>>();
+        sets.push(format!("{} = {}", quote_ident(spec.hash_column.as_str(), quote), backend.placeholder(values.len() + 1)));
+        let sql = format!("UPDATE {} SET {} WHERE {} = {}", quote_qualified(spec.table.as_str(), quote), sets.join(", "),
+                          quote_ident(spec.key_column.as_str(), quote), backend.placeholder(values.len() + 2));
+        let query = values.iter().fold(sqlx::query::<DB>(sql.as_str()), |q, (_, v)| q.bind_value(v.clone()));
+        self.execute_sql(query.bind_value(SqlValue::Text(hash)).bind_value(key)).await
+    }
+
+    // Reads the table in key order, batch_size rows per query, and reports the rows whose stored
+    // hash does not match their columns, e.g. after an update that bypassed update_hashed.
+    pub async fn verify_row_hashes(&mut self, spec: &RowHashSpec, batch_size: usize) -> Result<RowHashReport, EM::OutError>
+    where DB::Row: RowToMap {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let key_column = quote_ident(spec.key_column.as_str(), quote);
+        let mut select = vec![key_column.clone()];
+        select.extend(spec.columns.iter().map(|c| quote_ident(c, quote)));
+        select.push(quote_ident(spec.hash_column.as_str(), quote));
+        let select = format!("SELECT {} FROM {}", select.join(", "), quote_qualified(spec.table.as_str(), quote));
+        let first_sql = format!("{} ORDER BY {} LIMIT {}", select, key_column, batch_size.max(1));
+        let next_sql = format!("{} WHERE {} > {} ORDER BY {} LIMIT {}", select, key_column, backend.placeholder(1), key_column, batch_size.max(1));
+        let mut report = RowHashReport::default();
+        let mut last: Option<SqlValue> = None;
+        loop {
+            let rows = match last.take() {
+                Some(key) => self.query_all(sqlx::query::<DB>(next_sql.as_str()).bind_value(key)).await?,
+                None => self.query_all(sqlx::query::<DB>(first_sql.as_str())).await?,
+            };
+            for row in rows.iter() {
+                // Read by position, the backends disagree on the case of returned column names.
+                let mut values = map_row::<EM, _>(row, select.as_str())?.into_values();
+                let key = values.next().unwrap_or(SqlValue::Null(None));
+                let columns = spec.columns.iter().map(|c| (c.as_str(), values.next().unwrap_or(SqlValue::Null(None)))).collect::<Vec<_>>();
+                let stored = match values.next() {
+                    Some(SqlValue::Text(hash)) => Some(hash),
+                    Some(SqlValue::Blob(hash)) => String::from_utf8(hash).ok(),
+                    _ => None,
+                };
+                report.scanned += 1;
+                if stored.as_deref().map(|h| h.trim_end()) != Some(compute_row_hash(columns.as_slice()).as_str()) {
+                    report.mismatched.push(key.clone());
+                }
+                last = Some(key);
+            }
+            if rows.len() < batch_size.max(1) {
+                return Ok(report);
+            }
+        }
+    }
+}
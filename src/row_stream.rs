@@ -0,0 +1,53 @@
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use futures_core::stream::BoxStream;
+use futures_core::Stream;
+use sqlx::{Database, Execute, Executor};
+use crate::db_helper::{ErrorMap, SqlConnection};
+
+// Rows of one query as the server sends them, keeping the connection borrowed until dropped.
+// Dropping it before the end is fine, the rest of the result is discarded on the next use of
+// the connection.
+pub struct SqlRowStream<'c, DB: Database, EM: ErrorMap<InError = sqlx::Error>> {
+    rows: BoxStream<'c, Result<DB::Row, sqlx::Error>>,
+    sql: String,
+    _em: PhantomData<fn() -> EM>,
+}
+
+impl<'c, DB: Database, EM: ErrorMap<InError = sqlx::Error>> SqlRowStream<'c, DB, EM> {
+    // Next row, None at the end, for callers without a stream combinator crate.
+    pub async fn next(&mut self) -> Option<Result<DB::Row, EM::OutError>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<'c, DB: Database, EM: ErrorMap<InError = sqlx::Error>> Stream for SqlRowStream<'c, DB, EM> {
+    type Item = Result<DB::Row, EM::OutError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.rows.as_mut().poll_next(cx)
+            .map(|row| row.map(|row| row.map_err(|e| EM::map(e, format!("[{} {}]", line!(), this.sql).as_str()))))
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+    // Unlike query_all the rows are not collected, so a result of any size is read in bounded
+    // memory. Runs in the open transaction when there is one, each row is yielded as it arrives.
+    // Statement statistics and the adaptive timeout do not cover streamed queries. A streamed
+    // write invalidates the query cache up front, before it is known to succeed.
+    pub fn query_stream<'c, 'q: 'c>(&'c mut self, query: sqlx::query::Query<'q, DB, DB::Arguments<'q>>) -> Result<SqlRowStream<'c, DB, EM>, EM::OutError> {
+        let query = self.check_placeholders(query)?;
+        let sql = query.sql().to_string();
+        self.check_not_cut_off().map_err(|e| EM::map(e, format!("[{} {}]", line!(), sql).as_str()))?;
+        self.capture_write(sql.as_str());
+        Ok(SqlRowStream {
+            rows: self.executor().fetch(query),
+            sql,
+            _em: PhantomData,
+        })
+    }
+}
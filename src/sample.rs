@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hasher};
+use sqlx::{Database, Executor};
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, IndexMap, RowToMap, SqlValue};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SampleStrategy {
+    // ORDER BY RANDOM() LIMIT n, a full sort of the table, fine for small ones.
+    Random,
+    // Looks up the first row at or after random keys between the min and max of key_column,
+    // which needs an index on it and an integer type. Rows after gaps in the key range are
+    // picked more often. Other key types fall back to Random.
+    KeyRange { key_column: String },
+    // Every k-th row in key_column order from a random start, k being the row count over n.
+    SystematicEveryK { key_column: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleResult {
+    pub rows: Vec<IndexMap<String, SqlValue>>,
+    // The strategy the rows were taken with, Random after a fallback.
+    pub strategy: SampleStrategy,
+}
+
+// xorshift64*, seeded per call from the std random hasher keys. Sampling needs no more.
+struct SampleRng(u64);
+
+impl SampleRng {
+    fn new() -> Self {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Self(hasher.finish() | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    // Uniform enough in [low, high] for sampling.
+    fn between(&mut self, low: i128, high: i128) -> i128 {
+        let span = (high - low) as u128 + 1;
+        low + ((((self.next() as u128) << 64) | self.next() as u128) % span) as i128
+    }
+}
+
+const ROW_NUMBER_COLUMN: &str = "sample_rn";
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Up to n rows of table for data quality checks, every row when the table has no more.
+    // SampleResult::strategy tells which strategy was used after a fallback.
+    pub async fn sample_rows(&mut self, table: &str, n: usize, strategy: SampleStrategy) -> Result<SampleResult, EM::OutError>
+    where DB::Row: RowToMap {
+        let strategy = &strategy;
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let table_sql = quote_qualified(table, quote);
+        let mut rng = SampleRng::new();
+        let to_maps = |rows: Vec<DB::Row>| rows.iter().map(|row| map_row::<EM, _>(row, table)).collect::<Result<Vec<_>, _>>();
+        if n == 0 {
+            return Ok(SampleResult { rows: Vec::new(), strategy: strategy.clone() });
+        }
+
+        match strategy {
+            SampleStrategy::Random => {}
+            SampleStrategy::KeyRange { key_column } => {
+                let key = quote_ident(key_column, quote);
+                let sql = format!("SELECT MIN({}) AS lo, MAX({}) AS hi, COUNT(*) AS c FROM {}", key, key, table_sql);
+                let mut stats = map_row::<EM, _>(&self.query_one(sqlx::query::<DB>(sql.as_str())).await?, sql.as_str())?;
+                let count = stats.shift_remove("c").and_then(|c| c.to_i128().ok().flatten()).unwrap_or(0);
+                let low = stats.shift_remove("lo").and_then(|v| v.to_i128().ok().flatten());
+                let high = stats.shift_remove("hi").and_then(|v| v.to_i128().ok().flatten());
+                if count <= n as i128 {
+                    let rows = self.query_all(sqlx::query::<DB>(format!("SELECT * FROM {}", table_sql).as_str())).await?;
+                    return Ok(SampleResult { rows: to_maps(rows)?, strategy: strategy.clone() });
+                }
+                if let (Some(low), Some(high)) = (low, high) {
+                    let sql = format!("SELECT * FROM {} WHERE {} >= {} ORDER BY {} LIMIT 1", table_sql, key, backend.placeholder(1), key);
+                    let mut seen = HashSet::new();
+                    let mut rows = Vec::with_capacity(n);
+                    // Lookups landing on a row already taken are retried a bounded number of times.
+                    for _ in 0..n * 4 {
+                        if rows.len() >= n {
+                            break;
+                        }
+                        let probe = rng.between(low, high);
+                        let probe = i64::try_from(probe).map(SqlValue::Int).unwrap_or_else(|_| SqlValue::Text(probe.to_string()));
+                        if let Some(row) = self.query_optional(sqlx::query::<DB>(sql.as_str()).bind_value(probe)).await? {
+                            let row = map_row::<EM, _>(&row, sql.as_str())?;
+                            let found = row.get(key_column.as_str()).and_then(|v| v.to_i128().ok().flatten());
+                            if found.map(|k| seen.insert(k)).unwrap_or(false) {
+                                rows.push(row);
+                            }
+                        }
+                    }
+                    return Ok(SampleResult { rows, strategy: strategy.clone() });
+                }
+                log::info!("key {} of {} is not an integer, sample with Random", key_column, table);
+            }
+            SampleStrategy::SystematicEveryK { key_column } => {
+                let sql = format!("SELECT COUNT(*) AS c FROM {}", table_sql);
+                let mut stats = map_row::<EM, _>(&self.query_one(sqlx::query::<DB>(sql.as_str())).await?, sql.as_str())?;
+                let count = stats.shift_remove("c").and_then(|c| c.to_i128().ok().flatten()).unwrap_or(0) as u64;
+                let k = (count / n as u64).max(1);
+                let start = rng.next() % k;
+                let sql = format!("SELECT * FROM (SELECT sample_source.*, ROW_NUMBER() OVER (ORDER BY {}) AS {} FROM {} AS sample_source) AS sample_numbered WHERE ({} - 1) % {} = {} ORDER BY {} LIMIT {}",
+                                  quote_ident(key_column, quote), ROW_NUMBER_COLUMN, table_sql, ROW_NUMBER_COLUMN, k, start, ROW_NUMBER_COLUMN, n);
+                let mut rows = to_maps(self.query_all(sqlx::query::<DB>(sql.as_str())).await?)?;
+                for row in rows.iter_mut() {
+                    row.shift_remove(ROW_NUMBER_COLUMN);
+                }
+                return Ok(SampleResult { rows, strategy: strategy.clone() });
+            }
+        }
+
+        let random = if backend == SqlBackend::MySql { "RAND()" } else { "RANDOM()" };
+        let sql = format!("SELECT * FROM {} ORDER BY {} LIMIT {}", table_sql, random, n);
+        let rows = self.query_all(sqlx::query::<DB>(sql.as_str())).await?;
+        Ok(SampleResult { rows: to_maps(rows)?, strategy: SampleStrategy::Random })
+    }
+}
@@ -0,0 +1,173 @@
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, SqlConnection};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, IndexMap, SqlValue};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub enum SeedConflict {
+    // Keep the existing row.
+    Skip,
+    // Overwrite the non-key columns of the existing row.
+    Update,
+    // Insert unconditionally, the unique key on key_columns rejects duplicates.
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct SeedTable {
+    pub table: String,
+    pub key_columns: Vec<String>,
+    pub conflict: SeedConflict,
+    pub rows: Vec<IndexMap<String, SqlValue>>,
+}
+
+// Tables are applied in the declared order, so referenced rows go first.
+#[derive(Debug, Clone, Default)]
+pub struct SeedSet {
+    pub tables: Vec<SeedTable>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SeedTableReport {
+    pub table: String,
+    pub inserted: u64,
+    pub updated: u64,
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SeedReport {
+    pub tables: Vec<SeedTableReport>,
+}
+
+#[cfg(feature = "serde")]
+impl SeedSet {
+    // {"tables": [{"table": "roles", "key_columns": ["name"], "conflict": "Skip", "rows": [{"name": "admin"}]}]}
+    pub fn from_json(json: &str) -> crate::errors::SqlResult<Self> {
+        #[derive(serde::Deserialize)]
+        struct RawTable {
+            table: String,
+            key_columns: Vec<String>,
+            conflict: SeedConflict,
+            rows: Vec<serde_json::Map<String, serde_json::Value>>,
+        }
+        #[derive(serde::Deserialize)]
+        struct RawSet {
+            tables: Vec<RawTable>,
+        }
+
+        let raw: RawSet = serde_json::from_str(json)
+            .map_err(|e| crate::errors::sql_err!(crate::errors::SqlErrorCode::Failed, "invalid seed json: {}", e))?;
+        let mut tables = Vec::with_capacity(raw.tables.len());
+        for table in raw.tables {
+            let mut rows = Vec::with_capacity(table.rows.len());
+            for row in table.rows {
+                let mut values = IndexMap::with_capacity(row.len());
+                for (column, value) in row {
+                    let value = match value {
+                        serde_json::Value::Null => SqlValue::Null(None),
+                        serde_json::Value::Bool(v) => SqlValue::Bool(v),
+                        serde_json::Value::Number(v) => match (v.as_i64(), v.as_u64()) {
+                            (Some(v), _) => SqlValue::Int(v),
+                            (None, Some(v)) => SqlValue::UInt(v),
+                            _ => SqlValue::Float(v.as_f64().unwrap_or_default()),
+                        },
+                        serde_json::Value::String(v) => SqlValue::Text(v),
+                        v => SqlValue::Text(v.to_string()),
+                    };
+                    values.insert(column, value);
+                }
+                rows.push(values);
+            }
+            tables.push(SeedTable {
+                table: table.table,
+                key_columns: table.key_columns,
+                conflict: table.conflict,
+                rows,
+            });
+        }
+        Ok(Self { tables })
+    }
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Applies every table inside one transaction, nothing is kept if a row fails.
+    pub async fn apply_seed(&mut self, seed: &SeedSet) -> Result<SeedReport, EM::OutError> {
+        self.begin_transaction().await?;
+        let mut report = SeedReport::default();
+        for table in seed.tables.iter() {
+            let ret = self.apply_seed_table(table).await;
+            match ret {
+                Ok(table_report) => report.tables.push(table_report),
+                Err(e) => {
+                    let _ = self.rollback_transaction().await;
+                    return Err(e);
+                }
+            }
+        }
+        self.commit_transaction().await?;
+        Ok(report)
+    }
+
+    async fn apply_seed_table(&mut self, seed: &SeedTable) -> Result<SeedTableReport, EM::OutError> {
+        let quote = SqlBackend::from_db_name(DB::NAME).ident_quote();
+        let table = quote_qualified(seed.table.as_str(), quote);
+        let key_filter = seed.key_columns.iter()
+            .map(|c| format!("{} = ?", quote_ident(c, quote)))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        let mut report = SeedTableReport { table: seed.table.clone(), ..Default::default() };
+
+        for row in seed.rows.iter() {
+            let mut keys = Vec::with_capacity(seed.key_columns.len());
+            for column in seed.key_columns.iter() {
+                match row.get(column) {
+                    Some(value) => keys.push(value.clone()),
+                    None => return Err(EM::map(sqlx::Error::ColumnNotFound(column.clone()), format!("[{} {}]", line!(), seed.table).as_str())),
+                }
+            }
+
+            if seed.conflict != SeedConflict::Fail {
+                let sql = format!("SELECT 1 FROM {} WHERE {}", table, key_filter);
+                let mut query = sqlx::query::<DB>(sql.as_str());
+                for key in keys.iter() {
+                    query = query.bind_value(key.clone());
+                }
+                if self.query_optional(query).await?.is_some() {
+                    let values: Vec<(&String, &SqlValue)> = row.iter().filter(|(c, _)| !seed.key_columns.contains(*c)).collect();
+                    if seed.conflict == SeedConflict::Skip || values.is_empty() {
+                        report.skipped += 1;
+                        continue;
+                    }
+                    let sets = values.iter().map(|(c, _)| format!("{} = ?", quote_ident(c, quote))).collect::<Vec<_>>().join(", ");
+                    let sql = format!("UPDATE {} SET {} WHERE {}", table, sets, key_filter);
+                    let mut query = sqlx::query::<DB>(sql.as_str());
+                    for (_, value) in values {
+                        query = query.bind_value(value.clone());
+                    }
+                    for key in keys {
+                        query = query.bind_value(key);
+                    }
+                    self.execute_sql(query).await?;
+                    report.updated += 1;
+                    continue;
+                }
+            }
+
+            let columns = row.keys().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+            let sql = format!("INSERT INTO {} ({}) VALUES ({})", table, columns, vec!["?"; row.len()].join(", "));
+            let mut query = sqlx::query::<DB>(sql.as_str());
+            for value in row.values() {
+                query = query.bind_value(value.clone());
+            }
+            self.execute_sql(query).await?;
+            report.inserted += 1;
+        }
+        Ok(report)
+    }
+}
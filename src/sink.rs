@@ -0,0 +1,250 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sqlx::{Database, Executor};
+use crate::clock::Clock;
+use crate::db_helper::{ErrorMap, SqlConnection, SqlPool};
+use crate::seed::SeedConflict;
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, SqlValue};
+
+#[derive(Debug, Clone)]
+pub struct InsertSinkOptions {
+    // Buffered rows that trigger a flush.
+    pub batch_size: usize,
+    // Age of the oldest buffered row that triggers a flush on the next send.
+    pub max_age: Duration,
+    // Wraps every flush in its own transaction.
+    pub transactional: bool,
+    pub conflict: SeedConflict,
+    // Unique key used by SeedConflict::Update, required with it. Columns of the key are not
+    // overwritten by the update.
+    pub key_columns: Vec<String>,
+}
+
+impl Default for InsertSinkOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            max_age: Duration::from_secs(1),
+            transactional: false,
+            conflict: SeedConflict::Fail,
+            key_columns: Vec::new(),
+        }
+    }
+}
+
+pub enum SinkTarget<'a, DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    Pool(SqlPool<DB, EM>),
+    Conn(&'a mut SqlConnection<DB, EM>),
+}
+
+impl<'a, DB: Database, EM: ErrorMap<InError = sqlx::Error>> From<&SqlPool<DB, EM>> for SinkTarget<'a, DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn from(pool: &SqlPool<DB, EM>) -> Self {
+        SinkTarget::Pool(pool.clone())
+    }
+}
+
+impl<'a, DB: Database, EM: ErrorMap<InError = sqlx::Error>> From<&'a mut SqlConnection<DB, EM>> for SinkTarget<'a, DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    fn from(conn: &'a mut SqlConnection<DB, EM>) -> Self {
+        SinkTarget::Conn(conn)
+    }
+}
+
+pub struct InsertBatchError<E> {
+    // Position of the first row of the batch among all rows sent.
+    pub first_row: u64,
+    pub rows: usize,
+    pub error: E,
+}
+
+pub struct InsertSinkReport<E> {
+    pub rows_written: u64,
+    pub batches: u64,
+    pub errors: Vec<InsertBatchError<E>>,
+}
+
+// Buffers rows and writes them as multi-row inserts. send awaits the flush it triggers, so a
+// producer never gets more than batch_size rows ahead of the database. A failed batch is
+// recorded and skipped, the sink keeps accepting rows.
+pub struct InsertSink<'a, DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    target: SinkTarget<'a, DB, EM>,
+    table: String,
+    columns: Vec<String>,
+    options: InsertSinkOptions,
+    buffer: Vec<Vec<SqlValue>>,
+    clock: Arc<dyn Clock>,
+    oldest: Option<Instant>,
+    rows_sent: u64,
+    rows_written: u64,
+    batches: u64,
+    errors: Vec<InsertBatchError<EM::OutError>>,
+}
+
+impl<'a, DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> InsertSink<'a, DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Fails with ParameterMismatch for a sink without columns, and for SeedConflict::Update
+    // without key_columns or with a key column the sink does not write.
+    pub fn new(target: impl Into<SinkTarget<'a, DB, EM>>, table: &str, columns: &[&str], options: InsertSinkOptions) -> Result<Self, EM::OutError> {
+        if columns.is_empty() {
+            return Err(EM::map_parameter_mismatch(format!("insert sink {} has no columns", table).as_str()));
+        }
+        if options.conflict == SeedConflict::Update {
+            if options.key_columns.is_empty() {
+                return Err(EM::map_parameter_mismatch(format!("insert sink {} updates on conflict but has no key_columns", table).as_str()));
+            }
+            if let Some(key) = options.key_columns.iter().find(|k| !columns.contains(&k.as_str())) {
+                return Err(EM::map_parameter_mismatch(format!("key column {} is not a column of insert sink {}", key, table).as_str()));
+            }
+        }
+        let mut options = options;
+        let target = target.into();
+        let max_params = match &target {
+            SinkTarget::Pool(pool) => pool.max_bind_params(),
+            SinkTarget::Conn(conn) => conn.pool_state.bind_params(SqlBackend::from_db_name(DB::NAME)),
+        };
+        let max_rows = (max_params / columns.len().max(1)).max(1);
+        options.batch_size = options.batch_size.clamp(1, max_rows);
+        let clock = match &target {
+            SinkTarget::Pool(pool) => pool.clock(),
+            SinkTarget::Conn(conn) => conn.clock(),
+        };
+        Ok(Self {
+            target,
+            table: table.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            buffer: Vec::with_capacity(options.batch_size),
+            options,
+            clock,
+            oldest: None,
+            rows_sent: 0,
+            rows_written: 0,
+            batches: 0,
+            errors: Vec::new(),
+        })
+    }
+
+    pub async fn send(&mut self, row: Vec<SqlValue>) -> Result<(), EM::OutError> {
+        if row.len() != self.columns.len() {
+            return Err(EM::map_parameter_mismatch(format!("row has {} values, sink {} has {} columns", row.len(), self.table, self.columns.len()).as_str()));
+        }
+        if self.oldest.is_none() {
+            self.oldest = Some(self.clock.now());
+        }
+        self.buffer.push(row);
+        let aged = self.oldest.map(|t| self.clock.elapsed(t) >= self.options.max_age).unwrap_or(false);
+        if self.buffer.len() >= self.options.batch_size || aged {
+            self.flush().await;
+        }
+        Ok(())
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Writes the buffered rows, a failure is kept for the report returned by close.
+    pub async fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let batch = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.options.batch_size));
+        self.oldest = None;
+        let first_row = self.rows_sent;
+        self.rows_sent += batch.len() as u64;
+        self.batches += 1;
+
+        let ret = self.write_batch(&batch).await;
+        match ret {
+            Ok(()) => self.rows_written += batch.len() as u64,
+            Err(error) => self.errors.push(InsertBatchError { first_row, rows: batch.len(), error }),
+        }
+    }
+
+    pub async fn close(mut self) -> InsertSinkReport<EM::OutError> {
+        self.flush().await;
+        InsertSinkReport {
+            rows_written: self.rows_written,
+            batches: self.batches,
+            errors: self.errors,
+        }
+    }
+
+    fn insert_sql(&self, rows: usize) -> String {
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let quote = backend.ident_quote();
+        let table = quote_qualified(self.table.as_str(), quote);
+        let columns = self.columns.iter().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+        let row = format!("({})", vec!["?"; self.columns.len()].join(", "));
+        let values = vec![row.as_str(); rows].join(", ");
+        let updates: Vec<&String> = self.columns.iter().filter(|c| !self.options.key_columns.contains(*c)).collect();
+
+        match (self.options.conflict, backend) {
+            (SeedConflict::Skip, SqlBackend::MySql) => format!("INSERT IGNORE INTO {} ({}) VALUES {}", table, columns, values),
+            (SeedConflict::Skip, _) => format!("INSERT OR IGNORE INTO {} ({}) VALUES {}", table, columns, values),
+            (SeedConflict::Update, SqlBackend::MySql) if !updates.is_empty() => {
+                let sets = updates.iter().map(|c| {
+                    let c = quote_ident(c, quote);
+                    format!("{} = VALUES({})", c, c)
+                }).collect::<Vec<_>>().join(", ");
+                format!("INSERT INTO {} ({}) VALUES {} ON DUPLICATE KEY UPDATE {}", table, columns, values, sets)
+            }
+            (SeedConflict::Update, SqlBackend::MySql) => format!("INSERT IGNORE INTO {} ({}) VALUES {}", table, columns, values),
+            (SeedConflict::Update, _) => {
+                let keys = self.options.key_columns.iter().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+                let action = if updates.is_empty() {
+                    "NOTHING".to_string()
+                } else {
+                    let sets = updates.iter().map(|c| {
+                        let c = quote_ident(c, quote);
+                        format!("{} = excluded.{}", c, c)
+                    }).collect::<Vec<_>>().join(", ");
+                    format!("UPDATE SET {}", sets)
+                };
+                format!("INSERT INTO {} ({}) VALUES {} ON CONFLICT ({}) DO {}", table, columns, values, keys, action)
+            }
+            (SeedConflict::Fail, _) => format!("INSERT INTO {} ({}) VALUES {}", table, columns, values),
+        }
+    }
+
+    async fn write_batch(&mut self, batch: &[Vec<SqlValue>]) -> Result<(), EM::OutError> {
+        let sql = self.insert_sql(batch.len());
+        let transactional = self.options.transactional;
+        let mut pool_conn = None;
+        let conn = match &mut self.target {
+            SinkTarget::Pool(pool) => pool_conn.insert(pool.get_conn().await?),
+            SinkTarget::Conn(conn) => &mut **conn,
+        };
+
+        if transactional {
+            conn.begin_transaction().await?;
+        }
+        let mut query = sqlx::query::<DB>(sql.as_str());
+        for row in batch.iter() {
+            for value in row.iter() {
+                query = query.bind_value(value.clone());
+            }
+        }
+        let ret = conn.execute_sql(query).await;
+        match ret {
+            Ok(_) => {
+                if transactional {
+                    conn.commit_transaction().await?;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if transactional {
+                    let _ = conn.rollback_transaction().await;
+                }
+                Err(e)
+            }
+        }
+    }
+}
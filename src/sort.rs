@@ -0,0 +1,92 @@
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::target::SqlBackend;
+use crate::value::IndexMap;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+// Whitelist of the keys a caller may sort by, each mapped to the column expression it sorts on.
+// Expressions come from the application and are used as given, user input only picks keys.
+#[derive(Debug, Clone, Default)]
+pub struct SortSpec {
+    allowed: IndexMap<String, String>,
+    nulls_last: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SortOrder {
+    pub keys: Vec<(String, SortDirection)>,
+    expressions: Vec<String>,
+    nulls_last: bool,
+}
+
+impl SortSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, key: &str, expression: &str) -> Self {
+        self.allowed.insert(key.to_string(), expression.to_string());
+        self
+    }
+
+    // Sorts NULL after every value in both directions, sqlite and mysql put it first by default.
+    pub fn nulls_last(mut self, nulls_last: bool) -> Self {
+        self.nulls_last = nulls_last;
+        self
+    }
+
+    // Parses "name,-created_at", a leading '-' sorts descending and '+' ascending. A key that is
+    // not allowed or given twice fails with InvalidArgument.
+    pub fn parse(&self, input: &str) -> SqlResult<SortOrder> {
+        let mut order = SortOrder { nulls_last: self.nulls_last, ..Default::default() };
+        for part in input.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (key, direction) = match part.strip_prefix('-') {
+                Some(key) => (key, SortDirection::Desc),
+                None => (part.strip_prefix('+').unwrap_or(part), SortDirection::Asc),
+            };
+            let expression = match self.allowed.get(key) {
+                Some(expression) => expression,
+                None => {
+                    let allowed = self.allowed.keys().map(|k| k.as_str()).collect::<Vec<_>>().join(", ");
+                    return Err(sql_err!(SqlErrorCode::InvalidArgument, "unknown sort key {:?}, allowed: {}", key, allowed));
+                }
+            };
+            if order.keys.iter().any(|(k, _)| k == key) {
+                return Err(sql_err!(SqlErrorCode::InvalidArgument, "sort key {:?} given more than once", key));
+            }
+            order.keys.push((key.to_string(), direction));
+            order.expressions.push(expression.clone());
+        }
+        Ok(order)
+    }
+}
+
+impl SortOrder {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // " ORDER BY ..." to append to a statement, empty when no key was given. sqlite 3.30+ has
+    // NULLS LAST, mysql gets an extra `expr IS NULL` term sorting NULL after values.
+    pub fn to_sql(&self, backend: SqlBackend) -> String {
+        if self.keys.is_empty() {
+            return String::new();
+        }
+        let terms = self.keys.iter().zip(self.expressions.iter()).map(|((_, direction), expression)| {
+            let dir = match direction {
+                SortDirection::Asc => "ASC",
+                SortDirection::Desc => "DESC",
+            };
+            match (self.nulls_last, backend) {
+                (false, _) => format!("{} {}", expression, dir),
+                (true, SqlBackend::MySql) => format!("{} IS NULL, {} {}", expression, expression, dir),
+                (true, _) => format!("{} {} NULLS LAST", expression, dir),
+            }
+        }).collect::<Vec<_>>();
+        format!(" ORDER BY {}", terms.join(", "))
+    }
+}
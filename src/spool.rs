@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use sqlx::{Database, Execute, Executor};
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::value::{IndexMap, RowToMap, SqlValue, SqlValueType};
+
+static NEXT_SPOOL: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SpoolOptions {
+    // Fails the spool once the file would grow past this many bytes.
+    pub max_bytes: Option<u64>,
+}
+
+// Removes the spool file once the result is dropped, or when spooling failed.
+struct SpoolFile(PathBuf);
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            log::warn!("remove spool file {} failed: {}", self.0.display(), e);
+        }
+    }
+}
+
+// Rows of a query kept in a file in the order the query returned them, for results too big
+// for memory that are still read out of order. Each row is its length and its values; the
+// offsets of the rows stay in memory, 8 bytes per row.
+pub struct SpooledResult {
+    // Declared before file so it is closed before the file is removed.
+    reader: Mutex<BufReader<File>>,
+    file: SpoolFile,
+    columns: Vec<String>,
+    offsets: Vec<u64>,
+    bytes: u64,
+}
+
+impl SpooledResult {
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    // Empty when the query returned no rows.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file.0
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn get(&self, index: usize) -> SqlResult<Option<IndexMap<String, SqlValue>>> {
+        let offset = match self.offsets.get(index) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+        let mut reader = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::Start(offset)).map_err(|e| sql_err!(SqlErrorCode::Failed, "seek spool {} failed: {}", self.file.0.display(), e))?;
+        read_row(&mut *reader, &self.columns).map(Some)
+    }
+
+    // Reads the rows in order through a handle of its own, so get stays usable meanwhile.
+    pub fn iter(&self) -> SqlResult<SpoolIter<'_>> {
+        let file = File::open(&self.file.0).map_err(|e| sql_err!(SqlErrorCode::Failed, "open spool {} failed: {}", self.file.0.display(), e))?;
+        Ok(SpoolIter {
+            result: self,
+            reader: BufReader::new(file),
+            next: 0,
+        })
+    }
+}
+
+pub struct SpoolIter<'a> {
+    result: &'a SpooledResult,
+    reader: BufReader<File>,
+    next: usize,
+}
+
+impl Iterator for SpoolIter<'_> {
+    type Item = SqlResult<IndexMap<String, SqlValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.result.len() {
+            return None;
+        }
+        self.next += 1;
+        Some(read_row(&mut self.reader, &self.result.columns))
+    }
+}
+
+fn value_type_tag(value_type: Option<SqlValueType>) -> u8 {
+    match value_type {
+        None => 0,
+        Some(SqlValueType::Bool) => 1,
+        Some(SqlValueType::Int) => 2,
+        Some(SqlValueType::UInt) => 3,
+        Some(SqlValueType::Float) => 4,
+        Some(SqlValueType::Text) => 5,
+        Some(SqlValueType::Blob) => 6,
+    }
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &SqlValue) {
+    match value {
+        SqlValue::Null(value_type) => out.extend_from_slice(&[0, value_type_tag(*value_type)]),
+        SqlValue::Bool(v) => out.extend_from_slice(&[1, *v as u8]),
+        SqlValue::Int(v) => {
+            out.push(2);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        SqlValue::UInt(v) => {
+            out.push(3);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        SqlValue::Float(v) => {
+            out.push(4);
+            out.extend_from_slice(&v.to_bits().to_le_bytes());
+        }
+        SqlValue::Text(v) => {
+            out.push(5);
+            out.extend_from_slice(&(v.len() as u64).to_le_bytes());
+            out.extend_from_slice(v.as_bytes());
+        }
+        SqlValue::Blob(v) => {
+            out.push(6);
+            out.extend_from_slice(&(v.len() as u64).to_le_bytes());
+            out.extend_from_slice(v);
+        }
+    }
+}
+
+fn decode_value(bytes: &[u8], pos: &mut usize) -> Option<SqlValue> {
+    let mut take = |n: usize| -> Option<&[u8]> {
+        let slice = bytes.get(*pos..pos.checked_add(n)?)?;
+        *pos += n;
+        Some(slice)
+    };
+    let word = |b: &[u8]| -> [u8; 8] { b.try_into().unwrap_or_default() };
+    let value = match take(1)?[0] {
+        0 => SqlValue::Null(match take(1)?[0] {
+            1 => Some(SqlValueType::Bool),
+            2 => Some(SqlValueType::Int),
+            3 => Some(SqlValueType::UInt),
+            4 => Some(SqlValueType::Float),
+            5 => Some(SqlValueType::Text),
+            6 => Some(SqlValueType::Blob),
+            _ => None,
+        }),
+        1 => SqlValue::Bool(take(1)?[0] != 0),
+        2 => SqlValue::Int(i64::from_le_bytes(word(take(8)?))),
+        3 => SqlValue::UInt(u64::from_le_bytes(word(take(8)?))),
+        4 => SqlValue::Float(f64::from_bits(u64::from_le_bytes(word(take(8)?)))),
+        5 => {
+            let len = usize::try_from(u64::from_le_bytes(word(take(8)?))).ok()?;
+            SqlValue::Text(String::from_utf8(take(len)?.to_vec()).ok()?)
+        }
+        6 => {
+            let len = usize::try_from(u64::from_le_bytes(word(take(8)?))).ok()?;
+            SqlValue::Blob(take(len)?.to_vec())
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn read_row(reader: &mut impl Read, columns: &[String]) -> SqlResult<IndexMap<String, SqlValue>> {
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len).map_err(|e| sql_err!(SqlErrorCode::Failed, "read spool failed: {}", e))?;
+    let mut record = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut record).map_err(|e| sql_err!(SqlErrorCode::Failed, "read spool failed: {}", e))?;
+    let mut pos = 0;
+    let mut row = IndexMap::with_capacity(columns.len());
+    for column in columns.iter() {
+        let value = decode_value(&record, &mut pos)
+            .ok_or_else(|| sql_err!(SqlErrorCode::Failed, "spool record of column {} is corrupt", column))?;
+        row.insert(column.clone(), value);
+    }
+    Ok(row)
+}
+
+fn io_error<EM: ErrorMap<InError = sqlx::Error>>(e: std::io::Error, msg: String) -> EM::OutError {
+    EM::map(sqlx::Error::Io(e), msg.as_str())
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+    // Rows of query written to a file in spool_dir, for results too big to hold in memory that
+    // are still read by index. The rows are streamed into the file, one held in memory at a
+    // time. Fails once the file outgrows options.max_bytes.
+    pub async fn spool_query<'q>(&mut self, query: sqlx::query::Query<'q, DB, DB::Arguments<'q>>, spool_dir: &Path,
+                                 options: SpoolOptions) -> Result<SpooledResult, EM::OutError>
+    where DB::Row: RowToMap {
+        let sql = query.sql().to_string();
+        let path = spool_dir.join(format!("sfo-sql-spool-{}-{}.bin", std::process::id(), NEXT_SPOOL.fetch_add(1, Ordering::Relaxed)));
+        let writer = File::create(&path).map_err(|e| io_error::<EM>(e, format!("create spool {}", path.display())))?;
+        let file = SpoolFile(path);
+        let mut writer = BufWriter::new(writer);
+        let mut columns = Vec::new();
+        let mut offsets = Vec::new();
+        let mut bytes = 0u64;
+        let mut record = Vec::new();
+
+        let mut rows = self.query_stream(query)?;
+        while let Some(row) = rows.next().await {
+            let row = map_row::<EM, _>(&row?, sql.as_str())?;
+            if offsets.is_empty() {
+                columns = row.keys().cloned().collect();
+            }
+            record.clear();
+            for value in row.values() {
+                encode_value(&mut record, value);
+            }
+            let size = 8 + record.len() as u64;
+            if let Some(max_bytes) = options.max_bytes {
+                if bytes + size > max_bytes {
+                    let msg = format!("spool of more than {} rows exceeds max_bytes {}", offsets.len(), max_bytes);
+                    return Err(io_error::<EM>(std::io::Error::other(msg.clone()), msg));
+                }
+            }
+            offsets.push(bytes);
+            writer.write_all(&(record.len() as u64).to_le_bytes())
+                .and_then(|_| writer.write_all(&record))
+                .map_err(|e| io_error::<EM>(e, format!("write spool {}", file.0.display())))?;
+            bytes += size;
+        }
+        drop(rows);
+        writer.flush().map_err(|e| io_error::<EM>(e, format!("write spool {}", file.0.display())))?;
+        drop(writer);
+
+        let reader = File::open(&file.0).map_err(|e| io_error::<EM>(e, format!("open spool {}", file.0.display())))?;
+        Ok(SpooledResult {
+            reader: Mutex::new(BufReader::new(reader)),
+            file,
+            columns,
+            offsets,
+            bytes,
+        })
+    }
+}
@@ -0,0 +1,349 @@
+use crate::target::SqlBackend;
+
+// Minimal sql scanner shared by the helpers that need to look at statement text,
+// it skips string literals, quoted identifiers and comments. Backslash escapes and `#` comments
+// only exist in mysql.
+
+// Counts the arguments a statement expects. `?` takes the next index and `?NNN` (sqlite)
+// refers to an explicit one, so the count is the highest index referenced. postgres only
+// numbers them, `$N`, and `?` is one of its operators.
+pub(crate) fn count_placeholders(sql: &str, backend: SqlBackend) -> usize {
+    let mysql = backend == SqlBackend::MySql;
+    let postgres = backend == SqlBackend::Postgres;
+    let bytes = sql.as_bytes();
+    let mut max_index = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' => {
+                i = skip_quoted(bytes, i, bytes[i], mysql);
+            }
+            b'[' if !postgres => {
+                i = skip_until(bytes, i + 1, b"]");
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = skip_until(bytes, i + 2, b"\n");
+            }
+            b'#' if mysql => {
+                i = skip_until(bytes, i + 1, b"\n");
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_until(bytes, i + 2, b"*/");
+            }
+            b'$' if postgres => {
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                if i > start {
+                    let index = sql[start..i].parse::<usize>().unwrap_or(0);
+                    max_index = max_index.max(index);
+                } else {
+                    // A dollar-quoted string, $$...$$ or $tag$...$tag$.
+                    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                        i += 1;
+                    }
+                    if bytes.get(i) == Some(&b'$') {
+                        let tag = &bytes[start - 1..=i];
+                        i = skip_until(bytes, i + 1, tag);
+                    }
+                }
+            }
+            b'?' if !postgres => {
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                if i > start {
+                    let index = sql[start..i].parse::<usize>().unwrap_or(0);
+                    max_index = max_index.max(index);
+                } else {
+                    max_index += 1;
+                }
+            }
+            _ => {
+                i += 1;
+            }
+        }
+    }
+    max_index
+}
+
+// Statements of a ;-separated script, trimmed and without the empty ones. Semicolons in
+// literals, quoted identifiers, comments and postgres dollar quotes do not split, the ones ending
+// the statements of a sqlite trigger body do.
+pub(crate) fn split_statements(sql: &str, backend: SqlBackend) -> Vec<&str> {
+    let mysql = backend == SqlBackend::MySql;
+    let postgres = backend == SqlBackend::Postgres;
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' => i = skip_quoted(bytes, i, bytes[i], mysql),
+            b'[' if !postgres => i = skip_until(bytes, i + 1, b"]"),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => i = skip_until(bytes, i + 2, b"\n"),
+            b'#' if mysql => i = skip_until(bytes, i + 1, b"\n"),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_until(bytes, i + 2, b"*/"),
+            b'$' if postgres => {
+                let tag_start = i;
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                if bytes.get(i) == Some(&b'$') && !bytes[tag_start + 1].is_ascii_digit() {
+                    i = skip_until(bytes, i + 1, &bytes[tag_start..=i]);
+                }
+            }
+            b';' => {
+                statements.push(sql[start..i].trim());
+                i += 1;
+                start = i;
+            }
+            _ => i += 1,
+        }
+    }
+    statements.push(sql[start..].trim());
+    statements.retain(|s| !s.is_empty());
+    statements
+}
+
+// Returns the position after the closing quote, a doubled or backslash-escaped quote does not close it.
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8, backslash_escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if backslash_escapes && bytes[i] == b'\\' && quote != b'`' {
+            i += 2;
+        } else if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+fn skip_until(bytes: &[u8], start: usize, end: &[u8]) -> usize {
+    let mut i = start;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(end) {
+            return i + end.len();
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+// Statement text with string and numeric literals replaced by `?`, comments dropped and
+// whitespace collapsed, so statements differing only in their values share one form.
+pub(crate) fn normalize_sql(sql: &str, backend: SqlBackend) -> String {
+    let mysql = backend == SqlBackend::MySql;
+    let bytes = sql.as_bytes();
+    let is_word = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80;
+    let mut out = String::with_capacity(sql.len());
+    let mut space = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        // Whether the token just scanned is a literal.
+        let literal = match bytes[i] {
+            b'\'' => {
+                i = skip_quoted(bytes, i, b'\'', mysql);
+                true
+            }
+            // Double quotes delimit strings in mysql and identifiers in sqlite.
+            b'"' => {
+                i = skip_quoted(bytes, i, b'"', mysql);
+                mysql
+            }
+            b'`' => {
+                i = skip_quoted(bytes, i, b'`', mysql);
+                false
+            }
+            b'[' if !mysql => {
+                i = skip_until(bytes, i + 1, b"]");
+                false
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = skip_until(bytes, i + 2, b"\n");
+                space = true;
+                continue;
+            }
+            b'#' if mysql => {
+                i = skip_until(bytes, i + 1, b"\n");
+                space = true;
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = skip_until(bytes, i + 2, b"*/");
+                space = true;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                space = true;
+                continue;
+            }
+            c if is_word(c) => {
+                while i < bytes.len() && (is_word(bytes[i]) || (bytes[start].is_ascii_digit() && bytes[i] == b'.')) {
+                    i += 1;
+                }
+                bytes[start].is_ascii_digit()
+            }
+            _ => {
+                i += 1;
+                false
+            }
+        };
+        if space && !out.is_empty() {
+            out.push(' ');
+        }
+        space = false;
+        if literal {
+            out.push('?');
+        } else {
+            out.push_str(&sql[start..i]);
+        }
+    }
+    out
+}
+
+// Words of a statement outside literals and comments, quoted identifiers unquoted and
+// lowercased. Every other character is a token of its own.
+fn words(sql: &str, backend: SqlBackend) -> Vec<String> {
+    let mysql = backend == SqlBackend::MySql;
+    let bytes = sql.as_bytes();
+    let is_word = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80;
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'\'' => {
+                i = skip_quoted(bytes, i, b'\'', mysql);
+                out.push("'".to_string());
+            }
+            b'"' if mysql => {
+                i = skip_quoted(bytes, i, b'"', mysql);
+                out.push("'".to_string());
+            }
+            q @ (b'"' | b'`') => {
+                i = skip_quoted(bytes, i, q, mysql);
+                let end = if i > start + 1 && bytes[i - 1] == q { i - 1 } else { i };
+                let quote = q as char;
+                out.push(sql[start + 1..end].replace(&format!("{}{}", quote, quote), &quote.to_string()).to_lowercase());
+            }
+            b'[' if !mysql => {
+                i = skip_until(bytes, i + 1, b"]");
+                let end = if bytes[i - 1] == b']' { i - 1 } else { i };
+                out.push(sql[start + 1..end].to_lowercase());
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => i = skip_until(bytes, i + 2, b"\n"),
+            b'#' if mysql => i = skip_until(bytes, i + 1, b"\n"),
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_until(bytes, i + 2, b"*/"),
+            c if c.is_ascii_whitespace() => i += 1,
+            c if is_word(c) => {
+                while i < bytes.len() && is_word(bytes[i]) {
+                    i += 1;
+                }
+                out.push(sql[start..i].to_lowercase());
+            }
+            _ => {
+                i += 1;
+                out.push(sql[start..i].to_string());
+            }
+        }
+    }
+    out
+}
+
+// Whether a statement already carries a RETURNING clause.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+pub(crate) fn has_returning(sql: &str, backend: SqlBackend) -> bool {
+    words(sql, backend).iter().any(|w| w == "returning")
+}
+
+// Whether a statement only reads: a SELECT, VALUES, SHOW or EXPLAIN, also behind a WITH that
+// names no write.
+pub(crate) fn is_read(sql: &str, backend: SqlBackend) -> bool {
+    let words = words(sql, backend);
+    match words.first().map(|w| w.as_str()) {
+        Some("select" | "values" | "show" | "explain" | "describe" | "desc") => true,
+        Some("with") => !words.iter().any(|w| matches!(w.as_str(), "insert" | "replace" | "update" | "delete")),
+        _ => false,
+    }
+}
+
+// Tables a statement writes to, without schema prefix and lowercased. None for a statement that
+// does not write, an empty list for a write whose tables could not be told, which callers treat
+// as a write to every table.
+pub(crate) fn written_tables(sql: &str, backend: SqlBackend) -> Option<Vec<String>> {
+    let words = words(sql, backend);
+    // A WITH prefix is skipped by looking for the first write keyword at any position.
+    let first = words.first()?;
+    let start = if first == "with" {
+        words.iter().position(|w| matches!(w.as_str(), "insert" | "replace" | "update" | "delete"))?
+    } else {
+        0
+    };
+    let skip = |mut i: usize, optional: &[&str]| {
+        while i < words.len() && optional.contains(&words[i].as_str()) {
+            i += 1;
+        }
+        i
+    };
+    // Name at position i, taking the part after a schema prefix.
+    let name_at = |i: usize| -> Option<String> {
+        let name = words.get(i)?;
+        if name.len() == 1 && !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        match (words.get(i + 1), words.get(i + 2)) {
+            (Some(dot), Some(table)) if dot == "." => Some(table.clone()),
+            _ => Some(name.clone()),
+        }
+    };
+    let modifiers = ["low_priority", "delayed", "high_priority", "quick", "ignore", "or", "rollback", "abort", "fail", "replace"];
+    let tables = match words[start].as_str() {
+        "insert" | "replace" => name_at(skip(skip(start + 1, &modifiers), &["into"])).into_iter().collect(),
+        "update" => name_at(skip(start + 1, &modifiers)).into_iter().collect(),
+        "delete" => {
+            // Multi-table deletes name their targets before FROM, those are not resolved.
+            let i = skip(start + 1, &modifiers);
+            if words.get(i).map(|w| w == "from").unwrap_or(false) {
+                name_at(i + 1).into_iter().collect()
+            } else {
+                Vec::new()
+            }
+        }
+        "drop" | "alter" | "truncate" => {
+            let i = skip(start + 1, &["temporary"]);
+            match words.get(i).map(|w| w.as_str()) {
+                Some("table") => name_at(skip(i + 1, &["if", "exists"])).into_iter().collect(),
+                Some("index") | Some("view") | Some("trigger") => return None,
+                _ if words[start] == "truncate" => name_at(i).into_iter().collect(),
+                _ => return None,
+            }
+        }
+        "rename" => {
+            // RENAME TABLE a TO b, c TO d touches every name listed.
+            let mut tables = Vec::new();
+            let mut i = start + 2;
+            while let Some(name) = name_at(i) {
+                tables.push(name);
+                i += if words.get(i + 1).map(|w| w == ".").unwrap_or(false) { 3 } else { 1 };
+                i = skip(i, &["to", ","]);
+            }
+            tables
+        }
+        _ => return None,
+    };
+    Some(tables)
+}
@@ -1,9 +1,20 @@
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 use log::LevelFilter;
-use sqlx::ConnectOptions;
-use crate::errors::{sql_err, SqlError, SqlErrorCode, SqlResult};
+use sqlx::{Column, ConnectOptions, Execute, TypeInfo, ValueRef};
+use crate::errors::{log_sql_error, sql_err, SqlError, SqlErrorCode, SqlErrorSource, SqlResult};
 pub use crate::db_helper::*;
+use crate::sql_lexer::has_returning;
+use crate::text_search::{quote_ident, quote_qualified, search_terms};
+use crate::value::{named_column_error, sum_sql};
+
+pub mod manager;
+pub mod recover;
+pub mod uri;
+
+pub use manager::{SqlitePoolManager, TenantPoolOptions};
+pub use uri::SqliteUriBuilder;
 
 pub type SqlDB = sqlx::Sqlite;
 pub type SqlRawConnection = sqlx::SqliteConnection;
@@ -13,6 +24,16 @@ pub type SqlQuery<'a> = sqlx::query::Query<'a, sqlx::Sqlite, <sqlx::Sqlite as sq
 pub type RawSqlPool = sqlx::SqlitePool;
 pub type SqlArguments<'a> = <sqlx::Sqlite as sqlx::Database>::Arguments<'a>;
 pub type SqliteJournalMode = sqlx::sqlite::SqliteJournalMode;
+pub type SqliteSynchronous = sqlx::sqlite::SqliteSynchronous;
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SqliteFeature {
+    Json1,
+    Fts5,
+    Rtree,
+    MathFunctions,
+}
 
 #[derive(Clone)]
 pub struct RawErrorToSqlError;
@@ -24,39 +45,173 @@ impl ErrorMap for RawErrorToSqlError {
     fn map(e: sqlx::Error, msg: &str) -> SqlError {
         match e {
             sqlx::Error::RowNotFound => {
-                // let msg = format!("not found, {}", msg);
-                sql_err!(SqlErrorCode::NotFound, "not found")
+                let msg = format!("not found, {}", msg);
+                log_sql_error(SqlErrorCode::NotFound, msg.as_str());
+                SqlError::from((SqlErrorCode::NotFound, "not found", e))
             },
             sqlx::Error::Database(ref err) => {
                 let msg = format!("sql error: {:?} info:{}", e, msg);
-                if cfg!(test) {
-                    println!("{}", msg);
-                } else {
-                    log::error!("{}", msg);
-                }
-
-                if let Some(code) = err.code() {
-                    if code.to_string().as_str() == "1555" {
-                        return sql_err!(SqlErrorCode::AlreadyExists, "already exists");
-                    }
+                let code = match err.code().as_deref() {
+                    Some("1555") | Some("2067") => SqlErrorCode::AlreadyExists,
+                    Some("17") => SqlErrorCode::SchemaChanged,
+                    Some("5") | Some("517") | Some("6") => SqlErrorCode::Busy,
+                    Some("8") | Some("264") | Some("520") | Some("776") | Some("1032") | Some("1288") | Some("1544") => SqlErrorCode::ReadOnly,
+                    _ => SqlErrorCode::Failed,
+                };
+                log_sql_error(code, msg.as_str());
+                if code == SqlErrorCode::AlreadyExists {
+                    return SqlError::from((SqlErrorCode::AlreadyExists, "already exists", e));
                 }
-                sql_err!(SqlErrorCode::Failed, "{}", msg)
+                SqlError::from((code, msg, e))
+            }
+            sqlx::Error::Io(ref err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                let msg = format!("sql error: {} info:{}", err, msg);
+                log_sql_error(SqlErrorCode::Timeout, msg.as_str());
+                SqlError::from((SqlErrorCode::Timeout, msg, e))
+            }
+            sqlx::Error::PoolTimedOut => {
+                let msg = format!("sql error: {:?} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Timeout, msg.as_str());
+                SqlError::from((SqlErrorCode::Timeout, msg, e))
+            }
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::ColumnNotFound(_) | sqlx::Error::Decode(_) => {
+                let msg = format!("decode error: {} info:{}", e, msg);
+                log_sql_error(SqlErrorCode::Failed, msg.as_str());
+                SqlError::from((SqlErrorCode::Failed, msg, e))
             }
             _ => {
                 let msg = format!("sql error: {:?} info:{}", e, msg);
-                if cfg!(test) {
-                    println!("{}", msg);
-                } else {
-                    log::error!("{}", msg);
-                }
-                sql_err!(SqlErrorCode::Failed, "")
+                log_sql_error(SqlErrorCode::Failed, msg.as_str());
+                SqlError::from((SqlErrorCode::Failed, "", e))
             }
         }
     }
+
+    fn map_parameter_mismatch(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::ParameterMismatch, msg);
+        sql_err!(SqlErrorCode::ParameterMismatch, "{}", msg)
+    }
+
+    fn map_shutting_down(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::ShuttingDown, msg);
+        sql_err!(SqlErrorCode::ShuttingDown, "{}", msg)
+    }
+
+    fn map_rows_not_affected(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::RowsNotAffected, msg);
+        sql_err!(SqlErrorCode::RowsNotAffected, "{}", msg)
+    }
+
+    fn map_not_in_transaction(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::NotInTransaction, msg);
+        sql_err!(SqlErrorCode::NotInTransaction, "{}", msg)
+    }
+
+    fn map_locked(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::Locked, msg);
+        sql_err!(SqlErrorCode::Locked, "{}", msg)
+    }
+
+    fn map_rollback_only(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::RollbackOnly, msg);
+        sql_err!(SqlErrorCode::RollbackOnly, "{}", msg)
+    }
+
+    fn map_guard_tripped(msg: &str) -> SqlError {
+        log_sql_error(SqlErrorCode::GuardTripped, msg);
+        sql_err!(SqlErrorCode::GuardTripped, "{}", msg)
+    }
+
+    fn is_transient(e: &SqlError) -> bool {
+        e.code().is_retryable()
+    }
+
+    fn is_disconnect(e: &SqlError) -> bool {
+        matches!(e.sqlx_error(), Some(sqlx::Error::Io(_)) | Some(sqlx::Error::Tls(_)) | Some(sqlx::Error::WorkerCrashed))
+    }
 }
 
 pub type SqlPool = crate::db_helper::SqlPool<sqlx::Sqlite, RawErrorToSqlError>;
 pub type SqlConnection = crate::db_helper::SqlConnection<sqlx::Sqlite, RawErrorToSqlError>;
+pub type ConfigStore = crate::db_helper::ConfigStore<sqlx::Sqlite, RawErrorToSqlError>;
+pub type Outbox = crate::db_helper::Outbox<sqlx::Sqlite, RawErrorToSqlError>;
+pub type StatementBatcher = crate::db_helper::StatementBatcher<sqlx::Sqlite, RawErrorToSqlError>;
+pub type LeasedConnection = crate::db_helper::LeasedConnection<sqlx::Sqlite, RawErrorToSqlError>;
+pub type WriteCoalescer = crate::db_helper::WriteCoalescer<sqlx::Sqlite, RawErrorToSqlError>;
+pub type StatementCatalog = crate::db_helper::StatementCatalog<sqlx::Sqlite>;
+pub type InsertSink<'a> = crate::db_helper::InsertSink<'a, sqlx::Sqlite, RawErrorToSqlError>;
+pub type SwitchablePool = crate::db_helper::SwitchablePool<sqlx::Sqlite, RawErrorToSqlError>;
+pub type UnitOfWork = crate::db_helper::UnitOfWork<sqlx::Sqlite>;
+
+// Bound parameter limit of sqlite before 3.32.
+const LEGACY_MAX_BIND_PARAMS: usize = 999;
+
+// Pragmas set on every connection of a pool opened with open_with_options. None keeps the
+// default of sqlx, the default busy_timeout is the 300s open uses.
+#[derive(Debug, Clone)]
+pub struct SqliteOpenOptions {
+    pub journal_mode: Option<SqliteJournalMode>,
+    pub synchronous: Option<SqliteSynchronous>,
+    pub foreign_keys: Option<bool>,
+    pub busy_timeout: Duration,
+    // Pages when positive, KiB when negative, as PRAGMA cache_size takes it.
+    pub cache_size: Option<i64>,
+    // Bytes of the file to memory map, 0 turns mmap off.
+    pub mmap_size: Option<u64>,
+    pub pool: PoolConfig,
+}
+
+impl Default for SqliteOpenOptions {
+    fn default() -> Self {
+        Self {
+            journal_mode: None,
+            synchronous: None,
+            foreign_keys: None,
+            busy_timeout: Duration::from_secs(300),
+            cache_size: None,
+            mmap_size: None,
+            pool: PoolConfig::default(),
+        }
+    }
+}
+
+impl SqliteOpenOptions {
+    fn apply(&self, mut options: sqlx::sqlite::SqliteConnectOptions, read_only: bool) -> sqlx::sqlite::SqliteConnectOptions {
+        options = options.busy_timeout(self.busy_timeout);
+        // A read-only connection cannot switch its journal mode, and the pragmas changing how
+        // writes are made have nothing to act on.
+        if !read_only {
+            if let Some(journal_mode) = self.journal_mode {
+                options = options.journal_mode(journal_mode);
+            }
+            if let Some(synchronous) = self.synchronous {
+                options = options.synchronous(synchronous);
+            }
+        }
+        if let Some(foreign_keys) = self.foreign_keys {
+            options = options.foreign_keys(foreign_keys);
+        }
+        if let Some(cache_size) = self.cache_size {
+            options = options.pragma("cache_size", cache_size.to_string());
+        }
+        if let Some(mmap_size) = self.mmap_size {
+            options = options.pragma("mmap_size", mmap_size.to_string());
+        }
+        options
+    }
+}
+
+impl RowsAffected for sqlx::sqlite::SqliteQueryResult {
+    fn affected(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+impl ReplayArguments for sqlx::Sqlite {
+    fn replay_arguments<'q>(arguments: &Self::Arguments<'q>) -> Option<Self::Arguments<'q>> {
+        Some(arguments.clone())
+    }
+}
 
 impl SqlPool {
 
@@ -64,19 +219,52 @@ impl SqlPool {
                       max_connections: u32,
                       journal_mode: Option<sqlx::sqlite::SqliteJournalMode>,
     ) -> SqlResult<Self> {
-        log::info!("open pool {} max_connections {}", uri, max_connections);
-            let pool_options = sqlx::sqlite::SqlitePoolOptions::new()
-                .max_connections(max_connections)
-                .acquire_timeout(Duration::from_secs(300))
-                .min_connections(0)
-                .idle_timeout(Duration::from_secs(300));
-            let mut options = sqlx::sqlite::SqliteConnectOptions::from_str(uri).map_err(|e| {
+        Self::open_inner(uri, &journal_mode_options(PoolConfig::new(max_connections), journal_mode), false).await
+    }
+
+    pub async fn open_with_config(uri: &str,
+                                  config: &PoolConfig,
+                                  journal_mode: Option<sqlx::sqlite::SqliteJournalMode>,
+    ) -> SqlResult<Self> {
+        Self::open_inner(uri, &journal_mode_options(*config, journal_mode), false).await
+    }
+
+    // e.g. wal with synchronous = NORMAL:
+    // SqliteOpenOptions { journal_mode: Some(SqliteJournalMode::Wal), synchronous: Some(SqliteSynchronous::Normal), ..Default::default() }
+    pub async fn open_with_options(uri: &str, open_options: &SqliteOpenOptions) -> SqlResult<Self> {
+        Self::open_inner(uri, open_options, false).await
+    }
+
+    // Same as open, but a database file on read-only storage is opened read-only and immutable
+    // instead of failing, for databases bundled in a read-only app package.
+    pub async fn open_with_read_only_fallback(uri: &str,
+                                              max_connections: u32,
+                                              journal_mode: Option<sqlx::sqlite::SqliteJournalMode>,
+    ) -> SqlResult<Self> {
+        Self::open_inner(uri, &journal_mode_options(PoolConfig::new(max_connections), journal_mode), true).await
+    }
+
+    async fn open_inner(uri: &str,
+                        open_options: &SqliteOpenOptions,
+                        read_only_fallback: bool,
+    ) -> SqlResult<Self> {
+        let config = &open_options.pool;
+        log::info!("open pool {} {:?}", uri, config);
+            let target = TargetInfo::parse(uri);
+            let read_only = !target.is_memory() && target.path.as_deref().map(is_read_only_file).unwrap_or(false);
+            if read_only && !read_only_fallback {
+                return Err(sql_err!(SqlErrorCode::ReadOnly, "database {} is on read-only storage", target.uri));
+            }
+            let pool_options = config.pool_options::<sqlx::Sqlite>();
+            let options = sqlx::sqlite::SqliteConnectOptions::from_str(uri).map_err(|e| {
                 RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str())
-            })?
-                .busy_timeout(Duration::from_secs(300))
-                .create_if_missing(true);
-            if let Some(journal_mode) = journal_mode {
-                options = options.journal_mode(journal_mode);
+            })?;
+            let mut options = open_options.apply(options, read_only);
+            if read_only {
+                log::warn!("database {} is on read-only storage, open read-only", target.uri);
+                options = options.read_only(true).immutable(true);
+            } else {
+                options = options.create_if_missing(true);
             }
             #[cfg(target_os = "ios")]
             {
@@ -86,13 +274,185 @@ impl SqlPool {
             options = options.log_statements(LevelFilter::Off)
                 .log_slow_statements(LevelFilter::Off, Duration::from_secs(10));
             let pool = pool_options.connect_with(options).await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?;
-            Ok(Self {
-                pool,
-                uri: uri.to_string(),
-                _em: Default::default(),
-            })
+            let pool = Self::from_raw_pool_with_uri(pool, uri);
+            // Sinks and batchers size their batches without a connection at hand.
+            pool.get_conn().await?.max_bind_params().await?;
+            Ok(pool)
+    }
+
+    // Runs DDL after the other pooled connections have been returned and closed, so no
+    // connection keeps statements prepared against the old schema. get_conn waits until the
+    // change is done, a statement prepared during it would decode rows of the new schema with
+    // the columns of the old one.
+    pub async fn with_schema_change<F, T>(&self, quiesce_timeout: Duration, f: F) -> SqlResult<T>
+    where F: for<'c> FnOnce(&'c mut SqlConnection) -> SqlFuture<'c, SqlResult<T>> {
+        let mut conn = self.get_conn().await?;
+        let _gate = self.state.schema_gate.write().await;
+        self.quiesce(quiesce_timeout).await?;
+        let ret = f(&mut conn).await;
+        conn.clear_statement_cache().await?;
+        // Connections handed back by get_conn while the gate was shut are closed as well.
+        self.quiesce(quiesce_timeout).await?;
+        ret
+    }
+
+    async fn quiesce(&self, timeout: Duration) -> SqlResult<()> {
+        let clock = self.clock();
+        let start = clock.now();
+        loop {
+            let in_use = self.in_use();
+            if in_use <= 1 {
+                break;
+            }
+            if clock.elapsed(start) >= timeout {
+                return Err(sql_err!(SqlErrorCode::Timeout, "quiesce timeout, {} connections still in use", in_use - 1));
+            }
+            clock.sleep(Duration::from_millis(10)).await;
+        }
+        while let Some(conn) = self.pool.try_acquire() {
+            conn.close().await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), "close idle connection").as_str()))?;
+        }
+        Ok(())
+    }
+
+    // Startup gate: keeps opening the pool and running SELECT 1 until the database answers.
+    pub async fn wait_until_ready(uri: &str,
+                                  max_connections: u32,
+                                  journal_mode: Option<sqlx::sqlite::SqliteJournalMode>,
+                                  max_wait: Duration,
+    ) -> SqlResult<Self> {
+        let target = TargetInfo::parse(uri);
+        retry_until(Arc::new(RuntimeClock), format!("database {}", target.uri).as_str(), max_wait, || async {
+            let pool = Self::open(uri, max_connections, journal_mode).await?;
+            pool.ping().await?;
+            Ok::<_, SqlError>(pool)
+        }).await.map_err(|(e, attempt)| {
+            sql_err!(SqlErrorCode::Timeout, "database {} not ready after {} attempts: {}", target.uri, attempt, e)
+        })
+    }
+
+    // Probed on first use and cached, see Capabilities.
+    pub async fn capabilities(&self) -> SqlResult<Capabilities> {
+        if let Some(capabilities) = self.state.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let mut conn = self.get_conn().await?;
+        conn.capabilities().await
     }
 
+    pub async fn capabilities_report(&self) -> SqlResult<String> {
+        Ok(self.capabilities().await?.report())
+    }
+
+    // Runs a DDL statement, logging how long it has waited for the database lock. The wait
+    // ends at the busy timeout with a Busy error, unless fail_after gives up earlier.
+    pub async fn execute_ddl(&self, sql: &str, options: &LockWatchOptions) -> SqlResult<()> {
+        let mut conn = self.get_conn().await?;
+        conn.execute_watched(sql, options, lock_probe().as_mut()).await
+    }
+
+    // Runs the checks policy enables and returns what they found. A database that cannot be
+    // reached is reported as a connectivity problem and nothing else runs.
+    pub async fn startup_report(&self, policy: &StartupPolicy) -> StartupReport {
+        let mut report = StartupReport::default();
+        let mut conn = match self.get_conn().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                report.push("connectivity", false, format!("{:?}", e));
+                return report;
+            }
+        };
+        if policy.check_connectivity {
+            match conn.query_one(sql_query("SELECT 1")).await {
+                Ok(_) => report.push("connectivity", true, "ok"),
+                Err(e) => {
+                    report.push("connectivity", false, format!("{:?}", e));
+                    return report;
+                }
+            }
+        }
+        if let Some(expected) = &policy.journal_mode {
+            match conn.query_one(sql_query("PRAGMA journal_mode")).await {
+                Ok(row) => {
+                    let mode: String = row.get("journal_mode");
+                    report.push("journal_mode", mode.eq_ignore_ascii_case(expected), format!("journal_mode {}, expected {}", mode, expected));
+                }
+                Err(e) => report.push("journal_mode", false, format!("{:?}", e)),
+            }
+        }
+        if !policy.session_settings.is_empty() {
+            log::warn!("session settings are mysql only, not checked");
+        }
+        if let Some(migrations) = &policy.migrations {
+            if let Err(e) = conn.startup_migrations_with(migrations, &mut report, policy.lock_watch.map(|options| (options, lock_probe()))).await {
+                report.push("migrations", false, format!("{:?}", e));
+            }
+        }
+        if let Some(expected) = &policy.schema_fingerprint {
+            match conn.schema_fingerprint().await {
+                Ok(fingerprint) => {
+                    report.push("schema_fingerprint", fingerprint == *expected, format!("fingerprint {}, expected {}", fingerprint, expected));
+                    report.schema_fingerprint = Some(fingerprint);
+                }
+                Err(e) => report.push("schema_fingerprint", false, format!("{:?}", e)),
+            }
+        }
+        report
+    }
+
+    // Same checks as startup_report, failing with one error that lists every problem found.
+    pub async fn startup_check(&self, policy: &StartupPolicy) -> SqlResult<StartupReport> {
+        self.startup_report(policy).await.into_result()
+    }
+}
+
+// EROFS, the file system is mounted read-only.
+fn is_read_only_fs(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(30)
+}
+
+// Whether an existing database file is on read-only storage. sqlite creates its journal, wal
+// and shm files next to the database, so a writable file in a directory on read-only storage
+// counts too. A file or directory the process only lacks permission to write is not treated as
+// read-only storage, sqlite opens it and reports ReadOnly on the first write.
+fn is_read_only_file(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    if !path.exists() {
+        return false;
+    }
+    if let Err(e) = std::fs::OpenOptions::new().write(true).open(path) {
+        return is_read_only_fs(&e);
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let probe = dir.join(format!(".{}-{}.probe", path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(), std::process::id()));
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            false
+        }
+        Err(e) => is_read_only_fs(&e),
+    }
+}
+
+fn journal_mode_options(pool: PoolConfig, journal_mode: Option<SqliteJournalMode>) -> SqliteOpenOptions {
+    SqliteOpenOptions {
+        journal_mode,
+        pool,
+        ..Default::default()
+    }
+}
+
+impl SwitchablePool {
+    pub async fn open_standby(&self,
+                              uri: &str,
+                              max_connections: u32,
+                              journal_mode: Option<sqlx::sqlite::SqliteJournalMode>,
+    ) -> SqlResult<()> {
+        self.prepare_standby(SqlPool::open(uri, max_connections, journal_mode).await?).await
+    }
 }
 
 impl SqlConnection {
@@ -110,18 +470,126 @@ impl SqlConnection {
             options.connect().await.map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?
         };
 
-        Ok(Self {
-            conn: SqlConnectionType::Conn(conn),
-            _em: Default::default(),
-            trans: None
-        })
+        Ok(Self::from_conn_type(SqlConnectionType::Conn(conn), Arc::new(TargetInfo::parse(uri))))
+    }
+
+    // PRAGMA compile_options of the linked sqlite, cached on the pool the connection came from.
+    pub async fn compile_options(&mut self) -> SqlResult<Vec<String>> {
+        if let Some(options) = self.pool_state.compile_options.get() {
+            return Ok(options.clone());
+        }
+        let rows = self.query_all(sql_query("PRAGMA compile_options")).await?;
+        let options: Vec<String> = rows.iter().map(|row| row.get("compile_options")).collect();
+        let _ = self.pool_state.compile_options.set(options.clone());
+        Ok(options)
+    }
+
+    // Bound parameters a statement may have, the MAX_VARIABLE_NUMBER the linked sqlite was
+    // built with, else its default: 32766 since 3.32 and 999 before. Probed once per pool and
+    // cached, the sink and the batcher of the pool size their batches by it.
+    pub async fn max_bind_params(&mut self) -> SqlResult<usize> {
+        if let Some(max_params) = self.pool_state.bind_params.get() {
+            return Ok(*max_params);
+        }
+        let options = self.compile_options().await?;
+        let max_params = match options.iter().find_map(|o| o.strip_prefix("MAX_VARIABLE_NUMBER=").and_then(|v| v.parse::<usize>().ok())) {
+            Some(max_params) => max_params,
+            None => {
+                let version: String = self.query_one(sql_query("select sqlite_version() as v")).await?.get("v");
+                if parse_version(version.as_str()) >= (3, 32, 0) {
+                    SqlBackend::Sqlite.max_bind_params()
+                } else {
+                    LEGACY_MAX_BIND_PARAMS
+                }
+            }
+        };
+        let _ = self.pool_state.bind_params.set(max_params);
+        Ok(max_params)
+    }
+
+    // Runs sql, containing KEYS_MARKER where the key list goes as in "where id in ({keys})",
+    // once per chunk of keys small enough for the bound parameter limit, and concatenates the
+    // rows. key_of reorders the rows by the position of their key in keys.
+    pub async fn query_all_chunked_in<T, K>(&mut self, sql: &str, keys: &[K], other_args: &[SqlValue], key_of: Option<&dyn Fn(&T) -> K>) -> SqlResult<Vec<T>>
+    where T: for<'r> sqlx::FromRow<'r, SqlRowObject>,
+          K: Clone + Eq + std::hash::Hash + Into<SqlValue>, {
+        let max_params = self.max_bind_params().await?;
+        self.query_all_chunked_in_with(sql, keys, other_args, max_params, key_of).await
+    }
+
+    pub async fn supports(&mut self, feature: SqliteFeature) -> SqlResult<bool> {
+        let option = match feature {
+            SqliteFeature::Json1 => return self.probe_json1().await,
+            SqliteFeature::Fts5 => "ENABLE_FTS5",
+            SqliteFeature::Rtree => "ENABLE_RTREE",
+            SqliteFeature::MathFunctions => "ENABLE_MATH_FUNCTIONS",
+        };
+        Ok(self.compile_options().await?.iter().any(|o| o == option))
+    }
+
+    // JSON is built in since 3.38 unless omitted, older builds list ENABLE_JSON1 and a loaded
+    // extension lists nothing, so the functions are called rather than the options read. The
+    // statement runs unmapped, a missing function is the expected answer and not logged.
+    async fn probe_json1(&mut self) -> SqlResult<bool> {
+        if let Some(json1) = self.pool_state.json1.get() {
+            return Ok(*json1);
+        }
+        let json1 = match sqlx::query("SELECT json('1')").fetch_one(self.executor()).await {
+            Ok(_) => true,
+            Err(sqlx::Error::Database(e)) if e.message().contains("no such function") => false,
+            Err(e) => return Err(RawErrorToSqlError::map(e, format!("[{} {}]", line!(), "SELECT json('1')").as_str())),
+        };
+        let _ = self.pool_state.json1.set(json1);
+        Ok(json1)
     }
+
+    // Probed once per pool, cached on the pool the connection came from.
+    pub async fn capabilities(&mut self) -> SqlResult<Capabilities> {
+        if let Some(capabilities) = self.pool_state.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let server_version: String = self.query_one(sql_query("select sqlite_version() as v")).await?.get("v");
+        // Asking for wal on a filesystem without shared memory support leaves the old mode.
+        let journal_mode: String = self.query_one(sql_query("PRAGMA journal_mode")).await?.get("journal_mode");
+        let capabilities = Capabilities {
+            backend: SqlBackend::Sqlite,
+            returning: parse_version(server_version.as_str()) >= (3, 35, 0),
+            json_functions: self.supports(SqliteFeature::Json1).await?,
+            full_text_search: self.supports(SqliteFeature::Fts5).await?,
+            performance_schema: false,
+            wal_mode: journal_mode.eq_ignore_ascii_case("wal"),
+            server_version,
+        };
+        let _ = self.pool_state.capabilities.set(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    // The table needs an INTEGER PRIMARY KEY or rowid, the returned value is the rowid of the inserted row.
+    // From 3.35 the rowid comes from a RETURNING clause, so an upsert taking its DO UPDATE branch
+    // reports the updated row instead of whatever last_insert_rowid held from an earlier insert.
+    pub async fn insert_returning_id<'a>(&mut self, query: SqlQuery<'a>) -> SqlResult<i64> {
+        if !has_returning(query.sql(), SqlBackend::Sqlite) && self.capabilities().await?.returning {
+            let (sql, args, persistent) = self.split_query(query)?;
+            let sql = format!("{} RETURNING rowid", sql.trim_end().trim_end_matches(';'));
+            let rows = self.execute_returning(sqlx::query_with(sql.as_str(), args).persistent(persistent)).await?;
+            return match rows.first() {
+                Some(row) => row.try_get::<i64, _>(0).map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), sql).as_str())),
+                None => Err(sql_err!(SqlErrorCode::Failed, "no row inserted, no id available")),
+            };
+        }
+        let ret = self.execute_sql(query).await?;
+        if ret.rows_affected() == 0 {
+            return Err(sql_err!(SqlErrorCode::Failed, "no row inserted, no id available"));
+        }
+        Ok(ret.last_insert_rowid())
+    }
+
     pub async fn is_column_exist(&mut self, table_name: &str, column_name: &str, _db_name: Option<&str>) -> SqlResult<bool> {
         {
             let sql = r#"select * from sqlite_master where type='table' and tbl_name=?1 and sql like ?2"#;
             let ret = self.query_one(sql_query(sql)
                 .bind(table_name).bind(format!("%{}%", column_name))).await;
-            if let Err(_) = &ret {
+            if ret.is_err() {
                 Ok(false)
             } else {
                 Ok(true)
@@ -129,16 +597,299 @@ impl SqlConnection {
         }
     }
 
+    // Indexes of table, or of every table, with the ones made redundant by another flagged.
+    pub async fn analyze_indexes(&mut self, table: Option<&str>) -> SqlResult<IndexReport> {
+        let sql = r#"select m.name as tbl, il.name as idx, il."unique" as uniq, ii.name as col
+            from sqlite_master m join pragma_index_list(m.name) il join pragma_index_info(il.name) ii
+            where m.type = 'table' and m.name not like 'sqlite_%' and (?1 is null or m.name = ?1)
+            order by m.name, il.name, ii.seqno"#;
+        let rows = self.query_all(sql_query(sql).bind(table)).await?;
+        let mut report = IndexReport::default();
+        for row in rows.iter() {
+            let table: String = row.get("tbl");
+            let name: String = row.get("idx");
+            let column: Option<String> = row.get("col");
+            let column = column.unwrap_or_else(|| "<expr>".to_string());
+            match report.indexes.last_mut() {
+                Some(index) if index.table == table && index.name == name => index.columns.push(column),
+                _ => report.indexes.push(IndexInfo {
+                    table,
+                    name,
+                    columns: vec![column],
+                    unique: row.get::<i64, _>("uniq") != 0,
+                    ..Default::default()
+                }),
+            }
+        }
+        report.flag_redundant();
+        Ok(report)
+    }
+
+    // Sum of column over a table or over the rows of a SELECT, 0 when there are no rows.
+    // Integer overflow in SUM is reported by sqlite itself as an error.
+    pub async fn sum(&mut self, table_or_query: &str, column: &str) -> SqlResult<i128> {
+        let sql = sum_sql(table_or_query, column, SqlBackend::Sqlite);
+        let row = self.query_one(sql_query(sql.as_str())).await?;
+        Ok(row.get_aggregate_i128("s")?.unwrap_or(0))
+    }
+
+
+    // Hash of the schema, stable across builds: the sql of every table, index, view and trigger.
+    pub async fn schema_fingerprint(&mut self) -> SqlResult<String> {
+        let sql = "select type, name, tbl_name, sql from sqlite_master where name not like 'sqlite_%' order by type, name";
+        self.schema_fingerprint_with(sql).await
+    }
+
+
+
+
+
+    // table_name may be qualified by the name of an attached database, "aux.table".
+    pub async fn is_table_exist(&mut self, table_name: &str) -> SqlResult<bool> {
+        let (master, table) = match table_name.split_once('.') {
+            Some((schema, table)) => (format!("{}.sqlite_master", quote_ident(schema, '"')), table),
+            None => ("sqlite_master".to_string(), table_name),
+        };
+        let sql = format!("select count(*) as c from {} where type='table' and name=?1", master);
+        let row = self.query_one(sql_query(sql.as_str()).bind(table)).await?;
+        let count: i64 = row.get("c");
+        Ok(count > 0)
+    }
+
+    // Runs f in a transaction once every database of databases is attached to this connection,
+    // "main" and "temp" included. f reaches their tables by qualified names, "aux.table", and
+    // sqlite commits or rolls back the attached databases together, atomically unless the main
+    // database is in WAL mode or in memory, where each database is atomic by itself.
+    pub async fn with_databases<F, R>(&mut self, databases: &[&str], f: F) -> SqlResult<R>
+    where F: for<'c> FnOnce(&'c mut Self) -> SqlFuture<'c, SqlResult<R>> {
+        let rows = self.query_all(sql_query("select name from pragma_database_list")).await?;
+        let attached = rows.iter().map(|row| row.get::<String, _>("name")).collect::<Vec<_>>();
+        let missing = databases.iter().filter(|db| !attached.iter().any(|a| a == *db)).copied().collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(sql_err!(SqlErrorCode::NotFound, "databases {} not attached", missing.join(", ")));
+        }
+        self.transaction(f).await
+    }
+
+    // Puts replacement in place of current in one transaction. current is renamed to keep_old_as,
+    // or dropped when it is None. References in other tables, views and triggers keep pointing at
+    // the name current, not at the renamed old table: foreign key enforcement is off during the
+    // swap, so sqlite neither rewrites the REFERENCES clauses of child tables nor cascades the
+    // drop of the old table to them. With enforcement on, the child rows are then checked against
+    // replacement and the swap is rolled back when one of them has no parent there. Indexes move
+    // with their table and keep their names, so the indexes of replacement need names of their
+    // own, which stay in use after the swap. The tables may be qualified by an attached database,
+    // "aux.items", which all three have to share, sqlite renames only within a database. The swap
+    // needs a connection outside any transaction, in one the pragmas it relies on are no-ops and
+    // a failed swap could not be undone by itself, it fails with SqlErrorCode::NotInTransaction
+    // there.
+    pub async fn swap_tables(&mut self, current: &str, replacement: &str, keep_old_as: Option<&str>) -> SqlResult<()> {
+        if self.in_transaction {
+            return Err(sql_err!(SqlErrorCode::NotInTransaction, "swap of {} inside transaction {:?}, it needs a connection outside any transaction",
+                                current, self.transaction_id));
+        }
+        let old = keep_old_as.map(|n| n.to_string()).unwrap_or_else(|| format!("{}_swap_old", current));
+        let schema = |name: &str| name.split_once('.').map(|(schema, _)| schema.to_string()).unwrap_or_else(|| "main".to_string());
+        if schema(replacement) != schema(current) || schema(old.as_str()) != schema(current) {
+            return Err(sql_err!(SqlErrorCode::InvalidArgument, "{}, {} and {} are not in one database", current, replacement, old));
+        }
+        for table in [current, replacement] {
+            if !self.is_table_exist(table).await? {
+                return Err(sql_err!(SqlErrorCode::NotFound, "table {} not found", table));
+            }
+        }
+        if self.is_table_exist(old.as_str()).await? {
+            return Err(sql_err!(SqlErrorCode::AlreadyExists, "table {} already exists", old));
+        }
+
+        // Both pragmas are no-ops inside a transaction, they are set around it.
+        let legacy: i64 = self.query_one(sql_query("PRAGMA legacy_alter_table")).await?.get(0);
+        let foreign_keys: i64 = self.query_one(sql_query("PRAGMA foreign_keys")).await?.get(0);
+        self.execute_sql(sql_query("PRAGMA legacy_alter_table = ON")).await?;
+        self.execute_sql(sql_query("PRAGMA foreign_keys = OFF")).await?;
+        let ret = self.swap_tables_in_transaction(current, replacement, old.as_str(), keep_old_as.is_none(), foreign_keys == 1).await;
+        self.execute_sql(sql_query(format!("PRAGMA foreign_keys = {}", foreign_keys).as_str())).await?;
+        self.execute_sql(sql_query(format!("PRAGMA legacy_alter_table = {}", legacy).as_str())).await?;
+        ret
+    }
+
+    async fn swap_tables_in_transaction(&mut self, current: &str, replacement: &str, old: &str, drop_old: bool, check_references: bool) -> SqlResult<()> {
+        // RENAME TO takes the bare name, the table stays in its database.
+        let bare = |name: &str| quote_ident(name.rsplit('.').next().unwrap_or(name), '"');
+        let mut statements = vec![
+            format!("ALTER TABLE {} RENAME TO {}", quote_qualified(current, '"'), bare(old)),
+            format!("ALTER TABLE {} RENAME TO {}", quote_qualified(replacement, '"'), bare(current)),
+        ];
+        if drop_old {
+            statements.push(format!("DROP TABLE {}", quote_qualified(old, '"')));
+        }
+        self.begin_transaction().await?;
+        for sql in statements.iter() {
+            let ret = self.execute_sql(sql_query(sql.as_str())).await;
+            if let Err(e) = ret {
+                let _ = self.rollback_transaction().await;
+                return Err(e);
+            }
+        }
+        if check_references {
+            // Only violations against the swapped table, older ones elsewhere are not this swap's.
+            let (schema, parent) = current.split_once('.').unwrap_or(("main", current));
+            let sql = "SELECT \"table\", parent FROM pragma_foreign_key_check(NULL, ?) WHERE parent = ?";
+            let ret = self.query_all(sql_query(sql).bind(schema).bind(parent)).await;
+            let children = match ret {
+                Ok(rows) => rows.iter().map(|row| row.get::<String, _>(0)).collect::<Vec<_>>(),
+                Err(e) => {
+                    let _ = self.rollback_transaction().await;
+                    return Err(e);
+                }
+            };
+            if !children.is_empty() {
+                let _ = self.rollback_transaction().await;
+                return Err(sql_err!(SqlErrorCode::Failed, "swap of {} rolled back, {} rows of {} reference keys {} does not have",
+                                    current, children.len(), children[0], replacement));
+            }
+        }
+        self.commit_transaction().await
+    }
+
     pub async fn is_index_exist(&mut self, table_name: &str, index_name: &str, _db_name: Option<&str>) -> SqlResult<bool> {
         {
             let sql = r#"select * from sqlite_master where type='index' and tbl_name=?1 and name=?2"#;
             let ret = self.query_one(sql_query(sql)
                 .bind(table_name).bind(index_name)).await;
-            if let Err(_) = &ret {
+            if ret.is_err() {
                 Ok(false)
             } else {
                 Ok(true)
             }
         }
     }
+
+    // Creates an fts5 table <table>_fts over columns, kept in sync with the table by triggers.
+    pub async fn create_search_index(&mut self, table: &str, columns: &[&str], options: &TextSearchOptions) -> SqlResult<()> {
+        self.capabilities().await?.require(Capability::FullTextSearch, "create_search_index")?;
+        let fts = quote_ident(format!("{}_fts", table).as_str(), '"');
+        let source = quote_ident(table, '"');
+        let id = options.id_column.as_deref().map(|c| quote_ident(c, '"')).unwrap_or_else(|| "rowid".to_string());
+        let cols = columns.iter().map(|c| quote_ident(c, '"')).collect::<Vec<_>>();
+        let new_values = cols.iter().map(|c| format!("new.{}", c)).collect::<Vec<_>>().join(", ");
+        let old_values = cols.iter().map(|c| format!("old.{}", c)).collect::<Vec<_>>().join(", ");
+        let cols = cols.join(", ");
+
+        let mut fts_options = format!("content='{}', content_rowid='{}'", table.replace('\'', "''"), options.id_column.as_deref().unwrap_or("rowid").replace('\'', "''"));
+        if let Some(tokenizer) = options.tokenizer.as_ref() {
+            fts_options.push_str(format!(", tokenize='{}'", tokenizer.replace('\'', "''")).as_str());
+        }
+        let sqls = [
+            format!("CREATE VIRTUAL TABLE IF NOT EXISTS {} USING fts5({}, {})", fts, cols, fts_options),
+            format!("CREATE TRIGGER IF NOT EXISTS {} AFTER INSERT ON {} BEGIN INSERT INTO {}(rowid, {}) VALUES (new.{}, {}); END",
+                    quote_ident(format!("{}_fts_ai", table).as_str(), '"'), source, fts, cols, id, new_values),
+            format!("CREATE TRIGGER IF NOT EXISTS {} AFTER DELETE ON {} BEGIN INSERT INTO {}({}, rowid, {}) VALUES ('delete', old.{}, {}); END",
+                    quote_ident(format!("{}_fts_ad", table).as_str(), '"'), source, fts, fts, cols, id, old_values),
+            format!("CREATE TRIGGER IF NOT EXISTS {} AFTER UPDATE ON {} BEGIN INSERT INTO {}({}, rowid, {}) VALUES ('delete', old.{}, {}); INSERT INTO {}(rowid, {}) VALUES (new.{}, {}); END",
+                    quote_ident(format!("{}_fts_au", table).as_str(), '"'), source, fts, fts, cols, id, old_values, fts, cols, id, new_values),
+            format!("INSERT INTO {}({}) VALUES ('rebuild')", fts, fts),
+        ];
+        for sql in sqls.iter() {
+            self.execute_sql(sql_query(sql.as_str())).await?;
+        }
+        Ok(())
+    }
+
+    // Every term must match. Rank is the negated bm25 score, higher is more relevant;
+    // it is only comparable within one result set.
+    pub async fn search(&mut self, table: &str, query_text: &str, limit: u32) -> SqlResult<Vec<(i64, f64)>> {
+        let terms = search_terms(query_text);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let expr = terms.iter().map(|t| format!("\"{}\"", t)).collect::<Vec<_>>().join(" ");
+        let fts = quote_ident(format!("{}_fts", table).as_str(), '"');
+        let sql = format!("SELECT rowid AS id, -bm25({}) AS score FROM {} WHERE {} MATCH ?1 ORDER BY score DESC LIMIT ?2", fts, fts, fts);
+        let rows = self.query_all(sql_query(sql.as_str()).bind(expr).bind(limit)).await?;
+        Ok(rows.iter().map(|row| (row.get("id"), row.get("score"))).collect())
+    }
+
+    pub async fn drop_search_index(&mut self, table: &str) -> SqlResult<()> {
+        for suffix in ["_fts_ai", "_fts_ad", "_fts_au"] {
+            let sql = format!("DROP TRIGGER IF EXISTS {}", quote_ident(format!("{}{}", table, suffix).as_str(), '"'));
+            self.execute_sql(sql_query(sql.as_str())).await?;
+        }
+        let sql = format!("DROP TABLE IF EXISTS {}", quote_ident(format!("{}_fts", table).as_str(), '"'));
+        self.execute_sql(sql_query(sql.as_str())).await?;
+        Ok(())
+    }
+
+}
+
+// sqlite cannot tell which connection holds its lock, only the wait is logged.
+fn lock_probe() -> Box<LockProbe<'static, SqlError>> {
+    Box::new(|| -> SqlFuture<'static, SqlResult<Vec<LockBlocker>>> { Box::pin(async { Ok(Vec::new()) }) })
+}
+
+// Reads every column in order, using the storage class of each value.
+pub fn row_to_map(row: &SqlRowObject) -> SqlResult<IndexMap<String, SqlValue>> {
+    row.to_map().map_err(|e| RawErrorToSqlError::map(e, format!("[{} row_to_map]", line!()).as_str()))
+}
+
+impl RowToMap for sqlx::sqlite::SqliteRow {
+    fn to_map(&self) -> Result<IndexMap<String, SqlValue>, sqlx::Error> {
+        let mut map = IndexMap::with_capacity(self.len());
+        for (i, column) in self.columns().iter().enumerate() {
+            map.insert(column.name().to_string(), column_value(self, i)?);
+        }
+        Ok(map)
+    }
+}
+
+fn column_value(row: &SqlRowObject, i: usize) -> Result<SqlValue, sqlx::Error> {
+    let column = &row.columns()[i];
+    let raw = row.try_get_raw(i)?;
+    if raw.is_null() {
+        return Ok(SqlValue::Null(None));
+    }
+    let type_name = raw.type_info().name().to_string();
+    let ret = match type_name.as_str() {
+        "BOOLEAN" => row.try_get::<bool, _>(i).map(SqlValue::Bool),
+        "INTEGER" | "NUMERIC" => row.try_get::<i64, _>(i).map(SqlValue::Int),
+        "REAL" => row.try_get::<f64, _>(i).map(SqlValue::Float),
+        "TEXT" | "DATE" | "TIME" | "DATETIME" => row.try_get_unchecked::<String, _>(i).map(SqlValue::Text),
+        "BLOB" => row.try_get::<Vec<u8>, _>(i).map(SqlValue::Blob),
+        _ => {
+            log::warn!("column {} has unsupported type {}, read as text", column.name(), type_name);
+            row.try_get_unchecked::<Vec<u8>, _>(i).map(|v| SqlValue::Text(String::from_utf8_lossy(&v).to_string()))
+        }
+    };
+    ret.map_err(|e| named_column_error(e, column.name()))
+}
+
+impl DualWriteTarget for SqlPool {
+    fn execute<'a>(&'a self, statements: &'a [DualWriteStatement]) -> SqlFuture<'a, SqlResult<u64>> {
+        Box::pin(async move {
+            let mut conn = self.get_conn().await?;
+            conn.execute_statements(statements).await
+        })
+    }
+
+    fn query<'a>(&'a self, sql: &'a str, args: &'a [SqlValue]) -> SqlFuture<'a, SqlResult<Vec<IndexMap<String, SqlValue>>>> {
+        Box::pin(async move {
+            let mut conn = self.get_conn().await?;
+            let mut query = sql_query(sql);
+            for value in args.iter() {
+                query = query.bind_value(value.clone());
+            }
+            conn.query_all(query).await?.iter().map(row_to_map).collect()
+        })
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        SqlPool::clock(self)
+    }
+}
+
+impl AggregateRowExt for sqlx::sqlite::SqliteRow {
+    fn aggregate_value(&self, col: &str) -> SqlResult<SqlValue> {
+        let column = self.try_column(col).map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), col).as_str()))?;
+        column_value(self, column.ordinal()).map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), col).as_str()))
+    }
 }
@@ -0,0 +1,182 @@
+// One pool per tenant database file under a base directory, with the number of open pools
+// bounded for servers hosting many tenants.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use super::{Clock, IndexMap, RuntimeClock, SqlPool, SqliteUriBuilder};
+
+#[derive(Debug, Clone)]
+pub struct TenantPoolOptions {
+    pub max_connections: u32,
+    pub journal_mode: Option<sqlx::sqlite::SqliteJournalMode>,
+}
+
+impl Default for TenantPoolOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 4,
+            journal_mode: Some(sqlx::sqlite::SqliteJournalMode::Wal),
+        }
+    }
+}
+
+struct TenantPool {
+    pool: SqlPool,
+    last_used: Instant,
+}
+
+// Pools are evicted least recently used first once more than max_open_pools are open, and
+// after idle_ttl without a get. A pool is never evicted while a clone returned by get or a
+// connection taken from it is alive, the cap is exceeded instead until they are dropped.
+// Pools are opened and closed outside the lock, a slow file system only holds up the tenant
+// it is about.
+pub struct SqlitePoolManager {
+    base_dir: PathBuf,
+    options: TenantPoolOptions,
+    max_open_pools: usize,
+    idle_ttl: Duration,
+    clock: Arc<dyn Clock>,
+    pools: Mutex<IndexMap<String, TenantPool>>,
+}
+
+// Whether anyone but the manager holds the pool. Handles are only given out under the lock,
+// so one that is not counted here cannot appear before the pool is removed.
+fn pinned(pool: &SqlPool) -> bool {
+    pool.handles() > 1
+}
+
+// Tenant ids become file names, anything that could leave base_dir or be special to the
+// filesystem is rejected.
+fn check_tenant_id(tenant_id: &str) -> SqlResult<()> {
+    let valid = !tenant_id.is_empty()
+        && tenant_id.len() <= 128
+        && !tenant_id.starts_with('.')
+        && tenant_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !valid {
+        return Err(sql_err!(SqlErrorCode::Failed, "invalid tenant id {:?}", tenant_id));
+    }
+    Ok(())
+}
+
+async fn close_pools(pools: Vec<SqlPool>) {
+    for pool in pools {
+        pool.begin_shutdown();
+        pool.close().await;
+    }
+}
+
+impl SqlitePoolManager {
+    pub fn new(base_dir: impl Into<PathBuf>, options: TenantPoolOptions, max_open_pools: usize, idle_ttl: Duration) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+            options,
+            max_open_pools: max_open_pools.max(1),
+            idle_ttl,
+            clock: Arc::new(RuntimeClock),
+            pools: Mutex::new(IndexMap::new()),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn tenant_path(&self, tenant_id: &str) -> SqlResult<PathBuf> {
+        check_tenant_id(tenant_id)?;
+        Ok(self.base_dir.join(format!("{}.db", tenant_id)))
+    }
+
+    pub async fn get(&self, tenant_id: &str) -> SqlResult<SqlPool> {
+        let path = self.tenant_path(tenant_id)?;
+        if let Some((pool, evicted)) = self.touch(tenant_id, None) {
+            close_pools(evicted).await;
+            return Ok(pool);
+        }
+        let uri = SqliteUriBuilder::new().path(&path).build();
+        let opened = SqlPool::open(uri.as_str(), self.options.max_connections, self.options.journal_mode).await?;
+        // Another get may have opened the tenant meanwhile, the pool kept first wins.
+        let (pool, mut evicted) = self.touch(tenant_id, Some(opened.clone())).unwrap_or_else(|| (opened.clone(), Vec::new()));
+        if !Arc::ptr_eq(&pool.state, &opened.state) {
+            evicted.push(opened);
+        }
+        close_pools(evicted).await;
+        Ok(pool)
+    }
+
+    pub async fn open_pools(&self) -> Vec<String> {
+        self.lock().keys().cloned().collect()
+    }
+
+    // Closes the pool of tenant_id, waiting for its checked-out connections to come back.
+    // Returns false when no pool was open.
+    pub async fn close(&self, tenant_id: &str) -> bool {
+        let entry = self.lock().shift_remove(tenant_id);
+        match entry {
+            Some(entry) => {
+                close_pools(vec![entry.pool]).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Evicts the pools past idle_ttl, get does it as well.
+    pub async fn evict_idle(&self) {
+        let evicted = self.evict(&mut self.lock(), None);
+        close_pools(evicted).await;
+    }
+
+    pub async fn close_all(&self) {
+        let pools = std::mem::take(&mut *self.lock());
+        close_pools(pools.into_values().map(|e| e.pool).collect()).await;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, IndexMap<String, TenantPool>> {
+        self.pools.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Marks tenant_id used and hands out a clone of its pool, inserting opened when there is
+    // none. None when the tenant has no pool and none was given. Also takes out the pools to
+    // evict, which the caller closes once the lock is released.
+    fn touch(&self, tenant_id: &str, opened: Option<SqlPool>) -> Option<(SqlPool, Vec<SqlPool>)> {
+        let mut pools = self.lock();
+        let now = self.clock.now();
+        let entry = match (pools.shift_remove(tenant_id), opened) {
+            (Some(entry), _) => entry,
+            (None, Some(pool)) => TenantPool { pool, last_used: now },
+            (None, None) => return None,
+        };
+        let pool = entry.pool.clone();
+        pools.insert(tenant_id.to_string(), TenantPool { last_used: now, ..entry });
+        let evicted = self.evict(&mut pools, Some(tenant_id));
+        Some((pool, evicted))
+    }
+
+    fn evict(&self, pools: &mut IndexMap<String, TenantPool>, keep: Option<&str>) -> Vec<SqlPool> {
+        let now = self.clock.now();
+        let mut over = pools.len().saturating_sub(self.max_open_pools);
+        let mut evicted = Vec::new();
+        // Oldest first, so the over-cap pools taken are the least recently used ones.
+        for (tenant_id, entry) in pools.iter() {
+            if Some(tenant_id.as_str()) == keep || pinned(&entry.pool) {
+                continue;
+            }
+            if over > 0 {
+                over -= 1;
+                evicted.push(tenant_id.clone());
+            } else if now.saturating_duration_since(entry.last_used) >= self.idle_ttl {
+                evicted.push(tenant_id.clone());
+            }
+        }
+        if over > 0 {
+            log::warn!("{} tenant pools open over the cap of {}, all in use", over, self.max_open_pools);
+        }
+        evicted.into_iter().filter_map(|tenant_id| {
+            log::info!("evict pool of tenant {}", tenant_id);
+            pools.shift_remove(&tenant_id).map(|e| e.pool)
+        }).collect()
+    }
+}
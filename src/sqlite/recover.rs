@@ -0,0 +1,128 @@
+// Standalone inspection and repair of a sqlite file and its -wal/-shm/-journal companions,
+// usable before a pool can be opened.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use log::LevelFilter;
+use sqlx::{ConnectOptions, Connection, Executor, Row};
+use sqlx::sqlite::{SqliteConnection, SqliteLockingMode};
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use super::{ErrorMap, RawErrorToSqlError, SqliteUriBuilder};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileInfo {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileReport {
+    pub db: Option<FileInfo>,
+    pub wal: Option<FileInfo>,
+    pub shm: Option<FileInfo>,
+    pub journal: Option<FileInfo>,
+    // A non-empty WAL or rollback journal, sqlite replays it on the next open.
+    pub hot_journal: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CleanupPolicy {
+    // Checkpoint the WAL into the database and truncate it.
+    pub checkpoint_wal: bool,
+    // Remove the -shm file once no WAL content is left.
+    pub remove_shm: bool,
+    // How long to wait for other connections' locks before refusing.
+    pub busy_timeout: Duration,
+}
+
+impl Default for CleanupPolicy {
+    fn default() -> Self {
+        Self {
+            checkpoint_wal: true,
+            remove_shm: true,
+            busy_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+fn companion(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn file_info(path: &Path) -> Option<FileInfo> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some(FileInfo {
+        size: meta.len(),
+        modified: meta.modified().ok(),
+    })
+}
+
+pub fn inspect(path: impl AsRef<Path>) -> SqlResult<FileReport> {
+    let path = path.as_ref();
+    let wal = file_info(companion(path, "-wal").as_path());
+    let journal = file_info(companion(path, "-journal").as_path());
+    let hot_journal = wal.as_ref().map(|f| f.size > 0).unwrap_or(false)
+        || journal.as_ref().map(|f| f.size > 0).unwrap_or(false);
+    Ok(FileReport {
+        db: file_info(path),
+        wal,
+        shm: file_info(companion(path, "-shm").as_path()),
+        journal,
+        hot_journal,
+    })
+}
+
+// Opens the database once so sqlite replays any hot journal, then checkpoints and truncates the
+// WAL and removes a stale -shm. Refuses with Busy when another connection holds a lock; sqlite
+// uses its own byte-range locks, so those are what is probed rather than flock. The connection
+// takes the database in exclusive locking mode first and keeps the lock until the -shm is gone,
+// so no other connection can be using the shared memory the file backs when it is removed.
+pub async fn cleanup(path: impl AsRef<Path>, policy: &CleanupPolicy) -> SqlResult<FileReport> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Err(sql_err!(SqlErrorCode::NotFound, "database {} not found", path.display()));
+    }
+    let uri = SqliteUriBuilder::new().path(path).build();
+    let mut conn = sqlx::sqlite::SqliteConnectOptions::from_str(uri.as_str())
+        .map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?
+        .busy_timeout(policy.busy_timeout)
+        .locking_mode(SqliteLockingMode::Exclusive)
+        .log_statements(LevelFilter::Off)
+        .connect().await
+        .map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), uri).as_str()))?;
+    let ret = cleanup_locked(&mut conn, path, policy).await;
+    let closed = conn.close().await
+        .map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), "close").as_str()));
+    ret?;
+    closed?;
+    inspect(path)
+}
+
+async fn cleanup_locked(conn: &mut SqliteConnection, path: &Path, policy: &CleanupPolicy) -> SqlResult<()> {
+    // In exclusive locking mode the lock a write takes is kept after the commit, until close.
+    if conn.execute("BEGIN EXCLUSIVE").await.is_err() {
+        return Err(sql_err!(SqlErrorCode::Busy, "database {} is in use", path.display()));
+    }
+    conn.execute("COMMIT").await
+        .map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), "commit").as_str()))?;
+
+    if policy.checkpoint_wal {
+        let row = conn.fetch_one("PRAGMA wal_checkpoint(TRUNCATE)").await
+            .map_err(|e| RawErrorToSqlError::map(e, format!("[{} {}]", line!(), "wal checkpoint").as_str()))?;
+        let busy: i64 = row.get(0);
+        if busy != 0 {
+            return Err(sql_err!(SqlErrorCode::Busy, "database {} is in use, wal checkpoint blocked", path.display()));
+        }
+    }
+
+    let report = inspect(path)?;
+    if policy.remove_shm && report.shm.is_some() && !report.wal.as_ref().map(|f| f.size > 0).unwrap_or(false) {
+        let shm = companion(path, "-shm");
+        std::fs::remove_file(shm.as_path())
+            .map_err(|e| sql_err!(SqlErrorCode::Failed, "remove {} failed: {}", shm.display(), e))?;
+    }
+    Ok(())
+}
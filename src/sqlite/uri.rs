@@ -0,0 +1,182 @@
+// Builds the sqlite uris SqlPool::open and SqlConnection::open take, "sqlite://<path>?<params>"
+// with the path percent-encoded, and reads them back for modification.
+
+use std::path::Path;
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+use crate::target::percent_decode as decode;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+enum Location {
+    #[default]
+    AnonymousMemory,
+    NamedMemory(String),
+    File(String),
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SqliteUriBuilder {
+    location: Location,
+    read_only: bool,
+    create: bool,
+    immutable: bool,
+    shared_cache: bool,
+    vfs: Option<String>,
+    // Parameters this builder has no method for, kept as parsed. sqlx rejects parameters it does
+    // not know, so these only survive a round trip.
+    extra: Vec<(String, String)>,
+}
+
+// Encodes what sqlx would otherwise take as the end of the path or as an escape, and what is
+// not printable.
+fn encode(text: &str, query: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        let reserved = matches!(c, '%' | '?' | '#') || (query && matches!(c, '&' | '=' | '+' | ' '));
+        if reserved || c.is_ascii_control() {
+            out.push_str(format!("%{:02X}", c as u32).as_str());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// Windows paths use forward slashes: "C:\data\app.db" becomes "C:/data/app.db" and the UNC
+// path "\\server\share\app.db" becomes "//server/share/app.db", both of which sqlite opens.
+fn normalize_path(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let is_drive = text.len() >= 2 && text.as_bytes()[0].is_ascii_alphabetic() && text.as_bytes()[1] == b':';
+    if is_drive || text.starts_with("\\\\") || cfg!(windows) {
+        text.replace('\\', "/")
+    } else {
+        text.to_string()
+    }
+}
+
+impl SqliteUriBuilder {
+    // An anonymous in-memory database, private to each connection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn path(mut self, path: &Path) -> Self {
+        self.location = Location::File(normalize_path(path));
+        self
+    }
+
+    // A named in-memory database is shared by the connections of the process that open the
+    // same name, an anonymous one is private to its connection.
+    pub fn memory(mut self, named: Option<&str>) -> Self {
+        self.location = match named {
+            Some(name) => Location::NamedMemory(name.to_string()),
+            None => Location::AnonymousMemory,
+        };
+        self
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    // The file is created when it does not exist, for a connection opened without a pool.
+    pub fn create(mut self) -> Self {
+        self.create = true;
+        self
+    }
+
+    // The file is assumed not to change, sqlite skips locking. Only for read-only media.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    pub fn shared_cache(mut self) -> Self {
+        self.shared_cache = true;
+        self
+    }
+
+    pub fn vfs(mut self, name: &str) -> Self {
+        self.vfs = Some(name.to_string());
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let mut params = Vec::new();
+        let path = match &self.location {
+            Location::AnonymousMemory => return "sqlite::memory:".to_string(),
+            Location::NamedMemory(name) => {
+                params.push(("mode".to_string(), "memory".to_string()));
+                params.push(("cache".to_string(), "shared".to_string()));
+                name
+            }
+            Location::File(path) => {
+                if self.read_only {
+                    params.push(("mode".to_string(), "ro".to_string()));
+                } else if self.create {
+                    params.push(("mode".to_string(), "rwc".to_string()));
+                }
+                if self.shared_cache {
+                    params.push(("cache".to_string(), "shared".to_string()));
+                }
+                path
+            }
+        };
+        if self.immutable {
+            params.push(("immutable".to_string(), "1".to_string()));
+        }
+        if let Some(vfs) = &self.vfs {
+            params.push(("vfs".to_string(), vfs.clone()));
+        }
+        params.extend(self.extra.iter().cloned());
+
+        let mut uri = format!("sqlite://{}", encode(path, false));
+        if !params.is_empty() {
+            let query = params.iter().map(|(k, v)| format!("{}={}", encode(k, true), encode(v, true))).collect::<Vec<_>>().join("&");
+            uri.push('?');
+            uri.push_str(query.as_str());
+        }
+        uri
+    }
+
+    // Reads a uri in the forms build produces and sqlx accepts, "sqlite://path?params",
+    // "sqlite:path" and "sqlite::memory:".
+    pub fn parse(uri: &str) -> SqlResult<Self> {
+        let rest = uri.strip_prefix("sqlite:")
+            .ok_or_else(|| sql_err!(SqlErrorCode::Failed, "{:?} is not a sqlite uri", uri))?;
+        let rest = rest.strip_prefix("//").unwrap_or(rest);
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+        let path = decode(path, false)?;
+
+        let mut builder = Self::default();
+        let mut memory = path.is_empty() || path == ":memory:";
+        for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (key, value) = (decode(key, true)?, decode(value, true)?);
+            match (key.as_str(), value.as_str()) {
+                ("mode", "memory") => memory = true,
+                ("mode", "ro") => builder.read_only = true,
+                ("mode", "rwc") => builder.create = true,
+                ("mode", "rw") => {}
+                ("cache", "shared") => builder.shared_cache = true,
+                ("cache", "private") => {}
+                ("immutable", v) => builder.immutable = v == "1" || v.eq_ignore_ascii_case("true"),
+                ("vfs", v) => builder.vfs = Some(v.to_string()),
+                _ => builder.extra.push((key, value)),
+            }
+        }
+        builder.location = if !memory {
+            Location::File(path)
+        } else if path.is_empty() || path == ":memory:" {
+            Location::AnonymousMemory
+        } else {
+            // Shared cache is implied by build for a named database.
+            builder.shared_cache = false;
+            Location::NamedMemory(path)
+        };
+        Ok(builder)
+    }
+}
@@ -0,0 +1,204 @@
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use sqlx::{Database, Executor};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::db_helper::{map_row, ErrorMap, SqlConnection};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::lock_watch::LockProbe;
+use crate::lock_watch::LockWatchOptions;
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::target::SqlBackend;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::text_search::quote_qualified;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::value::{BindValue, RowToMap};
+use crate::value::SqlValue;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Migration {
+    pub version: i64,
+    // Run in order in one transaction. mysql commits DDL implicitly, so a failed migration
+    // there may be partly applied.
+    pub statements: Vec<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StartupMigrations {
+    // Table with a version column, one row per applied migration.
+    pub table: String,
+    pub expected_version: i64,
+    pub migrations: Vec<Migration>,
+    // Applies the migrations above the current version up to expected_version, otherwise a
+    // database behind expected_version is only reported.
+    pub apply_pending: bool,
+}
+
+// Every check is off unless set.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StartupPolicy {
+    pub check_connectivity: bool,
+    // sqlite, expected PRAGMA journal_mode such as "wal".
+    pub journal_mode: Option<String>,
+    // mysql, expected session variables. For sql_mode the listed modes must all be set.
+    pub session_settings: Vec<(String, String)>,
+    pub migrations: Option<StartupMigrations>,
+    // Expected schema_fingerprint of the connection.
+    pub schema_fingerprint: Option<String>,
+    // Watches the migration statements for lock waits, logging the sessions they wait on.
+    pub lock_watch: Option<LockWatchOptions>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StartupCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StartupReport {
+    // Checks in the order they ran.
+    pub checks: Vec<StartupCheck>,
+    pub migration_version: Option<i64>,
+    pub applied_migrations: Vec<i64>,
+    pub schema_fingerprint: Option<String>,
+}
+
+impl StartupReport {
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    pub fn problems(&self) -> impl Iterator<Item = &StartupCheck> {
+        self.checks.iter().filter(|c| !c.passed)
+    }
+
+    #[cfg(any(feature = "mysql", feature = "sqlite"))]
+    pub(crate) fn push(&mut self, name: &str, passed: bool, detail: impl Into<String>) {
+        self.checks.push(StartupCheck { name: name.to_string(), passed, detail: detail.into() });
+    }
+
+    // The report, or one error listing every failed check.
+    pub fn into_result(self) -> SqlResult<Self> {
+        if self.is_ok() {
+            return Ok(self);
+        }
+        let problems = self.problems().map(|c| format!("{}: {}", c.name, c.detail)).collect::<Vec<_>>();
+        Err(sql_err!(SqlErrorCode::Failed, "startup check failed: {}", problems.join("; ")))
+    }
+}
+
+// Text form of a value for comparing settings.
+pub(crate) fn value_text(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Null(_) => "NULL".to_string(),
+        SqlValue::Bool(v) => v.to_string(),
+        SqlValue::Int(v) => v.to_string(),
+        SqlValue::UInt(v) => v.to_string(),
+        SqlValue::Float(v) => v.to_string(),
+        SqlValue::Text(v) => v.clone(),
+        SqlValue::Blob(v) => String::from_utf8_lossy(v).to_string(),
+    }
+}
+
+// FNV-1a, stable across builds unlike the std hasher.
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, b| (h ^ u64::from(*b)).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SqlConnection<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue, {
+    // Hex hash of every row schema_sql returns, in order.
+    pub(crate) async fn schema_fingerprint_with(&mut self, schema_sql: &str) -> Result<String, EM::OutError>
+    where DB::Row: RowToMap {
+        let rows = self.query_all(sqlx::query::<DB>(schema_sql)).await?;
+        let mut hash = 0xcbf29ce484222325;
+        for row in rows.iter() {
+            for value in map_row::<EM, _>(row, schema_sql)?.values() {
+                hash = fnv1a(hash, value_text(value).as_bytes());
+                hash = fnv1a(hash, &[0]);
+            }
+            hash = fnv1a(hash, &[1]);
+        }
+        Ok(format!("{:016x}", hash))
+    }
+
+    // Current version of migrations.table, applying the pending migrations first when allowed.
+    // Problems are added to report.
+    pub(crate) async fn startup_migrations_with(&mut self, migrations: &StartupMigrations, report: &mut StartupReport,
+                                                mut lock_watch: Option<(LockWatchOptions, Box<LockProbe<'static, EM::OutError>>)>) -> Result<(), EM::OutError>
+    where DB::Row: RowToMap,
+          EM::OutError: std::fmt::Debug, {
+        let table = quote_qualified(migrations.table.as_str(), SqlBackend::from_db_name(DB::NAME).ident_quote());
+        let version_sql = format!("SELECT MAX(version) AS v FROM {}", table);
+        let read_version = |row: &DB::Row| -> Result<i64, EM::OutError> {
+            let value = map_row::<EM, _>(row, version_sql.as_str())?.shift_remove("v").unwrap_or(SqlValue::Null(None));
+            Ok(value.to_i128().ok().flatten().and_then(|v| i64::try_from(v).ok()).unwrap_or(0))
+        };
+        // A missing table is a database no migration has run on.
+        let mut version = match self.query_one(sqlx::query::<DB>(version_sql.as_str())).await {
+            Ok(row) => Some(read_version(&row)?),
+            Err(_) => None,
+        };
+
+        if migrations.apply_pending && version.unwrap_or(0) < migrations.expected_version {
+            if version.is_none() {
+                let sql = format!("CREATE TABLE IF NOT EXISTS {} (version BIGINT NOT NULL PRIMARY KEY)", table);
+                self.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+            }
+            let mut pending: Vec<&Migration> = migrations.migrations.iter()
+                .filter(|m| m.version > version.unwrap_or(0) && m.version <= migrations.expected_version)
+                .collect();
+            pending.sort_by_key(|m| m.version);
+            for migration in pending {
+                if let Err(e) = self.apply_migration(table.as_str(), migration, &mut lock_watch).await {
+                    report.push("migrations", false, format!("migration {} failed: {:?}", migration.version, e));
+                    break;
+                }
+                report.applied_migrations.push(migration.version);
+                version = Some(migration.version);
+            }
+        }
+
+        report.migration_version = version;
+        let current = version.unwrap_or(0);
+        if current != migrations.expected_version {
+            report.push("migrations", false, format!("{} at version {}, expected {}", migrations.table, current, migrations.expected_version));
+        } else {
+            report.push("migrations", true, format!("{} at version {}", migrations.table, current));
+        }
+        Ok(())
+    }
+
+    async fn apply_migration(&mut self, table: &str, migration: &Migration,
+                             lock_watch: &mut Option<(LockWatchOptions, Box<LockProbe<'static, EM::OutError>>)>) -> Result<(), EM::OutError>
+    where EM::OutError: std::fmt::Debug {
+        self.begin_transaction().await?;
+        match self.run_migration(table, migration, lock_watch).await {
+            Ok(()) => self.commit_transaction().await,
+            Err(e) => {
+                let _ = self.rollback_transaction().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn run_migration(&mut self, table: &str, migration: &Migration,
+                           lock_watch: &mut Option<(LockWatchOptions, Box<LockProbe<'static, EM::OutError>>)>) -> Result<(), EM::OutError>
+    where EM::OutError: std::fmt::Debug {
+        for sql in migration.statements.iter() {
+            match lock_watch {
+                Some((options, probe)) => self.execute_watched(sql.as_str(), options, probe.as_mut()).await?,
+                None => {
+                    self.execute_sql(sqlx::query::<DB>(sql.as_str())).await?;
+                }
+            }
+        }
+        let sql = format!("INSERT INTO {} (version) VALUES (?)", table);
+        self.execute_sql(sqlx::query::<DB>(sql.as_str()).bind_value(SqlValue::Int(migration.version))).await?;
+        Ok(())
+    }
+}
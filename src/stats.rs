@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::value::IndexMap;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StatementStat {
+    // Normalized statement, literals replaced by `?`. Of the first execution under a tag.
+    pub statement: String,
+    // Operation tag the entry is kept under, see SqlConnection::query_one_tagged. None for the
+    // statements run without one, which are kept per statement.
+    pub tag: Option<String>,
+    pub count: u64,
+    pub errors: u64,
+    pub total: Duration,
+    pub max: Duration,
+    pub rows: u64,
+    // Over the most recent successful executions.
+    pub p95: Duration,
+}
+
+// Latencies of the most recent successful executions kept per statement for the p95.
+const RECENT_LATENCIES: usize = 128;
+
+struct Entry {
+    stat: StatementStat,
+    recent: VecDeque<Duration>,
+    // Executions counted for eviction, including the count inherited from the entry it replaced.
+    weight: u64,
+}
+
+impl Entry {
+    fn p95(&self) -> Duration {
+        let mut sorted: Vec<Duration> = self.recent.iter().copied().collect();
+        sorted.sort();
+        match sorted.len() {
+            0 => Duration::ZERO,
+            n => sorted[((n * 95).div_ceil(100)).clamp(1, n) - 1],
+        }
+    }
+}
+
+// Per statement aggregates of one pool, holding at most capacity statements. When full, a new
+// statement replaces the one with the lowest weight and starts from that weight plus one, as in
+// space-saving heavy-hitter counting. Statements run once cannot push out the busy ones, and a
+// statement that turns busy still works its way in. Hits are O(1), only a new statement scans.
+pub(crate) struct StatementStats {
+    capacity: usize,
+    entries: Mutex<IndexMap<StatementKey, Entry>>,
+}
+
+// What an entry is kept under, the operation tag of the statements run with one, the normalized
+// statement otherwise. All statements of a tag share its entry.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub(crate) enum StatementKey {
+    Tag(String),
+    Statement(String),
+}
+
+impl StatementKey {
+    pub(crate) fn new(tag: Option<&str>, statement: &str) -> Self {
+        match tag {
+            Some(tag) => StatementKey::Tag(tag.to_string()),
+            None => StatementKey::Statement(statement.to_string()),
+        }
+    }
+}
+
+impl StatementStats {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(IndexMap::new()),
+        }
+    }
+
+    pub(crate) fn record(&self, key: StatementKey, statement: String, took: Duration, rows: Option<u64>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if !entries.contains_key(&key) {
+            let mut weight = 0;
+            if entries.len() >= self.capacity {
+                let lightest = entries.values().enumerate().min_by_key(|(_, e)| e.weight).map(|(i, e)| (i, e.weight));
+                if let Some((index, lightest)) = lightest {
+                    entries.swap_remove_index(index);
+                    weight = lightest;
+                }
+            }
+            let tag = match &key {
+                StatementKey::Tag(tag) => Some(tag.clone()),
+                StatementKey::Statement(_) => None,
+            };
+            let stat = StatementStat { statement, tag, ..Default::default() };
+            entries.insert(key.clone(), Entry { stat, recent: VecDeque::new(), weight });
+        }
+        let entry = match entries.get_mut(&key) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.weight += 1;
+        let stat = &mut entry.stat;
+        stat.count += 1;
+        stat.total += took;
+        stat.max = stat.max.max(took);
+        match rows {
+            Some(rows) => {
+                stat.rows += rows;
+                if entry.recent.len() >= RECENT_LATENCIES {
+                    entry.recent.pop_front();
+                }
+                entry.recent.push_back(took);
+            }
+            None => stat.errors += 1,
+        }
+    }
+
+    // Sorted by total time, slowest first.
+    pub(crate) fn snapshot(&self) -> Vec<StatementStat> {
+        let mut stats: Vec<StatementStat> = self.entries.lock().unwrap_or_else(|e| e.into_inner()).values()
+            .map(|e| StatementStat { p95: e.p95(), ..e.stat.clone() })
+            .collect();
+        stats.sort_by_key(|s| std::cmp::Reverse(s.total));
+        stats
+    }
+
+    // Successful executions and their p95 under key.
+    pub(crate) fn latency(&self, key: &StatementKey) -> Option<(u64, Duration)> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(key).map(|e| (e.stat.count - e.stat.errors, e.p95()))
+    }
+
+    pub(crate) fn reset(&self) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+// Per statement timeout of multiplier times the recent p95 of the statement, for the statements
+// with at least min_executions successful executions, bounded by floor and ceiling. Statements
+// with less history get default, no timeout when it is None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveTimeout {
+    pub min_executions: u64,
+    pub multiplier: f64,
+    pub floor: Duration,
+    pub ceiling: Duration,
+    pub default: Option<Duration>,
+}
+
+impl Default for AdaptiveTimeout {
+    fn default() -> Self {
+        Self {
+            min_executions: 20,
+            multiplier: 5.0,
+            floor: Duration::from_millis(100),
+            ceiling: Duration::from_secs(30),
+            default: None,
+        }
+    }
+}
+
+impl AdaptiveTimeout {
+    pub fn timeout_for(&self, executions: u64, p95: Duration) -> Option<Duration> {
+        if executions < self.min_executions.max(1) {
+            return self.default;
+        }
+        let timeout = Duration::try_from_secs_f64(p95.as_secs_f64() * self.multiplier).unwrap_or(self.ceiling);
+        Some(timeout.max(self.floor).min(self.ceiling.max(self.floor)))
+    }
+}
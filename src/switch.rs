@@ -0,0 +1,124 @@
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, Priority, SqlConnection, SqlPool};
+
+struct PreviousPool<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    pool: SqlPool<DB, EM>,
+    switched_at: Instant,
+}
+
+// Routes acquisitions to the current pool and swaps it for a pre-opened standby, for planned
+// failovers. A connection keeps working against the pool it was acquired from after a switch.
+// The old pool is drained but stays open until the next switch or close_previous, so within
+// grace_window the switch can be rolled back.
+pub struct SwitchablePool<DB: Database, EM: ErrorMap<InError = sqlx::Error>>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>, {
+    current: RwLock<SqlPool<DB, EM>>,
+    standby: Mutex<Option<SqlPool<DB, EM>>>,
+    previous: Mutex<Option<PreviousPool<DB, EM>>>,
+    grace_window: Duration,
+}
+
+impl<DB: Database, EM: 'static + ErrorMap<InError = sqlx::Error>> SwitchablePool<DB, EM>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>, {
+    pub fn new(pool: SqlPool<DB, EM>, grace_window: Duration) -> Self {
+        Self {
+            current: RwLock::new(pool),
+            standby: Mutex::new(None),
+            previous: Mutex::new(None),
+            grace_window,
+        }
+    }
+
+    pub fn current(&self) -> SqlPool<DB, EM> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn has_standby(&self) -> bool {
+        self.standby.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+
+    pub async fn get_conn(&self) -> Result<SqlConnection<DB, EM>, EM::OutError> {
+        self.get_conn_priority(Priority::Normal).await
+    }
+
+    pub async fn get_conn_priority(&self, priority: Priority) -> Result<SqlConnection<DB, EM>, EM::OutError> {
+        loop {
+            let pool = self.current();
+            match pool.get_conn_priority(priority).await {
+                // The pool was switched away from and drained after it was read, the one that
+                // replaced it takes the call.
+                Err(_) if pool.is_shutting_down() && !self.current().is_shutting_down() => continue,
+                ret => return ret,
+            }
+        }
+    }
+
+    // Checks the standby with SELECT 1 and keeps it without routing anything to it, a standby
+    // prepared earlier is closed.
+    pub async fn prepare_standby(&self, pool: SqlPool<DB, EM>) -> Result<(), EM::OutError> {
+        {
+            let mut conn = pool.get_conn().await?;
+            conn.execute_sql(sqlx::query("SELECT 1")).await?;
+        }
+        let old = self.standby.lock().unwrap_or_else(|e| e.into_inner()).replace(pool);
+        if let Some(old) = old {
+            old.close().await;
+        }
+        Ok(())
+    }
+
+    // Routes new acquisitions to the standby, then waits up to drain_timeout for the connections
+    // still out on the old pool and the acquisitions that read it before the switch. Only then
+    // does the old pool refuse new work, it is closed by the next switch or close_previous.
+    pub async fn switch_over(&self, drain_timeout: Duration) -> Result<(), EM::OutError> {
+        let standby = self.standby.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let standby = match standby {
+            Some(standby) => standby,
+            None => return Err(EM::map(sqlx::Error::Protocol("no standby prepared".to_string()), format!("[{} switch over]", line!()).as_str())),
+        };
+        let switched_at = standby.clock().now();
+        let old = std::mem::replace(&mut *self.current.write().unwrap_or_else(|e| e.into_inner()), standby);
+        log::info!("switched pool from {} to {}", old.target().uri, self.current().target().uri);
+        let drained = old.await_idle(drain_timeout).await;
+        old.begin_shutdown();
+
+        let earlier = self.previous.lock().unwrap_or_else(|e| e.into_inner()).replace(PreviousPool {
+            pool: old.clone(),
+            switched_at,
+        });
+        if let Some(earlier) = earlier {
+            earlier.pool.close().await;
+        }
+        drained
+    }
+
+    // Switches back to the pool replaced by the last switch_over, which becomes the standby again.
+    pub fn rollback_switch(&self) -> Result<(), EM::OutError> {
+        let mut previous = self.previous.lock().unwrap_or_else(|e| e.into_inner());
+        let expired = match previous.as_ref() {
+            Some(p) => p.pool.clock().elapsed(p.switched_at) > self.grace_window,
+            None => return Err(EM::map(sqlx::Error::Protocol("no switch to roll back".to_string()), format!("[{} rollback switch]", line!()).as_str())),
+        };
+        if expired {
+            return Err(EM::map(sqlx::Error::Protocol("grace window of the last switch has passed".to_string()), format!("[{} rollback switch]", line!()).as_str()));
+        }
+        let old = previous.take().unwrap().pool;
+        old.cancel_shutdown();
+        let replaced = std::mem::replace(&mut *self.current.write().unwrap_or_else(|e| e.into_inner()), old);
+        log::info!("rolled pool switch back from {} to {}", replaced.target().uri, self.current().target().uri);
+        *self.standby.lock().unwrap_or_else(|e| e.into_inner()) = Some(replaced);
+        Ok(())
+    }
+
+    // Closes the pool replaced by the last switch, ending the rollback window early.
+    pub async fn close_previous(&self) {
+        let previous = self.previous.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(previous) = previous {
+            previous.pool.close().await;
+        }
+    }
+}
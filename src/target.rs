@@ -0,0 +1,184 @@
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SqlBackend {
+    #[default]
+    Unknown,
+    MySql,
+    Sqlite,
+    Postgres,
+}
+
+impl SqlBackend {
+    pub(crate) fn from_db_name(name: &str) -> Self {
+        match name {
+            "MySQL" => SqlBackend::MySql,
+            "SQLite" => SqlBackend::Sqlite,
+            "PostgreSQL" => SqlBackend::Postgres,
+            _ => SqlBackend::Unknown,
+        }
+    }
+
+    pub(crate) fn ident_quote(&self) -> char {
+        match self {
+            SqlBackend::MySql => '`',
+            _ => '"',
+        }
+    }
+
+    // Bind parameter n, counted from 1.
+    pub(crate) fn placeholder(&self, n: usize) -> String {
+        match self {
+            SqlBackend::Postgres => format!("${}", n),
+            _ => "?".to_string(),
+        }
+    }
+
+    // Bound parameters one statement may have: the 16 bit count of the mysql and postgres
+    // protocols, SQLITE_MAX_VARIABLE_NUMBER of sqlite since 3.32. Older or differently built
+    // sqlite allows fewer, the pool probes the linked library on open, see
+    // sqlite SqlConnection::max_bind_params.
+    pub const fn max_bind_params(&self) -> usize {
+        match self {
+            SqlBackend::MySql | SqlBackend::Postgres => 65535,
+            SqlBackend::Sqlite => 32766,
+            SqlBackend::Unknown => 999,
+        }
+    }
+}
+
+// Credential-free description of the database a pool or connection points at.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct TargetInfo {
+    pub backend: SqlBackend,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+    pub database: Option<String>,
+    pub uri: String,
+}
+
+impl TargetInfo {
+    pub fn parse(uri: &str) -> Self {
+        match uri.split_once(':') {
+            Some(("mysql", rest)) | Some(("mariadb", rest)) => Self::parse_server(rest, "mysql", SqlBackend::MySql),
+            Some(("postgres", rest)) | Some(("postgresql", rest)) => Self::parse_server(rest, "postgres", SqlBackend::Postgres),
+            Some(("sqlite", rest)) => Self::parse_sqlite(rest),
+            Some(("file", _)) => Self { uri: uri.to_string(), ..Self::parse_sqlite(uri) },
+            _ => Self::default(),
+        }
+    }
+
+    pub fn is_memory(&self) -> bool {
+        self.backend == SqlBackend::Sqlite && self.path.as_deref() == Some(":memory:")
+    }
+
+    fn parse_server(rest: &str, scheme: &str, backend: SqlBackend) -> Self {
+        let rest = rest.trim_start_matches("//");
+        let (rest, params) = match rest.split_once('?') {
+            Some((rest, params)) => (rest, Some(params)),
+            None => (rest, None),
+        };
+        let (authority, database) = match rest.split_once('/') {
+            Some((authority, database)) if !database.is_empty() => (authority, Some(database.to_string())),
+            Some((authority, _)) => (authority, None),
+            None => (rest, None),
+        };
+        let host_port = match authority.rsplit_once('@') {
+            Some((_, host_port)) => host_port,
+            None => authority,
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => (host_port, None),
+            },
+            None => (host_port, None),
+        };
+
+        let mut uri = format!("{}://{}", scheme, host);
+        if let Some(port) = port {
+            uri.push_str(format!(":{}", port).as_str());
+        }
+        if let Some(database) = database.as_ref() {
+            uri.push('/');
+            uri.push_str(database.as_str());
+        }
+        if let Some(params) = params {
+            let params: Vec<&str> = params.split('&')
+                .filter(|p| !p.is_empty() && !p.to_ascii_lowercase().starts_with("password="))
+                .collect();
+            if !params.is_empty() {
+                uri.push('?');
+                uri.push_str(params.join("&").as_str());
+            }
+        }
+
+        Self {
+            backend,
+            host: if host.is_empty() { None } else { Some(host.to_string()) },
+            port,
+            path: None,
+            database,
+            uri,
+        }
+    }
+
+    // rest is what follows "sqlite:", a path or an sqlite "file:" uri, whose path may carry an
+    // authority, "file:///data/app.db" or "file://localhost/data/app.db". The path is stored
+    // percent-decoded, mode=memory makes it an in-memory database whatever the path.
+    fn parse_sqlite(rest: &str) -> Self {
+        let path = rest.trim_start_matches("//");
+        let (path, params) = match path.split_once('?') {
+            Some((path, params)) => (path, Some(params)),
+            None => (path, None),
+        };
+        let path = match path.strip_prefix("file:") {
+            Some(path) => match path.strip_prefix("//") {
+                Some(path) => path.strip_prefix("localhost").unwrap_or(path),
+                None => path,
+            },
+            None => path,
+        };
+        let memory = params.unwrap_or("").split('&').any(|p| p == "mode=memory");
+        let path = if path.is_empty() || memory {
+            ":memory:".to_string()
+        } else {
+            percent_decode(path, false).unwrap_or_else(|_| path.to_string())
+        };
+        Self {
+            backend: SqlBackend::Sqlite,
+            host: None,
+            port: None,
+            path: Some(path),
+            database: Some("main".to_string()),
+            uri: format!("sqlite:{}", rest),
+        }
+    }
+}
+
+// Reverses percent-encoding, and in a query string also the + for a space.
+pub(crate) fn percent_decode(text: &str, query: bool) -> SqlResult<String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| sql_err!(SqlErrorCode::Failed, "invalid percent escape in {:?}", text))?;
+                out.push(hex);
+                i += 3;
+            }
+            b'+' if query => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| sql_err!(SqlErrorCode::Failed, "{:?} is not utf-8 once decoded", text))
+}
@@ -0,0 +1,314 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use crate::clock::Clock;
+use crate::db_helper::SqlFuture;
+
+struct ManualClockState {
+    offset: Duration,
+    sleepers: Vec<Waker>,
+}
+
+// Clock that only moves when advance is called, sleeps resolve once the clock has been advanced
+// past their deadline. Clones share the same time.
+#[derive(Clone)]
+pub struct ManualClock {
+    base: Instant,
+    state: Arc<Mutex<ManualClockState>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            base: Instant::now(),
+            state: Arc::new(Mutex::new(ManualClockState {
+                offset: Duration::ZERO,
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let sleepers = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.offset += duration;
+            std::mem::take(&mut state.sleepers)
+        };
+        for waker in sleepers {
+            waker.wake();
+        }
+    }
+
+    // Number of sleeps currently waiting for the clock to move.
+    pub fn sleepers(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).sleepers.len()
+    }
+}
+
+struct ManualSleep {
+    clock: ManualClock,
+    deadline: Instant,
+}
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.clock.state.lock().unwrap_or_else(|e| e.into_inner());
+        if self.clock.base + state.offset >= self.deadline {
+            return Poll::Ready(());
+        }
+        state.sleepers.push(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + self.state.lock().unwrap_or_else(|e| e.into_inner()).offset
+    }
+
+    fn sleep(&self, duration: Duration) -> SqlFuture<'static, ()> {
+        Box::pin(ManualSleep {
+            clock: self.clone(),
+            deadline: self.now() + duration,
+        })
+    }
+}
+
+pub use fault::*;
+pub use isolation::*;
+
+// Naming and throwaway databases for test suites that run the same cases against every
+// backend in parallel.
+mod isolation {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // Environment variable with the url of a mysql server to run the mysql cases against.
+    pub const MYSQL_URL_ENV: &str = "TEST_MYSQL_URL";
+
+    pub fn mysql_test_url() -> Option<String> {
+        std::env::var(MYSQL_URL_ENV).ok().filter(|url| !url.is_empty())
+    }
+
+    // Environment variable with the url of a postgres database to run the postgres cases against.
+    pub const POSTGRES_URL_ENV: &str = "TEST_POSTGRES_URL";
+
+    pub fn postgres_test_url() -> Option<String> {
+        std::env::var(POSTGRES_URL_ENV).ok().filter(|url| !url.is_empty())
+    }
+
+    // Name unique within the process and across processes running at the same time, made of
+    // lowercase letters, digits and `_` so it is valid as file, schema and table name.
+    pub fn unique_test_name(prefix: &str) -> String {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let prefix: String = prefix.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        format!("{}_{}_{}_{:x}", prefix, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed), nanos)
+    }
+
+    // sqlite database file in the temp directory, removed with its journal files on drop.
+    pub struct TempSqliteDb {
+        path: PathBuf,
+    }
+
+    impl TempSqliteDb {
+        pub fn new(prefix: &str) -> Self {
+            Self {
+                path: std::env::temp_dir().join(format!("{}.db", unique_test_name(prefix))),
+            }
+        }
+
+        pub fn path(&self) -> &std::path::Path {
+            &self.path
+        }
+
+        pub fn uri(&self) -> String {
+            format!("sqlite://{}", self.path.display())
+        }
+    }
+
+    impl Drop for TempSqliteDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm", "-journal"] {
+                let mut path = self.path.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+mod fault {
+    use std::borrow::Cow;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use crate::target::SqlBackend;
+
+    #[derive(Debug, Clone)]
+    pub enum FaultMatcher {
+        // Statements whose text contains the substring.
+        SqlContains(String),
+        // The nth statement run through the pool since the injector was attached, from 0.
+        StatementIndex(u64),
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum Fault {
+        // sqlite SQLITE_BUSY, on mysql a lock wait timeout without error number, mapped to Busy.
+        Busy,
+        // mysql 1213 (sqlstate 40001), sqlite has no deadlock error and reports SQLITE_BUSY.
+        Deadlock,
+        // sqlite SQLITE_CONSTRAINT_UNIQUE, mysql 1062 (sqlstate 23000).
+        DuplicateKey,
+        // Database error with the given code as the backend reports it.
+        Database(String),
+        // Delays the statement, which then runs normally.
+        Latency(Duration),
+        // Fails with an io error and closes the connection instead of returning it to the pool.
+        Disconnect,
+    }
+
+    struct FaultRule {
+        matcher: FaultMatcher,
+        fault: Fault,
+        remaining: u32,
+    }
+
+    #[derive(Debug)]
+    struct InjectedDbError {
+        code: String,
+        message: String,
+        kind: ErrorKind,
+    }
+
+    impl std::fmt::Display for InjectedDbError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{} (code {})", self.message, self.code)
+        }
+    }
+
+    impl std::error::Error for InjectedDbError {}
+
+    impl DatabaseError for InjectedDbError {
+        fn message(&self) -> &str {
+            self.message.as_str()
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.code.as_str()))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            match self.kind {
+                ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+                _ => ErrorKind::Other,
+            }
+        }
+    }
+
+    // What a statement should do, decided by FaultInjector::on_statement.
+    pub(crate) enum FaultAction {
+        Run,
+        Delay(Duration),
+        Fail(sqlx::Error, bool),
+    }
+
+    // Programmed failures for the statements of a pool, attached with SqlPool::with_fault_injector.
+    // Every rule fires a limited number of times, the first matching rule wins and statements no
+    // rule matches run against the real database.
+    #[derive(Clone, Default)]
+    pub struct FaultInjector {
+        rules: Arc<Mutex<Vec<FaultRule>>>,
+        statements: Arc<AtomicU64>,
+        injected: Arc<AtomicU64>,
+    }
+
+    impl FaultInjector {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn add(&self, matcher: FaultMatcher, fault: Fault, times: u32) -> &Self {
+            self.rules.lock().unwrap_or_else(|e| e.into_inner()).push(FaultRule {
+                matcher,
+                fault,
+                remaining: times,
+            });
+            self
+        }
+
+        pub fn clear(&self) {
+            self.rules.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+
+        // Faults injected so far, latency included.
+        pub fn injected(&self) -> u64 {
+            self.injected.load(Ordering::Relaxed)
+        }
+
+        pub fn statements(&self) -> u64 {
+            self.statements.load(Ordering::Relaxed)
+        }
+
+        pub(crate) fn on_statement(&self, sql: &str, backend: SqlBackend) -> FaultAction {
+            let index = self.statements.fetch_add(1, Ordering::Relaxed);
+            let mut rules = self.rules.lock().unwrap_or_else(|e| e.into_inner());
+            let rule = rules.iter_mut().find(|r| r.remaining > 0 && match &r.matcher {
+                FaultMatcher::SqlContains(s) => sql.contains(s.as_str()),
+                FaultMatcher::StatementIndex(i) => *i == index,
+            });
+            let rule = match rule {
+                Some(rule) => rule,
+                None => return FaultAction::Run,
+            };
+            rule.remaining -= 1;
+            self.injected.fetch_add(1, Ordering::Relaxed);
+            log::debug!("inject {:?} into statement {}: {}", rule.fault, index, sql);
+
+            let mysql = backend == SqlBackend::MySql;
+            let db_error = |code: &str, message: &str, kind: ErrorKind| {
+                sqlx::Error::Database(Box::new(InjectedDbError {
+                    code: code.to_string(),
+                    message: message.to_string(),
+                    kind,
+                }))
+            };
+            match &rule.fault {
+                Fault::Latency(d) => FaultAction::Delay(*d),
+                Fault::Disconnect => FaultAction::Fail(sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "injected disconnect")), true),
+                Fault::Busy | Fault::Deadlock if !mysql => FaultAction::Fail(db_error("5", "injected database is locked", ErrorKind::Other), false),
+                Fault::Busy => FaultAction::Fail(db_error("HY000", "injected lock wait timeout", ErrorKind::Other), false),
+                Fault::Deadlock => FaultAction::Fail(db_error("40001", "injected deadlock", ErrorKind::Other), false),
+                Fault::DuplicateKey if mysql => FaultAction::Fail(db_error("23000", "injected duplicate entry", ErrorKind::UniqueViolation), false),
+                Fault::DuplicateKey => FaultAction::Fail(db_error("2067", "injected unique constraint failed", ErrorKind::UniqueViolation), false),
+                Fault::Database(code) => FaultAction::Fail(db_error(code, "injected database error", ErrorKind::Other), false),
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+#[derive(Debug, Clone, Default)]
+pub struct TextSearchOptions {
+    // sqlite: integer primary key column the fts rows point at, defaults to rowid.
+    pub id_column: Option<String>,
+    // sqlite: fts5 tokenize option, e.g. "porter unicode61".
+    pub tokenizer: Option<String>,
+    // mysql: fulltext parser, e.g. "ngram" for CJK text.
+    pub parser: Option<String>,
+}
+
+// Splits user input into plain terms, every term must match. Characters with a meaning in
+// fts5 or boolean mode syntax never reach the engine unquoted.
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) fn search_terms(query: &str) -> Vec<String> {
+    query.split_whitespace()
+        .map(|t| t.chars().filter(|c| !c.is_control() && *c != '"').collect::<String>())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+// Quotes every part of a schema qualified name such as "cache.items".
+pub(crate) fn quote_qualified(name: &str, quote: char) -> String {
+    name.split('.').map(|part| quote_ident(part, quote)).collect::<Vec<_>>().join(".")
+}
+
+pub(crate) fn quote_ident(name: &str, quote: char) -> String {
+    let mut quoted = String::with_capacity(name.len() + 2);
+    quoted.push(quote);
+    for c in name.chars() {
+        if c == quote {
+            quoted.push(quote);
+        }
+        quoted.push(c);
+    }
+    quoted.push(quote);
+    quoted
+}
@@ -0,0 +1,170 @@
+use std::marker::PhantomData;
+use sqlx::{Database, Executor};
+use crate::db_helper::{ErrorMap, RowsAffected, SqlPool};
+use crate::target::SqlBackend;
+use crate::text_search::{quote_ident, quote_qualified};
+use crate::value::{BindValue, IndexMap, SqlValue};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitOp {
+    Sql { sql: String, args: Vec<SqlValue> },
+    Insert { table: String, values: IndexMap<String, SqlValue> },
+    // Sets values on the rows matching every key column.
+    Update { table: String, values: IndexMap<String, SqlValue>, key: IndexMap<String, SqlValue> },
+    Delete { table: String, key: IndexMap<String, SqlValue> },
+}
+
+impl UnitOp {
+    fn to_sql(&self, backend: SqlBackend) -> (String, Vec<SqlValue>) {
+        let quote = backend.ident_quote();
+        let key_filter = |key: &IndexMap<String, SqlValue>| {
+            key.keys().map(|c| format!("{} = ?", quote_ident(c, quote))).collect::<Vec<_>>().join(" AND ")
+        };
+        match self {
+            UnitOp::Sql { sql, args } => (sql.clone(), args.clone()),
+            UnitOp::Insert { table, values } => {
+                let columns = values.keys().map(|c| quote_ident(c, quote)).collect::<Vec<_>>().join(", ");
+                (format!("INSERT INTO {} ({}) VALUES ({})", quote_qualified(table, quote), columns, vec!["?"; values.len()].join(", ")),
+                 values.values().cloned().collect())
+            }
+            UnitOp::Update { table, values, key } => {
+                let sets = values.keys().map(|c| format!("{} = ?", quote_ident(c, quote))).collect::<Vec<_>>().join(", ");
+                (format!("UPDATE {} SET {} WHERE {}", quote_qualified(table, quote), sets, key_filter(key)),
+                 values.values().chain(key.values()).cloned().collect())
+            }
+            UnitOp::Delete { table, key } => {
+                (format!("DELETE FROM {} WHERE {}", quote_qualified(table, quote), key_filter(key)),
+                 key.values().cloned().collect())
+            }
+        }
+    }
+}
+
+// Writes recorded without a connection, so they can be collected across the layers handling a
+// request and run together at the end. commit runs them in order in one transaction.
+pub struct UnitOfWork<DB: Database> {
+    ops: Vec<UnitOp>,
+    _db: PhantomData<fn() -> DB>,
+}
+
+impl<DB: Database> Clone for UnitOfWork<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            ops: self.ops.clone(),
+            _db: PhantomData,
+        }
+    }
+}
+
+impl<DB: Database> std::fmt::Debug for UnitOfWork<DB> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnitOfWork").field("ops", &self.ops).finish()
+    }
+}
+
+impl<DB: Database> Default for UnitOfWork<DB> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<DB: Database> UnitOfWork<DB> {
+    pub fn new() -> Self {
+        Self {
+            ops: Vec::new(),
+            _db: PhantomData,
+        }
+    }
+
+    pub fn execute(&mut self, sql: &str, args: Vec<SqlValue>) -> &mut Self {
+        self.push(UnitOp::Sql { sql: sql.to_string(), args })
+    }
+
+    pub fn insert(&mut self, table: &str, values: IndexMap<String, SqlValue>) -> &mut Self {
+        self.push(UnitOp::Insert { table: table.to_string(), values })
+    }
+
+    pub fn update(&mut self, table: &str, values: IndexMap<String, SqlValue>, key: IndexMap<String, SqlValue>) -> &mut Self {
+        self.push(UnitOp::Update { table: table.to_string(), values, key })
+    }
+
+    pub fn delete(&mut self, table: &str, key: IndexMap<String, SqlValue>) -> &mut Self {
+        self.push(UnitOp::Delete { table: table.to_string(), key })
+    }
+
+    pub fn push(&mut self, op: UnitOp) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    // Appends the operations of other after the ones already recorded.
+    pub fn append(&mut self, other: UnitOfWork<DB>) -> &mut Self {
+        self.ops.extend(other.ops);
+        self
+    }
+
+    pub fn operations(&self) -> &[UnitOp] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    // Drops operations equal to an earlier one, keeping the first occurrence in place.
+    // Returns how many were dropped.
+    pub fn dedup(&mut self) -> usize {
+        let before = self.ops.len();
+        let mut kept: Vec<UnitOp> = Vec::with_capacity(before);
+        for op in self.ops.drain(..) {
+            if !kept.contains(&op) {
+                kept.push(op);
+            }
+        }
+        self.ops = kept;
+        before - self.ops.len()
+    }
+}
+
+impl<DB: Database> UnitOfWork<DB>
+where for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+      for<'b> <DB as sqlx::Database>::Arguments<'b>: sqlx::IntoArguments<'b, DB>,
+      for<'q> sqlx::query::Query<'q, DB, <DB as sqlx::Database>::Arguments<'q>>: BindValue,
+      DB::QueryResult: RowsAffected, {
+    // Returns the affected row count of every operation. On the first failure the transaction
+    // is rolled back and nothing of the unit is kept.
+    pub async fn commit<EM: 'static + ErrorMap<InError = sqlx::Error>>(&self, pool: &SqlPool<DB, EM>) -> Result<Vec<u64>, EM::OutError> {
+        if self.ops.is_empty() {
+            return Ok(Vec::new());
+        }
+        let backend = SqlBackend::from_db_name(DB::NAME);
+        let mut conn = pool.get_conn().await?;
+        conn.begin_transaction().await?;
+        let mut affected = Vec::with_capacity(self.ops.len());
+        for (i, op) in self.ops.iter().enumerate() {
+            let (sql, args) = op.to_sql(backend);
+            let mut query = sqlx::query::<DB>(sql.as_str());
+            for value in args {
+                query = query.bind_value(value);
+            }
+            match conn.execute_sql(query).await {
+                Ok(ret) => affected.push(ret.affected()),
+                Err(e) => {
+                    log::warn!("unit of work failed at operation {} of {}, rolling back", i + 1, self.ops.len());
+                    let _ = conn.rollback_transaction().await;
+                    return Err(e);
+                }
+            }
+        }
+        conn.commit_transaction().await?;
+        Ok(affected)
+    }
+}
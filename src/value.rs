@@ -0,0 +1,211 @@
+use std::num::IntErrorKind;
+use sqlx::{Database, Encode, Type};
+pub use indexmap::IndexMap;
+use crate::errors::{sql_err, SqlErrorCode, SqlResult};
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::target::SqlBackend;
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+use crate::text_search::{quote_ident, quote_qualified};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SqlValueType {
+    Bool,
+    Int,
+    UInt,
+    Float,
+    Text,
+    Blob,
+}
+
+// Dynamically typed column value for code that does not know the schema at compile time.
+// Null carries an optional type hint, bind_value binds a typed NULL when it is present.
+// sqlite and mysql accept untyped NULL everywhere, backends that infer parameter types from the
+// bound value (postgres) need the hint when the NULL is the only thing fixing the type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null(Option<SqlValueType>),
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+macro_rules! impl_from_for_sql_value {
+    ($($ty:ty => $variant:ident as $conv:ty),* $(,)?) => {
+        $(
+            impl From<$ty> for SqlValue {
+                fn from(v: $ty) -> Self {
+                    SqlValue::$variant(<$conv>::from(v))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_sql_value!(
+    i8 => Int as i64, i16 => Int as i64, i32 => Int as i64, i64 => Int as i64,
+    u8 => UInt as u64, u16 => UInt as u64, u32 => UInt as u64, u64 => UInt as u64,
+    f32 => Float as f64, f64 => Float as f64,
+);
+
+impl From<bool> for SqlValue {
+    fn from(v: bool) -> Self {
+        SqlValue::Bool(v)
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+
+impl From<Vec<u8>> for SqlValue {
+    fn from(v: Vec<u8>) -> Self {
+        SqlValue::Blob(v)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(v: Option<T>) -> Self {
+        v.map(Into::into).unwrap_or(SqlValue::Null(None))
+    }
+}
+
+impl SqlValue {
+    // Integer value of a number in any representation: text is what mysql returns for DECIMAL,
+    // so for SUM over BIGINT. A fractional part other than zeros is an error, a value out of
+    // the i128 range an Overflow error. NULL gives None.
+    pub fn to_i128(&self) -> SqlResult<Option<i128>> {
+        match self {
+            SqlValue::Null(_) => Ok(None),
+            SqlValue::Bool(v) => Ok(Some(*v as i128)),
+            SqlValue::Int(v) => Ok(Some(*v as i128)),
+            SqlValue::UInt(v) => Ok(Some(*v as i128)),
+            SqlValue::Float(v) => {
+                if !v.is_finite() || v.fract() != 0.0 {
+                    return Err(sql_err!(SqlErrorCode::Failed, "{} is not an integer", v));
+                }
+                // 2^127, exact as f64.
+                if v.abs() >= 170141183460469231731687303715884105728.0 {
+                    return Err(sql_err!(SqlErrorCode::Overflow, "{} overflows i128", v));
+                }
+                Ok(Some(*v as i128))
+            }
+            SqlValue::Text(v) => {
+                let text = v.trim();
+                let (int_part, frac) = text.split_once('.').unwrap_or((text, ""));
+                if !frac.chars().all(|c| c == '0') {
+                    return Err(sql_err!(SqlErrorCode::Failed, "{} is not an integer", text));
+                }
+                int_part.parse::<i128>().map(Some).map_err(|e| match e.kind() {
+                    IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => sql_err!(SqlErrorCode::Overflow, "{} overflows i128", text),
+                    _ => sql_err!(SqlErrorCode::Failed, "{} is not an integer", text),
+                })
+            }
+            SqlValue::Blob(_) => Err(sql_err!(SqlErrorCode::Failed, "blob is not a number")),
+        }
+    }
+
+    // Nearest f64 of a number in any representation. NULL gives None.
+    pub fn to_f64(&self) -> SqlResult<Option<f64>> {
+        match self {
+            SqlValue::Null(_) => Ok(None),
+            SqlValue::Bool(v) => Ok(Some(f64::from(u8::from(*v)))),
+            SqlValue::Int(v) => Ok(Some(*v as f64)),
+            SqlValue::UInt(v) => Ok(Some(*v as f64)),
+            SqlValue::Float(v) => Ok(Some(*v)),
+            SqlValue::Text(v) => v.trim().parse::<f64>().map(Some)
+                .map_err(|_| sql_err!(SqlErrorCode::Failed, "{} is not a number", v)),
+            SqlValue::Blob(_) => Err(sql_err!(SqlErrorCode::Failed, "blob is not a number")),
+        }
+    }
+}
+
+// Reads SUM, AVG and the like whatever representation the backend returns them in, mysql gives
+// DECIMAL for SUM over integers and sqlite switches between INTEGER and REAL per value.
+pub trait AggregateRowExt {
+    fn aggregate_value(&self, col: &str) -> SqlResult<SqlValue>;
+
+    fn get_aggregate_i128(&self, col: &str) -> SqlResult<Option<i128>> {
+        self.aggregate_value(col)?.to_i128()
+    }
+
+    fn get_aggregate_f64(&self, col: &str) -> SqlResult<Option<f64>> {
+        self.aggregate_value(col)?.to_f64()
+    }
+}
+
+// SELECT SUM(column) AS s over a table, or over the rows of a query when table_or_query starts
+// with SELECT or WITH.
+#[cfg(any(feature = "mysql", feature = "sqlite"))]
+pub(crate) fn sum_sql(table_or_query: &str, column: &str, backend: SqlBackend) -> String {
+    let quote = backend.ident_quote();
+    let source = table_or_query.trim();
+    let lower = source.to_ascii_lowercase();
+    let from = if lower.starts_with("select") || lower.starts_with("with") {
+        format!("({}) AS sum_source", source.trim_end_matches(';'))
+    } else {
+        quote_qualified(source, quote)
+    };
+    format!("SELECT SUM({}) AS s FROM {}", quote_ident(column, quote), from)
+}
+
+// Every column of a backend's row by name, in select order. Implemented on the row type of each
+// backend, its row_to_map tells how the column types read.
+pub trait RowToMap {
+    fn to_map(&self) -> Result<IndexMap<String, SqlValue>, sqlx::Error>;
+}
+
+// A decode error of a column read by position, named after the column.
+pub(crate) fn named_column_error(e: sqlx::Error, column: &str) -> sqlx::Error {
+    match e {
+        sqlx::Error::ColumnDecode { source, .. } => sqlx::Error::ColumnDecode { index: column.to_string(), source },
+        e => e,
+    }
+}
+
+pub trait BindValue: Sized {
+    fn bind_value(self, value: SqlValue) -> Self;
+}
+
+// UInt is bound as a signed integer since not every backend encodes u64,
+// values above i64::MAX are bound as their decimal text.
+impl<'q, DB: Database> BindValue for sqlx::query::Query<'q, DB, DB::Arguments<'q>>
+where bool: 'q + Encode<'q, DB> + Type<DB>,
+      i64: 'q + Encode<'q, DB> + Type<DB>,
+      f64: 'q + Encode<'q, DB> + Type<DB>,
+      String: 'q + Encode<'q, DB> + Type<DB>,
+      Vec<u8>: 'q + Encode<'q, DB> + Type<DB>,
+      Option<bool>: 'q + Encode<'q, DB> + Type<DB>,
+      Option<i64>: 'q + Encode<'q, DB> + Type<DB>,
+      Option<f64>: 'q + Encode<'q, DB> + Type<DB>,
+      Option<String>: 'q + Encode<'q, DB> + Type<DB>,
+      Option<Vec<u8>>: 'q + Encode<'q, DB> + Type<DB>, {
+    fn bind_value(self, value: SqlValue) -> Self {
+        match value {
+            SqlValue::Null(None) | SqlValue::Null(Some(SqlValueType::Text)) => self.bind(None::<String>),
+            SqlValue::Null(Some(SqlValueType::Bool)) => self.bind(None::<bool>),
+            SqlValue::Null(Some(SqlValueType::Int)) | SqlValue::Null(Some(SqlValueType::UInt)) => self.bind(None::<i64>),
+            SqlValue::Null(Some(SqlValueType::Float)) => self.bind(None::<f64>),
+            SqlValue::Null(Some(SqlValueType::Blob)) => self.bind(None::<Vec<u8>>),
+            SqlValue::Bool(v) => self.bind(v),
+            SqlValue::Int(v) => self.bind(v),
+            SqlValue::UInt(v) => match i64::try_from(v) {
+                Ok(v) => self.bind(v),
+                Err(_) => self.bind(v.to_string()),
+            },
+            SqlValue::Float(v) => self.bind(v),
+            SqlValue::Text(v) => self.bind(v),
+            SqlValue::Blob(v) => self.bind(v),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// Captures log records for the tests asserting what the crate logs. The logger is process wide,
+// so tests using it take lock() to run one at a time.
+
+use std::sync::{Mutex, Once};
+use log::{Level, Log, Metadata, Record};
+
+static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+static SERIAL: async_lock::Mutex<()> = async_lock::Mutex::new(());
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        RECORDS.lock().unwrap_or_else(|e| e.into_inner()).push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+// Installs the capturing logger, clears what was captured so far and serializes the caller
+// against the other tests holding the guard.
+pub async fn lock() -> async_lock::MutexGuard<'static, ()> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    let guard = SERIAL.lock().await;
+    RECORDS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    guard
+}
+
+// Captured records whose message contains needle.
+pub fn records_with(needle: &str) -> Vec<(Level, String)> {
+    RECORDS.lock().unwrap_or_else(|e| e.into_inner()).iter().filter(|(_, msg)| msg.contains(needle)).cloned().collect()
+}
@@ -0,0 +1,547 @@
+// Shared harness of the integration tests. Every test gets a database of its own, a temp file for
+// sqlite and a fresh schema on the server named by TEST_MYSQL_URL or TEST_POSTGRES_URL for mysql
+// and postgres, so the tests run in
+// parallel without seeing each other's tables. The mysql and postgres tests are ignored unless
+// run with --include-ignored, which fails them when their url is not set. behaviour_suite! writes
+// the cases that must hold on every backend once and instantiates them per backend module. New
+// features add their cases there, or to the backend test crate when they only exist for one
+// backend.
+#![allow(dead_code, unused_macros, unused_imports)]
+
+use sfo_sql::test_util::TempSqliteDb;
+
+pub struct TestDb<P> {
+    pub pool: P,
+    sqlite: Option<TempSqliteDb>,
+    schema: Option<String>,
+    // Drops schema when the TestDb is dropped without finish, as a panicking test body does. It
+    // runs on a thread and runtime of its own, the test's runtime may be going down with it.
+    drop_schema: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<P> Drop for TestDb<P> {
+    fn drop(&mut self) {
+        if let Some(drop_schema) = self.drop_schema.take() {
+            let _ = std::thread::spawn(drop_schema).join();
+        }
+    }
+}
+
+// Runs drop on a runtime of its own, for the drop_schema of a TestDb.
+fn block_on_own_runtime<F: std::future::Future<Output = ()>>(drop: F) {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(drop);
+}
+
+impl<P> TestDb<P> {
+    pub fn sqlite_path(&self) -> Option<&std::path::Path> {
+        self.sqlite.as_ref().map(|db| db.path())
+    }
+}
+
+pub async fn sqlite_db(prefix: &str) -> Option<TestDb<sfo_sql::sqlite::SqlPool>> {
+    let db = TempSqliteDb::new(prefix);
+    let pool = sfo_sql::sqlite::SqlPool::open(db.uri().as_str(), 5, Some(sfo_sql::sqlite::SqliteJournalMode::Wal)).await.unwrap();
+    Some(TestDb { pool, sqlite: Some(db), schema: None, drop_schema: None })
+}
+
+impl TestDb<sfo_sql::sqlite::SqlPool> {
+    pub async fn finish(self) {
+        self.pool.raw_pool().await.close().await;
+    }
+}
+
+// Panics without TEST_MYSQL_URL, the mysql tests are ignored by default and only get here when run
+// against a server.
+#[cfg(feature = "mysql")]
+pub async fn mysql_db(prefix: &str) -> Option<TestDb<sfo_sql::mysql::SqlPool>> {
+    let url = sfo_sql::test_util::mysql_test_url().expect("TEST_MYSQL_URL is not set, the mysql tests need a server");
+    let (pool, schema) = sfo_sql::mysql::SqlPool::open_test_schema(url.as_str(), prefix, 5).await.unwrap();
+    let dropped = schema.clone();
+    let drop_schema = Box::new(move || block_on_own_runtime(async move {
+        if let Ok(pool) = sfo_sql::mysql::SqlPool::open(url.as_str(), 1).await {
+            let _ = pool.drop_test_schema(dropped.as_str()).await;
+            pool.raw_pool().await.close().await;
+        }
+    }));
+    Some(TestDb { pool, sqlite: None, schema: Some(schema), drop_schema: Some(drop_schema) })
+}
+
+#[cfg(feature = "mysql")]
+impl TestDb<sfo_sql::mysql::SqlPool> {
+    pub async fn finish(mut self) {
+        self.drop_schema = None;
+        if let Some(schema) = self.schema.as_deref() {
+            let _ = self.pool.drop_test_schema(schema).await;
+        }
+        self.pool.raw_pool().await.close().await;
+    }
+}
+
+// Panics without TEST_POSTGRES_URL, the postgres tests are ignored by default and only get here when run
+// against a server.
+#[cfg(feature = "postgres")]
+pub async fn postgres_db(prefix: &str) -> Option<TestDb<sfo_sql::postgres::SqlPool>> {
+    let url = sfo_sql::test_util::postgres_test_url().expect("TEST_POSTGRES_URL is not set, the postgres tests need a server");
+    let (pool, schema) = sfo_sql::postgres::SqlPool::open_test_schema(url.as_str(), prefix, 5).await.unwrap();
+    let dropped = schema.clone();
+    let drop_schema = Box::new(move || block_on_own_runtime(async move {
+        if let Ok(pool) = sfo_sql::postgres::SqlPool::open(url.as_str(), 1).await {
+            let _ = pool.drop_test_schema(dropped.as_str()).await;
+            pool.raw_pool().await.close().await;
+        }
+    }));
+    Some(TestDb { pool, sqlite: None, schema: Some(schema), drop_schema: Some(drop_schema) })
+}
+
+#[cfg(feature = "postgres")]
+impl TestDb<sfo_sql::postgres::SqlPool> {
+    pub async fn finish(mut self) {
+        self.drop_schema = None;
+        if let Some(schema) = self.schema.as_deref() {
+            let _ = self.pool.drop_test_schema(schema).await;
+        }
+        self.pool.raw_pool().await.close().await;
+    }
+}
+
+// sql with its ? placeholders numbered $1, $2 and so on for postgres, as is for the other
+// backends. ? in literals and comments is left alone. The suite cases are written with ? and
+// hold on to their statements like literals, so a converted text is kept for the whole run.
+pub fn placeholders<DB: sfo_sql::sqlx::Database>(sql: &'static str) -> &'static str {
+    static NUMBERED: std::sync::Mutex<Option<std::collections::HashMap<&'static str, &'static str>>> = std::sync::Mutex::new(None);
+    if DB::NAME != "PostgreSQL" {
+        return sql;
+    }
+    let mut numbered = NUMBERED.lock().unwrap();
+    numbered.get_or_insert_with(Default::default).entry(sql).or_insert_with(|| {
+        let mut out = String::with_capacity(sql.len() + 8);
+        let mut n = 0;
+        let mut chars = sql.chars().peekable();
+        while let Some(c) = chars.next() {
+            out.push(c);
+            match c {
+                '?' => {
+                    out.pop();
+                    n += 1;
+                    out.push_str(format!("${}", n).as_str());
+                }
+                '\'' | '"' => {
+                    for next in chars.by_ref() {
+                        out.push(next);
+                        if next == c {
+                            break;
+                        }
+                    }
+                }
+                '/' if chars.peek() == Some(&'*') => {
+                    while let Some(next) = chars.next() {
+                        out.push(next);
+                        if next == '*' && chars.peek() == Some(&'/') {
+                            out.push(chars.next().unwrap());
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Box::leak(out.into_boxed_str())
+    })
+}
+
+// Auto-increment key column definition of a backend.
+pub fn id_column(backend: sfo_sql::prelude::SqlBackend) -> &'static str {
+    match backend {
+        sfo_sql::prelude::SqlBackend::MySql => "id BIGINT PRIMARY KEY AUTO_INCREMENT",
+        sfo_sql::prelude::SqlBackend::Postgres => "id BIGSERIAL PRIMARY KEY",
+        _ => "id INTEGER PRIMARY KEY AUTOINCREMENT",
+    }
+}
+
+// Runs body with a fresh TestDb of the backend and cleans up after it, a server schema also when
+// body panics.
+macro_rules! with_db {
+    ($open:path, $name:expr, |$db:ident| $body:block) => {{
+        let $db = $open($name).await.unwrap();
+        $body
+        $db.finish().await;
+    }};
+}
+
+// Behaviour every backend must share, written once against the SqlPool and SqlConnection api.
+// $module is the backend module, $open returns an Option<TestDb> of its pool. The attributes
+// after them go on every case, e.g. #[ignore = "needs TEST_MYSQL_URL"].
+macro_rules! behaviour_suite {
+    ($module:path, $open:path $(, #[$attr:meta])*) => {
+        use $module as backend;
+        use sfo_sql::errors::SqlErrorCode;
+        use sfo_sql::prelude::{sql_query, RowExpectation, SqlBackend, SqlRow, SqlValue};
+
+        // The cases write their placeholders as ?, see placeholders.
+        fn q(sql: &'static str) -> &'static str {
+            crate::common::placeholders::<backend::SqlDB>(sql)
+        }
+
+        async fn create_users(conn: &mut backend::SqlConnection, backend: SqlBackend) {
+            let sql = format!("CREATE TABLE users ({}, name VARCHAR(64) NOT NULL UNIQUE, age BIGINT)", crate::common::id_column(backend));
+            conn.execute_sql(sql_query(sql.as_str())).await.unwrap();
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn crud() {
+            crate::common::with_db!($open, "crud", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                let insert = match db.pool.target().backend {
+                    SqlBackend::Postgres => "INSERT INTO users (name, age) VALUES (?, ?) RETURNING id",
+                    _ => "INSERT INTO users (name, age) VALUES (?, ?)",
+                };
+                let id = conn.insert_returning_id(sql_query(q(insert)).bind("alice").bind(30i64)).await.unwrap();
+                conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind("bob").bind(40i64)).await.unwrap();
+
+                let row = conn.query_one(sql_query(q("SELECT name, age FROM users WHERE id = ?")).bind(id)).await.unwrap();
+                assert_eq!(row.get::<String, _>("name"), "alice");
+                assert_eq!(row.get::<i64, _>("age"), 30);
+                assert_eq!(conn.query_all(sql_query("SELECT id FROM users ORDER BY id")).await.unwrap().len(), 2);
+
+                let updated = conn.execute_expecting_rows(sql_query(q("UPDATE users SET age = ? WHERE name = ?")).bind(31i64).bind("alice"),
+                                                          RowExpectation::ExactlyOne).await.unwrap();
+                assert_eq!(updated, 1);
+                conn.execute_sql(sql_query(q("DELETE FROM users WHERE name = ?")).bind("bob")).await.unwrap();
+                assert!(conn.query_optional(sql_query(q("SELECT id FROM users WHERE name = ?")).bind("bob")).await.unwrap().is_none());
+                assert_eq!(db.pool.query_all(sql_query("SELECT id FROM users")).await.unwrap().len(), 1);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn error_codes() {
+            crate::common::with_db!($open, "error_codes", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                conn.execute_sql(sql_query(q("INSERT INTO users (name) VALUES (?)")).bind("alice")).await.unwrap();
+                let e = conn.execute_sql(sql_query(q("INSERT INTO users (name) VALUES (?)")).bind("alice")).await.unwrap_err();
+                assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+                let e = conn.query_one(sql_query(q("SELECT id FROM users WHERE name = ?")).bind("nobody")).await.err().unwrap();
+                assert_eq!(e.code(), SqlErrorCode::NotFound);
+                let e = conn.query_all(sql_query("SELECT nothing FROM missing_table")).await.err().unwrap();
+                assert_ne!(e.code(), SqlErrorCode::NotFound);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn query_optional() {
+            crate::common::with_db!($open, "query_optional", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                let by_name = |name: &'static str| sql_query(q("SELECT name, age FROM users WHERE name = ?")).bind(name);
+                assert!(conn.query_optional(by_name("alice")).await.unwrap().is_none());
+
+                conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind("alice").bind(30i64)).await.unwrap();
+                let row = conn.query_optional(by_name("alice")).await.unwrap().unwrap();
+                assert_eq!(row.get::<i64, _>("age"), 30);
+                assert!(db.pool.query_optional(by_name("alice")).await.unwrap().is_some());
+
+                let e = conn.query_optional(sql_query("SELECT name FROM missing_table")).await.err().unwrap();
+                assert_ne!(e.code(), SqlErrorCode::NotFound);
+                conn.set_placeholder_validation(true);
+                let e = conn.query_optional(sql_query(q("SELECT name FROM users WHERE name = ? AND age = ?")).bind("alice")).await.err().unwrap();
+                assert_eq!(e.code(), SqlErrorCode::ParameterMismatch);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn query_optional_in_a_transaction() {
+            crate::common::with_db!($open, "query_optional_tx", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                let mut other = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                let by_name = |name: &'static str| sql_query(q("SELECT age FROM users WHERE name = ?")).bind(name);
+
+                conn.begin_transaction().await.unwrap();
+                assert!(conn.query_optional(by_name("alice")).await.unwrap().is_none());
+                conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind("alice").bind(30i64)).await.unwrap();
+                let row = conn.query_optional(by_name("alice")).await.unwrap().unwrap();
+                assert_eq!(row.get::<i64, _>("age"), 30);
+                assert!(other.query_optional(by_name("alice")).await.unwrap().is_none());
+
+                // A failed query leaves the transaction to roll back.
+                conn.query_optional(sql_query("SELECT age FROM missing_table")).await.err().unwrap();
+                assert!(conn.current_transaction_id().is_some());
+                conn.rollback_transaction().await.unwrap();
+                assert!(conn.query_optional(by_name("alice")).await.unwrap().is_none());
+                drop(other);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn placeholder_validation() {
+            crate::common::with_db!($open, "placeholder_validation", |db| {
+                let pool = db.pool.clone().with_placeholder_validation(true);
+                let mut conn = pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+
+                // A checked query runs with the arguments it was bound with.
+                conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind("alice").bind(30i64)).await.unwrap();
+                let row = conn.query_one(sql_query(q("SELECT age FROM users WHERE name = ?")).bind("alice")).await.unwrap();
+                assert_eq!(row.get::<i64, _>("age"), 30);
+                // Marks in literals and comments are not placeholders.
+                let row = pool.query_one(sql_query(q("SELECT '?' AS q, age FROM users WHERE name = ? /* ? */")).bind("alice")).await.unwrap();
+                assert_eq!(row.get::<String, _>("q"), "?");
+
+                let e = conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind("bob")).await.unwrap_err();
+                assert_eq!(e.code(), SqlErrorCode::ParameterMismatch);
+                assert!(format!("{:?}", e).contains("expected 2 actual 1"), "{:?}", e);
+                let e = pool.query_all(sql_query(q("SELECT age FROM users WHERE name = ?")).bind("alice").bind("bob")).await.err().unwrap();
+                assert_eq!(e.code(), SqlErrorCode::ParameterMismatch);
+                assert!(format!("{:?}", e).contains("expected 1 actual 2"), "{:?}", e);
+                // Rejected before it ran.
+                assert_eq!(conn.query_scalar::<i64>(sql_query("SELECT count(*) FROM users")).await.unwrap(), 1);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn query_scalar() {
+            crate::common::with_db!($open, "query_scalar", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?), (?, NULL)")).bind("alice").bind(30i64).bind("bob")).await.unwrap();
+
+                assert_eq!(conn.query_scalar::<i64>(sql_query("SELECT count(*) FROM users")).await.unwrap(), 2);
+                assert_eq!(db.pool.query_scalar::<i64>(sql_query("SELECT count(*) FROM users")).await.unwrap(), 2);
+                let name: String = conn.query_scalar(sql_query(q("SELECT name FROM users WHERE age = ?")).bind(30i64)).await.unwrap();
+                assert_eq!(name, "alice");
+                let age: Option<i64> = conn.query_scalar(sql_query(q("SELECT age FROM users WHERE name = ?")).bind("bob")).await.unwrap();
+                assert_eq!(age, None);
+                let missing: Option<i64> = conn.query_scalar_optional(sql_query(q("SELECT age FROM users WHERE name = ?")).bind("nobody")).await.unwrap();
+                assert_eq!(missing, None);
+                let e = conn.query_scalar::<i64>(sql_query(q("SELECT age FROM users WHERE name = ?")).bind("nobody")).await.unwrap_err();
+                assert_eq!(e.code(), SqlErrorCode::NotFound);
+
+                // Mismatches are errors, not panics.
+                let e = conn.query_scalar::<i64>(sql_query(q("SELECT name FROM users WHERE name = ?")).bind("alice")).await.unwrap_err();
+                assert!(format!("{:?}", e).contains("decode error"), "{:?}", e);
+                // sqlite decodes NULL as zero.
+                if db.pool.target().backend != SqlBackend::Sqlite {
+                    let e = conn.query_scalar::<i64>(sql_query(q("SELECT age FROM users WHERE name = ?")).bind("bob")).await.unwrap_err();
+                    assert!(format!("{:?}", e).contains("decode error"), "{:?}", e);
+                }
+                let e = db.pool.query_scalar_optional::<i64>(sql_query(q("SELECT name FROM users WHERE name = ?")).bind("alice")).await.unwrap_err();
+                assert!(format!("{:?}", e).contains("decode error"), "{:?}", e);
+                drop(conn);
+            });
+        }
+
+        struct User {
+            name: String,
+            age: i64,
+        }
+
+        impl<'r, R: sfo_sql::sqlx::Row> sfo_sql::sqlx::FromRow<'r, R> for User
+        where &'r str: sfo_sql::sqlx::ColumnIndex<R>,
+              String: sfo_sql::sqlx::Decode<'r, R::Database> + sfo_sql::sqlx::Type<R::Database>,
+              i64: sfo_sql::sqlx::Decode<'r, R::Database> + sfo_sql::sqlx::Type<R::Database>, {
+            fn from_row(row: &'r R) -> Result<Self, sfo_sql::sqlx::Error> {
+                Ok(User { name: row.try_get("name")?, age: row.try_get("age")? })
+            }
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn typed_rows() {
+            crate::common::with_db!($open, "typed_rows", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?), (?, ?)")).bind("alice").bind(30i64).bind("bob").bind(40i64)).await.unwrap();
+
+                let user: User = conn.query_one_as(sql_query(q("SELECT name, age FROM users WHERE name = ?")).bind("alice")).await.unwrap();
+                assert_eq!((user.name.as_str(), user.age), ("alice", 30));
+                let user: User = db.pool.query_one_as(sql_query(q("SELECT name, age FROM users WHERE name = ?")).bind("bob")).await.unwrap();
+                assert_eq!((user.name.as_str(), user.age), ("bob", 40));
+                let users: Vec<User> = conn.query_all_as(sql_query("SELECT name, age FROM users ORDER BY name")).await.unwrap();
+                assert_eq!(users.iter().map(|u| (u.name.as_str(), u.age)).collect::<Vec<_>>(), vec![("alice", 30), ("bob", 40)]);
+                let users: Vec<User> = db.pool.query_all_as(sql_query(q("SELECT name, age FROM users WHERE name = ?")).bind("nobody")).await.unwrap();
+                assert!(users.is_empty());
+
+                let e = conn.query_one_as::<User>(sql_query(q("SELECT name, age FROM users WHERE name = ?")).bind("nobody")).await.err().unwrap();
+                assert_eq!(e.code(), SqlErrorCode::NotFound);
+                let e = conn.query_one_as::<User>(sql_query(q("SELECT name FROM users WHERE name = ?")).bind("alice")).await.err().unwrap();
+                assert!(format!("{:?}", e).contains("age"), "{:?}", e);
+                // A row that does not decode names its index and the column.
+                let e = conn.query_all_as::<User>(sql_query("SELECT name, name AS age FROM users ORDER BY name")).await.err().unwrap();
+                let message = format!("{:?}", e);
+                assert!(message.contains("row 0 of 2") && message.contains("age"), "{}", message);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn row_expectations() {
+            crate::common::with_db!($open, "row_expectations", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                for name in ["alice", "bob", "carol"] {
+                    conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind(name).bind(20i64)).await.unwrap();
+                }
+                let update = |age: i64, filter: &'static str| sql_query(q(filter)).bind(age);
+
+                let n = conn.execute_expecting_rows(update(21, "UPDATE users SET age = ? WHERE name = 'alice'"), RowExpectation::ExactlyOne).await.unwrap();
+                assert_eq!(n, 1);
+                let n = conn.execute_expecting_rows(update(22, "UPDATE users SET age = ? WHERE name <> 'alice'"), RowExpectation::AtLeastOne).await.unwrap();
+                assert_eq!(n, 2);
+                let n = conn.execute_expecting_rows(update(23, "UPDATE users SET age = ? WHERE name = 'nobody'"), RowExpectation::Any).await.unwrap();
+                assert_eq!(n, 0);
+
+                // The actual count is in the error, the statement ran and is not undone.
+                let e = conn.execute_expecting_rows(update(24, "UPDATE users SET age = ? WHERE name <> 'alice'"), RowExpectation::ExactlyOne).await.unwrap_err();
+                assert_eq!(e.code(), SqlErrorCode::RowsNotAffected);
+                assert!(format!("{:?}", e).contains("actual 2"), "{:?}", e);
+                let e = conn.execute_expecting_rows(update(25, "UPDATE users SET age = ? WHERE name = 'nobody'"), RowExpectation::ExactlyOne).await.unwrap_err();
+                assert_eq!(e.code(), SqlErrorCode::RowsNotAffected);
+                assert!(format!("{:?}", e).contains("actual 0"), "{:?}", e);
+                let e = conn.execute_expecting_rows(sql_query(q("DELETE FROM users WHERE name = ?")).bind("nobody"), RowExpectation::AtLeastOne).await.unwrap_err();
+                assert_eq!(e.code(), SqlErrorCode::RowsNotAffected);
+                let ages: Vec<i64> = conn.query_all(sql_query("SELECT age FROM users ORDER BY name")).await.unwrap().iter().map(|r| r.get("age")).collect();
+                assert_eq!(ages, vec![21, 24, 24]);
+                drop(conn);
+            });
+        }
+
+        async fn filtered_names(conn: &mut backend::SqlConnection, filter: backend::FilterBuilder, kind: SqlBackend) -> Vec<String> {
+            let built = filter.build(kind).unwrap();
+            let sql = format!("SELECT name FROM users{} ORDER BY name", built.sql);
+            let rows = conn.query_all(built.bind(sql_query(sql.as_str()))).await.unwrap();
+            rows.iter().map(|r| r.get::<String, _>("name")).collect()
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn filters() {
+            crate::common::with_db!($open, "filters", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                let kind = db.pool.target().backend;
+                create_users(&mut conn, kind).await;
+                for (name, age) in [("amy", Some(17i64)), ("bob", Some(25)), ("b_x", Some(40)), ("cat", None), ("dan", Some(30))] {
+                    conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind(name).bind(age)).await.unwrap();
+                }
+                let filter = || backend::FilterBuilder::new(&["name", "age"]);
+
+                assert_eq!(filtered_names(&mut conn, filter(), kind).await, vec!["amy", "b_x", "bob", "cat", "dan"]);
+                // _ in the text is matched literally.
+                let f = filter().like("name", "b_", backend::LikeMode::StartsWith);
+                assert_eq!(filtered_names(&mut conn, f, kind).await, vec!["b_x"]);
+                let f = filter().or(|g| g.lt("age", 18i64).is_null("age").and(|g| g.between("age", 26i64, 35i64).ne("name", "bob")));
+                assert_eq!(filtered_names(&mut conn, f, kind).await, vec!["amy", "cat", "dan"]);
+                let f = filter().is_in("name", vec![SqlValue::from("bob"), SqlValue::from("dan"), SqlValue::from("eve")]).ge("age", 26i64);
+                assert_eq!(filtered_names(&mut conn, f, kind).await, vec!["dan"]);
+                let f = filter().is_in("name", Vec::new());
+                assert!(filtered_names(&mut conn, f, kind).await.is_empty());
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn query_page() {
+            crate::common::with_db!($open, "query_page", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+                for (i, name) in ["dan", "amy", "eve", "bob", "cat"].iter().enumerate() {
+                    let age = if i == 2 { None } else { Some(20 + i as i64) };
+                    conn.execute_sql(sql_query(q("INSERT INTO users (name, age) VALUES (?, ?)")).bind(*name).bind(age)).await.unwrap();
+                }
+                let spec = sfo_sql::prelude::SortSpec::new().allow("name", "name").allow("age", "age").nulls_last(true);
+                let filter = sfo_sql::prelude::FilterBuilder::new(&["name"]).ne("name", "bob").build(db.pool.target().backend).unwrap();
+                let sql = format!("SELECT name, age FROM users{}", filter.sql);
+                let names = |page: &backend::Page<backend::SqlRowObject>| page.rows.iter().map(|r| r.get::<String, _>("name")).collect::<Vec<_>>();
+
+                let order = spec.parse("-age,name").unwrap();
+                let first = conn.query_page(sql.as_str(), &filter.args, Some(&order), 0, 3).await.unwrap();
+                // NULL age sorts last although the order is descending.
+                assert_eq!(names(&first), vec!["cat", "amy", "dan"]);
+                assert_eq!((first.total, first.page_count(), first.has_next()), (4, 2, true));
+                let second = conn.query_page(sql.as_str(), &filter.args, Some(&order), 1, 3).await.unwrap();
+                assert_eq!(names(&second), vec!["eve"]);
+                assert!(!second.has_next());
+                let beyond = conn.query_page(sql.as_str(), &filter.args, Some(&order), 5, 3).await.unwrap();
+                assert!(beyond.rows.is_empty());
+                assert_eq!(beyond.total, 4);
+
+                let order = spec.parse("name").unwrap();
+                let all = conn.query_page(sql.as_str(), &filter.args, Some(&order), 0, 10).await.unwrap();
+                assert_eq!(names(&all), vec!["amy", "cat", "dan", "eve"]);
+                assert_eq!(spec.parse("-secret").unwrap_err().code(), SqlErrorCode::InvalidArgument);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn transactions() {
+            crate::common::with_db!($open, "transactions", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                create_users(&mut conn, db.pool.target().backend).await;
+
+                conn.begin_transaction().await.unwrap();
+                conn.execute_sql(sql_query(q("INSERT INTO users (name) VALUES (?)")).bind("rolled_back")).await.unwrap();
+                // Reads run in the transaction and see its writes, a failed statement leaves it
+                // open to roll back.
+                let row = conn.query_one(sql_query("SELECT count(*) AS c FROM users")).await.unwrap();
+                assert_eq!(row.get::<i64, _>("c"), 1);
+                conn.query_all(sql_query("SELECT nothing FROM missing_table")).await.err().unwrap();
+                assert!(conn.current_transaction_id().is_some());
+                conn.rollback_transaction().await.unwrap();
+                let row = conn.query_one(sql_query("SELECT count(*) AS c FROM users")).await.unwrap();
+                assert_eq!(row.get::<i64, _>("c"), 0);
+                conn.begin_transaction().await.unwrap();
+                conn.execute_sql(sql_query(q("INSERT INTO users (name) VALUES (?)")).bind("committed")).await.unwrap();
+                conn.commit_transaction().await.unwrap();
+
+                let ret = conn.transaction(|conn| Box::pin(async move {
+                    conn.execute_sql(sql_query(q("INSERT INTO users (name) VALUES (?)")).bind("closure")).await?;
+                    conn.execute_sql(sql_query(q("INSERT INTO users (name) VALUES (?)")).bind("closure")).await?;
+                    Ok(())
+                })).await;
+                assert_eq!(ret.unwrap_err().code(), SqlErrorCode::AlreadyExists);
+
+                let rows = conn.query_all(sql_query("SELECT name FROM users ORDER BY name")).await.unwrap();
+                let names: Vec<String> = rows.iter().map(|row| row.get("name")).collect();
+                assert_eq!(names, vec!["committed".to_string()]);
+                drop(conn);
+            });
+        }
+
+        #[tokio::test]
+        $(#[$attr])*
+        async fn schema_helpers() {
+            crate::common::with_db!($open, "schema_helpers", |db| {
+                let mut conn = db.pool.get_conn().await.unwrap();
+                assert!(!conn.is_table_exist("users").await.unwrap());
+                create_users(&mut conn, db.pool.target().backend).await;
+                conn.execute_sql(sql_query("CREATE INDEX users_age ON users (age)")).await.unwrap();
+                assert!(conn.is_table_exist("users").await.unwrap());
+                assert!(conn.is_column_exist("users", "age", None).await.unwrap());
+                assert!(!conn.is_column_exist("users", "missing", None).await.unwrap());
+                assert!(conn.is_index_exist("users", "users_age", None).await.unwrap());
+                assert!(!conn.is_index_exist("users", "missing", None).await.unwrap());
+                drop(conn);
+            });
+        }
+    };
+}
+
+pub(crate) use behaviour_suite;
+pub(crate) use with_db;
+
+pub mod logs;
@@ -0,0 +1,46 @@
+// Column encryption, run with the crypto feature.
+#![cfg(feature = "crypto")]
+
+use sfo_sql::crypto::{payload_key_id, seal, unseal, AesGcmCipher};
+use sfo_sql::errors::SqlErrorCode;
+
+fn message(e: sfo_sql::errors::SqlError) -> String {
+    assert_eq!(e.code(), SqlErrorCode::Crypto);
+    e.msg().to_string()
+}
+
+#[test]
+fn round_trip_and_rotation() {
+    let old = AesGcmCipher::new("k1", &[1u8; 32]);
+    let payload = seal(&old, b"secret token").unwrap();
+    assert_eq!(payload_key_id(payload.as_slice()), Some("k1"));
+    assert_eq!(unseal(&old, payload.as_slice()).unwrap(), b"secret token");
+
+    let rotated = AesGcmCipher::new("k2", &[2u8; 32]).with_old_key("k1", &[1u8; 32]);
+    assert_eq!(unseal(&rotated, payload.as_slice()).unwrap(), b"secret token");
+    let rewritten = seal(&rotated, b"secret token").unwrap();
+    assert_eq!(payload_key_id(rewritten.as_slice()), Some("k2"));
+    assert!(unseal(&old, rewritten.as_slice()).is_err());
+}
+
+#[test]
+fn wrong_key_and_tampered_data_are_told_apart() {
+    let cipher = AesGcmCipher::new("k1", &[1u8; 32]);
+    let payload = seal(&cipher, b"secret token").unwrap();
+
+    let wrong = AesGcmCipher::new("k1", &[9u8; 32]);
+    let wrong_key = message(unseal(&wrong, payload.as_slice()).unwrap_err());
+    assert!(wrong_key.contains("wrong key"), "{}", wrong_key);
+
+    let mut tampered = payload.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    let corrupted = message(unseal(&cipher, tampered.as_slice()).unwrap_err());
+    assert!(corrupted.contains("corrupted"), "{}", corrupted);
+    assert_ne!(wrong_key, corrupted);
+
+    let unknown = message(unseal(&AesGcmCipher::new("k2", &[1u8; 32]), payload.as_slice()).unwrap_err());
+    assert!(unknown.contains("unknown key id k1"), "{}", unknown);
+    for text in [wrong_key, corrupted, unknown] {
+        assert!(!text.contains("secret token"));
+    }
+}
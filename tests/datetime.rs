@@ -0,0 +1,80 @@
+// UTC datetimes bound and read back on sqlite, run with the chrono feature. A binary of its own,
+// the warnings for naive datetimes are remembered per process.
+#![cfg(feature = "chrono")]
+
+mod common;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use log::Level;
+use sfo_sql::datetime::{parse_storage_text, to_storage_text, BindDateTime, DateTimePrecision, DateTimeRowExt};
+use sfo_sql::sqlite::{sql_query, SqlRow};
+
+fn sample() -> DateTime<FixedOffset> {
+    // 10:30:00.123456 at +02:00 is 08:30:00.123456 UTC.
+    FixedOffset::east_opt(2 * 3600).unwrap()
+        .from_local_datetime(&NaiveDate::from_ymd_opt(2024, 5, 1).unwrap().and_hms_micro_opt(10, 30, 0, 123456).unwrap())
+        .unwrap()
+}
+
+#[test]
+fn storage_text_per_precision() {
+    let dt = sample();
+    assert_eq!(to_storage_text(&dt, DateTimePrecision::Seconds), "2024-05-01T08:30:00Z");
+    assert_eq!(to_storage_text(&dt, DateTimePrecision::Millis), "2024-05-01T08:30:00.123Z");
+    assert_eq!(to_storage_text(&dt, DateTimePrecision::Micros), "2024-05-01T08:30:00.123456Z");
+
+    let utc = Utc.with_ymd_and_hms(2024, 5, 1, 8, 30, 0).unwrap();
+    for text in ["2024-05-01T08:30:00Z", "2024-05-01 08:30:00", "2024-05-01T10:30:00+02:00", "2024-05-01 10:30:00+02:00", "2024-05-01T08:30:00"] {
+        assert_eq!(parse_storage_text(text).unwrap(), utc, "{}", text);
+    }
+    assert!(parse_storage_text("yesterday").is_err());
+}
+
+#[tokio::test]
+async fn values_round_trip_at_each_precision() {
+    let db = common::sqlite_db("datetime_round_trip").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE events (id INTEGER PRIMARY KEY, at TEXT NOT NULL)")).await.unwrap();
+    let dt = sample();
+    for (id, precision) in [(1, DateTimePrecision::Seconds), (2, DateTimePrecision::Millis), (3, DateTimePrecision::Micros)] {
+        conn.execute_sql(sql_query("INSERT INTO events (id, at) VALUES (?, ?)").bind(id).bind_datetime_utc(&dt, precision)).await.unwrap();
+        let row = conn.query_one(sql_query("SELECT at FROM events WHERE id = ?").bind(id)).await.unwrap();
+        let read = row.get_datetime_utc("at", precision).unwrap();
+        assert_eq!(read, precision.truncate(&dt));
+        assert_eq!(row.get::<String, _>("at"), to_storage_text(&dt, precision));
+    }
+    // Text of one precision orders as the values do.
+    let later = dt + chrono::Duration::milliseconds(1);
+    conn.execute_sql(sql_query("INSERT INTO events (id, at) VALUES (4, ?)").bind_datetime_utc(&later, DateTimePrecision::Millis)).await.unwrap();
+    let row = conn.query_one(sql_query("SELECT id FROM events WHERE id IN (2, 4) ORDER BY at DESC LIMIT 1")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("id"), 4);
+
+    let e = row.get_datetime_utc("missing", DateTimePrecision::Seconds).unwrap_err();
+    assert!(e.msg().contains("missing"), "{}", e.msg());
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn naive_binds_warn_once_per_call_site() {
+    let _guard = common::logs::lock().await;
+    let naive = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap().and_hms_opt(8, 30, 0).unwrap();
+    for _ in 0..3 {
+        let _ = sql_query::<sfo_sql::Sqlite>("SELECT ?").bind_naive_datetime(&naive, DateTimePrecision::Seconds);
+    }
+    let _ = sql_query::<sfo_sql::Sqlite>("SELECT ?").bind_naive_datetime(&naive, DateTimePrecision::Seconds);
+
+    let logged = common::logs::records_with("naive datetime 2024-05-01 08:30:00 bound at");
+    assert_eq!(logged.iter().map(|(level, _)| *level).collect::<Vec<_>>(),
+               vec![Level::Warn, Level::Debug, Level::Debug, Level::Warn]);
+    // The warning names the call site, not the crate.
+    assert!(logged.iter().all(|(_, msg)| msg.contains("tests/datetime.rs")), "{:?}", logged);
+    assert_ne!(logged[0].1, logged[3].1);
+
+    let db = common::sqlite_db("datetime_naive").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let row = conn.query_one(sql_query("SELECT ? AS at").bind_naive_datetime(&naive, DateTimePrecision::Seconds)).await.unwrap();
+    assert_eq!(row.get_datetime_utc("at", DateTimePrecision::Seconds).unwrap(), naive.and_utc());
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,87 @@
+// Levels mapped database errors are logged at. A binary of its own, the log policy and the
+// logger are process wide.
+
+mod common;
+
+use log::Level;
+use log::LevelFilter;
+use sfo_sql::errors::{error_log_level, error_log_policy, set_error_log_level, set_error_log_policy, ErrorLogPolicy, SqlErrorCode};
+use sfo_sql::sqlite::sql_query;
+
+#[tokio::test]
+async fn policy_picks_the_level_per_code() {
+    let _guard = common::logs::lock().await;
+    let db = common::sqlite_db("policy_levels").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE policy_levels (name TEXT PRIMARY KEY)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO policy_levels (name) VALUES ('a')")).await.unwrap();
+
+    set_error_log_policy(ErrorLogPolicy::new().level(SqlErrorCode::AlreadyExists, Some(Level::Error)));
+    let e = conn.execute_sql(sql_query("INSERT INTO policy_levels (name) VALUES ('a')")).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    let logged = common::logs::records_with("INSERT INTO policy_levels");
+    assert_eq!(logged.iter().map(|(level, _)| *level).collect::<Vec<_>>(), vec![Level::Error]);
+
+    set_error_log_policy(ErrorLogPolicy::new().level(SqlErrorCode::AlreadyExists, None));
+    conn.execute_sql(sql_query("INSERT INTO policy_levels (name) VALUES ('a')")).await.unwrap_err();
+    assert_eq!(common::logs::records_with("INSERT INTO policy_levels").len(), 1);
+
+    set_error_log_policy(ErrorLogPolicy::new());
+    conn.query_all(sql_query("SELECT missing FROM policy_levels")).await.err().unwrap();
+    let logged = common::logs::records_with("SELECT missing FROM policy_levels");
+    assert_eq!(logged.iter().map(|(level, _)| *level).collect::<Vec<_>>(), vec![Level::Error]);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn expected_failures_stay_off_the_error_level() {
+    let _guard = common::logs::lock().await;
+    set_error_log_policy(ErrorLogPolicy::new());
+    set_error_log_level(LevelFilter::Trace);
+    let db = common::sqlite_db("expected_failures").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE expected_failures (name TEXT PRIMARY KEY)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO expected_failures (name) VALUES ('a')")).await.unwrap();
+
+    let e = conn.execute_sql(sql_query("INSERT INTO expected_failures (name) VALUES ('a')")).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    let logged = common::logs::records_with("INSERT INTO expected_failures");
+    assert_eq!(logged.iter().map(|(level, _)| *level).collect::<Vec<_>>(), vec![Level::Debug]);
+
+    let e = conn.query_one(sql_query("SELECT name FROM expected_failures WHERE name = 'b'")).await.err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::NotFound);
+    assert!(common::logs::records_with("SELECT name FROM expected_failures").is_empty());
+    set_error_log_level(LevelFilter::Error);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn level_and_policy_share_one_setting() {
+    let _guard = common::logs::lock().await;
+    set_error_log_policy(ErrorLogPolicy::new().level(SqlErrorCode::AlreadyExists, Some(Level::Info)));
+    assert_eq!(error_log_level(), LevelFilter::Error);
+    assert_eq!(error_log_policy().logged_level(SqlErrorCode::AlreadyExists), None);
+
+    set_error_log_level(LevelFilter::Info);
+    let policy = error_log_policy();
+    assert_eq!(policy.level_of(SqlErrorCode::AlreadyExists), Some(Level::Info));
+    assert_eq!(policy.logged_level(SqlErrorCode::AlreadyExists), Some(Level::Info));
+    assert_eq!(policy.logged_level(SqlErrorCode::NotFound), None);
+
+    let db = common::sqlite_db("shared_setting").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE shared_setting (name TEXT PRIMARY KEY)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO shared_setting (name) VALUES ('a')")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO shared_setting (name) VALUES ('a')")).await.unwrap_err();
+    let logged = common::logs::records_with("INSERT INTO shared_setting");
+    assert_eq!(logged.iter().map(|(level, _)| *level).collect::<Vec<_>>(), vec![Level::Info]);
+
+    set_error_log_policy(ErrorLogPolicy::new().max_level(LevelFilter::Warn));
+    assert_eq!(error_log_level(), LevelFilter::Warn);
+    set_error_log_policy(ErrorLogPolicy::new());
+    assert_eq!(error_log_level(), LevelFilter::Error);
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,36 @@
+// Runs the example binaries cargo test builds next to the test executables, so an example that
+// stops working fails the suite instead of only failing to compile. A run limited with --test
+// does not build the examples, they are skipped then. Tests build the crate with the sqlite
+// feature, so the examples run on sqlite, each against a temporary file.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+const EXAMPLES: &[&str] = &["basic", "bulk_insert", "lease", "migration", "prelude", "schema", "transaction"];
+
+fn example_path(name: &str) -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let dir = exe.parent().unwrap().parent().unwrap().join("examples");
+    dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX))
+}
+
+fn run_example(name: &str, env: &[(&str, String)]) {
+    let path = example_path(name);
+    if !path.exists() {
+        eprintln!("example {} not built at {}, skipped", name, path.display());
+        return;
+    }
+    let output = Command::new(&path).envs(env.iter().map(|(k, v)| (*k, v.as_str()))).output().unwrap();
+    assert!(output.status.success(), "example {} failed\nstdout:\n{}\nstderr:\n{}", name,
+            String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn examples_run_on_sqlite() {
+    for name in EXAMPLES {
+        let path = std::env::temp_dir().join(format!("sfo_sql_example_test_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        run_example(name, &[("SFO_SQL_SQLITE_PATH", path.display().to_string())]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,59 @@
+// FilterBuilder rendering per backend, the values always bound.
+
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{FilterBuilder, LikeMode, SqlBackend, SqlValue};
+
+fn filter() -> FilterBuilder {
+    FilterBuilder::new(&["name", "age", "team"])
+        .ge("age", 18i64)
+        .or(|g| g.like("name", "50%_a", LikeMode::StartsWith).is_in("team", vec![SqlValue::Int(1), SqlValue::Int(2)]))
+        .and(|g| g.between("age", 20i64, 30i64).is_not_null("team"))
+}
+
+#[test]
+fn placeholders_follow_the_backend() {
+    let built = filter().build(SqlBackend::Sqlite).unwrap();
+    assert_eq!(built.sql, " WHERE \"age\" >= ? AND (\"name\" LIKE ? ESCAPE '!' OR \"team\" IN (?, ?)) AND (\"age\" BETWEEN ? AND ? AND \"team\" IS NOT NULL)");
+    assert_eq!(built.args, vec![SqlValue::Int(18), SqlValue::Text("50!%!_a%".to_string()), SqlValue::Int(1), SqlValue::Int(2),
+                                SqlValue::Int(20), SqlValue::Int(30)]);
+
+    let built = filter().build(SqlBackend::MySql).unwrap();
+    assert_eq!(built.sql, " WHERE `age` >= ? AND (`name` LIKE ? ESCAPE '!' OR `team` IN (?, ?)) AND (`age` BETWEEN ? AND ? AND `team` IS NOT NULL)");
+
+    let built = filter().build(SqlBackend::Postgres).unwrap();
+    assert_eq!(built.sql, " WHERE \"age\" >= $1 AND (\"name\" LIKE $2 ESCAPE '!' OR \"team\" IN ($3, $4)) AND (\"age\" BETWEEN $5 AND $6 AND \"team\" IS NOT NULL)");
+    assert_eq!(built.args.len(), 6);
+
+    // After two parameters of the statement it is appended to.
+    let built = FilterBuilder::new(&["age"]).eq("age", 1i64).build_after(SqlBackend::Postgres, 2).unwrap();
+    assert_eq!(built.sql, " WHERE \"age\" = $3");
+    let built = FilterBuilder::new(&["age"]).eq("age", 1i64).build_after(SqlBackend::Sqlite, 2).unwrap();
+    assert_eq!(built.sql, " WHERE \"age\" = ?");
+}
+
+#[test]
+fn empty_filters_and_unknown_columns() {
+    let built = FilterBuilder::new(&["age"]).and(|g| g.or(|g| g)).build(SqlBackend::Postgres).unwrap();
+    assert_eq!(built.sql, "");
+    assert!(built.args.is_empty());
+    assert!(FilterBuilder::new(&["age"]).or(|g| g).is_empty());
+
+    let built = FilterBuilder::new(&["age"]).is_in("age", Vec::new()).build(SqlBackend::Sqlite).unwrap();
+    assert_eq!(built.sql, " WHERE 1 = 0");
+
+    let e = FilterBuilder::new(&["age"]).eq("password", "x").build(SqlBackend::Sqlite).unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::InvalidArgument);
+    let e = FilterBuilder::new(&["age"]).or(|g| g.is_null("age; DROP TABLE t")).build(SqlBackend::Sqlite).unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::InvalidArgument);
+}
+
+#[test]
+fn long_in_lists_are_split() {
+    let values: Vec<SqlValue> = (0..2500).map(SqlValue::Int).collect();
+    let built = FilterBuilder::new(&["id"]).is_in("id", values).build(SqlBackend::Postgres).unwrap();
+    assert_eq!(built.args.len(), 2500);
+    assert_eq!(built.sql.matches(" IN (").count(), 3);
+    assert!(built.sql.starts_with(" WHERE (\"id\" IN ($1, $2,"));
+    assert!(built.sql.contains("$1000) OR \"id\" IN ($1001,"));
+    assert!(built.sql.ends_with("$2500))"));
+}
@@ -0,0 +1,28 @@
+use sfo_sql::mysql::{sql_query, SqlRow};
+use crate::common;
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn four_byte_characters_survive_with_the_default_charset() {
+    common::with_db!(common::mysql_db, "charset_emoji", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        assert_eq!(conn.connection_charset().await.unwrap(), ("utf8mb4".to_string(), "utf8mb4_unicode_ci".to_string()));
+        assert_eq!(db.pool.table_options(), "DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci");
+
+        let sql = format!("CREATE TABLE notes (id INT PRIMARY KEY, body VARCHAR(32) NOT NULL) {}", db.pool.table_options());
+        conn.execute_sql(sql_query(sql.as_str())).await.unwrap();
+        let text = "crab 🦀 ok ✓";
+        conn.execute_sql(sql_query("INSERT INTO notes VALUES (1, ?)").bind(text)).await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO notes VALUES (2, 'crab 🦀 ok ✓')")).await.unwrap();
+
+        for id in [1, 2] {
+            let row = conn.query_one(sql_query("SELECT body, CHAR_LENGTH(body) AS chars, LENGTH(body) AS bytes FROM notes WHERE id = ?").bind(id)).await.unwrap();
+            assert_eq!(row.get::<String, _>("body"), text);
+            assert_eq!(row.get::<i64, _>("chars"), text.chars().count() as i64);
+            assert_eq!(row.get::<i64, _>("bytes"), text.len() as i64);
+        }
+        let row = conn.query_one(sql_query("SELECT CAST(table_collation AS CHAR) FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = 'notes'")).await.unwrap();
+        assert_eq!(row.get::<String, _>(0), "utf8mb4_unicode_ci");
+        drop(conn);
+    });
+}
@@ -0,0 +1,81 @@
+use sfo_sql::errors::{sql_err, SqlErrorCode};
+use sfo_sql::mysql::{sql_query, SqlConnection, SqlRow};
+use crate::common;
+
+async fn counts(conn: &mut SqlConnection, other: &str) -> (i64, i64) {
+    let orders = conn.query_one(sql_query("SELECT count(*) AS c FROM orders")).await.unwrap().get("c");
+    let sql = format!("SELECT count(*) AS c FROM `{}`.stock", other);
+    let stock = conn.query_one(sql_query(sql.as_str())).await.unwrap().get("c");
+    (orders, stock)
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn a_cross_schema_insert_pair_commits_and_rolls_back_together() {
+    common::with_db!(common::mysql_db, "databases_pair", |db| {
+        common::with_db!(common::mysql_db, "databases_pair_other", |other_db| {
+            let other: String = other_db.pool.query_one(sql_query("SELECT database() AS d")).await.unwrap().get("d");
+            let mut conn = db.pool.get_conn().await.unwrap();
+            let current: String = conn.query_one(sql_query("SELECT database() AS d")).await.unwrap().get("d");
+            conn.execute_sql(sql_query("CREATE TABLE orders (id BIGINT PRIMARY KEY, item VARCHAR(16) NOT NULL)")).await.unwrap();
+            let sql = format!("CREATE TABLE `{}`.stock (item VARCHAR(16) PRIMARY KEY, reserved BIGINT NOT NULL)", other);
+            conn.execute_sql(sql_query(sql.as_str())).await.unwrap();
+
+            let insert_stock = format!("INSERT INTO `{}`.stock (item, reserved) VALUES (?, 1)", other);
+            let databases = [current.as_str(), other.as_str()];
+            let sql = insert_stock.clone();
+            conn.with_databases(&databases, move |conn| Box::pin(async move {
+                conn.execute_sql(sql_query("INSERT INTO orders (id, item) VALUES (1, 'bolt')")).await?;
+                conn.execute_sql(sql_query(sql.as_str()).bind("bolt")).await?;
+                Ok(())
+            })).await.unwrap();
+            assert_eq!(counts(&mut conn, other.as_str()).await, (1, 1));
+
+            let e = conn.with_databases(&databases, move |conn| Box::pin(async move {
+                conn.execute_sql(sql_query("INSERT INTO orders (id, item) VALUES (2, 'nut')")).await?;
+                conn.execute_sql(sql_query(insert_stock.as_str()).bind("nut")).await?;
+                Err::<(), _>(sql_err!(SqlErrorCode::Failed, "out of nuts"))
+            })).await.unwrap_err();
+            assert_eq!(e.code(), SqlErrorCode::Failed);
+            assert_eq!(counts(&mut conn, other.as_str()).await, (1, 1));
+
+            // A body switching the current database is rolled back, its unqualified names
+            // would no longer hit the schema the caller meant.
+            let switch = format!("USE `{}`", other);
+            let e = conn.with_databases(&databases, move |conn| Box::pin(async move {
+                conn.execute_sql(sql_query("INSERT INTO orders (id, item) VALUES (3, 'washer')")).await?;
+                conn.execute_sql(sql_query(switch.as_str())).await?;
+                Ok(())
+            })).await.unwrap_err();
+            assert_eq!(e.code(), SqlErrorCode::Failed);
+            conn.execute_sql(sql_query(format!("USE `{}`", current).as_str())).await.unwrap();
+            assert_eq!(counts(&mut conn, other.as_str()).await, (1, 1));
+
+            let e = conn.with_databases(&[current.as_str(), "no_such_schema_here"], |_conn| Box::pin(async move {
+                Ok(())
+            })).await.unwrap_err();
+            assert_eq!(e.code(), SqlErrorCode::NotFound);
+            drop(conn);
+        });
+    });
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn qualified_tables_are_swapped_in_their_schema() {
+    common::with_db!(common::mysql_db, "databases_swap", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        let current: String = conn.query_one(sql_query("SELECT database() AS d")).await.unwrap().get("d");
+        conn.execute_sql(sql_query("CREATE TABLE items (id BIGINT PRIMARY KEY)")).await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE items_next (id BIGINT PRIMARY KEY)")).await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO items_next VALUES (1), (2)")).await.unwrap();
+
+        let table = |name: &str| format!("{}.{}", current, name);
+        conn.swap_tables(table("items").as_str(), table("items_next").as_str(), None).await.unwrap();
+        let row = conn.query_one(sql_query("SELECT count(*) AS c FROM items")).await.unwrap();
+        assert_eq!(row.get::<i64, _>("c"), 2);
+        assert!(!conn.is_table_exist("items_next").await.unwrap());
+        assert!(!conn.is_table_exist("items_swap_old").await.unwrap());
+        drop(conn);
+    });
+}
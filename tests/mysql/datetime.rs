@@ -0,0 +1,42 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use sfo_sql::datetime::{BindDateTime, DateTimePrecision, DateTimeRowExt};
+use sfo_sql::mysql::{sql_query, SqlRow, SESSION_TIME_ZONE};
+use crate::common;
+
+fn sample() -> DateTime<FixedOffset> {
+    FixedOffset::west_opt(5 * 3600).unwrap()
+        .from_local_datetime(&NaiveDate::from_ymd_opt(2024, 5, 1).unwrap().and_hms_micro_opt(3, 30, 0, 123456).unwrap())
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn session_time_zone_is_utc() {
+    common::with_db!(common::mysql_db, "datetime_zone", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        let row = conn.query_one(sql_query("SELECT CAST(@@session.time_zone AS CHAR) AS tz")).await.unwrap();
+        assert_eq!(row.get::<String, _>("tz"), SESSION_TIME_ZONE);
+        drop(conn);
+    });
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn datetime_and_timestamp_round_trip_as_utc() {
+    common::with_db!(common::mysql_db, "datetime_round_trip", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE events (id INT PRIMARY KEY, at DATETIME(6) NOT NULL, stamped TIMESTAMP(3) NOT NULL)")).await.unwrap();
+        let dt = sample();
+        conn.execute_sql(sql_query("INSERT INTO events VALUES (1, ?, ?)")
+            .bind_datetime_utc(&dt, DateTimePrecision::Micros)
+            .bind_datetime_utc(&dt, DateTimePrecision::Millis)).await.unwrap();
+        let row = conn.query_one(sql_query("SELECT at, stamped, CAST(UNIX_TIMESTAMP(stamped) * 1000 AS SIGNED) AS epoch_ms FROM events WHERE id = 1")).await.unwrap();
+        assert_eq!(row.get_datetime_utc("at", DateTimePrecision::Micros).unwrap(), DateTimePrecision::Micros.truncate(&dt));
+        let stamped = row.get_datetime_utc("stamped", DateTimePrecision::Millis).unwrap();
+        assert_eq!(stamped, DateTimePrecision::Millis.truncate(&dt));
+        assert_eq!(stamped, Utc.with_ymd_and_hms(2024, 5, 1, 8, 30, 0).unwrap() + chrono::Duration::milliseconds(123));
+        // The server stored the instant, not the wall clock of some other zone.
+        assert_eq!(row.get::<i64, _>("epoch_ms"), 1714552200123);
+        drop(conn);
+    });
+}
@@ -0,0 +1,95 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::mysql::{sql_query, RawErrorToSqlError, RetryPolicy};
+use sfo_sql::prelude::ErrorMap;
+use sfo_sql::sqlx::error::{DatabaseError, ErrorKind};
+use sfo_sql::test_util::{Fault, FaultInjector, FaultMatcher};
+use crate::common;
+
+// A database error carrying only a SQLSTATE, as the fault injector raises them.
+#[derive(Debug)]
+struct StateOnly(&'static str, ErrorKind);
+
+impl std::fmt::Display for StateOnly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sqlstate {}", self.0)
+    }
+}
+
+impl std::error::Error for StateOnly {}
+
+impl DatabaseError for StateOnly {
+    fn message(&self) -> &str {
+        self.0
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed(self.0))
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self.1 {
+            ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+fn mapped(state: &'static str, kind: ErrorKind) -> SqlErrorCode {
+    RawErrorToSqlError::map(sfo_sql::sqlx::Error::Database(Box::new(StateOnly(state, kind))), "").code()
+}
+
+#[test]
+fn errors_without_a_number_map_by_sqlstate() {
+    assert_eq!(mapped("23000", ErrorKind::UniqueViolation), SqlErrorCode::AlreadyExists);
+    assert_eq!(mapped("23000", ErrorKind::ForeignKeyViolation), SqlErrorCode::Failed);
+    assert_eq!(mapped("40001", ErrorKind::Other), SqlErrorCode::Deadlock);
+    assert_eq!(mapped("HY000", ErrorKind::Other), SqlErrorCode::Busy);
+    assert!(mapped("HY000", ErrorKind::Other).is_retryable());
+    assert_eq!(mapped("42S02", ErrorKind::Other), SqlErrorCode::Failed);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn injected_faults_map_like_the_server_errors() {
+    common::with_db!(common::mysql_db, "injected", |db| {
+        let injector = FaultInjector::new();
+        injector.add(FaultMatcher::SqlContains("INSERT INTO injected".to_string()), Fault::Busy, 2);
+        let pool = db.pool.clone().with_fault_injector(injector.clone());
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE injected (v BIGINT)")).await.unwrap();
+
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        let policy = RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) };
+        conn.with_retrying_transaction(&policy, move |conn| {
+            let counter = counter.clone();
+            Box::pin(async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                conn.execute_sql(sql_query("INSERT INTO injected (v) VALUES (1)")).await?;
+                Ok(())
+            })
+        }).await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        injector.add(FaultMatcher::SqlContains("INSERT INTO injected".to_string()), Fault::DuplicateKey, 1);
+        let e = conn.execute_sql(sql_query("INSERT INTO injected (v) VALUES (2)")).await.unwrap_err();
+        assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+        drop(conn);
+    });
+}
@@ -0,0 +1,52 @@
+use sfo_sql::mysql::{export_to_sqlite, sql_query, SqlValue, TableExportSpec};
+use sfo_sql::sqlite;
+use sfo_sql::sqlite::SqlRow;
+use crate::common;
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn tables_are_paged_by_primary_key_into_a_path_with_uri_characters() {
+    common::with_db!(common::mysql_db, "export_keyset", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE items (id INT PRIMARY KEY, name VARCHAR(32) NOT NULL, created DATETIME)")).await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE tags (a INT, b INT, label VARCHAR(32), PRIMARY KEY (a, b))")).await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE notes (body VARCHAR(32))")).await.unwrap();
+        // More rows than one export chunk, so paging continues after a full chunk.
+        for start in (0..2500).step_by(500) {
+            let values = (start..start + 500).map(|i| format!("({}, 'item{}', '2024-01-02 03:04:05')", i, i)).collect::<Vec<_>>().join(", ");
+            conn.execute_sql(sql_query(format!("INSERT INTO items VALUES {}", values).as_str())).await.unwrap();
+            let values = (start..start + 500).map(|i| format!("({}, {}, 'tag{}')", i % 3, i, i)).collect::<Vec<_>>().join(", ");
+            conn.execute_sql(sql_query(format!("INSERT INTO tags VALUES {}", values).as_str())).await.unwrap();
+        }
+        conn.execute_sql(sql_query("INSERT INTO notes VALUES ('one'), ('two'), ('three')")).await.unwrap();
+
+        let dir = std::env::temp_dir().join(format!("export ?#%25 {}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("snap?shot#1%.db");
+        let _ = std::fs::remove_file(&dest);
+        let report = export_to_sqlite(&mut conn, &dest, &[
+            TableExportSpec::new("items").filter("id >= ?", vec![SqlValue::Int(500)]),
+            // The key is left out of the export but still pages the rows.
+            TableExportSpec::new("tags").columns(&["label"]),
+            TableExportSpec::new("notes"),
+        ]).await.unwrap();
+        assert_eq!(report.tables.iter().map(|t| t.rows).collect::<Vec<_>>(), vec![2000, 2500, 3]);
+        drop(conn);
+
+        let uri = sqlite::SqliteUriBuilder::new().path(&dest).read_only().build();
+        let snapshot = sqlite::SqlPool::open(uri.as_str(), 1, None).await.unwrap();
+        let mut conn = snapshot.get_conn().await.unwrap();
+        let row = conn.query_one(sqlite::sql_query("SELECT count(*), count(DISTINCT id), min(id), max(created) FROM items")).await.unwrap();
+        assert_eq!(row.get::<i64, _>(0), 2000);
+        assert_eq!(row.get::<i64, _>(1), 2000);
+        assert_eq!(row.get::<i64, _>(2), 500);
+        assert_eq!(row.get::<String, _>(3), "2024-01-02 03:04:05");
+        let row = conn.query_one(sqlite::sql_query("SELECT count(DISTINCT label) FROM tags")).await.unwrap();
+        assert_eq!(row.get::<i64, _>(0), 2500);
+        let row = conn.query_one(sqlite::sql_query("SELECT group_concat(body, ',') FROM (SELECT body FROM notes ORDER BY body)")).await.unwrap();
+        assert_eq!(row.get::<String, _>(0), "one,three,two");
+        drop(conn);
+        snapshot.raw_pool().await.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    });
+}
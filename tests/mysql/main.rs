@@ -0,0 +1,24 @@
+// Integration tests of the mysql backend. They run against the server named by TEST_MYSQL_URL,
+// see test_util::mysql_test_url, each in a schema of its own. They are ignored by default, run
+// them with TEST_MYSQL_URL set and cargo test -- --include-ignored.
+#![cfg(feature = "mysql")]
+
+#[path = "../common/mod.rs"]
+mod common;
+
+mod behaviour {
+    crate::common::behaviour_suite!(sfo_sql::mysql, crate::common::mysql_db, #[ignore = "needs TEST_MYSQL_URL"]);
+}
+
+mod charset;
+mod databases;
+#[cfg(feature = "chrono")]
+mod datetime;
+mod error_map;
+mod export;
+mod read_replay;
+mod row_map;
+mod search;
+mod tagging;
+mod transactions;
+mod user_vars;
@@ -0,0 +1,28 @@
+use std::time::Duration;
+use sfo_sql::mysql::{sql_query, SqlPool, SqlRow};
+use crate::common;
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn a_read_whose_connection_is_killed_is_replayed() {
+    common::with_db!(common::mysql_db, "read_replay", |db| {
+        // One connection, so the read runs on the one whose id is known.
+        let pool = SqlPool::open(db.pool.target().uri.as_str(), 1).await.unwrap().retry_reads_on_disconnect(true);
+        let id: u64 = pool.query_scalar(sql_query("SELECT CONNECTION_ID()")).await.unwrap();
+
+        // Killed underneath while the read runs, from a connection of the raw pool. An idle one
+        // would be replaced by the pool's check on acquire before the read got it.
+        let raw = db.pool.raw_pool().await;
+        let killer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            sfo_sql::sqlx::query(format!("KILL {}", id).as_str()).execute(&raw).await.unwrap();
+        });
+        let row = pool.query_one(sql_query("SELECT SLEEP(1) AS s, ? AS v").bind(7i64)).await.unwrap();
+        killer.await.unwrap();
+        assert_eq!(row.get::<i64, _>("v"), 7);
+        assert_eq!(pool.stats().read_replays, 1);
+        let other: u64 = pool.query_scalar(sql_query("SELECT CONNECTION_ID()")).await.unwrap();
+        assert_ne!(other, id);
+        pool.raw_pool().await.close().await;
+    });
+}
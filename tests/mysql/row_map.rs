@@ -0,0 +1,27 @@
+use sfo_sql::mysql::{row_to_map, sql_query, SqlValue};
+use crate::common;
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn temporal_columns_read_as_their_text_form() {
+    common::with_db!(common::mysql_db, "row_map_temporal", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE events (d DATE, dt DATETIME(6), ts TIMESTAMP NULL, t TIME(6), zero DATETIME)")).await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO events VALUES ('2024-02-29', '2024-02-29 13:04:05.000123', '2024-03-01 00:00:00', '-838:59:58.5', '2024-01-01 00:00:00')")).await.unwrap();
+
+        // Bound parameters make sqlx prepare the statement, values then come in the binary protocol.
+        let row = conn.query_one(sql_query("SELECT d, dt, ts, t, zero FROM events WHERE d = ?").bind("2024-02-29")).await.unwrap();
+        let map = row_to_map(&row).unwrap();
+        let text = |name: &str| match map.get(name) {
+            Some(SqlValue::Text(v)) => v.clone(),
+            other => panic!("{} is {:?}", name, other),
+        };
+        assert_eq!(map.keys().cloned().collect::<Vec<_>>(), vec!["d", "dt", "ts", "t", "zero"]);
+        assert_eq!(text("d"), "2024-02-29");
+        assert_eq!(text("dt"), "2024-02-29 13:04:05.000123");
+        assert_eq!(text("ts"), "2024-03-01 00:00:00");
+        assert_eq!(text("t"), "-838:59:58.500000");
+        assert_eq!(text("zero"), "2024-01-01 00:00:00");
+        drop(conn);
+    });
+}
@@ -0,0 +1,31 @@
+use sfo_sql::mysql::{sql_query, SqlConnection, TextSearchOptions};
+use crate::common;
+
+async fn ids(conn: &mut SqlConnection, query: &str) -> Vec<i64> {
+    let hits = conn.search("articles", query, 10).await.unwrap();
+    assert!(hits.windows(2).all(|w| w[0].1 >= w[1].1), "{:?}", hits);
+    hits.iter().map(|(id, _)| *id).collect()
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn hits_are_ranked_by_relevance() {
+    common::with_db!(common::mysql_db, "search_rank", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE articles (id BIGINT PRIMARY KEY, title VARCHAR(64), body TEXT)")).await.unwrap();
+        for (id, title, body) in [
+            (1, "Gardening", "Tomatoes need sun, water and a little patience over the long summer months"),
+            (2, "Rust", "Rust ownership and rust borrowing, rust all the way"),
+            (3, "Languages", "A long survey of languages where rust comes up once among many other words and topics"),
+            (4, "Tools", "Rust tooling: cargo builds rust crates"),
+        ] {
+            conn.execute_sql(sql_query("INSERT INTO articles (id, title, body) VALUES (?, ?, ?)").bind(id).bind(title).bind(body)).await.unwrap();
+        }
+        conn.create_search_index("articles", &["title", "body"], &TextSearchOptions::default()).await.unwrap();
+
+        assert_eq!(ids(&mut conn, "rust").await, vec![2, 4, 3]);
+        // Every term has to match.
+        assert_eq!(ids(&mut conn, "rust cargo").await, vec![4]);
+        drop(conn);
+    });
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use sfo_sql::mysql::{sql_query, SqlRow, StatementJournal};
+use crate::common;
+
+const CURRENT_STATEMENT: &str = "SELECT info FROM information_schema.processlist WHERE id = CONNECTION_ID() AND info LIKE ?";
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn the_server_sees_the_transaction_tag() {
+    common::with_db!(common::mysql_db, "tagging", |db| {
+        let journal = Arc::new(StatementJournal::new(16));
+        let pool = db.pool.clone().tag_transactions(true).with_observer(journal.clone());
+        let mut conn = pool.get_conn().await.unwrap();
+        let row = conn.query_one(sql_query(CURRENT_STATEMENT).bind("%processlist%")).await.unwrap();
+        assert!(!row.get::<String, _>("info").contains("txn:"));
+
+        conn.begin_transaction().await.unwrap();
+        let id = conn.current_transaction_id().unwrap();
+        let tagged = conn.tag_sql(CURRENT_STATEMENT).into_owned();
+        let row = conn.query_one(sql_query(tagged.as_str()).bind("%processlist%").persistent(false)).await.unwrap();
+        assert!(row.get::<String, _>("info").ends_with(format!("/* txn:{} */", id).as_str()));
+        // Sent as passed otherwise, the id is only in the event.
+        let row = conn.query_one(sql_query(CURRENT_STATEMENT).bind("%processlist%")).await.unwrap();
+        assert!(!row.get::<String, _>("info").contains("txn:"));
+        conn.commit_transaction().await.unwrap();
+
+        let events = journal.transaction(id);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].sql, tagged);
+        assert_eq!(events[1].sql, CURRENT_STATEMENT);
+        drop(conn);
+    });
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use sfo_sql::errors::{SqlError, SqlErrorCode};
+use sfo_sql::mysql::{sql_query, RetryPolicy, SqlPool, SqlRow};
+use tokio::sync::Barrier;
+use crate::common;
+
+// Inserts rows tagged owner one by one, yielding in between so the other transaction runs, and
+// fails at the end when fail is set.
+async fn fill(pool: &SqlPool, owner: &'static str, fail: bool) -> Result<i64, SqlError> {
+    pool.with_transaction(move |conn| Box::pin(async move {
+        for i in 0..20i64 {
+            conn.execute_sql(sql_query("INSERT INTO filled (owner, n) VALUES (?, ?)").bind(owner).bind(i)).await?;
+            tokio::task::yield_now().await;
+        }
+        let row = conn.query_one(sql_query("SELECT count(*) AS c FROM filled WHERE owner = ?").bind(owner)).await?;
+        if fail {
+            conn.execute_sql(sql_query("INSERT INTO filled (owner, n) VALUES (?, 0)").bind(owner)).await?;
+        }
+        Ok(row.get::<i64, _>("c"))
+    })).await
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn concurrent_pool_transactions_do_not_interfere() {
+    common::with_db!(common::mysql_db, "concurrent_tx", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE filled (owner VARCHAR(16) NOT NULL, n BIGINT NOT NULL, PRIMARY KEY (owner, n))")).await.unwrap();
+        drop(conn);
+
+        let (kept, failed) = tokio::join!(fill(&db.pool, "kept", false), fill(&db.pool, "failed", true));
+        assert_eq!(kept.unwrap(), 20);
+        assert_eq!(failed.unwrap_err().code(), SqlErrorCode::AlreadyExists);
+
+        let rows = db.pool.query_all(sql_query("SELECT owner, count(*) AS c FROM filled GROUP BY owner")).await.unwrap();
+        let counts: Vec<(String, i64)> = rows.iter().map(|row| (row.get("owner"), row.get("c"))).collect();
+        assert_eq!(counts, vec![("kept".to_string(), 20)]);
+    });
+}
+
+// Locks first, then waits for the other transaction to hold its lock before taking second, so
+// the two deadlock on their first attempt. The server kills one, which runs again.
+async fn cross_update(pool: &SqlPool, first: i64, second: i64, barrier: Arc<Barrier>, attempts: Arc<AtomicU32>) -> Result<(), SqlError> {
+    let policy = RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(50) };
+    pool.retry_transaction(&policy, move |conn| {
+        let (barrier, attempts) = (barrier.clone(), attempts.clone());
+        Box::pin(async move {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            conn.execute_sql(sql_query("UPDATE accounts SET balance = balance + 1 WHERE id = ?").bind(first)).await?;
+            if attempt == 0 {
+                barrier.wait().await;
+            }
+            conn.execute_sql(sql_query("UPDATE accounts SET balance = balance + 1 WHERE id = ?").bind(second)).await?;
+            Ok(())
+        })
+    }).await
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn deadlocked_transaction_is_retried() {
+    common::with_db!(common::mysql_db, "deadlock_retry", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE accounts (id BIGINT PRIMARY KEY, balance BIGINT NOT NULL) ENGINE=InnoDB")).await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO accounts (id, balance) VALUES (1, 0), (2, 0)")).await.unwrap();
+        drop(conn);
+
+        let barrier = Arc::new(Barrier::new(2));
+        let (left, right) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+        let (a, b) = tokio::join!(cross_update(&db.pool, 1, 2, barrier.clone(), left.clone()),
+                                  cross_update(&db.pool, 2, 1, barrier, right.clone()));
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(left.load(Ordering::SeqCst) + right.load(Ordering::SeqCst), 3);
+
+        let rows = db.pool.query_all(sql_query("SELECT balance FROM accounts ORDER BY id")).await.unwrap();
+        assert_eq!(rows.iter().map(|row| row.get::<i64, _>("balance")).collect::<Vec<_>>(), vec![2, 2]);
+    });
+}
@@ -0,0 +1,51 @@
+use sfo_sql::mysql::{sql_query, SqlConnection, SqlRow, SqlValue};
+use crate::common;
+
+async fn connection_id(conn: &mut SqlConnection) -> i64 {
+    conn.query_one(sql_query("SELECT CAST(CONNECTION_ID() AS SIGNED)")).await.unwrap().get(0)
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn released_connections_forget_user_variables() {
+    common::with_db!(common::mysql_db, "user_vars_release", |db| {
+        let pool = db.pool.clone().clear_user_vars_on_release(true);
+        let mut conn = pool.get_conn().await.unwrap();
+        let id = connection_id(&mut conn).await;
+        let application_name = conn.get_user_var("application_name").await.unwrap();
+        conn.set_user_var("tracked", SqlValue::Int(1)).await.unwrap();
+        conn.execute_sql(sql_query("SET @raw = 2, @`odd name` = 3")).await.unwrap();
+        assert_eq!(conn.get_user_var("tracked").await.unwrap(), SqlValue::Int(1));
+        drop(conn);
+
+        // Connections are returned in the background, take them until the released one comes back.
+        let mut held = Vec::new();
+        let mut conn = loop {
+            let mut conn = pool.get_conn().await.unwrap();
+            if connection_id(&mut conn).await == id {
+                break conn;
+            }
+            held.push(conn);
+        };
+        assert_eq!(conn.get_user_var("tracked").await.unwrap(), SqlValue::Null(None));
+        assert_eq!(conn.get_user_var("raw").await.unwrap(), SqlValue::Null(None));
+        let row = conn.query_one(sql_query("SELECT @`odd name` IS NULL")).await.unwrap();
+        assert_eq!(row.get::<i64, _>(0), 1);
+        assert_eq!(conn.get_user_var("application_name").await.unwrap(), application_name);
+        drop(conn);
+        drop(held);
+    });
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_MYSQL_URL"]
+async fn user_variable_names_are_checked() {
+    common::with_db!(common::mysql_db, "user_vars_names", |db| {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        assert!(conn.set_user_var("a = 1; DROP TABLE t; SET @b", SqlValue::Int(1)).await.is_err());
+        assert!(conn.get_user_var("").await.is_err());
+        conn.set_user_var("v_1.$", SqlValue::Int(5)).await.unwrap();
+        assert_eq!(conn.get_user_var("v_1.$").await.unwrap(), SqlValue::Int(5));
+        drop(conn);
+    });
+}
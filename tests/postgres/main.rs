@@ -0,0 +1,11 @@
+// Integration tests of the postgres backend. They run against the database named by
+// TEST_POSTGRES_URL, see test_util::postgres_test_url, each in a schema of its own. They are
+// ignored by default, run them with TEST_POSTGRES_URL set and cargo test -- --include-ignored.
+#![cfg(feature = "postgres")]
+
+#[path = "../common/mod.rs"]
+mod common;
+
+mod behaviour {
+    crate::common::behaviour_suite!(sfo_sql::postgres, crate::common::postgres_db, #[ignore = "needs TEST_POSTGRES_URL"]);
+}
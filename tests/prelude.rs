@@ -0,0 +1,55 @@
+// Written the way an application would, with nothing from the crate but the prelude, so an item
+// an application needs going missing from it fails to compile here.
+
+use sfo_sql::prelude::*;
+
+async fn open_pool(path: &std::path::Path) -> SqlResult<SqlPool> {
+    SqlPool::open(SqliteUriBuilder::new().path(path).build().as_str(), 5, Some(SqliteJournalMode::Wal)).await
+}
+
+async fn item_qty(conn: &mut SqlConnection, name: &str) -> SqlResult<i64> {
+    let row = conn.query_optional(sql_query("SELECT qty FROM items WHERE name = ?").bind(name)).await?;
+    match row {
+        Some(row) => Ok(row.get("qty")),
+        None => Err(sql_err!(SqlErrorCode::NotFound, "no item {}", name)),
+    }
+}
+
+fn remove_db(path: &std::path::Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+#[tokio::test]
+async fn an_application_gets_by_with_the_prelude_alone() {
+    let path = std::env::temp_dir().join(format!("sfo_sql_prelude_{}.db", std::process::id()));
+    remove_db(&path);
+    let pool = open_pool(&path).await.unwrap();
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (name TEXT NOT NULL, qty BIGINT NOT NULL)")).await.unwrap();
+
+    conn.begin_transaction().await.unwrap();
+    for (name, qty) in [("a", 1), ("b", 2)] {
+        conn.execute_sql(sql_query("INSERT INTO items (name, qty) VALUES (?, ?)").bind_value(SqlValue::Text(name.to_string())).bind(qty)).await.unwrap();
+    }
+    conn.commit_transaction().await.unwrap();
+
+    assert_eq!(item_qty(&mut conn, "b").await.unwrap(), 2);
+    assert_eq!(item_qty(&mut conn, "c").await.unwrap_err().code(), SqlErrorCode::NotFound);
+
+    let order = SortSpec::new().allow("name", "name").parse("-name").unwrap();
+    let sql = format!("SELECT name, qty FROM items{}", order.to_sql(SqlBackend::Sqlite));
+    let rows = conn.query_all(sql_query(sql.as_str())).await.unwrap();
+    let maps = rows.iter().map(row_to_map).collect::<SqlResult<Vec<IndexMap<String, SqlValue>>>>().unwrap();
+    assert_eq!(maps[0].get("name"), Some(&SqlValue::Text("b".to_string())));
+    assert_eq!(maps[1].get("qty"), Some(&SqlValue::Int(1)));
+
+    let total = conn.query_one(sql_query("SELECT SUM(qty) AS s FROM items")).await.unwrap();
+    assert_eq!(total.get_aggregate_i128("s").unwrap(), Some(3));
+    drop(conn);
+    pool.raw_pool().await.close().await;
+    remove_db(&path);
+}
@@ -0,0 +1,48 @@
+// SortSpec parsing of user sort strings and the ORDER BY it renders per backend.
+
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{SortDirection, SortSpec, SqlBackend};
+
+fn spec() -> SortSpec {
+    SortSpec::new().allow("name", "u.name").allow("created_at", "u.created_at").allow("age", "age")
+}
+
+#[test]
+fn keys_parse_in_order_with_their_direction() {
+    let order = spec().parse(" name, -created_at ,+age,").unwrap();
+    assert_eq!(order.keys, vec![
+        ("name".to_string(), SortDirection::Asc),
+        ("created_at".to_string(), SortDirection::Desc),
+        ("age".to_string(), SortDirection::Asc),
+    ]);
+    assert_eq!(order.to_sql(SqlBackend::Sqlite), " ORDER BY u.name ASC, u.created_at DESC, age ASC");
+    assert_eq!(order.to_sql(SqlBackend::MySql), " ORDER BY u.name ASC, u.created_at DESC, age ASC");
+
+    let order = spec().parse("").unwrap();
+    assert!(order.is_empty());
+    assert_eq!(order.to_sql(SqlBackend::Sqlite), "");
+}
+
+#[test]
+fn unknown_and_repeated_keys_are_invalid_arguments() {
+    let e = spec().parse("name,password").unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::InvalidArgument);
+    let msg = format!("{:?}", e);
+    assert!(msg.contains("\"password\"") && msg.contains("name, created_at, age"), "{}", msg);
+
+    // The expression is not a key, user input cannot name it.
+    assert_eq!(spec().parse("u.name").unwrap_err().code(), SqlErrorCode::InvalidArgument);
+    assert_eq!(spec().parse("name;DROP TABLE u").unwrap_err().code(), SqlErrorCode::InvalidArgument);
+
+    let e = spec().parse("name,-name").unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::InvalidArgument);
+    assert!(format!("{:?}", e).contains("more than once"));
+}
+
+#[test]
+fn nulls_last_is_emulated_on_mysql() {
+    let order = spec().nulls_last(true).parse("-age,name").unwrap();
+    assert_eq!(order.to_sql(SqlBackend::Sqlite), " ORDER BY age DESC NULLS LAST, u.name ASC NULLS LAST");
+    assert_eq!(order.to_sql(SqlBackend::Postgres), " ORDER BY age DESC NULLS LAST, u.name ASC NULLS LAST");
+    assert_eq!(order.to_sql(SqlBackend::MySql), " ORDER BY age IS NULL, age DESC, u.name IS NULL, u.name ASC");
+}
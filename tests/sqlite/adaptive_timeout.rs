@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, AdaptiveTimeout, StatementJournal};
+use crate::common;
+
+// Runs for seconds, long after the timeout.
+const SLOW_SQL: &str = "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000000) SELECT count(*) FROM n";
+
+fn cut_off_at(timeout: Duration) -> AdaptiveTimeout {
+    AdaptiveTimeout { min_executions: u64::MAX, default: Some(timeout), ..Default::default() }
+}
+
+#[tokio::test]
+async fn cut_off_connection_fails_fast_and_the_observer_sees_it() {
+    let db = common::sqlite_db("cut_off").await.unwrap();
+    let journal = Arc::new(StatementJournal::new(16));
+    let pool = db.pool.clone().with_adaptive_timeout(cut_off_at(Duration::from_millis(50))).with_observer(journal.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE jobs (id INTEGER PRIMARY KEY)")).await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (1)")).await.unwrap();
+
+    let e = conn.query_one(sql_query(SLOW_SQL)).await.err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::Timeout);
+
+    // Without the check these would queue behind the statement still running on the worker.
+    let started = Instant::now();
+    let e = conn.query_all(sql_query("SELECT id FROM jobs")).await.err().unwrap();
+    assert!(format!("{:?}", e).contains("cut off"));
+    conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (2)")).await.err().unwrap();
+    conn.execute_batch("DELETE FROM jobs").await.err().unwrap();
+    assert!(conn.query_stream(sql_query("SELECT id FROM jobs")).is_err());
+    conn.commit_transaction().await.err().unwrap();
+    assert_eq!(conn.current_transaction_id(), None);
+    conn.begin_transaction().await.err().unwrap();
+    assert!(started.elapsed() < Duration::from_millis(500));
+
+    let events = journal.events();
+    let last = events.last().unwrap();
+    assert_eq!(last.sql, SLOW_SQL);
+    assert!(last.timed_out);
+    assert_eq!(last.rows, None);
+    assert!(events[..events.len() - 1].iter().all(|event| !event.timed_out));
+    drop(conn);
+
+    // The insert of the cut off transaction was rolled back with the connection.
+    let rows = db.pool.query_all(sql_query("SELECT id FROM jobs")).await.unwrap();
+    assert!(rows.is_empty());
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn rollback_after_a_cut_off_only_ends_the_transaction() {
+    let db = common::sqlite_db("cut_off_rollback").await.unwrap();
+    let pool = db.pool.clone().with_adaptive_timeout(cut_off_at(Duration::from_millis(50)));
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    conn.query_one(sql_query(SLOW_SQL)).await.err().unwrap();
+
+    let started = Instant::now();
+    conn.rollback_transaction().await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert_eq!(conn.current_transaction_id(), None);
+    drop(conn);
+
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.query_one(sql_query("SELECT 1")).await.unwrap();
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, SqlRow, SqlValue, StatementBatcher};
+use crate::common;
+
+#[tokio::test]
+async fn concurrent_submits_are_written_in_few_statements() {
+    let db = common::sqlite_db("batcher").await.unwrap();
+    let pool = db.pool.clone().with_statement_stats(64);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT NOT NULL)")).await.unwrap();
+    drop(conn);
+
+    let batcher = StatementBatcher::builder(&pool)
+        .statement("event", "events", &["id", "name"])
+        .max_batch(100)
+        .max_delay(Duration::from_millis(20))
+        .start();
+    let tasks = (1..=1000i64).map(|id| {
+        let batcher = batcher.clone();
+        tokio::spawn(async move {
+            batcher.submit("event", vec![SqlValue::Int(id), SqlValue::Text(format!("e{}", id))]).await
+        })
+    }).collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    batcher.shutdown().await;
+    assert_eq!(batcher.pending_len(), 0);
+
+    let mut conn = pool.get_conn().await.unwrap();
+    let row = conn.query_one(sql_query("SELECT count(*), sum(id) FROM events")).await.unwrap();
+    assert_eq!((row.get::<i64, _>(0), row.get::<i64, _>(1)), (1000, 500500));
+    drop(conn);
+    let inserts: u64 = pool.statement_stats().iter()
+        .filter(|s| s.statement.starts_with("INSERT INTO"))
+        .map(|s| s.count)
+        .sum();
+    assert!((10..=100).contains(&inserts), "1000 rows written in {} inserts", inserts);
+    db.finish().await;
+}
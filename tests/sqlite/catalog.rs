@@ -0,0 +1,46 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlBackend, SqlRow, StatementCatalog};
+use crate::common;
+
+#[tokio::test]
+async fn statements_are_validated_together_and_run_by_name() {
+    let db = common::sqlite_db("catalog").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL)")).await.unwrap();
+
+    // Wrong placeholder count and a missing required name are both reported by build.
+    let e = StatementCatalog::builder()
+        .statement("user_by_id", "SELECT name FROM users WHERE id = ?", &["id", "name"])
+        .require(&["user_by_id", "insert_user"])
+        .build().err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::Failed);
+    let msg = format!("{:?}", e);
+    assert!(msg.contains("insert_user: not registered") && msg.contains("user_by_id: 1 placeholders but 2"), "{}", msg);
+
+    // A statement against a missing column builds but fails validation, next to a good one.
+    let catalog = StatementCatalog::builder()
+        .statement("insert_user", "INSERT INTO users (name) VALUES (?)", &["name"])
+        .statement("user_by_email", "SELECT name FROM users WHERE email = ?", &["email"])
+        .build().unwrap();
+    let e = catalog.validate(&mut conn).await.unwrap_err();
+    let msg = format!("{:?}", e);
+    assert!(msg.contains("user_by_email") && !msg.contains("insert_user"), "{}", msg);
+
+    let catalog = StatementCatalog::builder()
+        .statement("insert_user", "INSERT INTO users (name) VALUES (?)", &["name"])
+        .statement("user_by_id", "SELECT name FROM users WHERE id = ?", &["id"])
+        .statement_for(SqlBackend::Sqlite, "user_by_id", "SELECT name FROM users WHERE id = ?1", &["id"])
+        .statement_for(SqlBackend::MySql, "user_by_id", "SELECT name FROM users WHERE id = ? LIMIT 1", &["id"])
+        .require(&["insert_user", "user_by_id"])
+        .build().unwrap();
+    catalog.validate(&mut conn).await.unwrap();
+    assert_eq!(catalog.sql("user_by_id").unwrap(), "SELECT name FROM users WHERE id = ?1");
+    assert_eq!(catalog.params("insert_user").unwrap(), ["name".to_string()]);
+    assert_eq!(catalog.sql("unknown").unwrap_err().code(), SqlErrorCode::NotFound);
+
+    conn.execute_sql(catalog.query("insert_user").unwrap().bind("ada")).await.unwrap();
+    let row = conn.query_one(catalog.query("user_by_id").unwrap().bind(1)).await.unwrap();
+    assert_eq!(row.get::<String, _>("name"), "ada");
+    drop(conn);
+    db.finish().await;
+}
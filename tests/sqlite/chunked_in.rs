@@ -0,0 +1,45 @@
+use sfo_sql::sqlite::{sql_query, SqlBackend, SqlRow, SqlValue, KEYS_MARKER};
+use crate::common;
+
+#[tokio::test]
+async fn limit_follows_the_linked_sqlite() {
+    let db = common::sqlite_db("bind_limit").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let version: String = conn.query_one(sql_query("select sqlite_version() as v")).await.unwrap().get("v");
+    let options = conn.compile_options().await.unwrap();
+    let built_with = options.iter().find_map(|o| o.strip_prefix("MAX_VARIABLE_NUMBER=").map(|v| v.parse::<usize>().unwrap()));
+    let parts: Vec<u32> = version.split('.').map(|p| p.parse().unwrap()).collect();
+    let expected = built_with.unwrap_or(if (parts[0], parts[1]) >= (3, 32) { SqlBackend::Sqlite.max_bind_params() } else { 999 });
+    assert_eq!(conn.max_bind_params().await.unwrap(), expected);
+    // Probed on open, the pool knows it without a connection.
+    assert_eq!(db.pool.max_bind_params(), expected);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn keys_past_the_limit_are_split_and_kept_in_order() {
+    let db = common::sqlite_db("chunked_in").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE chunked (id INTEGER PRIMARY KEY, grp INTEGER NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 80000) \
+                                INSERT INTO chunked (id, grp) SELECT i, i % 2 FROM n")).await.unwrap();
+    let limit = conn.max_bind_params().await.unwrap();
+    // More keys than one statement takes, in descending order, with a duplicate.
+    let mut keys: Vec<i64> = (1..=(limit as i64 * 2 + 10)).rev().collect();
+    keys.push(5);
+    let sql = format!("SELECT id, grp FROM chunked WHERE grp = ? AND id IN ({})", KEYS_MARKER);
+    let key_of = |row: &(i64, i64)| row.0;
+    let rows: Vec<(i64, i64)> = conn.query_all_chunked_in(sql.as_str(), &keys, &[SqlValue::Int(1)], Some(&key_of)).await.unwrap();
+    let expected: Vec<i64> = keys[..keys.len() - 1].iter().copied().filter(|k| k % 2 == 1).collect();
+    assert_eq!(rows.iter().map(|r| r.0).collect::<Vec<_>>(), expected);
+
+    // No room left for a key under the limit.
+    let args = vec![SqlValue::Int(1); limit];
+    let placeholders = vec!["?"; limit].join(", ");
+    let sql = format!("SELECT id, grp FROM chunked WHERE grp IN ({}) AND id IN ({})", placeholders, KEYS_MARKER);
+    let e = conn.query_all_chunked_in::<(i64, i64), i64>(sql.as_str(), &[1], &args, None).await.err().unwrap();
+    assert_eq!(e.code(), sfo_sql::errors::SqlErrorCode::ParameterMismatch);
+    drop(conn);
+    db.finish().await;
+}
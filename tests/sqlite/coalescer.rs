@@ -0,0 +1,75 @@
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, SqlRow, WriteCoalescer};
+use sfo_sql::test_util::{Fault, FaultInjector, FaultMatcher};
+use crate::common;
+
+async fn create_counters(db: &common::TestDb<sfo_sql::sqlite::SqlPool>) {
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE counters (id INTEGER PRIMARY KEY, views INTEGER NOT NULL DEFAULT 0)")).await.unwrap();
+    for id in 0..100i64 {
+        conn.execute_sql(sql_query("INSERT INTO counters (id) VALUES (?)").bind(id)).await.unwrap();
+    }
+}
+
+async fn views(db: &common::TestDb<sfo_sql::sqlite::SqlPool>) -> Vec<i64> {
+    let rows = db.pool.query_all(sql_query("SELECT views FROM counters ORDER BY id")).await.unwrap();
+    rows.iter().map(|row| row.get("views")).collect()
+}
+
+#[tokio::test]
+async fn ten_thousand_increments_add_up() {
+    let db = common::sqlite_db("coalescer_totals").await.unwrap();
+    create_counters(&db).await;
+    let coalescer = WriteCoalescer::builder(&db.pool).build();
+    for i in 0..10_000i64 {
+        coalescer.add("counters", i % 100, "views", 1 + i % 2);
+    }
+    assert_eq!(coalescer.pending_len(), 100);
+    coalescer.flush_now().await.unwrap();
+    assert_eq!(coalescer.pending_len(), 0);
+
+    let views = views(&db).await;
+    assert_eq!(views.len(), 100);
+    for (id, total) in views.iter().enumerate() {
+        // Keys of even id get +1 a hundred times, odd ones +2.
+        assert_eq!(*total, if id % 2 == 0 { 100 } else { 200 }, "key {}", id);
+    }
+    assert_eq!(views.iter().sum::<i64>(), 15_000);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn failed_flush_requeues_the_deltas() {
+    let db = common::sqlite_db("coalescer_requeue").await.unwrap();
+    create_counters(&db).await;
+    let injector = FaultInjector::new();
+    injector.add(FaultMatcher::SqlContains("UPDATE \"counters\"".to_string()), Fault::Busy, 1);
+    let pool = db.pool.clone().with_fault_injector(injector);
+    let coalescer = WriteCoalescer::builder(&pool).build();
+    coalescer.add("counters", 1, "views", 5);
+    coalescer.flush_now().await.unwrap_err();
+    assert_eq!(coalescer.pending_len(), 1);
+    coalescer.add("counters", 1, "views", 2);
+    coalescer.flush_now().await.unwrap();
+    assert_eq!(views(&db).await[1], 7);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn background_task_flushes_and_shutdown_writes_the_rest() {
+    let db = common::sqlite_db("coalescer_task").await.unwrap();
+    create_counters(&db).await;
+    let coalescer = WriteCoalescer::builder(&db.pool).interval(Duration::from_millis(20)).start();
+    coalescer.add("counters", 3, "views", 4);
+    for _ in 0..100 {
+        if views(&db).await[3] == 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(views(&db).await[3], 4);
+    coalescer.add("counters", 3, "views", 1);
+    coalescer.shutdown().await.unwrap();
+    assert_eq!(views(&db).await[3], 5);
+    db.finish().await;
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+use sfo_sql::sqlite::{ConfigEntry, ConfigStore};
+use crate::common;
+
+type Limits = (String, Vec<u32>, bool);
+
+#[tokio::test]
+async fn typed_values_round_trip_with_their_version() {
+    let db = common::sqlite_db("config_typed").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let store = ConfigStore::ensure(&mut conn).await.unwrap();
+
+    assert_eq!(store.get::<Limits>(&mut conn, "limits").await.unwrap(), None);
+    let limits: Limits = ("eu".to_string(), vec![10, 20], true);
+    store.set(&mut conn, "limits", &limits).await.unwrap();
+    assert_eq!(store.get_versioned::<Limits>(&mut conn, "limits").await.unwrap(), Some((limits.clone(), 1)));
+    store.set(&mut conn, "limits", &("us".to_string(), Vec::<u32>::new(), false)).await.unwrap();
+    let (value, version) = store.get_versioned::<Limits>(&mut conn, "limits").await.unwrap().unwrap();
+    assert_eq!((value.0.as_str(), version), ("us", 2));
+    // Stored as json text, read back as another type it fails to decode.
+    assert_eq!(store.get_raw(&mut conn, "limits").await.unwrap().unwrap().value, br#"["us",[],false]"#);
+    assert!(store.get::<u64>(&mut conn, "limits").await.is_err());
+
+    assert!(store.remove(&mut conn, "limits").await.unwrap());
+    assert!(!store.remove(&mut conn, "limits").await.unwrap());
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn one_of_two_concurrent_compare_and_sets_loses() {
+    let db = common::sqlite_db("config_cas").await.unwrap();
+    let mut first = db.pool.get_conn().await.unwrap();
+    let mut second = db.pool.get_conn().await.unwrap();
+    let store = ConfigStore::ensure(&mut first).await.unwrap();
+    assert!(store.compare_and_set(&mut first, "leader", 0, "none").await.unwrap());
+    assert!(!store.compare_and_set(&mut second, "leader", 0, "second").await.unwrap());
+
+    // Both read version 1 and race to replace it.
+    let (_, version) = store.get_versioned::<String>(&mut first, "leader").await.unwrap().unwrap();
+    let (a, b) = tokio::join!(
+        store.compare_and_set(&mut first, "leader", version, "first"),
+        store.compare_and_set(&mut second, "leader", version, "second"),
+    );
+    let (a, b) = (a.unwrap(), b.unwrap());
+    assert!(a != b, "both compare_and_set calls returned {}", a);
+    let winner = if a { "first" } else { "second" };
+    assert_eq!(store.get_versioned::<String>(&mut second, "leader").await.unwrap(), Some((winner.to_string(), 2)));
+    drop((first, second));
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn watch_poll_sees_writes_of_another_connection() {
+    let db = common::sqlite_db("config_watch").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let store = ConfigStore::ensure(&mut conn).await.unwrap();
+    store.set_raw(&mut conn, "mode", b"on").await.unwrap();
+    let mut watch = store.watch_poll(&db.pool, "mode", Duration::from_millis(10)).await.unwrap();
+
+    store.set_raw(&mut conn, "mode", b"off").await.unwrap();
+    let change = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+    assert_eq!(change, Some(ConfigEntry { value: b"off".to_vec(), version: 2 }));
+
+    store.remove(&mut conn, "mode").await.unwrap();
+    let change = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+    assert_eq!(change, None);
+    drop(watch);
+    drop(conn);
+    db.finish().await;
+}
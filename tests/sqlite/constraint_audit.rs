@@ -0,0 +1,53 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, PlannedConstraint, SqlValue};
+use crate::common;
+
+fn text(s: &str) -> SqlValue {
+    SqlValue::Text(s.to_string())
+}
+
+#[tokio::test]
+async fn seeded_violations_are_counted_for_each_kind() {
+    let db = common::sqlite_db("constraint_audit").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE teams (id INTEGER PRIMARY KEY)")).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT, team_id INTEGER)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO teams (id) VALUES (1), (2)")).await.unwrap();
+    // Two NULL emails, a@x three times and b@x twice, and users of the missing teams 98 and 99.
+    conn.execute_sql(sql_query("INSERT INTO users (id, email, team_id) VALUES
+        (1, 'a@x', 1), (2, 'a@x', 1), (3, 'a@x', 99), (4, 'b@x', 2), (5, 'b@x', NULL),
+        (6, NULL, 98), (7, NULL, 1), (8, 'c@x', 1)")).await.unwrap();
+
+    let audits = conn.audit_constraints(&[
+        PlannedConstraint::NotNull { table: "users".to_string(), column: "email".to_string() },
+        PlannedConstraint::Unique { table: "users".to_string(), columns: vec!["email".to_string()] },
+        PlannedConstraint::ForeignKey { child: "users".to_string(), columns: vec!["team_id".to_string()],
+                                        parent: "teams".to_string(), parent_columns: vec!["id".to_string()] },
+        PlannedConstraint::NotNull { table: "users".to_string(), column: "id".to_string() },
+    ]).await.unwrap();
+    assert_eq!(audits.iter().map(|a| a.violations).collect::<Vec<_>>(), vec![2, 2, 2, 0]);
+    assert!(audits[0].examples.is_empty());
+    assert_eq!(audits[1].examples, vec![vec![text("a@x"), SqlValue::Int(3)], vec![text("b@x"), SqlValue::Int(2)]]);
+    let mut orphans = audits[2].examples.clone();
+    orphans.sort_by_key(|row| format!("{:?}", row));
+    assert_eq!(orphans, vec![vec![SqlValue::Int(98)], vec![SqlValue::Int(99)]]);
+    assert!(audits[3].is_satisfied() && !audits[0].is_satisfied());
+
+    // Fixing the data satisfies the constraints.
+    conn.execute_sql(sql_query("UPDATE users SET email = 'u' || id || '@x', team_id = 1")).await.unwrap();
+    let audits = conn.audit_constraints(&[
+        PlannedConstraint::NotNull { table: "users".to_string(), column: "email".to_string() },
+        PlannedConstraint::Unique { table: "users".to_string(), columns: vec!["email".to_string()] },
+        PlannedConstraint::ForeignKey { child: "users".to_string(), columns: vec!["team_id".to_string()],
+                                        parent: "teams".to_string(), parent_columns: vec!["id".to_string()] },
+    ]).await.unwrap();
+    assert!(audits.iter().all(|a| a.is_satisfied()), "{:?}", audits);
+
+    let e = conn.audit_constraints(&[PlannedConstraint::ForeignKey {
+        child: "users".to_string(), columns: vec!["team_id".to_string(), "email".to_string()],
+        parent: "teams".to_string(), parent_columns: vec!["id".to_string()],
+    }]).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::ParameterMismatch);
+    drop(conn);
+    db.finish().await;
+}
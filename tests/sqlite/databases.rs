@@ -0,0 +1,78 @@
+use sfo_sql::errors::{sql_err, SqlErrorCode};
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqlRow};
+use sfo_sql::test_util::TempSqliteDb;
+use crate::common;
+
+// Attaches a second database file as aux to conn, with a table in each database.
+async fn attach(conn: &mut SqlConnection, aux: &TempSqliteDb) {
+    let sql = format!("ATTACH DATABASE '{}' AS aux", aux.path().display().to_string().replace('\'', "''"));
+    conn.execute_sql(sql_query(sql.as_str())).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE orders (id INTEGER PRIMARY KEY, item TEXT NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE aux.stock (item TEXT PRIMARY KEY, reserved INTEGER NOT NULL)")).await.unwrap();
+}
+
+async fn counts(conn: &mut SqlConnection) -> (i64, i64) {
+    let orders = conn.query_one(sql_query("SELECT count(*) FROM main.orders")).await.unwrap().get(0);
+    let stock = conn.query_one(sql_query("SELECT count(*) FROM aux.stock")).await.unwrap().get(0);
+    (orders, stock)
+}
+
+#[tokio::test]
+async fn a_cross_database_insert_pair_commits_and_rolls_back_together() {
+    let db = common::sqlite_db("databases_pair").await.unwrap();
+    let aux = TempSqliteDb::new("databases_pair_aux");
+    let mut conn = db.pool.get_conn().await.unwrap();
+    attach(&mut conn, &aux).await;
+
+    conn.with_databases(&["main", "aux"], |conn| Box::pin(async move {
+        conn.execute_sql(sql_query("INSERT INTO main.orders (id, item) VALUES (1, 'bolt')")).await?;
+        conn.execute_sql(sql_query("INSERT INTO aux.stock (item, reserved) VALUES ('bolt', 1)")).await?;
+        Ok(())
+    })).await.unwrap();
+    assert_eq!(counts(&mut conn).await, (1, 1));
+
+    let e = conn.with_databases(&["main", "aux"], |conn| Box::pin(async move {
+        conn.execute_sql(sql_query("INSERT INTO main.orders (id, item) VALUES (2, 'nut')")).await?;
+        conn.execute_sql(sql_query("INSERT INTO aux.stock (item, reserved) VALUES ('nut', 1)")).await?;
+        Err::<(), _>(sql_err!(SqlErrorCode::Failed, "out of nuts"))
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Failed);
+    assert_eq!(counts(&mut conn).await, (1, 1));
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn databases_not_attached_are_refused_before_the_body_runs() {
+    let db = common::sqlite_db("databases_missing").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let e = conn.with_databases::<_, ()>(&["main", "archive"], |_conn| Box::pin(async move {
+        panic!("body ran without archive attached")
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::NotFound);
+    assert!(format!("{:?}", e).contains("archive"), "{:?}", e);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn tables_of_an_attached_database_are_swapped_in_place() {
+    let db = common::sqlite_db("databases_swap").await.unwrap();
+    let aux = TempSqliteDb::new("databases_swap_aux");
+    let mut conn = db.pool.get_conn().await.unwrap();
+    attach(&mut conn, &aux).await;
+    conn.execute_sql(sql_query("INSERT INTO aux.stock VALUES ('bolt', 1)")).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE aux.stock_next (item TEXT PRIMARY KEY, reserved INTEGER NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO aux.stock_next VALUES ('bolt', 5), ('nut', 2)")).await.unwrap();
+
+    let e = conn.swap_tables("aux.stock", "stock_next", None).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::InvalidArgument);
+
+    conn.swap_tables("aux.stock", "aux.stock_next", Some("aux.stock_old")).await.unwrap();
+    assert_eq!(counts(&mut conn).await, (0, 2));
+    assert!(conn.is_table_exist("aux.stock_old").await.unwrap());
+    assert!(!conn.is_table_exist("aux.stock_next").await.unwrap());
+    assert!(!conn.is_table_exist("stock").await.unwrap());
+    drop(conn);
+    db.finish().await;
+}
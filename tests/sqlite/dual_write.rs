@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, DualWriteOptions, DualWritePool, SqlPool, SqlRow, SqlValue};
+use sfo_sql::test_util::ManualClock;
+use crate::common;
+
+async fn count(pool: &SqlPool) -> i64 {
+    pool.query_one(sql_query("SELECT count(*) FROM t")).await.unwrap().get(0)
+}
+
+async fn create_tables(pools: &[&SqlPool]) {
+    for pool in pools.iter() {
+        pool.get_conn().await.unwrap().execute_sql(sql_query("CREATE TABLE t (v INTEGER)")).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn the_replay_task_waits_on_the_primary_clock() {
+    let primary = common::sqlite_db("dual_write_clock_primary").await.unwrap();
+    let secondary = common::sqlite_db("dual_write_clock_secondary").await.unwrap();
+    create_tables(&[&primary.pool, &secondary.pool]).await;
+    let clock = ManualClock::new();
+    let dual = DualWritePool::new(Arc::new(primary.pool.clone().with_clock(Arc::new(clock.clone()))),
+                                  Arc::new(secondary.pool.clone()),
+                                  DualWriteOptions::default()).start();
+    while clock.sleepers() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    dual.execute("INSERT INTO t VALUES (?)", vec![SqlValue::Int(1)]).await.unwrap();
+    // The task sleeps until the clock moves, however much real time passes.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(dual.pending(), 1);
+    assert_eq!(count(&secondary.pool).await, 0);
+
+    clock.advance(Duration::from_millis(10));
+    while dual.stats().replayed == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert_eq!(dual.pending(), 0);
+    assert_eq!(count(&secondary.pool).await, 1);
+    assert_eq!(count(&primary.pool).await, 1);
+
+    dual.shutdown().await;
+    clock.advance(Duration::from_millis(10));
+    primary.finish().await;
+    secondary.finish().await;
+}
+
+#[tokio::test]
+async fn a_full_queue_drops_batches_and_sampled_reads_are_compared() {
+    let primary = common::sqlite_db("dual_write_queue_primary").await.unwrap();
+    let secondary = common::sqlite_db("dual_write_queue_secondary").await.unwrap();
+    create_tables(&[&primary.pool, &secondary.pool]).await;
+    let mismatches = Arc::new(Mutex::new(Vec::new()));
+    let seen = mismatches.clone();
+    let options = DualWriteOptions { queue_capacity: 2, read_sample_percent: 100 };
+    let dual = DualWritePool::with_mismatch_callback(Arc::new(primary.pool.clone()), Arc::new(secondary.pool.clone()), options,
+                                                     move |m| seen.lock().unwrap().push(m.clone()));
+
+    for v in 0..3i64 {
+        dual.execute("INSERT INTO t VALUES (?)", vec![SqlValue::Int(v)]).await.unwrap();
+    }
+    assert_eq!(dual.pending(), 2);
+    assert_eq!(dual.stats().dropped, 1);
+    assert_eq!(dual.replay_now().await, 2);
+    assert_eq!(count(&primary.pool).await, 3);
+    assert_eq!(count(&secondary.pool).await, 2);
+
+    let rows = dual.query("SELECT count(*) AS n FROM t", Vec::new()).await.unwrap();
+    assert_eq!(rows[0].get("n"), Some(&SqlValue::Int(3)));
+    while dual.stats().compared_reads == 0 || mismatches.lock().unwrap().is_empty() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let stats = dual.stats();
+    assert_eq!((stats.replayed, stats.mismatches), (2, 1));
+    let mismatch = mismatches.lock().unwrap()[0].clone();
+    assert_eq!(mismatch.sql, "SELECT count(*) AS n FROM t");
+    assert_eq!(mismatch.summary, "row 0 column n: Int(3) vs Int(2)");
+    primary.finish().await;
+    secondary.finish().await;
+}
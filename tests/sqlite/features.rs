@@ -0,0 +1,21 @@
+use sfo_sql::sqlite::SqliteFeature;
+use crate::common;
+
+#[tokio::test]
+async fn bundled_sqlite_reports_its_features() {
+    let db = common::sqlite_db("features").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    assert!(!conn.compile_options().await.unwrap().is_empty());
+    assert!(conn.supports(SqliteFeature::Json1).await.unwrap());
+    for feature in [SqliteFeature::Fts5, SqliteFeature::Rtree, SqliteFeature::MathFunctions] {
+        conn.supports(feature).await.unwrap();
+    }
+    assert!(conn.capabilities().await.unwrap().json_functions);
+    drop(conn);
+
+    // The probe is cached on the pool, other connections answer the same.
+    let mut other = db.pool.get_conn().await.unwrap();
+    assert!(other.supports(SqliteFeature::Json1).await.unwrap());
+    drop(other);
+    db.finish().await;
+}
@@ -0,0 +1,61 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, Guard, SqlConnection, SqlRow};
+use crate::common;
+
+async fn setup(conn: &mut SqlConnection) {
+    conn.execute_sql(sql_query("CREATE TABLE balances (id INTEGER PRIMARY KEY, amount INTEGER NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100) INSERT INTO balances SELECT i, i FROM n")).await.unwrap();
+}
+
+async fn total(conn: &mut SqlConnection) -> i64 {
+    conn.query_one(sql_query("SELECT sum(amount) FROM balances")).await.unwrap().get(0)
+}
+
+#[tokio::test]
+async fn an_unbounded_update_trips_the_guard_and_is_rolled_back() {
+    let db = common::sqlite_db("guard_unbounded").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    setup(&mut conn).await;
+    let guard = Guard { max_affected: 10 };
+
+    let e = conn.execute_guarded(sql_query("UPDATE balances SET amount = 0"), guard).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::GuardTripped);
+    assert!(format!("{:?}", e).contains("100 rows affected"), "{:?}", e);
+    assert_eq!(conn.current_transaction_id(), None);
+    assert_eq!(total(&mut conn).await, 5050);
+
+    // In the caller's transaction the guard rolls back the transaction as a whole.
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("UPDATE balances SET amount = amount + 1 WHERE id = 1")).await.unwrap();
+    let e = conn.execute_guarded(sql_query("DELETE FROM balances WHERE id > 50"), guard).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::GuardTripped);
+    assert_eq!(conn.current_transaction_id(), None);
+    assert_eq!(total(&mut conn).await, 5050);
+
+    // A write within the guard goes through.
+    let affected = conn.execute_guarded(sql_query("UPDATE balances SET amount = 0 WHERE id <= ?").bind(10), guard).await.unwrap();
+    assert_eq!(affected, 10);
+    assert_eq!(total(&mut conn).await, 5050 - 55);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn writes_behind_a_common_table_expression_are_guarded_and_unknown_statements_refused() {
+    let db = common::sqlite_db("guard_cte").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    setup(&mut conn).await;
+    let guard = Guard { max_affected: 10 };
+
+    let sql = "WITH low AS (SELECT id FROM balances WHERE amount < 50) UPDATE balances SET amount = 0 WHERE id IN (SELECT id FROM low)";
+    let e = conn.execute_guarded(sql_query(sql), guard).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::GuardTripped);
+    assert_eq!(total(&mut conn).await, 5050);
+    // Reads are not guarded, statements the guard cannot read are not run.
+    conn.execute_guarded(sql_query("SELECT * FROM balances"), guard).await.unwrap();
+    let e = conn.execute_guarded(sql_query("CREATE INDEX balances_amount ON balances (amount)"), guard).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::GuardTripped);
+    assert!(!conn.is_index_exist("balances", "balances_amount", None).await.unwrap());
+    drop(conn);
+    db.finish().await;
+}
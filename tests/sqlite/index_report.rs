@@ -0,0 +1,39 @@
+use sfo_sql::sqlite::{sql_query, IndexInfo};
+use crate::common;
+
+#[tokio::test]
+async fn a_leading_prefix_index_is_flagged_redundant() {
+    let db = common::sqlite_db("index_report").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    for sql in [
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, customer INTEGER, placed TEXT, status TEXT, code TEXT)",
+        "CREATE INDEX orders_customer ON orders (customer)",
+        "CREATE INDEX orders_customer_placed ON orders (customer, placed)",
+        // Not a leading prefix of orders_customer_placed.
+        "CREATE INDEX orders_placed ON orders (placed)",
+        // Unique, it still enforces a constraint the wider index does not.
+        "CREATE UNIQUE INDEX orders_code ON orders (code)",
+        "CREATE INDEX orders_code_status ON orders (code, status)",
+        "CREATE INDEX orders_lower_status ON orders (lower(status))",
+        "CREATE TABLE other (customer INTEGER, placed TEXT)",
+        "CREATE INDEX other_customer ON other (customer)",
+    ] {
+        conn.execute_sql(sql_query(sql)).await.unwrap();
+    }
+
+    let report = conn.analyze_indexes(Some("orders")).await.unwrap();
+    let redundant = report.redundant().map(|i| (i.name.as_str(), i.redundant_with.as_deref())).collect::<Vec<_>>();
+    assert_eq!(redundant, vec![("orders_customer", Some("orders_customer_placed"))]);
+    let find = |name: &str| -> &IndexInfo { report.indexes.iter().find(|i| i.name == name).unwrap() };
+    assert_eq!(find("orders_customer_placed").columns, vec!["customer".to_string(), "placed".to_string()]);
+    assert!(find("orders_code").unique);
+    assert_eq!(find("orders_lower_status").columns, vec!["<expr>".to_string()]);
+    assert!(report.indexes.iter().all(|i| i.table == "orders"));
+
+    // Across the whole database indexes of other tables are not compared with each other.
+    let report = conn.analyze_indexes(None).await.unwrap();
+    assert_eq!(report.redundant().count(), 1);
+    assert!(report.indexes.iter().any(|i| i.name == "other_customer"));
+    drop(conn);
+    db.finish().await;
+}
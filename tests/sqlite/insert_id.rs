@@ -0,0 +1,25 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::sql_query;
+use crate::common;
+
+#[tokio::test]
+async fn insert_returning_id_returns_the_rowid() {
+    let db = common::sqlite_db("insert_id").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    assert!(conn.capabilities().await.unwrap().returning);
+    conn.execute_sql(sql_query("CREATE TABLE items (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE)")).await.unwrap();
+
+    let first = conn.insert_returning_id(sql_query("INSERT INTO items (name) VALUES (?)").bind("a")).await.unwrap();
+    let second = conn.insert_returning_id(sql_query("INSERT INTO items (name) VALUES (?);").bind("b")).await.unwrap();
+    assert_eq!((first, second), (1, 2));
+    // A statement with a RETURNING clause of its own runs unchanged.
+    let third = conn.insert_returning_id(sql_query("INSERT INTO items (name) VALUES (?) RETURNING id").bind("c")).await.unwrap();
+    assert_eq!(third, 3);
+
+    let e = conn.insert_returning_id(sql_query("INSERT OR IGNORE INTO items (name) VALUES (?)").bind("a")).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Failed);
+    let e = conn.insert_returning_id(sql_query("INSERT INTO items (name) VALUES (?)").bind("a")).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    drop(conn);
+    db.finish().await;
+}
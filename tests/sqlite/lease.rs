@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, SqlRow};
+use sfo_sql::test_util::ManualClock;
+use crate::common;
+
+#[tokio::test]
+async fn handles_share_one_connection_until_release() {
+    let db = common::sqlite_db("lease_share").await.unwrap();
+    let lease = db.pool.lease_conn("temp table").await.unwrap();
+    let other = lease.clone_handle();
+    lease.lock().await.unwrap().execute_sql(sql_query("CREATE TEMP TABLE scratch (v INTEGER)")).await.unwrap();
+    lease.lock().await.unwrap().execute_sql(sql_query("INSERT INTO scratch (v) VALUES (7)")).await.unwrap();
+    drop(lease);
+    let row = other.lock().await.unwrap().query_one(sql_query("SELECT v FROM scratch")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("v"), 7);
+    assert_eq!(db.pool.in_use(), 1);
+
+    let third = other.clone_handle();
+    other.release().await;
+    // sqlx hands the connection back to the pool from a spawned task.
+    for _ in 0..100 {
+        if db.pool.in_use() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(db.pool.in_use(), 0);
+    assert!(third.lock().await.is_err());
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn forgotten_lease_is_reported_after_the_leak_timeout() {
+    let _logs = common::logs::lock().await;
+    let clock = ManualClock::new();
+    let db = common::sqlite_db("lease_leak").await.unwrap();
+    let pool = db.pool.clone().with_clock(Arc::new(clock.clone())).with_lease_leak_timeout(Duration::from_secs(60));
+    let released = pool.lease_conn("released lease").await.unwrap();
+    let leaked = pool.lease_conn("forgotten lease").await.unwrap();
+    released.release().await;
+    std::mem::forget(leaked);
+
+    while clock.sleepers() < 2 {
+        tokio::task::yield_now().await;
+    }
+    clock.advance(Duration::from_secs(61));
+    for _ in 0..100 {
+        if !common::logs::records_with("[forgotten lease]").is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(common::logs::records_with("[forgotten lease]").len(), 1);
+    assert!(common::logs::records_with("[released lease]").is_empty());
+}
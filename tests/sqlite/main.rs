@@ -0,0 +1,55 @@
+// Integration tests of the sqlite backend, each against a database file of its own.
+
+#[path = "../common/mod.rs"]
+mod common;
+
+mod behaviour {
+    crate::common::behaviour_suite!(sfo_sql::sqlite, crate::common::sqlite_db);
+}
+
+mod adaptive_timeout;
+mod batcher;
+mod catalog;
+mod chunked_in;
+mod coalescer;
+mod config_store;
+mod constraint_audit;
+mod databases;
+mod dual_write;
+mod features;
+mod guard;
+mod index_report;
+mod insert_id;
+mod lease;
+mod manager;
+mod metrics;
+mod nesting;
+mod observer;
+mod outbox;
+mod partition;
+mod poisoned;
+mod priority;
+mod query_cache;
+mod read_only;
+mod read_transaction;
+mod read_replay;
+mod ready;
+mod reconcile;
+mod recover;
+mod retention;
+mod row_hash;
+mod sample;
+mod schema_change;
+mod search;
+mod seed;
+mod shutdown;
+mod sink;
+mod spool;
+mod startup;
+mod statement_stats;
+mod stream;
+mod swap;
+mod switch;
+mod transactions;
+mod unit_of_work;
+mod uri;
@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, SqlitePoolManager, SqlRow, TenantPoolOptions};
+use sfo_sql::test_util::{ManualClock, TempSqliteDb};
+
+fn base_dir(prefix: &str) -> PathBuf {
+    let dir = TempSqliteDb::new(prefix).path().with_extension("d");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn open(manager: &SqlitePoolManager) -> Vec<String> {
+    let mut tenants = manager.open_pools().await;
+    tenants.sort();
+    tenants
+}
+
+#[tokio::test]
+async fn a_pool_handed_out_is_not_evicted_until_dropped() {
+    let dir = base_dir("manager_pinned");
+    let manager = SqlitePoolManager::new(&dir, TenantPoolOptions::default(), 1, Duration::from_secs(3600));
+    // Held without a connection checked out.
+    let a = manager.get("a").await.unwrap();
+    let b = manager.get("b").await.unwrap();
+    assert_eq!(open(&manager).await, vec!["a", "b"]);
+    let mut conn = a.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE t (v INTEGER)")).await.unwrap();
+    drop(conn);
+    drop(b);
+
+    // b is the only pool nobody holds, it goes to make room.
+    drop(manager.get("c").await.unwrap());
+    assert_eq!(open(&manager).await, vec!["a", "c"]);
+    assert!(!a.is_shutting_down());
+    drop(a);
+    drop(manager.get("d").await.unwrap());
+    assert_eq!(open(&manager).await, vec!["d"]);
+
+    // Opened again, the tenant file still has its data.
+    let a = manager.get("a").await.unwrap();
+    let mut conn = a.get_conn().await.unwrap();
+    assert_eq!(conn.query_one(sql_query("SELECT count(*) FROM t")).await.unwrap().get::<i64, _>(0), 0);
+    drop(conn);
+    drop(a);
+    manager.close_all().await;
+    assert!(open(&manager).await.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn idle_pools_are_evicted_after_the_ttl() {
+    let dir = base_dir("manager_idle");
+    let clock = ManualClock::new();
+    let manager = SqlitePoolManager::new(&dir, TenantPoolOptions::default(), 10, Duration::from_secs(60))
+        .with_clock(Arc::new(clock.clone()));
+    drop(manager.get("a").await.unwrap());
+    clock.advance(Duration::from_secs(30));
+    let b = manager.get("b").await.unwrap();
+    clock.advance(Duration::from_secs(40));
+    manager.evict_idle().await;
+    assert_eq!(open(&manager).await, vec!["b"]);
+
+    // Past its ttl but still held.
+    clock.advance(Duration::from_secs(60));
+    manager.evict_idle().await;
+    assert_eq!(open(&manager).await, vec!["b"]);
+    drop(b);
+    manager.evict_idle().await;
+    assert!(open(&manager).await.is_empty());
+
+    assert!(manager.get("../escape").await.is_err());
+    assert!(!manager.close("a").await);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_gets_of_one_tenant_share_a_pool() {
+    let dir = base_dir("manager_concurrent");
+    let manager = Arc::new(SqlitePoolManager::new(&dir, TenantPoolOptions::default(), 4, Duration::from_secs(3600)));
+    let mut tasks = Vec::new();
+    for _ in 0..8 {
+        let manager = manager.clone();
+        tasks.push(tokio::spawn(async move {
+            let pool = manager.get("shared").await.unwrap();
+            let mut conn = pool.get_conn().await.unwrap();
+            conn.query_one(sql_query("SELECT 1")).await.unwrap().get::<i64, _>(0)
+        }));
+    }
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), 1);
+    }
+    assert_eq!(open(&manager).await, vec!["shared"]);
+    assert!(manager.close("shared").await);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,77 @@
+use serde_json::{Map, Value};
+use sfo_sql::sqlite::{sql_query, METRICS_SCHEMA_VERSION};
+use crate::common;
+
+// The key shape of metrics_json version 1, checked in so a changed layout fails here and gets a
+// new schema version instead of breaking dashboards.
+const METRICS_V1: &str = include_str!("../fixtures/metrics_v1.json");
+
+fn leaf(value: &Value) -> Value {
+    Value::String(match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        _ => unreachable!(),
+    }.to_string())
+}
+
+// Leaves become their json type, array elements are merged into one and leaves differing
+// between elements into "a|b".
+fn shape(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), shape(v))).collect()),
+        Value::Array(items) => Value::Array(items.iter().map(shape).reduce(merge).into_iter().collect()),
+        _ => leaf(value),
+    }
+}
+
+fn merge(a: Value, b: Value) -> Value {
+    match (a, b) {
+        (Value::Object(mut a), Value::Object(b)) => {
+            for (k, v) in b {
+                let merged = match a.remove(&k) {
+                    Some(old) => merge(old, v),
+                    None => v,
+                };
+                a.insert(k, merged);
+            }
+            Value::Object(a)
+        }
+        (Value::String(a), Value::String(b)) => {
+            let mut types = a.split('|').chain(b.split('|')).map(str::to_string).collect::<Vec<_>>();
+            types.sort();
+            types.dedup();
+            Value::String(types.join("|"))
+        }
+        (a, b) => panic!("{} and {} do not merge", a, b),
+    }
+}
+
+#[tokio::test]
+async fn metrics_json_keeps_the_checked_in_key_shape() {
+    let db = common::sqlite_db("metrics").await.unwrap();
+    let pool = db.pool.clone().with_statement_stats(16);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (name TEXT NOT NULL)")).await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql_tagged("add_item", sql_query("INSERT INTO items (name) VALUES (?)").bind("a")).await.unwrap();
+    conn.commit_transaction().await.unwrap();
+    assert!(conn.query_one(sql_query("SELECT name FROM items WHERE name = 'b'")).await.is_err());
+    drop(conn);
+
+    let metrics = pool.metrics_json();
+    assert_eq!(metrics["schema_version"], METRICS_SCHEMA_VERSION);
+    assert_eq!(metrics["transactions"]["committed"], 1);
+    let statements = metrics["statements"].as_array().unwrap();
+    assert!(statements.iter().any(|s| s["tag"] == "add_item") && statements.iter().any(|s| s["tag"].is_null()));
+
+    // errors is keyed by the codes seen in the whole process, other tests included.
+    let mut shape = shape(&metrics);
+    let errors = metrics["errors"].as_object().unwrap();
+    assert!(errors.contains_key("NotFound") && errors.values().all(Value::is_u64), "{:?}", errors);
+    shape["errors"] = Value::Object(Map::from_iter([("<SqlErrorCode name>".to_string(), Value::String("number".to_string()))]));
+    let expected: Value = serde_json::from_str(METRICS_V1).unwrap();
+    assert_eq!(shape, expected, "\n{}", serde_json::to_string_pretty(&shape).unwrap());
+    db.finish().await;
+}
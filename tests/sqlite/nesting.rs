@@ -0,0 +1,129 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqlPool, SqlRow};
+use crate::common;
+
+async fn setup(prefix: &str) -> (common::TestDb<SqlPool>, SqlConnection) {
+    let db = common::sqlite_db(prefix).await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE jobs (id INTEGER PRIMARY KEY)")).await.unwrap();
+    (db, conn)
+}
+
+async fn insert(conn: &mut SqlConnection, id: i64) {
+    conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (?)").bind(id)).await.unwrap();
+}
+
+async fn count(conn: &mut SqlConnection) -> i64 {
+    conn.query_one(sql_query("SELECT count(*) AS c FROM jobs")).await.unwrap().get::<i64, _>("c")
+}
+
+#[tokio::test]
+async fn only_the_outermost_commit_commits() {
+    let (db, mut conn) = setup("nesting_commit").await;
+    conn.begin_transaction().await.unwrap();
+    let id = conn.current_transaction_id();
+    insert(&mut conn, 1).await;
+    conn.begin_transaction().await.unwrap();
+    assert_eq!(conn.current_transaction_id(), id);
+    insert(&mut conn, 2).await;
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(conn.current_transaction_id(), id);
+
+    conn.rollback_transaction().await.unwrap();
+    assert_eq!(count(&mut conn).await, 0);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn inner_rollback_fails_the_outer_commit() {
+    let (db, mut conn) = setup("nesting_rollback").await;
+    conn.begin_transaction().await.unwrap();
+    insert(&mut conn, 1).await;
+    conn.begin_transaction().await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    insert(&mut conn, 2).await;
+    conn.rollback_transaction().await.unwrap();
+    conn.commit_transaction().await.unwrap();
+
+    let e = conn.commit_transaction().await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::RollbackOnly);
+    assert_eq!(conn.current_transaction_id(), None);
+    assert_eq!(count(&mut conn).await, 0);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn transaction_helper_nests_in_an_open_transaction() {
+    let (db, mut conn) = setup("nesting_helper").await;
+    conn.begin_transaction().await.unwrap();
+    let id = conn.current_transaction_id();
+    conn.transaction(|conn| Box::pin(async move {
+        insert(conn, 1).await;
+        conn.transaction(|conn| Box::pin(async move {
+            insert(conn, 2).await;
+            Ok(())
+        })).await
+    })).await.unwrap();
+    assert_eq!(conn.current_transaction_id(), id);
+    assert_eq!(count(&mut conn).await, 2);
+
+    conn.rollback_transaction().await.unwrap();
+    assert_eq!(count(&mut conn).await, 0);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn failed_nested_helper_makes_the_outer_transaction_rollback_only() {
+    let (db, mut conn) = setup("nesting_helper_failed").await;
+    let e = conn.transaction(|conn| Box::pin(async move {
+        insert(conn, 1).await;
+        let e = conn.transaction(|conn| Box::pin(async move {
+            conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (1)")).await
+        })).await.unwrap_err();
+        assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+        Ok(())
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::RollbackOnly);
+
+    assert_eq!(conn.current_transaction_id(), None);
+    assert_eq!(count(&mut conn).await, 0);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn helper_body_left_unbalanced_is_rolled_back() {
+    let (db, mut conn) = setup("nesting_unbalanced").await;
+    let e = conn.transaction(|conn| Box::pin(async move {
+        insert(conn, 1).await;
+        conn.begin_transaction().await
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::RollbackOnly);
+    assert_eq!(conn.current_transaction_id(), None);
+    assert_eq!(count(&mut conn).await, 0);
+
+    // Ending the helper's own transaction leaves it nothing to commit.
+    let e = conn.transaction(|conn| Box::pin(async move {
+        insert(conn, 2).await;
+        conn.commit_transaction().await
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::NotInTransaction);
+    assert_eq!(conn.current_transaction_id(), None);
+
+    // Ending a level of the caller's transaction makes the caller's commit fail.
+    conn.begin_transaction().await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    let e = conn.transaction(|conn| Box::pin(async move {
+        conn.commit_transaction().await?;
+        conn.commit_transaction().await
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::RollbackOnly);
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(conn.commit_transaction().await.unwrap_err().code(), SqlErrorCode::RollbackOnly);
+    assert_eq!(conn.current_transaction_id(), None);
+    drop(conn);
+    db.finish().await;
+}
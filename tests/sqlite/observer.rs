@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqlRow, StatementJournal};
+use crate::common;
+
+#[tokio::test]
+async fn journal_carries_the_transaction_ids() {
+    let db = common::sqlite_db("observer_ids").await.unwrap();
+    let journal = Arc::new(StatementJournal::new(64));
+    let pool = db.pool.clone().with_observer(journal.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE observed (v INTEGER)")).await.unwrap();
+    assert_eq!(conn.current_transaction_id(), None);
+
+    conn.begin_transaction().await.unwrap();
+    let first = conn.current_transaction_id().unwrap();
+    conn.execute_sql(sql_query("INSERT INTO observed (v) VALUES (1)")).await.unwrap();
+    conn.query_all(sql_query("SELECT v FROM observed")).await.unwrap();
+    assert_eq!(conn.current_transaction_id(), Some(first));
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(conn.current_transaction_id(), None);
+
+    conn.begin_transaction().await.unwrap();
+    let second = conn.current_transaction_id().unwrap();
+    assert!(second > first);
+    conn.query_one(sql_query("SELECT max(v) AS v FROM observed")).await.unwrap();
+    conn.query_all(sql_query("SELECT missing FROM observed")).await.err().unwrap();
+    conn.rollback_transaction().await.unwrap();
+
+    let events = journal.transaction(first);
+    assert_eq!(events.iter().map(|e| e.sql.as_str()).collect::<Vec<_>>(),
+               vec!["INSERT INTO observed (v) VALUES (1)", "SELECT v FROM observed"]);
+    assert_eq!(events[1].rows, Some(1));
+    let events = journal.transaction(second);
+    assert_eq!(events.iter().map(|e| e.rows).collect::<Vec<_>>(), vec![Some(1), None]);
+    assert_eq!(journal.events()[0].transaction_id, None);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn journal_keeps_the_most_recent() {
+    let db = common::sqlite_db("observer_capacity").await.unwrap();
+    let journal = Arc::new(StatementJournal::new(2));
+    let pool = db.pool.clone().with_observer(journal.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    for i in 0..4 {
+        conn.query_one(sql_query(format!("SELECT {} AS v", i).as_str())).await.unwrap();
+    }
+    assert_eq!(journal.events().iter().map(|e| e.sql.as_str()).collect::<Vec<_>>(), vec!["SELECT 2 AS v", "SELECT 3 AS v"]);
+    journal.clear();
+    assert!(journal.events().is_empty());
+    drop(conn);
+    db.finish().await;
+}
+
+// The worker of a sqlite connection updates the count after sending the rows, a statement
+// without arguments, which is never cached, orders the read after that.
+async fn cached_statements(conn: &mut SqlConnection) -> usize {
+    conn.execute_sql(sql_query("SELECT 1")).await.unwrap();
+    conn.cached_statements()
+}
+
+#[tokio::test]
+async fn statements_in_a_transaction_are_tagged_through_tag_sql() {
+    let db = common::sqlite_db("observer_tagged").await.unwrap();
+    let journal = Arc::new(StatementJournal::new(64));
+    let pool = db.pool.clone().tag_transactions(true).with_observer(journal.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE tagged (name TEXT)")).await.unwrap();
+    conn.query_all(sql_query("SELECT name FROM tagged WHERE name = ?").bind("x")).await.unwrap();
+    let cached = cached_statements(&mut conn).await;
+
+    conn.begin_transaction().await.unwrap();
+    let id = conn.current_transaction_id().unwrap();
+    let insert = conn.tag_sql("INSERT INTO tagged (name) VALUES (?)").into_owned();
+    assert_eq!(insert, format!("INSERT INTO tagged (name) VALUES (?) /* txn:{} */", id));
+    let name = String::from("ann");
+    conn.execute_sql(sql_query(insert.as_str()).bind(name.as_str()).persistent(false)).await.unwrap();
+    // Sent as passed, the statement cached before is used again.
+    let rows = conn.query_all(sql_query("SELECT name FROM tagged WHERE name = ?").bind(name.as_str())).await.unwrap();
+    assert_eq!(rows[0].get::<String, _>("name"), "ann");
+    conn.execute_batch("UPDATE tagged SET name = 'anna'; UPDATE tagged SET name = name || '!'").await.unwrap();
+    assert_eq!(cached_statements(&mut conn).await, cached);
+    conn.commit_transaction().await.unwrap();
+
+    // The journal has the text as passed, with the id next to it.
+    let events = journal.transaction(id);
+    let sql: Vec<&str> = events.iter().map(|e| e.sql.as_str()).collect();
+    assert_eq!(sql, vec![insert.as_str(), "SELECT name FROM tagged WHERE name = ?",
+                         "UPDATE tagged SET name = 'anna'; UPDATE tagged SET name = name || '!'", "SELECT 1"]);
+
+    assert_eq!(conn.tag_sql("SELECT 1"), "SELECT 1");
+    let row = conn.query_one(sql_query("SELECT name FROM tagged WHERE name <> ?").bind("x")).await.unwrap();
+    assert_eq!(row.get::<String, _>("name"), "anna!");
+    assert_eq!(cached_statements(&mut conn).await, cached + 1);
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,69 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, Outbox, SqlRow};
+use crate::common;
+
+const HIDDEN: Duration = Duration::from_secs(60);
+
+#[tokio::test]
+async fn an_event_is_published_only_with_its_transaction() {
+    let db = common::sqlite_db("outbox_rollback").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let outbox = Outbox::ensure(&mut conn).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE orders (id INTEGER PRIMARY KEY)")).await.unwrap();
+
+    let e = outbox.enqueue(&mut conn, "order_created", r#"{"id":1}"#).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::NotInTransaction);
+
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO orders (id) VALUES (1)")).await.unwrap();
+    outbox.enqueue(&mut conn, "order_created", r#"{"id":1}"#).await.unwrap();
+    conn.rollback_transaction().await.unwrap();
+    assert!(outbox.poll(&db.pool, 10, HIDDEN).await.unwrap().is_empty());
+    let row = conn.query_one(sql_query(format!("SELECT count(*) FROM {}", outbox.table()).as_str())).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 0);
+
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO orders (id) VALUES (2)")).await.unwrap();
+    outbox.enqueue(&mut conn, "order_created", r#"{"id":2}"#).await.unwrap();
+    conn.commit_transaction().await.unwrap();
+    let messages = outbox.poll(&db.pool, 10, HIDDEN).await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!((messages[0].topic.as_str(), messages[0].payload.as_str(), messages[0].attempts), ("order_created", r#"{"id":2}"#, 1));
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn concurrent_pollers_claim_disjoint_events() {
+    let db = common::sqlite_db("outbox_pollers").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let outbox = Outbox::ensure(&mut conn).await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    for id in 0..20 {
+        outbox.enqueue(&mut conn, "tick", format!("{{\"n\":{}}}", id).as_str()).await.unwrap();
+    }
+    conn.commit_transaction().await.unwrap();
+
+    let (a, b) = tokio::join!(outbox.poll(&db.pool, 8, HIDDEN), outbox.poll(&db.pool, 8, HIDDEN));
+    let (a, b) = (a.unwrap(), b.unwrap());
+    let a_ids = a.iter().map(|m| m.id).collect::<HashSet<_>>();
+    let b_ids = b.iter().map(|m| m.id).collect::<HashSet<_>>();
+    assert_eq!((a_ids.len(), b_ids.len()), (8, 8));
+    assert!(a_ids.is_disjoint(&b_ids), "{:?} and {:?}", a_ids, b_ids);
+    let rest = outbox.poll(&db.pool, 8, HIDDEN).await.unwrap();
+    assert_eq!(rest.len(), 4);
+    assert!(outbox.poll(&db.pool, 8, HIDDEN).await.unwrap().is_empty());
+
+    // A failed event comes back with its attempts counted, a delivered one is gone.
+    assert!(outbox.mark_done(&mut conn, &a[0]).await.unwrap());
+    assert!(outbox.mark_failed(&mut conn, &b[0], Duration::ZERO).await.unwrap());
+    let retried = outbox.poll(&db.pool, 8, HIDDEN).await.unwrap();
+    assert_eq!(retried.iter().map(|m| (m.id, m.attempts)).collect::<Vec<_>>(), vec![(b[0].id, 2)]);
+    // The claim of the first poll no longer holds.
+    assert!(!outbox.mark_done(&mut conn, &b[0]).await.unwrap());
+    assert!(outbox.mark_done(&mut conn, &retried[0]).await.unwrap());
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,47 @@
+use sfo_sql::sqlite::{sql_query, FilterBuilder, PartitionRouter, PartitionScheme, SqlBackend, SqlRow, SqlValue, TABLE_MARKER};
+use crate::common;
+
+// 2024-01-01T00:00:00Z
+const JAN_1: i64 = 1_704_067_200;
+const DAY: i64 = 86_400;
+
+fn router() -> PartitionRouter {
+    PartitionRouter::new("events", PartitionScheme::Monthly,
+                         format!("CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, ts INTEGER NOT NULL)", TABLE_MARKER).as_str())
+}
+
+#[tokio::test]
+async fn a_range_query_spans_the_month_boundaries() {
+    let db = common::sqlite_db("partition").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let router = router();
+
+    // A row at noon of every day of the first quarter of 2024, a leap year.
+    for day in 0..91 {
+        let ts = JAN_1 + day * DAY + DAY / 2;
+        let row = [("id".to_string(), SqlValue::Int(day + 1)), ("ts".to_string(), SqlValue::Int(ts))].into_iter().collect();
+        router.insert_routed(&mut conn, ts, &row).await.unwrap();
+    }
+    for (table, count) in [("events_2024_01", 31), ("events_2024_02", 29), ("events_2024_03", 31)] {
+        let row = conn.query_one(sql_query(format!("SELECT count(*) FROM {}", table).as_str())).await.unwrap();
+        assert_eq!(row.get::<i64, _>(0), count, "{}", table);
+    }
+
+    // January 20th to March 10th, through every partition.
+    let (from, to) = (JAN_1 + 19 * DAY, JAN_1 + 70 * DAY - 1);
+    assert_eq!(router.partitions_between(from, to), ["events_2024_01", "events_2024_02", "events_2024_03"]);
+    let filter = FilterBuilder::new(&["ts"]).between("ts", from, to).build(SqlBackend::Sqlite).unwrap();
+    let rows = router.query_range(&mut conn, from, to, Some(&filter)).await.unwrap();
+    let ts = rows.iter().map(|row| row.get::<i64, _>("ts")).collect::<Vec<_>>();
+    assert_eq!(ts.len(), 12 + 29 + 10);
+    assert!(ts.iter().all(|t| (from..=to).contains(t)));
+    // Partitions are concatenated in key order.
+    assert!(ts.windows(2).all(|w| router.partition_name(w[0]) <= router.partition_name(w[1])));
+
+    // Partitions not created yet are skipped, without a filter the one at the start is read whole.
+    let rows = router.query_range(&mut conn, JAN_1 + 80 * DAY, JAN_1 + 120 * DAY, None).await.unwrap();
+    assert_eq!(rows.len(), 31);
+    assert!(router.query_range(&mut conn, JAN_1 - 60 * DAY, JAN_1 - 1, None).await.unwrap().is_empty());
+    drop(conn);
+    db.finish().await;
+}
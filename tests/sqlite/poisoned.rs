@@ -0,0 +1,72 @@
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, SqlRow};
+use crate::common;
+
+const SLOW_SQL: &str = "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000000) SELECT count(*) FROM n";
+
+async fn wait_for_pool_size(pool: &sfo_sql::sqlite::SqlPool, size: u32) {
+    for _ in 0..500 {
+        if pool.pool_size() == size {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("pool size {} never reached {}", pool.pool_size(), size);
+}
+
+#[tokio::test]
+async fn connection_dropped_mid_statement_is_closed() {
+    let _logs = common::logs::lock().await;
+    let db = common::sqlite_db("poisoned_mid_statement").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let size = db.pool.pool_size();
+    assert!(tokio::time::timeout(Duration::from_millis(50), conn.query_one(sql_query(SLOW_SQL))).await.is_err());
+    drop(conn);
+
+    assert_eq!(db.pool.stats().poisoned_connections, 1);
+    assert_eq!(common::logs::records_with("dropped mid-statement").len(), 1);
+    wait_for_pool_size(&db.pool, size - 1).await;
+    let row = db.pool.query_one(sql_query("SELECT 1 AS one")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("one"), 1);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn panic_with_a_transaction_open_closes_the_connection() {
+    let db = common::sqlite_db("poisoned_panic").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE jobs (id INTEGER PRIMARY KEY)")).await.unwrap();
+    drop(conn);
+
+    let pool = db.pool.clone();
+    let task = tokio::spawn(async move {
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.begin_transaction().await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (1)")).await.unwrap();
+        panic!("job failed half way");
+    });
+    assert!(task.await.unwrap_err().is_panic());
+
+    assert_eq!(db.pool.stats().poisoned_connections, 1);
+    let rows = db.pool.query_all(sql_query("SELECT id FROM jobs")).await.unwrap();
+    assert!(rows.is_empty());
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn panic_outside_a_transaction_keeps_the_connection() {
+    let db = common::sqlite_db("poisoned_none").await.unwrap();
+    let pool = db.pool.clone();
+    let task = tokio::spawn(async move {
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.query_one(sql_query("SELECT 1")).await.unwrap();
+        panic!("failed outside a transaction");
+    });
+    assert!(task.await.unwrap_err().is_panic());
+
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    drop(conn);
+    assert_eq!(db.pool.stats().poisoned_connections, 0);
+    db.finish().await;
+}
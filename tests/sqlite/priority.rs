@@ -0,0 +1,38 @@
+use std::time::Duration;
+use sfo_sql::sqlite::{Priority, SqlPool, SqliteJournalMode};
+use crate::common;
+
+#[tokio::test]
+async fn high_priority_acquires_while_the_normal_class_is_saturated() {
+    let db = common::sqlite_db("priority").await.unwrap();
+    // Two connections for normal acquires, the third kept for high priority.
+    let pool = SqlPool::open(db.pool.target().uri.as_str(), 3, Some(SqliteJournalMode::Wal)).await.unwrap().reserve_for_priority(1);
+    // Opening the pool acquires on its own.
+    let before = pool.stats();
+    let first = pool.get_conn().await.unwrap();
+    let second = pool.get_conn().await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(100), pool.get_conn()).await.is_err());
+
+    let waiting = {
+        let pool = pool.clone();
+        tokio::spawn(async move { pool.get_conn().await.map(drop) })
+    };
+    let high = tokio::time::timeout(Duration::from_secs(1), pool.get_conn_priority(Priority::High)).await
+        .expect("high priority waited for the normal class").unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!waiting.is_finished());
+
+    // A normal connection coming back lets the waiting normal acquire through.
+    drop(first);
+    waiting.await.unwrap().unwrap();
+    let stats = pool.stats();
+    assert_eq!(stats.high_acquires - before.high_acquires, 1);
+    assert!(stats.high_wait - before.high_wait < Duration::from_secs(1), "{:?}", stats);
+    // The two immediate ones, the one timed out above is not counted, and the one that waited.
+    assert_eq!(stats.normal_acquires - before.normal_acquires, 3);
+    assert!(stats.normal_wait - before.normal_wait >= Duration::from_millis(100), "{:?}", stats);
+    drop(second);
+    drop(high);
+    pool.raw_pool().await.close().await;
+    db.finish().await;
+}
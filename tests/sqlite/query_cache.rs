@@ -0,0 +1,70 @@
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqlValue, UnitOfWork};
+use crate::common;
+
+const READ: &str = "SELECT name FROM cached ORDER BY name";
+
+async fn cached_names(conn: &mut SqlConnection) -> Vec<String> {
+    conn.query_all_cached(READ, &[], &["cached"]).await.unwrap().iter().map(|row| match row.get("name") {
+        Some(SqlValue::Text(name)) => name.clone(),
+        other => panic!("unexpected name {:?}", other),
+    }).collect()
+}
+
+#[tokio::test]
+async fn writes_through_every_method_invalidate() {
+    let db = common::sqlite_db("cache_writes").await.unwrap();
+    let pool = db.pool.clone().with_query_cache(16);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE cached (name TEXT PRIMARY KEY)")).await.unwrap();
+    assert!(cached_names(&mut conn).await.is_empty());
+
+    conn.execute_sql(sql_query("INSERT INTO cached (name) VALUES ('a')")).await.unwrap();
+    assert_eq!(cached_names(&mut conn).await, vec!["a"]);
+
+    conn.execute_returning(sql_query("INSERT INTO cached (name) VALUES ('b') RETURNING name")).await.unwrap();
+    assert_eq!(cached_names(&mut conn).await, vec!["a", "b"]);
+
+    // A write in a plain fetch.
+    conn.query_all(sql_query("INSERT INTO cached (name) VALUES ('c') RETURNING name")).await.unwrap();
+    assert_eq!(cached_names(&mut conn).await, vec!["a", "b", "c"]);
+    conn.query_one(sql_query("DELETE FROM cached WHERE name = 'c' RETURNING name")).await.unwrap();
+    assert_eq!(cached_names(&mut conn).await, vec!["a", "b"]);
+
+    let mut stream = conn.query_stream(sql_query("INSERT INTO cached (name) VALUES ('d') RETURNING name")).unwrap();
+    stream.next().await.unwrap().unwrap();
+    assert!(stream.next().await.is_none());
+    drop(stream);
+    assert_eq!(cached_names(&mut conn).await, vec!["a", "b", "d"]);
+
+    conn.execute_batch("INSERT INTO cached (name) VALUES ('e'); DELETE FROM cached WHERE name = 'a'").await.unwrap();
+    assert_eq!(cached_names(&mut conn).await, vec!["b", "d", "e"]);
+    drop(conn);
+
+    let mut unit = UnitOfWork::new();
+    unit.execute("DELETE FROM cached WHERE name = ?", vec![SqlValue::Text("b".to_string())]);
+    unit.commit(&pool).await.unwrap();
+    let mut conn = pool.get_conn().await.unwrap();
+    assert_eq!(cached_names(&mut conn).await, vec!["d", "e"]);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn fetched_write_in_a_transaction_invalidates_at_commit() {
+    let db = common::sqlite_db("cache_tx_fetch").await.unwrap();
+    let pool = db.pool.clone().with_query_cache(16);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE cached (name TEXT PRIMARY KEY)")).await.unwrap();
+    let mut other = pool.get_conn().await.unwrap();
+    assert!(cached_names(&mut other).await.is_empty());
+
+    conn.begin_transaction().await.unwrap();
+    conn.query_all(sql_query("INSERT INTO cached (name) VALUES ('a') RETURNING name")).await.unwrap();
+    // The transaction reads its own write, the cache is bypassed for it.
+    assert_eq!(cached_names(&mut conn).await, vec!["a"]);
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(cached_names(&mut other).await, vec!["a"]);
+    drop(conn);
+    drop(other);
+    db.finish().await;
+}
@@ -0,0 +1,49 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlPool, SqliteUriBuilder, SqlRow};
+use crate::common;
+
+async fn seeded(prefix: &str) -> common::TestDb<SqlPool> {
+    let db = common::sqlite_db(prefix).await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE notes (body TEXT)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO notes (body) VALUES ('bundled')")).await.unwrap();
+    drop(conn);
+    db.pool.raw_pool().await.close().await;
+    db
+}
+
+#[tokio::test]
+async fn writes_to_a_read_only_database_map_to_read_only() {
+    let db = seeded("read_only_mode").await;
+    let uri = SqliteUriBuilder::new().path(db.sqlite_path().unwrap()).read_only().build();
+    let pool = SqlPool::open(uri.as_str(), 1, None).await.unwrap();
+    let mut conn = pool.get_conn().await.unwrap();
+    let row = conn.query_one(sql_query("SELECT body FROM notes")).await.unwrap();
+    assert_eq!(row.get::<String, _>("body"), "bundled");
+    let e = conn.execute_sql(sql_query("INSERT INTO notes (body) VALUES ('new')")).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::ReadOnly);
+    drop(conn);
+    pool.raw_pool().await.close().await;
+}
+
+// Missing write permission is not read-only storage, the file is opened as usual rather than
+// immutable and a write that the permissions refuse is reported as ReadOnly.
+#[cfg(unix)]
+#[tokio::test]
+async fn permission_denied_is_not_read_only_storage() {
+    use std::os::unix::fs::PermissionsExt;
+    let db = seeded("read_only_perm").await;
+    let path = db.sqlite_path().unwrap().to_path_buf();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+    let uri = SqliteUriBuilder::new().path(&path).build();
+    let pool = SqlPool::open(uri.as_str(), 1, None).await.unwrap();
+    let mut conn = pool.get_conn().await.unwrap();
+    assert_eq!(conn.query_one(sql_query("SELECT body FROM notes")).await.unwrap().get::<String, _>("body"), "bundled");
+    // Root writes regardless of the permissions.
+    if let Err(e) = conn.execute_sql(sql_query("INSERT INTO notes (body) VALUES ('new')")).await {
+        assert_eq!(e.code(), SqlErrorCode::ReadOnly);
+    }
+    drop(conn);
+    pool.raw_pool().await.close().await;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+}
@@ -0,0 +1,42 @@
+use sfo_sql::sqlite::{sql_query, SqlRow, SqlRowObject};
+use sfo_sql::test_util::{Fault, FaultInjector, FaultMatcher};
+use crate::common;
+
+const BY_ID: &str = "SELECT name FROM items WHERE id >= ? ORDER BY id";
+
+#[tokio::test]
+async fn a_broken_read_is_replayed_once_on_a_new_connection() {
+    let db = common::sqlite_db("read_replay").await.unwrap();
+    let injector = FaultInjector::new();
+    let plain = db.pool.clone().with_fault_injector(injector.clone());
+    let pool = plain.clone().retry_reads_on_disconnect(true);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO items (id, name) VALUES (1, 'a'), (2, 'b'), (3, 'c')")).await.unwrap();
+    drop(conn);
+    let names = |rows: Vec<SqlRowObject>| rows.iter().map(|r| r.get::<String, _>("name")).collect::<Vec<_>>();
+
+    // The connection breaks under the read, which runs again with its arguments.
+    injector.add(FaultMatcher::SqlContains("FROM items".to_string()), Fault::Disconnect, 1);
+    let rows = pool.query_all(sql_query(BY_ID).bind(2)).await.unwrap();
+    assert_eq!(names(rows), vec!["b", "c"]);
+    assert_eq!(pool.stats().read_replays, 1);
+    let row = pool.query_optional(sql_query("SELECT name FROM items WHERE id = 1")).await.unwrap();
+    assert_eq!(row.unwrap().get::<String, _>("name"), "a");
+
+    // Replayed once only.
+    injector.add(FaultMatcher::SqlContains("FROM items".to_string()), Fault::Disconnect, 2);
+    assert!(pool.query_one(sql_query(BY_ID).bind(1)).await.is_err());
+    assert_eq!(pool.stats().read_replays, 2);
+
+    // Neither without the option nor on a connection.
+    injector.add(FaultMatcher::SqlContains("FROM items".to_string()), Fault::Disconnect, 1);
+    assert!(plain.query_all(sql_query(BY_ID).bind(1)).await.is_err());
+    injector.add(FaultMatcher::SqlContains("FROM items".to_string()), Fault::Disconnect, 1);
+    let mut conn = pool.get_conn().await.unwrap();
+    assert!(conn.query_all(sql_query(BY_ID).bind(1)).await.is_err());
+    drop(conn);
+    assert_eq!(pool.stats().read_replays, 2);
+    assert_eq!(names(pool.query_all(sql_query(BY_ID).bind(1)).await.unwrap()), vec!["a", "b", "c"]);
+    db.finish().await;
+}
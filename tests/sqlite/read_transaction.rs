@@ -0,0 +1,89 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqlPool, SqlRow, TransactionDropPolicy};
+use crate::common;
+
+async fn setup(pool: &SqlPool) {
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO notes (body) VALUES ('first')")).await.unwrap();
+}
+
+async fn query_only(conn: &mut SqlConnection) -> i64 {
+    conn.query_one(sql_query("PRAGMA query_only")).await.unwrap().get(0)
+}
+
+async fn insert(conn: &mut SqlConnection) -> Result<(), sfo_sql::errors::SqlError> {
+    conn.execute_sql(sql_query("INSERT INTO notes (body) VALUES ('second')")).await.map(|_| ())
+}
+
+#[tokio::test]
+async fn a_read_transaction_refuses_writes_and_serves_reads() {
+    let db = common::sqlite_db("read_tx_writes").await.unwrap();
+    setup(&db.pool).await;
+    let mut conn = db.pool.get_conn().await.unwrap();
+
+    conn.begin_read_transaction().await.unwrap();
+    assert_eq!(insert(&mut conn).await.unwrap_err().code(), SqlErrorCode::ReadOnly);
+    let row = conn.query_one(sql_query("SELECT body FROM notes")).await.unwrap();
+    assert_eq!(row.get::<String, _>(0), "first");
+    // Nested levels stay read only.
+    conn.begin_read_transaction().await.unwrap();
+    assert!(insert(&mut conn).await.is_err());
+    conn.commit_transaction().await.unwrap();
+    conn.commit_transaction().await.unwrap();
+
+    assert_eq!(query_only(&mut conn).await, 0);
+    insert(&mut conn).await.unwrap();
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn a_read_transaction_cannot_nest_in_a_read_write_one() {
+    let db = common::sqlite_db("read_tx_nested").await.unwrap();
+    setup(&db.pool).await;
+    let mut conn = db.pool.get_conn().await.unwrap();
+
+    conn.begin_transaction().await.unwrap();
+    let e = conn.begin_read_transaction().await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::NotInTransaction);
+    // The read write transaction goes on untouched.
+    insert(&mut conn).await.unwrap();
+    conn.commit_transaction().await.unwrap();
+    let row = conn.query_one(sql_query("SELECT count(*) FROM notes")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 2);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn query_only_is_lifted_on_commit_rollback_and_drop() {
+    let db = common::sqlite_db("read_tx_reset").await.unwrap();
+    setup(&db.pool).await;
+    let mut conn = db.pool.get_conn().await.unwrap();
+
+    conn.begin_read_transaction().await.unwrap();
+    assert_eq!(query_only(&mut conn).await, 1);
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(query_only(&mut conn).await, 0);
+
+    conn.begin_read_transaction().await.unwrap();
+    assert_eq!(query_only(&mut conn).await, 1);
+    conn.rollback_transaction().await.unwrap();
+    assert_eq!(query_only(&mut conn).await, 0);
+
+    // Dropped with the read transaction open, the connection is closed rather than handed out
+    // again with the pragma still set.
+    conn.set_transaction_drop_policy(TransactionDropPolicy::RollbackSilently);
+    conn.begin_read_transaction().await.unwrap();
+    drop(conn);
+    let mut conns = Vec::new();
+    for _ in 0..5 {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        assert_eq!(query_only(&mut conn).await, 0);
+        insert(&mut conn).await.unwrap();
+        conns.push(conn);
+    }
+    drop(conns);
+    db.finish().await;
+}
@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlPool, SqliteJournalMode, SqliteUriBuilder};
+use crate::common;
+
+#[tokio::test]
+async fn wait_until_ready_opens_a_reachable_database() {
+    let db = common::sqlite_db("ready_ok").await.unwrap();
+    let uri = SqliteUriBuilder::new().path(db.sqlite_path().unwrap()).build();
+    let pool = SqlPool::wait_until_ready(uri.as_str(), 1, None, Duration::from_secs(5)).await.unwrap();
+    pool.ping().await.unwrap();
+    pool.raw_pool().await.close().await;
+    db.finish().await;
+}
+
+// Setting the journal mode waits on the busy timeout while another connection holds an exclusive
+// lock, much longer than max_wait, the hanging attempt is cut off at max_wait.
+#[tokio::test]
+async fn wait_until_ready_gives_up_at_max_wait() {
+    let db = common::sqlite_db("ready_locked").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("PRAGMA journal_mode = DELETE")).await.unwrap();
+    conn.execute_sql(sql_query("BEGIN EXCLUSIVE")).await.unwrap();
+
+    let uri = SqliteUriBuilder::new().path(db.sqlite_path().unwrap()).build();
+    let start = Instant::now();
+    let e = SqlPool::wait_until_ready(uri.as_str(), 1, Some(SqliteJournalMode::Delete), Duration::from_millis(300)).await.err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(2), "took {:?}", start.elapsed());
+
+    conn.execute_sql(sql_query("ROLLBACK")).await.unwrap();
+    drop(conn);
+    db.finish().await;
+}
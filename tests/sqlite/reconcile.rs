@@ -0,0 +1,97 @@
+use sfo_sql::sqlite::{sql_query, ReconcileOptions, SqlRow, SqlValue};
+use crate::common;
+
+async fn seed(db: &common::TestDb<sfo_sql::sqlite::SqlPool>) {
+    let mut conn = db.pool.get_conn().await.unwrap();
+    for table in ["source", "target"] {
+        conn.execute_sql(sql_query(format!("CREATE TABLE {} (id INTEGER PRIMARY KEY, name TEXT, score REAL)", table).as_str())).await.unwrap();
+    }
+    for id in 1..=20i64 {
+        conn.execute_sql(sql_query("INSERT INTO source VALUES (?, ?, ?)").bind(id).bind(format!("n{}", id)).bind(id as f64 / 2.0)).await.unwrap();
+    }
+    // Target misses 3, 10 and 20, has 7 and 15 changed and 21, 22 and 0 that source does not have.
+    conn.execute_sql(sql_query("INSERT INTO target SELECT * FROM source WHERE id NOT IN (3, 10, 20)")).await.unwrap();
+    conn.execute_sql(sql_query("UPDATE target SET name = 'stale' WHERE id = 7")).await.unwrap();
+    conn.execute_sql(sql_query("UPDATE target SET score = NULL WHERE id = 15")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO target VALUES (0, 'x', 0), (21, 'x', 0), (22, 'x', 0)")).await.unwrap();
+}
+
+async fn table_rows(db: &common::TestDb<sfo_sql::sqlite::SqlPool>, table: &str) -> Vec<(i64, Option<String>, Option<f64>)> {
+    let rows = db.pool.query_all(sql_query(format!("SELECT id, name, score FROM {} ORDER BY id", table).as_str())).await.unwrap();
+    rows.iter().map(|r| (r.get(0), r.get(1), r.get(2))).collect()
+}
+
+fn ids(keys: &[Vec<SqlValue>]) -> Vec<SqlValue> {
+    keys.iter().map(|k| k[0].clone()).collect()
+}
+
+#[tokio::test]
+async fn dry_run_reports_every_category_and_writes_nothing() {
+    let db = common::sqlite_db("reconcile_dry_run").await.unwrap();
+    seed(&db).await;
+    let before = table_rows(&db, "target").await;
+    let mut conn = db.pool.get_conn().await.unwrap();
+    // Chunks of 4 put the differences on both sides of chunk boundaries.
+    let options = ReconcileOptions { chunk_size: 4, delete_extra: true, dry_run: true, ..Default::default() };
+    let report = conn.reconcile_tables("source", "target", &["id"], &options).await.unwrap();
+    drop(conn);
+
+    assert!(!report.applied);
+    assert_eq!((report.missing, report.changed, report.extra), (3, 2, 3));
+    assert_eq!(ids(&report.missing_keys), vec![SqlValue::Int(3), SqlValue::Int(10), SqlValue::Int(20)]);
+    assert_eq!(ids(&report.changed_keys), vec![SqlValue::Int(7), SqlValue::Int(15)]);
+    assert_eq!(ids(&report.extra_keys), vec![SqlValue::Int(0), SqlValue::Int(21), SqlValue::Int(22)]);
+    assert_eq!(table_rows(&db, "target").await, before);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn applying_makes_target_match_source() {
+    let db = common::sqlite_db("reconcile_apply").await.unwrap();
+    seed(&db).await;
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let options = ReconcileOptions { chunk_size: 4, sample_limit: 1, ..Default::default() };
+    let report = conn.reconcile_tables("source", "target", &["id"], &options).await.unwrap();
+    assert!(report.applied);
+    assert_eq!((report.missing, report.changed, report.extra), (3, 2, 3));
+    // Samples are bounded, counts are not.
+    assert_eq!(report.missing_keys.len(), 1);
+    assert_eq!(report.extra_keys.len(), 1);
+    // Without delete_extra the extra rows are only reported.
+    let expected = table_rows(&db, "source").await;
+    let target = table_rows(&db, "target").await;
+    assert_eq!(target.iter().filter(|r| r.0 == 0 || r.0 > 20).count(), 3);
+    assert_eq!(target.into_iter().filter(|r| r.0 >= 1 && r.0 <= 20).collect::<Vec<_>>(), expected);
+
+    let options = ReconcileOptions { chunk_size: 4, delete_extra: true, ..Default::default() };
+    let report = conn.reconcile_tables("source", "target", &["id"], &options).await.unwrap();
+    assert_eq!((report.missing, report.changed, report.extra), (0, 0, 3));
+    let report = conn.reconcile_tables("source", "target", &["id"], &options).await.unwrap();
+    assert_eq!((report.missing, report.changed, report.extra), (0, 0, 0));
+    drop(conn);
+    assert_eq!(table_rows(&db, "target").await, expected);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn composite_keys_and_missing_key_columns() {
+    let db = common::sqlite_db("reconcile_composite").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    for table in ["source", "target"] {
+        conn.execute_sql(sql_query(format!("CREATE TABLE {} (a INTEGER, b TEXT, v TEXT, PRIMARY KEY (a, b))", table).as_str())).await.unwrap();
+    }
+    conn.execute_sql(sql_query("INSERT INTO source VALUES (1, 'x', 'one'), (1, 'y', 'two'), (2, 'x', 'three')")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO target VALUES (1, 'x', 'one'), (1, 'y', 'old'), (2, 'y', 'gone')")).await.unwrap();
+    let options = ReconcileOptions { chunk_size: 2, delete_extra: true, ..Default::default() };
+    let report = conn.reconcile_tables("source", "target", &["a", "b"], &options).await.unwrap();
+    assert_eq!((report.missing, report.changed, report.extra), (1, 1, 1));
+    assert_eq!(report.changed_keys, vec![vec![SqlValue::Int(1), SqlValue::Text("y".to_string())]]);
+    let rows = conn.query_all(sql_query("SELECT a, b, v FROM target ORDER BY a, b")).await.unwrap();
+    let rows: Vec<(i64, String, String)> = rows.iter().map(|r| (r.get(0), r.get(1), r.get(2))).collect();
+    assert_eq!(rows, vec![(1, "x".to_string(), "one".to_string()), (1, "y".to_string(), "two".to_string()), (2, "x".to_string(), "three".to_string())]);
+
+    assert!(conn.reconcile_tables("source", "target", &[], &options).await.is_err());
+    assert!(conn.reconcile_tables("source", "target", &["missing"], &options).await.is_err());
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,80 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::recover::{cleanup, inspect, CleanupPolicy};
+use sfo_sql::sqlite::{sql_query, SqlPool, SqliteJournalMode, SqliteUriBuilder, SqlRow};
+use sfo_sql::test_util::TempSqliteDb;
+use crate::common;
+
+fn copy_with_companions(from: &std::path::Path, to: &std::path::Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let src = format!("{}{}", from.display(), suffix);
+        if std::path::Path::new(src.as_str()).exists() {
+            std::fs::copy(src.as_str(), format!("{}{}", to.display(), suffix)).unwrap();
+        }
+    }
+}
+
+// The files are copied while a writer holds an open transaction, the copy looks like what a
+// crash mid-transaction leaves behind.
+#[tokio::test]
+async fn cleanup_recovers_the_files_of_a_crashed_writer() {
+    let db = common::sqlite_db("recover_src").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (v INTEGER)")).await.unwrap();
+    for v in 0..50i64 {
+        conn.execute_sql(sql_query("INSERT INTO items (v) VALUES (?)").bind(v)).await.unwrap();
+    }
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO items (v) VALUES (1000)")).await.unwrap();
+
+    let copy = TempSqliteDb::new("recover_copy");
+    copy_with_companions(db.sqlite_path().unwrap(), copy.path());
+    let before = inspect(copy.path()).unwrap();
+    assert!(before.hot_journal);
+    assert!(before.shm.is_some());
+
+    let after = cleanup(copy.path(), &CleanupPolicy::default()).await.unwrap();
+    assert!(!after.hot_journal);
+    assert!(after.wal.map(|f| f.size == 0).unwrap_or(true));
+    assert!(after.shm.is_none());
+
+    let uri = SqliteUriBuilder::new().path(copy.path()).build();
+    let pool = SqlPool::open(uri.as_str(), 1, Some(SqliteJournalMode::Wal)).await.unwrap();
+    let row = pool.query_one(sql_query("SELECT count(*) AS c, max(v) AS m FROM items")).await.unwrap();
+    assert_eq!((row.get::<i64, _>("c"), row.get::<i64, _>("m")), (50, 49));
+    pool.raw_pool().await.close().await;
+
+    conn.rollback_transaction().await.unwrap();
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn cleanup_refuses_a_database_in_use() {
+    let db = common::sqlite_db("recover_busy").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (v INTEGER)")).await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO items (v) VALUES (1)")).await.unwrap();
+
+    let e = cleanup(db.sqlite_path().unwrap(), &CleanupPolicy::default()).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Busy);
+    assert!(inspect(db.sqlite_path().unwrap()).unwrap().shm.is_some());
+
+    conn.commit_transaction().await.unwrap();
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn cleanup_refuses_a_database_with_idle_connections() {
+    let db = common::sqlite_db("recover_idle").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (v INTEGER)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO items (v) VALUES (1)")).await.unwrap();
+    drop(conn);
+
+    let e = cleanup(db.sqlite_path().unwrap(), &CleanupPolicy::default()).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Busy);
+    assert!(inspect(db.sqlite_path().unwrap()).unwrap().shm.is_some());
+    db.finish().await;
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+use sfo_sql::sqlite::{sql_query, RetentionPolicy, SqlRow};
+use crate::common;
+
+fn policy(table: &str) -> RetentionPolicy {
+    RetentionPolicy {
+        table: table.to_string(),
+        timestamp_column: "created_at".to_string(),
+        keep: Duration::from_secs(24 * 3600),
+        batch_size: 10,
+        compact_after: None,
+    }
+}
+
+#[tokio::test]
+async fn only_expired_rows_are_removed_table_by_table() {
+    let db = common::sqlite_db("retention").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    for table in ["logs", "sessions"] {
+        conn.execute_sql(sql_query(format!("CREATE TABLE {} (id INTEGER PRIMARY KEY, created_at TEXT NOT NULL)", table).as_str())).await.unwrap();
+    }
+    // 25 logs a year old and 5 from now, sessions all from the last hour.
+    conn.execute_sql(sql_query("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 25) INSERT INTO logs (created_at) SELECT strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '-1 year') FROM n")).await.unwrap();
+    for table in ["logs", "sessions"] {
+        conn.execute_sql(sql_query(format!("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5) INSERT INTO {} (created_at) SELECT strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '-' || i || ' minutes') FROM n", table).as_str())).await.unwrap();
+    }
+    drop(conn);
+
+    let logs = RetentionPolicy { compact_after: Some(1), ..policy("logs") };
+    let report = db.pool.apply_retention(&[logs, policy("sessions"), policy("audit")]).await.unwrap();
+    let tables = report.tables.iter().map(|t| (t.table.as_str(), t.removed, t.compacted)).collect::<Vec<_>>();
+    assert_eq!(tables, vec![("logs", 25, true), ("sessions", 0, false), ("audit", 0, false)]);
+    assert_eq!(report.removed(), 25);
+    let skipped = report.skipped().map(|t| t.table.as_str()).collect::<Vec<_>>();
+    assert_eq!(skipped, vec!["audit"]);
+
+    let mut conn = db.pool.get_conn().await.unwrap();
+    for table in ["logs", "sessions"] {
+        let row = conn.query_one(sql_query(format!("SELECT count(*) FROM {}", table).as_str())).await.unwrap();
+        assert_eq!(row.get::<i64, _>(0), 5, "{}", table);
+    }
+    drop(conn);
+
+    // A second run finds nothing left to remove.
+    assert_eq!(db.pool.apply_retention(&[policy("logs"), policy("sessions")]).await.unwrap().removed(), 0);
+    db.finish().await;
+}
@@ -0,0 +1,56 @@
+use sfo_sql::sqlite::{sql_query, SampleResult, SampleStrategy, SqlValue};
+use crate::common;
+
+fn sorted_ids(sample: &SampleResult) -> Vec<i64> {
+    let mut ids = sample.rows.iter().map(|row| match row.get("id") {
+        Some(SqlValue::Int(id)) => *id,
+        other => panic!("id {:?}", other),
+    }).collect::<Vec<_>>();
+    ids.sort();
+    ids
+}
+
+// How many of the ten id deciles of 1..=10000 the sample hits.
+fn deciles(ids: &[i64]) -> usize {
+    let mut hit = [false; 10];
+    for id in ids {
+        hit[((id - 1) / 1000) as usize] = true;
+    }
+    hit.iter().filter(|h| **h).count()
+}
+
+#[tokio::test]
+async fn each_strategy_samples_the_requested_size_across_the_table() {
+    let db = common::sqlite_db("sample").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE events (id INTEGER PRIMARY KEY, code TEXT NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 10000) INSERT INTO events SELECT i, 'c' || i FROM n")).await.unwrap();
+
+    let random = conn.sample_rows("events", 100, SampleStrategy::Random).await.unwrap();
+    let key_range = conn.sample_rows("events", 100, SampleStrategy::KeyRange { key_column: "id".to_string() }).await.unwrap();
+    for sample in [&random, &key_range] {
+        let ids = sorted_ids(sample);
+        assert_eq!(ids.len(), 100);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]), "duplicates in {:?}", ids);
+        assert!(deciles(&ids) >= 8, "{:?}", ids);
+    }
+    assert_eq!(key_range.strategy, SampleStrategy::KeyRange { key_column: "id".to_string() });
+
+    let systematic = conn.sample_rows("events", 100, SampleStrategy::SystematicEveryK { key_column: "id".to_string() }).await.unwrap();
+    let ids = sorted_ids(&systematic);
+    assert_eq!(ids.len(), 100);
+    assert!(ids[0] <= 100 && ids.windows(2).all(|w| w[1] - w[0] == 100), "{:?}", ids);
+    assert!(!systematic.rows[0].contains_key("sample_rn"));
+
+    // A text key cannot be probed by range, the sample falls back to Random.
+    let fallback = conn.sample_rows("events", 50, SampleStrategy::KeyRange { key_column: "code".to_string() }).await.unwrap();
+    assert_eq!(fallback.strategy, SampleStrategy::Random);
+    assert_eq!(fallback.rows.len(), 50);
+
+    // Asking for more rows than the table has returns all of them.
+    conn.execute_sql(sql_query("DELETE FROM events WHERE id > 30")).await.unwrap();
+    let all = conn.sample_rows("events", 100, SampleStrategy::KeyRange { key_column: "id".to_string() }).await.unwrap();
+    assert_eq!(sorted_ids(&all), (1..=30).collect::<Vec<_>>());
+    drop(conn);
+    db.finish().await;
+}
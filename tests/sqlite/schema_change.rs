@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, RetryPolicy};
+use sfo_sql::sqlx::Row;
+use sfo_sql::test_util::{Fault, FaultInjector, FaultMatcher};
+use crate::common;
+
+fn quick_policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy { max_attempts, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) }
+}
+
+#[tokio::test]
+async fn alter_while_another_task_selects() {
+    let db = common::sqlite_db("schema_change").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL)")).await.unwrap();
+    for name in ["a", "b", "c"] {
+        conn.execute_sql(sql_query("INSERT INTO items (name) VALUES (?)").bind(name)).await.unwrap();
+    }
+    drop(conn);
+
+    // Selects on a connection of the pool per round, until it sees the new column.
+    let stop = Arc::new(AtomicBool::new(false));
+    let pool = db.pool.clone();
+    let done = stop.clone();
+    let reader = tokio::spawn(async move {
+        let mut rounds = 0u32;
+        while !done.load(Ordering::SeqCst) {
+            let rows = pool.with_retry(&quick_policy(5), |conn| Box::pin(async move {
+                conn.query_all(sql_query("SELECT * FROM items ORDER BY id")).await
+            })).await?;
+            assert_eq!(rows.len(), 3);
+            rounds += 1;
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        Ok::<_, sfo_sql::errors::SqlError>(rounds)
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    db.pool.with_schema_change(Duration::from_secs(10), |conn| Box::pin(async move {
+        conn.execute_sql(sql_query("ALTER TABLE items ADD COLUMN note TEXT NOT NULL DEFAULT 'n'")).await?;
+        Ok(())
+    })).await.unwrap();
+    let row = db.pool.query_one(sql_query("SELECT * FROM items ORDER BY id")).await.unwrap();
+    assert_eq!(row.len(), 3);
+    assert_eq!(row.get::<String, _>("note"), "n");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stop.store(true, Ordering::SeqCst);
+
+    // No select failed, before, during or after the change.
+    let rounds = reader.await.unwrap().unwrap();
+    assert!(rounds > 0);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn schema_changed_is_retried() {
+    let db = common::sqlite_db("schema_changed_retry").await.unwrap();
+    let injector = FaultInjector::new();
+    let pool = db.pool.clone().with_fault_injector(injector.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE items (id INTEGER PRIMARY KEY)")).await.unwrap();
+
+    // SQLITE_SCHEMA.
+    injector.add(FaultMatcher::SqlContains("FROM items".to_string()), Fault::Database("17".to_string()), 3);
+    let e = conn.query_all(sql_query("SELECT id FROM items")).await.err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::SchemaChanged);
+    assert!(e.code().is_retryable());
+    drop(conn);
+
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counter = attempts.clone();
+    let rows = pool.with_retry(&quick_policy(3), move |conn| {
+        let counter = counter.clone();
+        Box::pin(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            conn.query_all(sql_query("SELECT id FROM items")).await
+        })
+    }).await.unwrap();
+    assert!(rows.is_empty());
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    db.finish().await;
+}
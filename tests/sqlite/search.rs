@@ -0,0 +1,36 @@
+use sfo_sql::sqlite::{sql_query, SqlConnection, TextSearchOptions};
+use crate::common;
+
+async fn ids(conn: &mut SqlConnection, query: &str) -> Vec<i64> {
+    let hits = conn.search("articles", query, 10).await.unwrap();
+    assert!(hits.windows(2).all(|w| w[0].1 >= w[1].1), "{:?}", hits);
+    hits.iter().map(|(id, _)| *id).collect()
+}
+
+#[tokio::test]
+async fn hits_are_ranked_by_relevance() {
+    let db = common::sqlite_db("search_rank").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE articles (id INTEGER PRIMARY KEY, title TEXT, body TEXT)")).await.unwrap();
+    conn.create_search_index("articles", &["title", "body"], &TextSearchOptions { id_column: Some("id".to_string()), ..Default::default() }).await.unwrap();
+    for (id, title, body) in [
+        (1, "Gardening", "Tomatoes need sun, water and a little patience over the long summer months"),
+        (2, "Rust", "Rust ownership and rust borrowing, rust all the way"),
+        (3, "Languages", "A long survey of languages where rust comes up once among many other words and topics"),
+        (4, "Tools", "Rust tooling: cargo builds rust crates"),
+    ] {
+        conn.execute_sql(sql_query("INSERT INTO articles (id, title, body) VALUES (?, ?, ?)").bind(id).bind(title).bind(body)).await.unwrap();
+    }
+
+    assert_eq!(ids(&mut conn, "rust").await, vec![2, 4, 3]);
+    // Every term has to match.
+    assert_eq!(ids(&mut conn, "rust cargo").await, vec![4]);
+    assert!(ids(&mut conn, "\"  ").await.is_empty());
+
+    // The index follows updates and deletes of the table.
+    conn.execute_sql(sql_query("UPDATE articles SET body = 'Rust rust rust rust rust rust' WHERE id = 3")).await.unwrap();
+    conn.execute_sql(sql_query("DELETE FROM articles WHERE id = 2")).await.unwrap();
+    assert_eq!(ids(&mut conn, "rust").await, vec![3, 4]);
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,59 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SeedConflict, SeedSet, SeedTable, SeedTableReport, SqlConnection, SqlRow, SqlValue};
+use crate::common;
+
+fn roles(conflict: SeedConflict, rows: &[(&str, i64)]) -> SeedSet {
+    let rows = rows.iter()
+        .map(|(name, level)| [("name".to_string(), SqlValue::Text(name.to_string())), ("level".to_string(), SqlValue::Int(*level))].into_iter().collect())
+        .collect();
+    SeedSet { tables: vec![SeedTable { table: "roles".to_string(), key_columns: vec!["name".to_string()], conflict, rows }] }
+}
+
+fn report(inserted: u64, updated: u64, skipped: u64) -> Vec<SeedTableReport> {
+    vec![SeedTableReport { table: "roles".to_string(), inserted, updated, skipped }]
+}
+
+async fn levels(conn: &mut SqlConnection) -> Vec<(String, i64)> {
+    let rows = conn.query_all(sql_query("SELECT name, level FROM roles ORDER BY name")).await.unwrap();
+    rows.iter().map(|row| (row.get("name"), row.get("level"))).collect()
+}
+
+async fn seeded(prefix: &str, conflict: SeedConflict) -> (common::TestDb<sfo_sql::sqlite::SqlPool>, SqlConnection) {
+    let db = common::sqlite_db(prefix).await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE roles (name TEXT PRIMARY KEY, level INTEGER NOT NULL)")).await.unwrap();
+    let first = conn.apply_seed(&roles(conflict, &[("admin", 1), ("user", 2)])).await.unwrap();
+    assert_eq!(first.tables, report(2, 0, 0));
+    (db, conn)
+}
+
+#[tokio::test]
+async fn skip_keeps_the_rows_already_there() {
+    let (db, mut conn) = seeded("seed_skip", SeedConflict::Skip).await;
+    let second = conn.apply_seed(&roles(SeedConflict::Skip, &[("admin", 10), ("user", 20), ("guest", 30)])).await.unwrap();
+    assert_eq!(second.tables, report(1, 0, 2));
+    assert_eq!(levels(&mut conn).await, vec![("admin".to_string(), 1), ("guest".to_string(), 30), ("user".to_string(), 2)]);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn update_overwrites_the_rows_already_there() {
+    let (db, mut conn) = seeded("seed_update", SeedConflict::Update).await;
+    let second = conn.apply_seed(&roles(SeedConflict::Update, &[("admin", 10), ("user", 20), ("guest", 30)])).await.unwrap();
+    assert_eq!(second.tables, report(1, 2, 0));
+    assert_eq!(levels(&mut conn).await, vec![("admin".to_string(), 10), ("guest".to_string(), 30), ("user".to_string(), 20)]);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn fail_rejects_the_second_run_as_a_whole() {
+    let (db, mut conn) = seeded("seed_fail", SeedConflict::Fail).await;
+    // guest goes in before admin collides, and is rolled back with the rest.
+    let e = conn.apply_seed(&roles(SeedConflict::Fail, &[("guest", 30), ("admin", 10)])).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    assert_eq!(levels(&mut conn).await, vec![("admin".to_string(), 1), ("user".to_string(), 2)]);
+    drop(conn);
+    db.finish().await;
+}
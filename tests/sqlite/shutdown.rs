@@ -0,0 +1,77 @@
+use std::time::Duration;
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlPool};
+use crate::common;
+
+#[tokio::test]
+async fn shutdown_rejects_new_work_and_waits_for_the_borrower() {
+    let db = common::sqlite_db("shutdown").await.unwrap();
+    let clone = db.pool.clone();
+    let borrower = db.pool.lease_conn("nightly report").await.unwrap();
+
+    clone.begin_shutdown();
+    assert!(db.pool.is_shutting_down());
+    assert_eq!(db.pool.get_conn().await.err().unwrap().code(), SqlErrorCode::ShuttingDown);
+
+    let e = db.pool.await_idle(Duration::from_millis(50)).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Timeout);
+    assert!(format!("{:?}", e).contains("nightly report"));
+
+    let waiter = tokio::spawn({
+        let pool = db.pool.clone();
+        async move { pool.await_idle(Duration::from_secs(5)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    borrower.release().await;
+    waiter.await.unwrap().unwrap();
+    db.finish().await;
+}
+
+#[test]
+fn connection_dropped_after_the_runtime_shut_down() {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let (db, conn) = runtime.block_on(async {
+        let db = common::sqlite_db("dropped_late").await.unwrap();
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("CREATE TABLE jobs (id INTEGER PRIMARY KEY)")).await.unwrap();
+        conn.begin_transaction().await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (1)")).await.unwrap();
+        (db, conn)
+    });
+    drop(runtime);
+    drop(conn);
+
+    // The open transaction was rolled back and the file is not left locked.
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let uri = format!("sqlite://{}", db.sqlite_path().unwrap().display());
+        let pool = SqlPool::open(uri.as_str(), 1, None).await.unwrap();
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO jobs (id) VALUES (2)")).await.unwrap();
+        let rows = conn.query_all(sql_query("SELECT id FROM jobs")).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        drop(conn);
+        pool.raw_pool().await.close().await;
+        db.finish().await;
+    });
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn close_blocking_on_a_current_thread_runtime() {
+    let db = common::sqlite_db("close_current").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE jobs (id INTEGER PRIMARY KEY)")).await.unwrap();
+    drop(conn);
+
+    assert!(db.pool.close_blocking(Duration::from_secs(5)));
+    assert!(db.pool.raw_pool().await.is_closed());
+}
+
+#[test]
+fn close_blocking_outside_a_runtime() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let db = runtime.block_on(async { common::sqlite_db("close_outside").await.unwrap() });
+    drop(runtime);
+
+    assert!(db.pool.close_blocking(Duration::from_secs(5)));
+}
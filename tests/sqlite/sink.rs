@@ -0,0 +1,49 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, InsertSink, InsertSinkOptions, SeedConflict, SqlRow, SqlValue};
+use crate::common;
+
+#[tokio::test]
+async fn hundred_thousand_rows_through_a_small_buffer() {
+    let db = common::sqlite_db("sink_bulk").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT)")).await.unwrap();
+    drop(conn);
+
+    let options = InsertSinkOptions { batch_size: 250, transactional: true, ..Default::default() };
+    let mut sink = InsertSink::new(&db.pool, "events", &["id", "name"], options).unwrap();
+    for id in 0..100_000i64 {
+        sink.send(vec![SqlValue::Int(id), SqlValue::Text(format!("event {}", id))]).await.unwrap();
+        // send flushes the batch it fills, the buffer never holds more than batch_size rows.
+        assert!(sink.buffered() < 250);
+    }
+    let report = sink.close().await;
+    assert!(report.errors.is_empty());
+    assert_eq!((report.rows_written, report.batches), (100_000, 400));
+    let row = db.pool.query_one(sql_query("SELECT count(*) AS c FROM events")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("c"), 100_000);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn upsert_needs_key_columns_it_writes() {
+    let db = common::sqlite_db("sink_upsert").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE users (name TEXT PRIMARY KEY, age INTEGER)")).await.unwrap();
+
+    let update = InsertSinkOptions { conflict: SeedConflict::Update, ..Default::default() };
+    let e = InsertSink::new(&mut conn, "users", &["name", "age"], update.clone()).err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::ParameterMismatch);
+    let e = InsertSink::new(&mut conn, "users", &["name", "age"], InsertSinkOptions { key_columns: vec!["id".to_string()], ..update.clone() }).err().unwrap();
+    assert_eq!(e.code(), SqlErrorCode::ParameterMismatch);
+
+    let mut sink = InsertSink::new(&mut conn, "users", &["name", "age"], InsertSinkOptions { key_columns: vec!["name".to_string()], ..update }).unwrap();
+    sink.send(vec![SqlValue::Text("alice".to_string()), SqlValue::Int(30)]).await.unwrap();
+    sink.flush().await;
+    sink.send(vec![SqlValue::Text("alice".to_string()), SqlValue::Int(31)]).await.unwrap();
+    let report = sink.close().await;
+    assert!(report.errors.is_empty());
+    let row = conn.query_one(sql_query("SELECT age FROM users WHERE name = 'alice'")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("age"), 31);
+    drop(conn);
+    db.finish().await;
+}
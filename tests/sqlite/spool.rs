@@ -0,0 +1,66 @@
+use sfo_sql::sqlite::{sql_query, SpoolOptions, SqlValue};
+use sfo_sql::test_util::unique_test_name;
+use crate::common;
+
+const ROWS: i64 = 100_000;
+
+fn files_in(dir: &std::path::Path) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+#[tokio::test]
+async fn a_large_result_is_read_back_by_index_and_its_file_removed() {
+    let db = common::sqlite_db("spool").await.unwrap();
+    let dir = std::env::temp_dir().join(unique_test_name("spool_dir"));
+    std::fs::create_dir(&dir).unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT NOT NULL, score REAL, payload BLOB)")).await.unwrap();
+    conn.execute_sql(sql_query(format!("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {}) \
+        INSERT INTO events SELECT i, 'event ' || i, CASE WHEN i % 3 = 0 THEN NULL ELSE i / 4.0 END, randomblob(i % 16) FROM n", ROWS).as_str())).await.unwrap();
+
+    let spooled = conn.spool_query(sql_query("SELECT id, name, score, length(payload) AS size FROM events ORDER BY id"), &dir, SpoolOptions::default()).await.unwrap();
+    assert_eq!(spooled.len(), ROWS as usize);
+    assert_eq!(spooled.columns(), ["id", "name", "score", "size"]);
+    assert!(spooled.path().starts_with(&dir) && spooled.path().exists());
+    assert_eq!(spooled.bytes(), std::fs::metadata(spooled.path()).unwrap().len());
+
+    // Rows in an order unrelated to the file, read back as they were queried.
+    let mut index = 7usize;
+    for _ in 0..2000 {
+        index = (index * 48271 + 11) % ROWS as usize;
+        let row = spooled.get(index).unwrap().unwrap();
+        let id = index as i64 + 1;
+        assert_eq!(row["id"], SqlValue::Int(id));
+        assert_eq!(row["name"], SqlValue::Text(format!("event {}", id)));
+        if id % 3 == 0 {
+            assert!(matches!(row["score"], SqlValue::Null(_)), "{:?}", row);
+        } else {
+            assert_eq!(row["score"], SqlValue::Float(id as f64 / 4.0));
+        }
+        assert_eq!(row["size"], SqlValue::Int(id % 16));
+    }
+    assert_eq!(spooled.get(ROWS as usize).unwrap(), None);
+
+    // Iterating does not disturb get.
+    let mut iter = spooled.iter().unwrap();
+    assert_eq!(iter.next().unwrap().unwrap()["id"], SqlValue::Int(1));
+    assert_eq!(spooled.get(500).unwrap().unwrap()["id"], SqlValue::Int(501));
+    assert_eq!(iter.next().unwrap().unwrap()["id"], SqlValue::Int(2));
+    assert_eq!(iter.count(), ROWS as usize - 2);
+
+    drop(spooled);
+    assert_eq!(files_in(&dir), 0);
+
+    // A spool outgrowing max_bytes fails and leaves no file behind.
+    let options = SpoolOptions { max_bytes: Some(64 * 1024) };
+    assert!(conn.spool_query(sql_query("SELECT * FROM events"), &dir, options).await.is_err());
+    assert_eq!(files_in(&dir), 0);
+    let empty = conn.spool_query(sql_query("SELECT * FROM events WHERE id < 0"), &dir, options).await.unwrap();
+    assert!(empty.is_empty() && empty.columns().is_empty());
+    drop(empty);
+    assert_eq!(files_in(&dir), 0);
+
+    std::fs::remove_dir(&dir).unwrap();
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,61 @@
+use sfo_sql::sqlite::{sql_query, Migration, SqlRow, StartupMigrations, StartupPolicy};
+use crate::common;
+
+fn migrations(apply_pending: bool) -> StartupMigrations {
+    StartupMigrations {
+        table: "schema_migrations".to_string(),
+        expected_version: 2,
+        migrations: vec![
+            Migration { version: 1, statements: vec!["CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)".to_string()] },
+            Migration { version: 2, statements: vec!["ALTER TABLE notes ADD COLUMN author TEXT".to_string()] },
+        ],
+        apply_pending,
+    }
+}
+
+#[tokio::test]
+async fn every_problem_is_reported_at_once() {
+    let db = common::sqlite_db("startup_report").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    for sql in [
+        "CREATE TABLE schema_migrations (version BIGINT NOT NULL PRIMARY KEY)",
+        "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)",
+        "INSERT INTO schema_migrations (version) VALUES (1)",
+    ] {
+        conn.execute_sql(sql_query(sql)).await.unwrap();
+    }
+    drop(conn);
+
+    // The pool runs in wal mode and the database misses migration 2.
+    let policy = StartupPolicy {
+        check_connectivity: true,
+        journal_mode: Some("delete".to_string()),
+        migrations: Some(migrations(false)),
+        ..Default::default()
+    };
+    let report = db.pool.startup_report(&policy).await;
+    assert!(!report.is_ok());
+    let problems = report.problems().map(|c| c.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(problems, vec!["journal_mode", "migrations"]);
+    assert_eq!(report.migration_version, Some(1));
+    assert!(report.applied_migrations.is_empty());
+    let e = db.pool.startup_check(&policy).await.unwrap_err();
+    let msg = format!("{:?}", e);
+    assert!(msg.contains("journal_mode wal, expected delete") && msg.contains("schema_migrations at version 1, expected 2"), "{}", msg);
+
+    // Allowed to, the check applies the missing migration and passes.
+    let policy = StartupPolicy {
+        check_connectivity: true,
+        journal_mode: Some("wal".to_string()),
+        migrations: Some(migrations(true)),
+        ..Default::default()
+    };
+    let report = db.pool.startup_check(&policy).await.unwrap();
+    assert_eq!(report.applied_migrations, vec![2]);
+    assert_eq!(report.migration_version, Some(2));
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let row = conn.query_one(sql_query("SELECT count(*) FROM pragma_table_info('notes') WHERE name = 'author'")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 1);
+    drop(conn);
+    db.finish().await;
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use sfo_sql::sqlite::{sql_query, StatementJournal, StatementStat};
+use crate::common;
+
+fn find<'a>(stats: &'a [StatementStat], statement: &str) -> &'a StatementStat {
+    stats.iter().find(|s| s.statement == statement).unwrap_or_else(|| panic!("{} not in {:?}", statement, stats))
+}
+
+#[tokio::test]
+async fn statements_differing_in_literals_share_one_entry() {
+    let db = common::sqlite_db("stats_aggregate").await.unwrap();
+    let pool = db.pool.clone().with_statement_stats(16);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")).await.unwrap();
+    for id in 1..=4 {
+        conn.execute_sql(sql_query(format!("INSERT INTO t VALUES ({}, 'n{}')", id, id).as_str())).await.unwrap();
+    }
+    conn.query_all(sql_query("SELECT name FROM t WHERE id = 5")).await.unwrap();
+    conn.query_all(sql_query("SELECT name FROM t WHERE id = 7")).await.unwrap();
+    conn.query_all(sql_query("SELECT   name FROM t\n WHERE id = 1 -- by key")).await.unwrap();
+    conn.query_all(sql_query("SELECT name FROM t WHERE id > ?").bind(1)).await.unwrap();
+    assert!(conn.query_all(sql_query("SELECT name FROM missing WHERE id = 1")).await.is_err());
+    drop(conn);
+
+    let stats = pool.statement_stats();
+    // rows counts the rows returned, none for an insert.
+    let insert = find(&stats, "INSERT INTO t VALUES (?, ?)");
+    assert_eq!((insert.count, insert.errors, insert.rows), (4, 0, 0));
+    let by_id = find(&stats, "SELECT name FROM t WHERE id = ?");
+    assert_eq!((by_id.count, by_id.errors, by_id.rows), (3, 0, 1));
+    assert!(by_id.max <= by_id.total);
+    let range = find(&stats, "SELECT name FROM t WHERE id > ?");
+    assert_eq!((range.count, range.rows), (1, 3));
+    let failed = find(&stats, "SELECT name FROM missing WHERE id = ?");
+    assert_eq!((failed.count, failed.errors), (1, 1));
+    // Slowest first.
+    assert!(stats.windows(2).all(|w| w[0].total >= w[1].total));
+
+    pool.reset_statement_stats();
+    assert!(pool.statement_stats().is_empty());
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn at_most_top_n_statements_are_kept_and_busy_ones_stay() {
+    let db = common::sqlite_db("stats_top_n").await.unwrap();
+    let pool = db.pool.clone().with_statement_stats(3);
+    let mut conn = pool.get_conn().await.unwrap();
+    for _ in 0..20 {
+        conn.query_all(sql_query("SELECT 1")).await.unwrap();
+    }
+    // Each one-off statement replaces the lightest entry, never the busy one.
+    for i in 0..10 {
+        conn.query_all(sql_query(format!("SELECT {} AS c{}", i, i).as_str())).await.unwrap();
+    }
+    let stats = pool.statement_stats();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(find(&stats, "SELECT ?").count, 20);
+
+    // A statement repeated between one-off ones works its way in and stays.
+    for i in 0..10 {
+        conn.query_all(sql_query("SELECT 2 + 2 AS four")).await.unwrap();
+        conn.query_all(sql_query(format!("SELECT 0 AS other{}", i).as_str())).await.unwrap();
+    }
+    drop(conn);
+    let stats = pool.statement_stats();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(find(&stats, "SELECT ?").count, 20);
+    assert_eq!(find(&stats, "SELECT ? + ? AS four").count, 10);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn tagged_statements_are_kept_and_observed_under_their_tag() {
+    let db = common::sqlite_db("stats_tagged").await.unwrap();
+    let journal = Arc::new(StatementJournal::new(16));
+    let pool = db.pool.clone().with_statement_stats(16).with_observer(journal.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")).await.unwrap();
+    // Statements built differently count for the one operation.
+    for id in 1..=3 {
+        let sql = format!("INSERT INTO t (id, name) VALUES ({}, ?){}", id, if id == 3 { " -- last" } else { "" });
+        conn.execute_sql_tagged("add_item", sql_query(sql.as_str()).bind("n")).await.unwrap();
+    }
+    conn.query_all_tagged("list_items", sql_query("SELECT name FROM t")).await.unwrap();
+    conn.query_one_tagged("list_items", sql_query("SELECT name FROM t WHERE id = 1")).await.unwrap();
+    assert!(conn.query_optional_tagged("missing_item", sql_query("SELECT name FROM missing")).await.is_err());
+    conn.query_all(sql_query("SELECT name FROM t")).await.unwrap();
+    drop(conn);
+
+    let stats = pool.statement_stats();
+    let tagged = |tag: &str| stats.iter().find(|s| s.tag.as_deref() == Some(tag)).unwrap_or_else(|| panic!("{} not in {:?}", tag, stats));
+    let add = tagged("add_item");
+    assert_eq!((add.count, add.errors, add.statement.as_str()), (3, 0, "INSERT INTO t (id, name) VALUES (?, ?)"));
+    let list = tagged("list_items");
+    assert_eq!((list.count, list.rows), (2, 4));
+    assert_eq!((tagged("missing_item").count, tagged("missing_item").errors), (1, 1));
+    // The untagged run of the same text has an entry of its own.
+    let untagged = stats.iter().find(|s| s.tag.is_none() && s.statement == "SELECT name FROM t").unwrap();
+    assert_eq!(untagged.count, 1);
+
+    let events = journal.events();
+    let tags: Vec<Option<&str>> = events.iter().map(|e| e.tag.as_deref()).collect();
+    assert_eq!(tags, vec![None, Some("add_item"), Some("add_item"), Some("add_item"), Some("list_items"),
+                          Some("list_items"), Some("missing_item"), None]);
+    db.finish().await;
+}
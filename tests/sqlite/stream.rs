@@ -0,0 +1,49 @@
+use sfo_sql::sqlite::sql_query;
+use sfo_sql::sqlx::Row;
+use crate::common;
+
+const ROWS: i64 = 100_000;
+
+#[tokio::test]
+async fn streams_100k_rows_inside_a_transaction() {
+    let db = common::sqlite_db("stream_100k").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE numbers (id INTEGER PRIMARY KEY)")).await.unwrap();
+    conn.execute_sql(sql_query("WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < ?) INSERT INTO numbers (id) SELECT x FROM n").bind(ROWS)).await.unwrap();
+
+    // The stream sees the transaction's own uncommitted row.
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO numbers (id) VALUES (?)").bind(ROWS + 1)).await.unwrap();
+    let mut stream = conn.query_stream(sql_query("SELECT id FROM numbers ORDER BY id")).unwrap();
+    let mut count = 0;
+    let mut last = 0;
+    while let Some(row) = stream.next().await {
+        let id: i64 = row.unwrap().get(0);
+        assert_eq!(id, last + 1);
+        last = id;
+        count += 1;
+    }
+    drop(stream);
+    assert_eq!(count, ROWS + 1);
+    conn.rollback_transaction().await.unwrap();
+    assert_eq!(conn.query_scalar::<i64>(sql_query("SELECT count(*) FROM numbers")).await.unwrap(), ROWS);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn stream_yields_rows_before_the_query_ends() {
+    let db = common::sqlite_db("stream_incremental").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    // The query never ends, so every row read came out of a result that was never collected.
+    let mut stream = conn.query_stream(sql_query("WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) SELECT x FROM n")).unwrap();
+    for expected in 1..=ROWS {
+        let x: i64 = stream.next().await.unwrap().unwrap().get(0);
+        assert_eq!(x, expected);
+    }
+    // Dropping it stops the query and leaves the connection usable.
+    drop(stream);
+    assert_eq!(conn.query_scalar::<i64>(sql_query("SELECT 1")).await.unwrap(), 1);
+    drop(conn);
+    db.finish().await;
+}
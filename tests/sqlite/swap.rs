@@ -0,0 +1,96 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqlRow};
+use crate::common;
+
+async fn setup(conn: &mut SqlConnection) {
+    for sql in [
+        "CREATE TABLE parent (id INTEGER PRIMARY KEY, name TEXT)",
+        "CREATE TABLE child (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES parent (id) ON DELETE CASCADE)",
+        "CREATE TABLE parent_next (id INTEGER PRIMARY KEY, name TEXT)",
+        "INSERT INTO parent VALUES (1, 'old one'), (2, 'old two')",
+        "INSERT INTO child VALUES (10, 1), (20, 2)",
+    ] {
+        conn.execute_sql(sql_query(sql)).await.unwrap();
+    }
+}
+
+async fn child_sql(conn: &mut SqlConnection) -> String {
+    conn.query_one(sql_query("SELECT sql FROM sqlite_master WHERE name = 'child'")).await.unwrap().get(0)
+}
+
+#[tokio::test]
+async fn child_tables_keep_referencing_the_swapped_name() {
+    let db = common::sqlite_db("swap_children").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    setup(&mut conn).await;
+    conn.execute_sql(sql_query("INSERT INTO parent_next VALUES (1, 'new one'), (2, 'new two'), (3, 'new three')")).await.unwrap();
+    let before = child_sql(&mut conn).await;
+
+    conn.swap_tables("parent", "parent_next", None).await.unwrap();
+    assert_eq!(child_sql(&mut conn).await, before);
+    // Dropping the old table did not cascade to the children.
+    let row = conn.query_one(sql_query("SELECT count(*) FROM child JOIN parent ON parent.id = child.parent_id WHERE parent.name LIKE 'new%'")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 2);
+    assert!(!conn.is_table_exist("parent_swap_old").await.unwrap());
+    // Enforcement is back on and applies to the new parent.
+    let row = conn.query_one(sql_query("PRAGMA foreign_keys")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 1);
+    assert!(conn.execute_sql(sql_query("INSERT INTO child VALUES (30, 4)")).await.is_err());
+    conn.execute_sql(sql_query("DELETE FROM parent WHERE id = 1")).await.unwrap();
+    let row = conn.query_one(sql_query("SELECT count(*) FROM child")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 1);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn a_replacement_missing_referenced_keys_is_rolled_back() {
+    let db = common::sqlite_db("swap_missing_keys").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    setup(&mut conn).await;
+    conn.execute_sql(sql_query("INSERT INTO parent_next VALUES (1, 'new one')")).await.unwrap();
+
+    let e = conn.swap_tables("parent", "parent_next", Some("parent_old")).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Failed);
+    assert!(format!("{:?}", e).contains("1 rows of child"), "{:?}", e);
+    for table in ["parent", "parent_next"] {
+        assert!(conn.is_table_exist(table).await.unwrap());
+    }
+    assert!(!conn.is_table_exist("parent_old").await.unwrap());
+    let row = conn.query_one(sql_query("SELECT name FROM parent WHERE id = 2")).await.unwrap();
+    assert_eq!(row.get::<String, _>(0), "old two");
+    let row = conn.query_one(sql_query("PRAGMA foreign_keys")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 1);
+
+    // Kept under its new name, the old table is no longer what the children reference.
+    conn.execute_sql(sql_query("INSERT INTO parent_next VALUES (2, 'new two')")).await.unwrap();
+    conn.swap_tables("parent", "parent_next", Some("parent_old")).await.unwrap();
+    assert_eq!(conn.query_one(sql_query("SELECT count(*) FROM parent_old")).await.unwrap().get::<i64, _>(0), 2);
+    conn.execute_sql(sql_query("DELETE FROM parent_old")).await.unwrap();
+    assert_eq!(conn.query_one(sql_query("SELECT count(*) FROM child")).await.unwrap().get::<i64, _>(0), 2);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn a_swap_inside_an_open_transaction_is_refused() {
+    let db = common::sqlite_db("swap_in_transaction").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    setup(&mut conn).await;
+    conn.execute_sql(sql_query("INSERT INTO parent_next VALUES (1, 'new one'), (2, 'new two')")).await.unwrap();
+    let before = child_sql(&mut conn).await;
+
+    conn.begin_transaction().await.unwrap();
+    let e = conn.swap_tables("parent", "parent_next", None).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::NotInTransaction);
+    conn.commit_transaction().await.unwrap();
+    for table in ["parent", "parent_next"] {
+        assert!(conn.is_table_exist(table).await.unwrap());
+    }
+    assert_eq!(child_sql(&mut conn).await, before);
+
+    conn.swap_tables("parent", "parent_next", None).await.unwrap();
+    assert_eq!(child_sql(&mut conn).await, before);
+    drop(conn);
+    db.finish().await;
+}
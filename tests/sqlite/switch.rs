@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, SqlPool, SqlRow, SwitchablePool};
+use sfo_sql::test_util::TempSqliteDb;
+use crate::common;
+
+async fn create_hits(pool: &SqlPool) {
+    pool.get_conn().await.unwrap().execute_sql(sql_query("CREATE TABLE hits (task INTEGER, n INTEGER)")).await.unwrap();
+}
+
+async fn hits(pool: &SqlPool) -> i64 {
+    pool.query_one(sql_query("SELECT count(*) FROM hits")).await.unwrap().get(0)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn acquires_during_a_switch_all_succeed() {
+    let old = common::sqlite_db("switch_concurrent_old").await.unwrap();
+    let new = common::sqlite_db("switch_concurrent_new").await.unwrap();
+    create_hits(&old.pool).await;
+    create_hits(&new.pool).await;
+    let switchable = Arc::new(SwitchablePool::new(old.pool.clone(), Duration::from_secs(60)));
+    switchable.prepare_standby(new.pool.clone()).await.unwrap();
+
+    let mut tasks = Vec::new();
+    for task in 0..8i64 {
+        let switchable = switchable.clone();
+        tasks.push(tokio::spawn(async move {
+            for n in 0..50i64 {
+                let mut conn = switchable.get_conn().await?;
+                conn.execute_sql(sql_query("INSERT INTO hits VALUES (?, ?)").bind(task).bind(n)).await?;
+                drop(conn);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            Ok::<_, sfo_sql::errors::SqlError>(())
+        }));
+    }
+    tokio::time::sleep(Duration::from_millis(15)).await;
+    switchable.switch_over(Duration::from_secs(5)).await.unwrap();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    assert!(old.pool.is_shutting_down());
+    assert_eq!(old.pool.get_conn().await.err().unwrap().code(), SqlErrorCode::ShuttingDown);
+    let after = hits(&new.pool).await;
+    // Rolling back reopens the old pool for new work.
+    switchable.rollback_switch().unwrap();
+    assert!(!old.pool.is_shutting_down());
+    let before = hits(&old.pool).await;
+    assert_eq!(before + after, 400);
+    assert!(before > 0 && after > 0, "{} before and {} after the switch", before, after);
+    assert!(switchable.has_standby());
+    old.finish().await;
+    new.finish().await;
+}
+
+#[tokio::test]
+async fn switch_over_waits_for_connections_on_the_old_pool() {
+    let old = common::sqlite_db("switch_drain_old").await.unwrap();
+    let new = common::sqlite_db("switch_drain_new").await.unwrap();
+    create_hits(&old.pool).await;
+    let switchable = SwitchablePool::new(old.pool.clone(), Duration::from_secs(60));
+    let mut held = switchable.get_conn().await.unwrap();
+
+    switchable.prepare_standby(new.pool.clone()).await.unwrap();
+    let e = switchable.switch_over(Duration::from_millis(50)).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Timeout);
+    // Routing moved anyway, the held connection keeps working against the old pool.
+    assert_eq!(switchable.current().target().uri, new.pool.target().uri);
+    held.execute_sql(sql_query("INSERT INTO hits VALUES (1, 1)")).await.unwrap();
+    drop(held);
+    switchable.rollback_switch().unwrap();
+    assert_eq!(hits(&old.pool).await, 1);
+
+    old.finish().await;
+    new.finish().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn switch_over_waits_for_acquires_that_read_the_old_pool() {
+    let file = TempSqliteDb::new("switch_pending_old");
+    let old = SqlPool::open(file.uri().as_str(), 1, None).await.unwrap();
+    let new = common::sqlite_db("switch_pending_new").await.unwrap();
+    create_hits(&old).await;
+    let switchable = Arc::new(SwitchablePool::new(old.clone(), Duration::from_secs(60)));
+    switchable.prepare_standby(new.pool.clone()).await.unwrap();
+    let held = switchable.get_conn().await.unwrap();
+
+    // Waits inside the old pool for its only connection.
+    let done = Arc::new(AtomicBool::new(false));
+    let waiter = tokio::spawn({
+        let (switchable, done) = (switchable.clone(), done.clone());
+        async move {
+            let mut conn = switchable.get_conn().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            conn.execute_sql(sql_query("INSERT INTO hits VALUES (1, 1)")).await.unwrap();
+            done.store(true, Ordering::SeqCst);
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let switch = tokio::spawn({
+        let switchable = switchable.clone();
+        async move { switchable.switch_over(Duration::from_secs(5)).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(held);
+
+    switch.await.unwrap().unwrap();
+    assert!(done.load(Ordering::SeqCst));
+    waiter.await.unwrap();
+    switchable.rollback_switch().unwrap();
+    assert_eq!(hits(&old).await, 1);
+    old.close().await;
+    new.finish().await;
+}
@@ -0,0 +1,317 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, RetryPolicy, SqlPool, SqlRow, TransactionDropPolicy};
+use sfo_sql::test_util::{Fault, FaultInjector, FaultMatcher};
+use crate::common;
+
+fn quick_policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy { max_attempts, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) }
+}
+
+#[tokio::test]
+async fn retrying_transaction_succeeds_on_the_third_attempt() {
+    let db = common::sqlite_db("retry_tx").await.unwrap();
+    let injector = FaultInjector::new();
+    injector.add(FaultMatcher::SqlContains("INSERT INTO retried".to_string()), Fault::Busy, 2);
+    let pool = db.pool.clone().with_fault_injector(injector.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE retried (v INTEGER)")).await.unwrap();
+
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counter = attempts.clone();
+    let ret = conn.with_retrying_transaction(&quick_policy(3), move |conn| {
+        let counter = counter.clone();
+        Box::pin(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            conn.execute_sql(sql_query("INSERT INTO retried (v) VALUES (1)")).await?;
+            Ok(7)
+        })
+    }).await.unwrap();
+    assert_eq!(ret, 7);
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    assert_eq!(injector.injected(), 2);
+    let row = conn.query_one(sql_query("SELECT count(*) AS c FROM retried")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("c"), 1);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn retrying_transaction_surfaces_the_last_error_once_exhausted() {
+    let db = common::sqlite_db("retry_tx_exhausted").await.unwrap();
+    let injector = FaultInjector::new();
+    injector.add(FaultMatcher::SqlContains("INSERT INTO retried".to_string()), Fault::Busy, 5);
+    let pool = db.pool.clone().with_fault_injector(injector);
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE retried (v INTEGER)")).await.unwrap();
+
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counter = attempts.clone();
+    let e = conn.with_retrying_transaction(&quick_policy(3), move |conn| {
+        let counter = counter.clone();
+        Box::pin(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            conn.execute_sql(sql_query("INSERT INTO retried (v) VALUES (1)")).await?;
+            Ok(())
+        })
+    }).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::Busy);
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    let row = conn.query_one(sql_query("SELECT count(*) AS c FROM retried")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("c"), 0);
+    drop(conn);
+    db.finish().await;
+}
+
+type Fired = Arc<std::sync::Mutex<Vec<&'static str>>>;
+
+// Callback recording its name in fired when it runs.
+fn record(fired: &Fired, name: &'static str) -> impl FnOnce() -> std::future::Ready<()> + Send + 'static {
+    let fired = fired.clone();
+    move || {
+        fired.lock().unwrap().push(name);
+        std::future::ready(())
+    }
+}
+
+#[tokio::test]
+async fn commit_callbacks_fire_after_commit_only() {
+    let db = common::sqlite_db("on_commit").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let fired = Fired::default();
+
+    conn.on_commit(record(&fired, "outside")).await;
+    assert_eq!(*fired.lock().unwrap(), vec!["outside"]);
+
+    conn.begin_transaction().await.unwrap();
+    conn.on_commit(record(&fired, "first")).await;
+    conn.on_commit(record(&fired, "second")).await;
+    assert_eq!(fired.lock().unwrap().len(), 1);
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(*fired.lock().unwrap(), vec!["outside", "first", "second"]);
+
+    conn.begin_transaction().await.unwrap();
+    conn.on_commit(record(&fired, "rolled back")).await;
+    conn.rollback_transaction().await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(fired.lock().unwrap().len(), 3);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn rollback_to_savepoint_drops_the_callbacks_of_its_scope() {
+    let db = common::sqlite_db("on_commit_savepoint").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let fired = Fired::default();
+
+    conn.begin_transaction().await.unwrap();
+    conn.on_commit(record(&fired, "before")).await;
+    conn.begin_savepoint("risky").await.unwrap();
+    conn.on_commit(record(&fired, "inside")).await;
+    conn.rollback_to_savepoint("risky").await.unwrap();
+    conn.on_commit(record(&fired, "after")).await;
+    conn.release_savepoint("risky").await.unwrap();
+    conn.commit_transaction().await.unwrap();
+    assert_eq!(*fired.lock().unwrap(), vec!["before", "after"]);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn savepoints_need_a_transaction_and_a_name() {
+    let db = common::sqlite_db("savepoint_names").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    assert_eq!(conn.begin_savepoint("sp").await.unwrap_err().code(), SqlErrorCode::NotInTransaction);
+    conn.begin_transaction().await.unwrap();
+    assert_eq!(conn.savepoint("").await.unwrap_err().code(), SqlErrorCode::ParameterMismatch);
+    assert_eq!(conn.savepoint("a\0b").await.unwrap_err().code(), SqlErrorCode::ParameterMismatch);
+    // Quoted, so any other text is a valid name.
+    conn.savepoint("it's \"odd\"; DROP").await.unwrap();
+    conn.release_savepoint("it's \"odd\"; DROP").await.unwrap();
+    conn.rollback_transaction().await.unwrap();
+    drop(conn);
+    db.finish().await;
+}
+
+// The transaction lives in the connection's own protocol state, nothing borrows the connection,
+// so it can move between tasks and through boxes with a transaction open.
+#[tokio::test]
+async fn open_transaction_moves_with_the_connection() {
+    let db = common::sqlite_db("moved_tx").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE moved (v INTEGER)")).await.unwrap();
+    conn.begin_transaction().await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO moved (v) VALUES (1)")).await.unwrap();
+
+    let mut boxed = Box::new(conn);
+    boxed.execute_sql(sql_query("INSERT INTO moved (v) VALUES (2)")).await.unwrap();
+    let mut conn = tokio::spawn(async move {
+        let mut conn = *boxed;
+        conn.execute_sql(sql_query("INSERT INTO moved (v) VALUES (3)")).await.unwrap();
+        conn.commit_transaction().await.unwrap();
+        conn
+    }).await.unwrap();
+
+    let row = conn.query_one(sql_query("SELECT count(*) AS c FROM moved")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("c"), 3);
+    drop(conn);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn moved_connection_dropped_mid_transaction_rolls_back() {
+    let db = common::sqlite_db("moved_tx_dropped").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE moved (v INTEGER)")).await.unwrap();
+    drop(conn);
+
+    // Opening a connection waits for the write lock, so all are open before the first write.
+    let mut held = Vec::new();
+    for _ in 0..3 {
+        held.push(db.pool.get_conn().await.unwrap());
+    }
+    for (i, conn) in held.iter_mut().enumerate() {
+        conn.set_transaction_drop_policy(TransactionDropPolicy::RollbackSilently);
+        conn.begin_transaction().await.unwrap();
+        // sqlite takes one writer at a time, the others only read in their transaction.
+        if i == 0 {
+            conn.execute_sql(sql_query("INSERT INTO moved (v) VALUES (1)")).await.unwrap();
+        } else {
+            conn.query_all(sql_query("SELECT v FROM moved")).await.unwrap();
+        }
+    }
+    tokio::spawn(async move { drop(held) }).await.unwrap();
+
+    for _ in 0..10 {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.begin_transaction().await.unwrap();
+        conn.rollback_transaction().await.unwrap();
+    }
+    let row = db.pool.query_one(sql_query("SELECT count(*) AS c FROM moved")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("c"), 0);
+    db.finish().await;
+}
+
+// sqlx hands connections back to the pool from a spawned task.
+async fn all_released(pool: &SqlPool) -> bool {
+    for _ in 0..100 {
+        if pool.in_use() == 0 {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn pool_transaction_commits_rolls_back_and_releases_the_connection() {
+    let db = common::sqlite_db("pool_tx").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE pooled (v INTEGER UNIQUE)")).await.unwrap();
+    drop(conn);
+
+    let v = db.pool.with_transaction(|conn| Box::pin(async move {
+        conn.execute_sql(sql_query("INSERT INTO pooled (v) VALUES (1)")).await?;
+        Ok(1)
+    })).await.unwrap();
+    assert_eq!(v, 1);
+    let e = db.pool.with_transaction(|conn| Box::pin(async move {
+        conn.execute_sql(sql_query("INSERT INTO pooled (v) VALUES (2)")).await?;
+        conn.execute_sql(sql_query("INSERT INTO pooled (v) VALUES (1)")).await?;
+        Ok(())
+    })).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    assert!(all_released(&db.pool).await);
+
+    let pool = db.pool.clone();
+    let task = tokio::spawn(async move {
+        pool.with_transaction::<_, ()>(|conn| Box::pin(async move {
+            conn.execute_sql(sql_query("INSERT INTO pooled (v) VALUES (3)")).await?;
+            panic!("body failed");
+        })).await
+    });
+    assert!(task.await.unwrap_err().is_panic());
+    assert!(all_released(&db.pool).await);
+
+    let rows = db.pool.query_all(sql_query("SELECT v FROM pooled")).await.unwrap();
+    assert_eq!(rows.iter().map(|row| row.get::<i64, _>("v")).collect::<Vec<_>>(), vec![1]);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn dropped_transaction_is_rolled_back_as_the_policy_says() {
+    let _logs = common::logs::lock().await;
+    let db = common::sqlite_db("drop_policy").await.unwrap();
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE dropped (v TEXT)")).await.unwrap();
+    drop(conn);
+
+    for (policy, v) in [(TransactionDropPolicy::RollbackAndLog, "logged"), (TransactionDropPolicy::RollbackSilently, "silent")] {
+        let mut conn = db.pool.get_conn().await.unwrap();
+        conn.set_transaction_drop_policy(policy);
+        conn.begin_transaction().await.unwrap();
+        conn.execute_sql(sql_query(&format!("INSERT INTO dropped (v) VALUES ('{}')", v))).await.unwrap();
+        drop(conn);
+    }
+    let logged = common::logs::records_with("dropped with transaction");
+    assert_eq!(logged.len(), 1);
+    assert!(logged[0].1.contains("VALUES ('logged')"), "{}", logged[0].1);
+
+    let pool = db.pool.clone();
+    let task = tokio::spawn(async move {
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.set_transaction_drop_policy(TransactionDropPolicy::Panic);
+        conn.begin_transaction().await.unwrap();
+        conn.execute_sql(sql_query("INSERT INTO dropped (v) VALUES ('panicked')")).await.unwrap();
+    });
+    assert!(task.await.unwrap_err().is_panic());
+
+    assert!(all_released(&db.pool).await);
+    let rows = db.pool.query_all(sql_query("SELECT v FROM dropped")).await.unwrap();
+    assert!(rows.is_empty());
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn pool_retry_transaction_retries_transient_errors_only() {
+    let db = common::sqlite_db("pool_retry_tx").await.unwrap();
+    let injector = FaultInjector::new();
+    injector.add(FaultMatcher::SqlContains("INSERT INTO retried".to_string()), Fault::Busy, 2);
+    let pool = db.pool.clone().with_fault_injector(injector.clone());
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE retried (v INTEGER UNIQUE)")).await.unwrap();
+    drop(conn);
+
+    let attempts = Arc::new(AtomicU32::new(0));
+    let counter = attempts.clone();
+    pool.retry_transaction(&quick_policy(3), move |conn| {
+        let counter = counter.clone();
+        Box::pin(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            conn.execute_sql(sql_query("INSERT INTO retried (v) VALUES (1)")).await?;
+            Ok(())
+        })
+    }).await.unwrap();
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+    // A duplicate key fails the same way every time, it is returned after the first attempt.
+    let counter = attempts.clone();
+    let e = pool.retry_transaction(&quick_policy(3), move |conn| {
+        let counter = counter.clone();
+        Box::pin(async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            conn.execute_sql(sql_query("INSERT INTO retried (v) VALUES (2)")).await?;
+            conn.execute_sql(sql_query("INSERT INTO retried (v) VALUES (1)")).await?;
+            Ok(())
+        })
+    }).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    let row = pool.query_one(sql_query("SELECT count(*) AS c FROM retried")).await.unwrap();
+    assert_eq!(row.get::<i64, _>("c"), 1);
+    db.finish().await;
+}
@@ -0,0 +1,85 @@
+use sfo_sql::errors::SqlErrorCode;
+use sfo_sql::sqlite::{sql_query, IndexMap, SqlPool, SqlRow, SqlValue, UnitOfWork};
+use crate::common;
+
+fn values(pairs: &[(&str, SqlValue)]) -> IndexMap<String, SqlValue> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+}
+
+// Each layer of a request records its writes into the unit it is handed.
+fn create_account(unit: &mut UnitOfWork, id: i64, owner: &str) {
+    unit.insert("accounts", values(&[("id", SqlValue::Int(id)), ("owner", SqlValue::Text(owner.to_string()))]));
+}
+
+fn audit(action: &str) -> UnitOfWork {
+    let mut unit = UnitOfWork::new();
+    unit.execute("INSERT INTO audit (action) VALUES (?)", vec![SqlValue::Text(action.to_string())]);
+    unit
+}
+
+async fn create_tables(db: &common::TestDb<SqlPool>) {
+    let mut conn = db.pool.get_conn().await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE accounts (id INTEGER PRIMARY KEY, owner TEXT NOT NULL)")).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE audit (action TEXT NOT NULL)")).await.unwrap();
+}
+
+async fn count(db: &common::TestDb<SqlPool>, table: &str) -> i64 {
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let row = conn.query_one(sql_query(&format!("SELECT count(*) AS c FROM {}", table))).await.unwrap();
+    row.get::<i64, _>("c")
+}
+
+#[tokio::test]
+async fn unit_built_across_layers_commits_in_order() {
+    let db = common::sqlite_db("uow_commit").await.unwrap();
+    create_tables(&db).await;
+
+    let mut unit = UnitOfWork::new();
+    create_account(&mut unit, 1, "ann");
+    create_account(&mut unit, 2, "bob");
+    unit.update("accounts", values(&[("owner", SqlValue::Text("anna".to_string()))]), values(&[("id", SqlValue::Int(1))]));
+    unit.delete("accounts", values(&[("id", SqlValue::Int(2))]));
+    unit.append(audit("created"));
+    assert_eq!(unit.len(), 5);
+
+    let affected = unit.commit(&db.pool).await.unwrap();
+    assert_eq!(affected, vec![1, 1, 1, 1, 1]);
+    let mut conn = db.pool.get_conn().await.unwrap();
+    let rows = conn.query_all(sql_query("SELECT id, owner FROM accounts")).await.unwrap();
+    assert_eq!(rows.iter().map(|r| (r.get::<i64, _>("id"), r.get::<String, _>("owner"))).collect::<Vec<_>>(),
+               vec![(1, "anna".to_string())]);
+    drop(conn);
+    assert_eq!(count(&db, "audit").await, 1);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn failing_operation_keeps_nothing_of_the_unit() {
+    let db = common::sqlite_db("uow_rollback").await.unwrap();
+    create_tables(&db).await;
+
+    let mut unit = UnitOfWork::new();
+    create_account(&mut unit, 1, "ann");
+    unit.append(audit("created"));
+    // Same key again, the constraint fails the third operation.
+    create_account(&mut unit, 1, "ann");
+    let e = unit.commit(&db.pool).await.unwrap_err();
+    assert_eq!(e.code(), SqlErrorCode::AlreadyExists);
+    assert_eq!(count(&db, "accounts").await, 0);
+    assert_eq!(count(&db, "audit").await, 0);
+
+    // The unit is left as recorded, committing it without the duplicate succeeds.
+    assert_eq!(unit.dedup(), 1);
+    assert_eq!(unit.commit(&db.pool).await.unwrap(), vec![1, 1]);
+    assert_eq!(count(&db, "accounts").await, 1);
+    db.finish().await;
+}
+
+#[tokio::test]
+async fn empty_unit_commits_without_a_connection() {
+    let db = common::sqlite_db("uow_empty").await.unwrap();
+    let unit = UnitOfWork::new();
+    assert!(unit.is_empty());
+    assert!(unit.commit(&db.pool).await.unwrap().is_empty());
+    db.finish().await;
+}
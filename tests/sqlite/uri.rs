@@ -0,0 +1,24 @@
+use sfo_sql::sqlite::{sql_query, SqlConnection, SqliteUriBuilder, SqlRow};
+use sfo_sql::test_util::TempSqliteDb;
+
+#[tokio::test]
+async fn a_connection_creates_a_file_whose_name_has_uri_characters() {
+    // Test names are sanitized, the file name is made from one.
+    let name = TempSqliteDb::new("uri").path().with_extension("a?b#c%25 d.db");
+    let uri = SqliteUriBuilder::new().path(&name).create().build();
+    assert_eq!(SqliteUriBuilder::parse(uri.as_str()).unwrap().build(), uri);
+
+    let mut conn = SqlConnection::open(uri.as_str()).await.unwrap();
+    conn.execute_sql(sql_query("CREATE TABLE t (v INTEGER)")).await.unwrap();
+    conn.execute_sql(sql_query("INSERT INTO t VALUES (7)")).await.unwrap();
+    drop(conn);
+    assert!(name.exists());
+
+    // Opened again without create, the same file is found.
+    let uri = SqliteUriBuilder::new().path(&name).build();
+    let mut conn = SqlConnection::open(uri.as_str()).await.unwrap();
+    let row = conn.query_one(sql_query("SELECT v FROM t")).await.unwrap();
+    assert_eq!(row.get::<i64, _>(0), 7);
+    drop(conn);
+    std::fs::remove_file(&name).unwrap();
+}